@@ -1,6 +1,7 @@
 use std::{collections::HashSet, fs::File, io::Read, panic::{catch_unwind, AssertUnwindSafe}, path::PathBuf, sync::{atomic::{AtomicU32, AtomicU8, Ordering}, mpsc::{channel, Receiver, Sender}, Arc}, thread};
 
-use crude::{dsp::DSP, Gamecube};
+use crude::{bus::BusErrorPolicy, dsp::DSP, Gamecube};
+use log::error;
 
 pub type SharedInstructionBuffer = Arc<[(AtomicU32, AtomicU32)]>;
 
@@ -14,6 +15,8 @@ pub fn start_emu(ipl_path: impl ToString, instruction_buffer: SharedInstructionB
 	let aram = Arc::new(std::iter::repeat_with(|| AtomicU8::new(0)).take(0x0100_0000).collect::<Vec<_>>());
 	let (mut dsp, client) = DSP::new(aram.clone());
 	let mut gamecube = Gamecube::new(bios_data, aram, client);
+	//stray accesses get logged instead of taking the whole debugger down with them
+	gamecube.bus_policy = BusErrorPolicy::OpenBus;
 
 	processor_state.update(&mut gamecube);
 	update_instruction_buffer(&mut gamecube, &instruction_buffer);
@@ -24,8 +27,10 @@ pub fn start_emu(ipl_path: impl ToString, instruction_buffer: SharedInstructionB
 		    Command::Run => {
 			'shmeep: loop {
 			    for _ in 0..2000 {
-				dsp.step();
-				crude::step(&mut gamecube);
+				if checkstopped(&gamecube) {
+				    break 'shmeep;
+				}
+				crude::step(&mut gamecube, &mut dsp);
 				if breakpoints.contains(&gamecube.cpu.cia) {
 				    break 'shmeep;
 				}
@@ -41,8 +46,10 @@ pub fn start_emu(ipl_path: impl ToString, instruction_buffer: SharedInstructionB
 		    Command::Step => {
 			update_instruction_buffer(&mut gamecube, &instruction_buffer);
 			processor_state.update(&mut gamecube);
-			dsp.step();
-			crude::step(&mut gamecube);
+			if !checkstopped(&gamecube) {
+			    crude::step(&mut gamecube, &mut dsp);
+			    checkstopped(&gamecube);
+			}
 		    },
 		    Command::Breakpoint(addr) => {
 			breakpoints.insert(addr);
@@ -96,12 +103,21 @@ impl SharedProcessorState {
     }
 }
 
+//a checkstopped cpu won't run anything again, so say where it stopped instead of stepping on
+fn checkstopped(gc: &Gamecube) -> bool {
+    if let Some(addr) = gc.cpu.checkstop {
+	error!("the cpu checkstopped at {addr:#010X}, a machine check came in with MSR[ME] clear");
+	return true;
+    }
+    false
+}
+
 fn update_instruction_buffer(gc: &mut Gamecube, buffer: &SharedInstructionBuffer) {
     let mut start = gc.cpu.cia.wrapping_sub(5 * 4);
 
     for (addr, instr) in buffer.iter() {
 	addr.store(start, Ordering::Relaxed);
-	let instruction = catch_unwind(AssertUnwindSafe(|| gc.read_u32(start, true))).unwrap_or(0);
+	let instruction = gc.peek_u32(start, true).unwrap_or(0);
 	instr.store(instruction, Ordering::Relaxed);
 	start = start.wrapping_add(4);
     }
@@ -1,36 +1,160 @@
+use std::collections::VecDeque;
+
 use log::debug;
 
-use crate::Gamecube;
+use crate::{bus::mmio::{merge, Mmio, MmioHandlers}, processor_interface::{set_interrupt, PI_INT_AI}, scheduler::{self, EventKind, CPU_CLOCK}, Gamecube};
+
+//AICR
+const AICR_PSTAT: u32 = 1 << 0;
+const AICR_AISFR: u32 = 1 << 1;
+const AICR_AIINTMSK: u32 = 1 << 2;
+const AICR_AIINT: u32 = 1 << 3;
+const AICR_AIINTVLD: u32 = 1 << 4;
+const AICR_SCRESET: u32 = 1 << 5;
+const AICR_AIDFR: u32 = 1 << 6;
+
+//the bits that stick, SCRESET only does something at the time it's written
+const AICR_MASK: u32 = AICR_PSTAT | AICR_AISFR | AICR_AIINTMSK | AICR_AIINTVLD | AICR_AIDFR;
+
+//a second of dma audio is as much as gets held on to when nothing's draining it
+const SAMPLE_BUFFER_MAX: usize = 48_000;
 
 pub struct AudioInterface {
     pub control: u32,
+    pub volume: u32,
+    pub interrupt_timing: u32,
+    //the sample counter as it was at sample_counter_cycle. it runs from there while PSTAT is set
+    sample_counter: u32,
+    sample_counter_cycle: u64,
+    //stereo samples the dsp has pushed out through the dma, left then right, for the frontend to play
+    pub samples: VecDeque<[i16; 2]>,
 }
 
 impl AudioInterface {
     pub fn new() -> Self {
         Self {
 	    control: 0,
+	    volume: 0,
+	    interrupt_timing: 0,
+	    sample_counter: 0,
+	    sample_counter_cycle: 0,
+	    samples: VecDeque::new(),
+	}
+    }
+
+    //the rate dma audio plays at, 32kHz unless AIDFR is clear
+    pub fn dma_sample_rate(&self) -> u64 {
+	if self.control & AICR_AIDFR != 0 { 32_000 } else { 48_000 }
+    }
+
+    //the rate of the dvd's streamed audio, which is what the sample counter counts
+    pub fn stream_sample_rate(&self) -> u64 {
+	if self.control & AICR_AISFR != 0 { 48_000 } else { 32_000 }
+    }
+
+    fn sample_count(&self, now: u64) -> u32 {
+	if self.control & AICR_PSTAT == 0 {
+	    return self.sample_counter;
 	}
+
+	let elapsed = (now - self.sample_counter_cycle) * self.stream_sample_rate() / CPU_CLOCK;
+	self.sample_counter.wrapping_add(elapsed as u32)
     }
 }
 
-pub fn ai_write_u16(gc: &mut Gamecube, offset: u32, val: u16) {
-    debug!("STUB: AI write_u16 at offset {offset:#010X} with val {val:#06X}");
-    match offset {
-	_ => unimplemented!("AI write_u16 at offset {offset:#010X} with val {val:#06X}"),
+impl Default for AudioInterface {
+    fn default() -> Self {
+	Self::new()
     }
 }
 
-pub fn ai_write_u32(gc: &mut Gamecube, offset: u32, val: u32) {
+pub fn register_mmio(mmio: &mut Mmio) {
+    mmio.register("AI", 0x0C00_6C00, 0x400, MmioHandlers {
+	read_u32: Some(ai_read_u32),
+	write_u32: Some(ai_write_u32),
+	..MmioHandlers::NONE
+    });
+}
+
+pub fn ai_write_u32(gc: &mut Gamecube, offset: u32, val: u32, mask: u32) -> Option<()> {
     match offset {
-	0x00 => gc.ai.control = val,
-	_ => unimplemented!("AI write_u32 at offset {offset:#010X} with val {val:#010X}"),
+	0x00 => {
+	    //the counter is brought up to now before the rate or PSTAT can change under it
+	    let now = gc.cpu.cycles;
+	    gc.ai.sample_counter = if val & mask & AICR_SCRESET != 0 { 0 } else { gc.ai.sample_count(now) };
+	    gc.ai.sample_counter_cycle = now;
+
+	    //AIINT is write 1 to clear
+	    let status = gc.ai.control & AICR_AIINT & !(val & mask);
+	    gc.ai.control = (merge(gc.ai.control, val, mask) & AICR_MASK) | status;
+	    update_interrupts(gc);
+	    schedule_interrupt(gc);
+	},
+	0x04 => gc.ai.volume = merge(gc.ai.volume, val, mask),
+	0x08 => {
+	    gc.ai.sample_counter = merge(gc.ai.sample_count(gc.cpu.cycles), val, mask);
+	    gc.ai.sample_counter_cycle = gc.cpu.cycles;
+	    schedule_interrupt(gc);
+	},
+	0x0C => {
+	    gc.ai.interrupt_timing = merge(gc.ai.interrupt_timing, val, mask);
+	    schedule_interrupt(gc);
+	},
+	_ => debug!("STUB: AI write_u32 at offset {offset:#010X} with val {val:#010X}"),
     }
+    Some(())
 }
 
-pub fn ai_read_u32(gc: &mut Gamecube, offset: u32) -> u32 {
-    match offset {
+pub fn ai_read_u32(gc: &mut Gamecube, offset: u32) -> Option<u32> {
+    Some(match offset {
 	0x00 => gc.ai.control,
-	_ => unimplemented!("AI read_u32 at offset {offset:#010X}"),
+	0x04 => gc.ai.volume,
+	0x08 => gc.ai.sample_count(gc.cpu.cycles),
+	0x0C => gc.ai.interrupt_timing,
+	_ => {
+	    debug!("STUB: AI read_u32 at offset {offset:#010X}");
+	    0
+	},
+    })
+}
+
+//AIINT goes off when the sample counter reaches AIIT, so the event sits at the cycle that'll happen on
+fn schedule_interrupt(gc: &mut Gamecube) {
+    gc.scheduler.cancel(EventKind::AiInterrupt);
+    if gc.ai.control & AICR_PSTAT == 0 {
+	return;
+    }
+
+    let now = gc.cpu.cycles;
+    //already being there means waiting for the counter to wrap all the way round
+    let samples = match gc.ai.interrupt_timing.wrapping_sub(gc.ai.sample_count(now)) {
+	0 => 1 << 32,
+	samples => u64::from(samples),
+    };
+    //rounded up so the counter has definitely got there by the time it fires
+    let cycles = (samples * CPU_CLOCK).div_ceil(gc.ai.stream_sample_rate());
+    scheduler::schedule_in(gc, cycles, EventKind::AiInterrupt, interrupt_timing_reached);
+}
+
+fn interrupt_timing_reached(gc: &mut Gamecube, _kind: EventKind) {
+    gc.ai.control |= AICR_AIINT;
+    update_interrupts(gc);
+    schedule_interrupt(gc);
+}
+
+fn update_interrupts(gc: &mut Gamecube) {
+    let control = gc.ai.control;
+    set_interrupt(gc, PI_INT_AI, control & AICR_AIINT != 0 && control & AICR_AIINTMSK != 0);
+}
+
+//a 32 byte block the dsp's audio dma fetched out of ram: eight stereo samples, big endian, right then left
+pub fn push_dma_block(gc: &mut Gamecube, block: &[u8]) {
+    for sample in block.chunks_exact(4) {
+	let right = i16::from_be_bytes([sample[0], sample[1]]);
+	let left = i16::from_be_bytes([sample[2], sample[3]]);
+	gc.ai.samples.push_back([left, right]);
     }
+
+    let excess = gc.ai.samples.len().saturating_sub(SAMPLE_BUFFER_MAX);
+    gc.ai.samples.drain(..excess);
 }
@@ -0,0 +1,102 @@
+pub mod mmio;
+
+use std::fmt;
+
+use crate::cpu::mmu::TranslationFault;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AccessWidth {
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+impl AccessWidth {
+    pub fn bytes(&self) -> u32 {
+	match self {
+	    AccessWidth::U8 => 1,
+	    AccessWidth::U16 => 2,
+	    AccessWidth::U32 => 4,
+	    AccessWidth::U64 => 8,
+	}
+    }
+}
+
+impl fmt::Display for AccessWidth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	match self {
+	    AccessWidth::U8 => write!(f, "u8"),
+	    AccessWidth::U16 => write!(f, "u16"),
+	    AccessWidth::U32 => write!(f, "u32"),
+	    AccessWidth::U64 => write!(f, "u64"),
+	}
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AccessDirection {
+    Read,
+    Write,
+}
+
+//what happens when something touches an address nothing is mapped at
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BusErrorPolicy {
+    //blow up, same as the old unimplemented!() arms
+    Panic,
+    //log it, reads return open bus (0) and writes are dropped
+    OpenBus,
+    //log it and hand a machine check to the cpu, the faulting instruction doesn't complete
+    Exception,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BusError {
+    pub addr: u32,
+    pub width: AccessWidth,
+    pub direction: AccessDirection,
+    pub pc: u32,
+}
+
+impl fmt::Display for BusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	let direction = match self.direction {
+	    AccessDirection::Read => "read",
+	    AccessDirection::Write => "write",
+	};
+	write!(f, "unmapped {direction}_{} at physical address {:#010X} (pc: {:#010X})", self.width, self.addr, self.pc)
+    }
+}
+
+impl std::error::Error for BusError {}
+
+//anything that can stop a cpu side access from completing
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MemoryError {
+    Bus(BusError),
+    Translation(TranslationFault),
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	match self {
+	    MemoryError::Bus(err) => write!(f, "{err}"),
+	    MemoryError::Translation(fault) => write!(f, "{:?} fault translating {:#010X} for {:?}", fault.kind, fault.addr, fault.access),
+	}
+    }
+}
+
+impl std::error::Error for MemoryError {}
+
+impl From<BusError> for MemoryError {
+    fn from(err: BusError) -> Self {
+	MemoryError::Bus(err)
+    }
+}
+
+impl From<TranslationFault> for MemoryError {
+    fn from(fault: TranslationFault) -> Self {
+	MemoryError::Translation(fault)
+    }
+}
@@ -0,0 +1,241 @@
+use std::ops::{BitAnd, BitOr, Not};
+
+use crate::Gamecube;
+
+pub const MMIO_BASE: u32 = 0x0C00_0000;
+pub const MMIO_SIZE: u32 = 0x8000;
+
+//every block on the flipper side is 1kb aligned, so one slot per kb is enough to find it without searching
+const SLOT_SHIFT: u32 = 10;
+const SLOT_COUNT: usize = (MMIO_SIZE >> SLOT_SHIFT) as usize;
+
+//handlers return None for offsets inside their block that nothing answers to, which ends up as a bus error.
+//writes come with a mask of the byte lanes the cpu actually wrote: a narrow store to a wider register only
+//fills in its own lanes of val, and the device decides what that does to the rest
+pub type MmioRead<T> = fn(&mut Gamecube, u32) -> Option<T>;
+pub type MmioWrite<T> = fn(&mut Gamecube, u32, T, T) -> Option<()>;
+
+#[derive(Copy, Clone)]
+pub struct MmioHandlers {
+    pub read_u8: Option<MmioRead<u8>>,
+    pub read_u16: Option<MmioRead<u16>>,
+    pub read_u32: Option<MmioRead<u32>>,
+    pub write_u8: Option<MmioWrite<u8>>,
+    pub write_u16: Option<MmioWrite<u16>>,
+    pub write_u32: Option<MmioWrite<u32>>,
+}
+
+impl MmioHandlers {
+    pub const NONE: Self = Self {
+	read_u8: None,
+	read_u16: None,
+	read_u32: None,
+	write_u8: None,
+	write_u16: None,
+	write_u32: None,
+    };
+}
+
+#[derive(Copy, Clone)]
+struct MmioRegion {
+    name: &'static str,
+    base: u32,
+    handlers: MmioHandlers,
+}
+
+pub struct Mmio {
+    slots: [Option<MmioRegion>; SLOT_COUNT],
+}
+
+impl Mmio {
+    pub fn new() -> Self {
+	Self {
+	    slots: [None; SLOT_COUNT],
+	}
+    }
+
+    pub fn register(&mut self, name: &'static str, base: u32, size: u32, handlers: MmioHandlers) {
+	assert!(base >= MMIO_BASE && base + size <= MMIO_BASE + MMIO_SIZE, "{name} at {base:#010X} is outside of the mmio window");
+	assert!((base | size) & ((1 << SLOT_SHIFT) - 1) == 0, "{name} at {base:#010X} isn't slot aligned");
+
+	let first = ((base - MMIO_BASE) >> SLOT_SHIFT) as usize;
+	let last = first + (size >> SLOT_SHIFT) as usize;
+	for slot in &mut self.slots[first..last] {
+	    if let Some(other) = slot {
+		panic!("{name} at {base:#010X} overlaps {}", other.name);
+	    }
+	    *slot = Some(MmioRegion {
+		name,
+		base,
+		handlers,
+	    });
+	}
+    }
+
+    fn region(&self, phys: u32) -> Option<MmioRegion> {
+	self.slots[((phys - MMIO_BASE) >> SLOT_SHIFT) as usize]
+    }
+}
+
+impl Default for Mmio {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+//the written lanes of val over the rest of old, for plain registers that don't care how they're written
+pub fn merge<T: Copy + BitAnd<Output = T> + BitOr<Output = T> + Not<Output = T>>(old: T, val: T, mask: T) -> T {
+    (old & !mask) | (val & mask)
+}
+
+//hardware registers are big endian like everything else, so the lower address is always the upper half.
+//accesses with no native handler get split into (or merged out of) whatever widths the block does have, narrow
+//writes never read the register back
+pub fn mmio_read_u8(gc: &mut Gamecube, phys: u32) -> Option<u8> {
+    let region = gc.mmio.region(phys)?;
+    let offset = phys - region.base;
+
+    if let Some(read) = region.handlers.read_u8 {
+	read(gc, offset)
+    } else if let Some(read) = region.handlers.read_u16 {
+	let val = read(gc, offset & !1)?;
+	Some((val >> ((!offset & 1) * 8)) as u8)
+    } else if let Some(read) = region.handlers.read_u32 {
+	let val = read(gc, offset & !3)?;
+	Some((val >> ((!offset & 3) * 8)) as u8)
+    } else {
+	None
+    }
+}
+
+pub fn mmio_read_u16(gc: &mut Gamecube, phys: u32) -> Option<u16> {
+    let region = gc.mmio.region(phys)?;
+    let offset = phys - region.base;
+
+    if let Some(read) = region.handlers.read_u16 {
+	read(gc, offset)
+    } else if let Some(read) = region.handlers.read_u32 {
+	let val = read(gc, offset & !3)?;
+	Some((val >> ((!offset & 2) * 8)) as u16)
+    } else if let Some(read) = region.handlers.read_u8 {
+	let hi = read(gc, offset)? as u16;
+	let lo = read(gc, offset + 1)? as u16;
+	Some((hi << 8) | lo)
+    } else {
+	None
+    }
+}
+
+pub fn mmio_read_u32(gc: &mut Gamecube, phys: u32) -> Option<u32> {
+    let region = gc.mmio.region(phys)?;
+    let offset = phys - region.base;
+
+    if let Some(read) = region.handlers.read_u32 {
+	read(gc, offset)
+    } else if let Some(read) = region.handlers.read_u16 {
+	let hi = read(gc, offset)? as u32;
+	let lo = read(gc, offset + 2)? as u32;
+	Some((hi << 16) | lo)
+    } else if let Some(read) = region.handlers.read_u8 {
+	let mut val = 0;
+	for i in 0..4 {
+	    val = (val << 8) | read(gc, offset + i)? as u32;
+	}
+	Some(val)
+    } else {
+	None
+    }
+}
+
+pub fn mmio_write_u8(gc: &mut Gamecube, phys: u32, val: u8) -> Option<()> {
+    let region = gc.mmio.region(phys)?;
+    let offset = phys - region.base;
+
+    if let Some(write) = region.handlers.write_u8 {
+	write(gc, offset, val, 0xFF)?;
+    } else if let Some(write) = region.handlers.write_u16 {
+	let shift = (!offset & 1) * 8;
+	write(gc, offset & !1, (val as u16) << shift, 0xFF << shift)?;
+    } else {
+	let write = region.handlers.write_u32?;
+	let shift = (!offset & 3) * 8;
+	write(gc, offset & !3, (val as u32) << shift, 0xFF << shift)?;
+    }
+
+    Some(())
+}
+
+pub fn mmio_write_u16(gc: &mut Gamecube, phys: u32, val: u16) -> Option<()> {
+    let region = gc.mmio.region(phys)?;
+    let offset = phys - region.base;
+
+    if let Some(write) = region.handlers.write_u16 {
+	write(gc, offset, val, 0xFFFF)?;
+    } else if let Some(write) = region.handlers.write_u32 {
+	let shift = (!offset & 2) * 8;
+	write(gc, offset & !3, (val as u32) << shift, 0xFFFF << shift)?;
+    } else {
+	let write = region.handlers.write_u8?;
+	write(gc, offset, (val >> 8) as u8, 0xFF)?;
+	write(gc, offset + 1, val as u8, 0xFF)?;
+    }
+
+    Some(())
+}
+
+pub fn mmio_write_u32(gc: &mut Gamecube, phys: u32, val: u32) -> Option<()> {
+    let region = gc.mmio.region(phys)?;
+    let offset = phys - region.base;
+
+    if let Some(write) = region.handlers.write_u32 {
+	write(gc, offset, val, 0xFFFF_FFFF)?;
+    } else if let Some(write) = region.handlers.write_u16 {
+	write(gc, offset, (val >> 16) as u16, 0xFFFF)?;
+	write(gc, offset + 2, val as u16, 0xFFFF)?;
+    } else {
+	let write = region.handlers.write_u8?;
+	for i in 0..4 {
+	    write(gc, offset + i, (val >> (24 - i * 8)) as u8, 0xFF)?;
+	}
+    }
+
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{bus::BusErrorPolicy, cpu::MACHINE_CHECK_EXCEPTION, processor_interface::{PI_INT_ERROR, PI_INT_RSW}, test_gamecube};
+
+    #[test]
+    fn unknown_offsets_in_a_mapped_block_are_bus_errors() {
+	let mut gc = test_gamecube();
+
+	//DI only answers at 0x24
+	gc.bus_policy = BusErrorPolicy::OpenBus;
+	assert_eq!(gc.read_u32(0x0C00_6024, false).unwrap(), 1);
+	assert_eq!(gc.read_u32(0x0C00_6000, false).unwrap(), 0);
+	assert_eq!(gc.cpu.exceptions & MACHINE_CHECK_EXCEPTION, 0);
+
+	gc.bus_policy = BusErrorPolicy::Exception;
+	assert!(gc.write_u16(0x0C00_4000, 0x1234).is_err());
+	assert_ne!(gc.cpu.exceptions & MACHINE_CHECK_EXCEPTION, 0);
+    }
+
+    #[test]
+    fn narrow_writes_only_touch_their_own_lanes() {
+	let mut gc = test_gamecube();
+
+	gc.pi.intmr = 0x1234_5678;
+	gc.write_u8(0x0C00_3005, 0xAB).unwrap();
+	assert_eq!(gc.pi.intmr, 0x12AB_5678);
+	gc.write_u16(0x0C00_3006, 0xCDEF).unwrap();
+	assert_eq!(gc.pi.intmr, 0x12AB_CDEF);
+
+	//INTSR is write 1 to clear, so the set bits in lanes that weren't written mustn't clear anything
+	gc.pi.intsr = PI_INT_ERROR | PI_INT_RSW;
+	gc.write_u8(0x0C00_3000, 0).unwrap();
+	assert_eq!(gc.pi.intsr, PI_INT_ERROR | PI_INT_RSW);
+	gc.write_u8(0x0C00_3003, PI_INT_ERROR as u8).unwrap();
+	assert_eq!(gc.pi.intsr, PI_INT_RSW);
+    }
+}
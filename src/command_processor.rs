@@ -0,0 +1,225 @@
+use std::mem;
+
+use log::debug;
+
+use crate::{bus::mmio::{merge, Mmio, MmioHandlers}, gx::command, processor_interface::{set_interrupt, FIFO_BURST_SIZE, PI_INT_CP}, Gamecube};
+
+//status register
+const CP_SR_OVERFLOW: u16 = 1 << 0;
+const CP_SR_UNDERFLOW: u16 = 1 << 1;
+const CP_SR_READ_IDLE: u16 = 1 << 2;
+const CP_SR_COMMAND_IDLE: u16 = 1 << 3;
+const CP_SR_BREAKPOINT: u16 = 1 << 4;
+
+//control register
+const CP_CR_READ_ENABLE: u16 = 1 << 0;
+const CP_CR_BP_ENABLE: u16 = 1 << 1;
+const CP_CR_OVERFLOW_INT: u16 = 1 << 2;
+const CP_CR_UNDERFLOW_INT: u16 = 1 << 3;
+const CP_CR_LINK_ENABLE: u16 = 1 << 4;
+const CP_CR_BP_INT: u16 = 1 << 5;
+
+//clear register
+const CP_CLEAR_OVERFLOW: u16 = 1 << 0;
+const CP_CLEAR_UNDERFLOW: u16 = 1 << 1;
+
+const FIFO_ADDR_MASK: u32 = 0x03FF_FFE0;
+
+pub struct CommandProcessor {
+    pub status: u16,
+    pub control: u16,
+    pub fifo_base: u32,
+    pub fifo_end: u32,
+    pub hi_watermark: u32,
+    pub lo_watermark: u32,
+    pub distance: u32,
+    pub wptr: u32,
+    pub rptr: u32,
+    pub breakpoint: u32,
+    //bytes read out of the fifo that don't make up a whole command yet
+    pending: Vec<u8>,
+}
+
+impl CommandProcessor {
+    pub fn new() -> Self {
+	Self {
+	    status: CP_SR_READ_IDLE | CP_SR_COMMAND_IDLE,
+	    control: 0,
+	    fifo_base: 0,
+	    fifo_end: 0,
+	    hi_watermark: 0,
+	    lo_watermark: 0,
+	    distance: 0,
+	    wptr: 0,
+	    rptr: 0,
+	    breakpoint: 0,
+	    pending: Vec::new(),
+	}
+    }
+}
+
+impl Default for CommandProcessor {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+pub fn register_mmio(mmio: &mut Mmio) {
+    mmio.register("CP", 0x0C00_0000, 0x1000, MmioHandlers {
+	read_u16: Some(cp_read_u16),
+	write_u16: Some(cp_write_u16),
+	..MmioHandlers::NONE
+    });
+}
+
+//the pointers are split over two registers, the low half at the lower address
+fn set_lo(reg: &mut u32, val: u16, mask: u16) {
+    *reg = merge(*reg, u32::from(val), u32::from(mask)) & FIFO_ADDR_MASK;
+}
+
+fn set_hi(reg: &mut u32, val: u16, mask: u16) {
+    *reg = merge(*reg, u32::from(val) << 16, u32::from(mask) << 16) & FIFO_ADDR_MASK;
+}
+
+pub fn cp_write_u16(gc: &mut Gamecube, offset: u32, val: u16, mask: u16) -> Option<()> {
+    debug!("CP write_u16 at offset {offset:#010X} with val {val:#06X}");
+    let cp = &mut gc.cp;
+    match offset {
+	0x02 => {
+	    cp.control = merge(cp.control, val, mask);
+	    //turning the breakpoint off lets the read pointer past it
+	    if cp.control & CP_CR_BP_ENABLE == 0 {
+		cp.status &= !CP_SR_BREAKPOINT;
+	    }
+	},
+	0x04 => {
+	    //only the bits actually written do anything
+	    let val = val & mask;
+	    if val & CP_CLEAR_OVERFLOW != 0 {
+		cp.status &= !CP_SR_OVERFLOW;
+	    }
+	    if val & CP_CLEAR_UNDERFLOW != 0 {
+		cp.status &= !CP_SR_UNDERFLOW;
+	    }
+	},
+	0x20 => set_lo(&mut cp.fifo_base, val, mask),
+	0x22 => set_hi(&mut cp.fifo_base, val, mask),
+	0x24 => set_lo(&mut cp.fifo_end, val, mask),
+	0x26 => set_hi(&mut cp.fifo_end, val, mask),
+	0x28 => set_lo(&mut cp.hi_watermark, val, mask),
+	0x2A => set_hi(&mut cp.hi_watermark, val, mask),
+	0x2C => set_lo(&mut cp.lo_watermark, val, mask),
+	0x2E => set_hi(&mut cp.lo_watermark, val, mask),
+	0x30 => set_lo(&mut cp.distance, val, mask),
+	0x32 => set_hi(&mut cp.distance, val, mask),
+	0x34 => set_lo(&mut cp.wptr, val, mask),
+	0x36 => set_hi(&mut cp.wptr, val, mask),
+	0x38 => set_lo(&mut cp.rptr, val, mask),
+	0x3A => set_hi(&mut cp.rptr, val, mask),
+	0x3C => set_lo(&mut cp.breakpoint, val, mask),
+	0x3E => set_hi(&mut cp.breakpoint, val, mask),
+	_ => debug!("STUB: CP write_u16 at offset {offset:#010X} with val {val:#06X}"),
+    }
+
+    //most of these can let the cp get going on whatever's already in the fifo
+    run(gc);
+    Some(())
+}
+
+pub fn cp_read_u16(gc: &mut Gamecube, offset: u32) -> Option<u16> {
+    debug!("CP read_u16 at offset {offset:#010X}");
+    let cp = &gc.cp;
+    Some(match offset {
+	0x00 => cp.status,
+	0x02 => cp.control,
+	0x20 => cp.fifo_base as u16,
+	0x22 => (cp.fifo_base >> 16) as u16,
+	0x24 => cp.fifo_end as u16,
+	0x26 => (cp.fifo_end >> 16) as u16,
+	0x28 => cp.hi_watermark as u16,
+	0x2A => (cp.hi_watermark >> 16) as u16,
+	0x2C => cp.lo_watermark as u16,
+	0x2E => (cp.lo_watermark >> 16) as u16,
+	0x30 => cp.distance as u16,
+	0x32 => (cp.distance >> 16) as u16,
+	0x34 => cp.wptr as u16,
+	0x36 => (cp.wptr >> 16) as u16,
+	0x38 => cp.rptr as u16,
+	0x3A => (cp.rptr >> 16) as u16,
+	0x3C => cp.breakpoint as u16,
+	0x3E => (cp.breakpoint >> 16) as u16,
+	_ => {
+	    debug!("STUB: CP read_u16 at offset {offset:#010X}");
+	    0
+	},
+    })
+}
+
+//called by the PI after each burst from the gather pipe. with the two linked the cp's write pointer follows the PI's
+pub fn gather_pipe_burst(gc: &mut Gamecube) {
+    if gc.cp.control & CP_CR_LINK_ENABLE == 0 {
+	return;
+    }
+
+    gc.cp.wptr = gc.pi.fifo_wptr & FIFO_ADDR_MASK;
+    gc.cp.distance += FIFO_BURST_SIZE as u32;
+    run(gc);
+}
+
+//reads everything between the read and write pointers (or up to the breakpoint) and runs it. the gpu
+//gets through it all at once, so the fifo is always empty again by the time the cpu looks
+fn run(gc: &mut Gamecube) {
+    let mut pending = mem::take(&mut gc.cp.pending);
+
+    let cp = &mut gc.cp;
+    while cp.control & CP_CR_READ_ENABLE != 0 && cp.distance != 0 {
+	if cp.control & CP_CR_BP_ENABLE != 0 && cp.rptr == cp.breakpoint {
+	    cp.status |= CP_SR_BREAKPOINT;
+	    break;
+	}
+
+	let rptr = cp.rptr as usize;
+	match gc.memory.get(rptr..(rptr + FIFO_BURST_SIZE)) {
+	    Some(burst) => pending.extend_from_slice(burst),
+	    None => debug!("CP read from {rptr:#010X} is outside of ram"),
+	}
+
+	cp.rptr = if cp.rptr == cp.fifo_end {
+	    cp.fifo_base
+	} else {
+	    cp.rptr + FIFO_BURST_SIZE as u32
+	};
+	cp.distance -= FIFO_BURST_SIZE as u32;
+    }
+
+    let done = command::process(gc, &pending, false);
+    pending.drain(..done);
+    gc.cp.pending = pending;
+
+    update_status(gc);
+}
+
+fn update_status(gc: &mut Gamecube) {
+    let cp = &mut gc.cp;
+
+    //the watermarks only mean anything while the gather pipe is filling the fifo
+    if cp.control & CP_CR_LINK_ENABLE != 0 {
+	if cp.distance > cp.hi_watermark {
+	    cp.status |= CP_SR_OVERFLOW;
+	}
+	if cp.distance < cp.lo_watermark {
+	    cp.status |= CP_SR_UNDERFLOW;
+	}
+    }
+
+    if cp.control & CP_CR_READ_ENABLE == 0 || cp.distance == 0 || cp.status & CP_SR_BREAKPOINT != 0 {
+	cp.status |= CP_SR_READ_IDLE | CP_SR_COMMAND_IDLE;
+    } else {
+	cp.status &= !(CP_SR_READ_IDLE | CP_SR_COMMAND_IDLE);
+    }
+
+    let active = (cp.status & CP_SR_OVERFLOW != 0 && cp.control & CP_CR_OVERFLOW_INT != 0)
+	|| (cp.status & CP_SR_UNDERFLOW != 0 && cp.control & CP_CR_UNDERFLOW_INT != 0)
+	|| (cp.status & CP_SR_BREAKPOINT != 0 && cp.control & CP_CR_BP_INT != 0);
+    set_interrupt(gc, PI_INT_CP, active);
+}
@@ -1,20 +1,26 @@
-use std::{cmp::Ordering, ops::Mul};
-
-use log::debug;
+use std::cmp::Ordering;
 
 use crate::Gamecube;
 
 use super::instr::Instruction;
 
+//signed overflow happened if both inputs had the same sign and the result's differs. subtraction passes !rA
+fn overflowed(a: u32, b: u32, result: u32) -> bool {
+    ((a ^ result) & (b ^ result)) & 0x8000_0000 != 0
+}
+
 pub fn add(gc: &mut Gamecube, instr: &Instruction) {
-    gc.cpu.gprs[instr.d()] = gc.cpu.gprs[instr.a()].wrapping_add(gc.cpu.gprs[instr.b()]);
+    let a = gc.cpu.gprs[instr.a()];
+    let b = gc.cpu.gprs[instr.b()];
+    let r = a.wrapping_add(b);
+    gc.cpu.gprs[instr.d()] = r;
 
-    if instr.rc() {
-	gc.cpu.do_cr0(gc.cpu.gprs[instr.d()]);
+    if instr.oe() {
+	gc.cpu.xer.set_ov(overflowed(a, b, r));
     }
 
-    if instr.oe() {
-	unimplemented!("xer");
+    if instr.rc() {
+	gc.cpu.do_cr0(r);
     }
 }
 
@@ -27,27 +33,60 @@ pub fn addi(gc: &mut Gamecube, instr: &Instruction) {
 }
 
 pub fn addc(gc: &mut Gamecube, instr: &Instruction) {
-    let (result, carry) = gc.cpu.gprs[instr.a()].overflowing_add(gc.cpu.gprs[instr.b()]);
+    let a = gc.cpu.gprs[instr.a()];
+    let b = gc.cpu.gprs[instr.b()];
+    let (result, carry) = a.overflowing_add(b);
     gc.cpu.gprs[instr.d()] = result;
-    if instr.rc() {
-	gc.cpu.do_cr0(result);
-    }
     gc.cpu.xer.set_ca(carry);
     if instr.oe() {
-	unimplemented!("xer");
+	gc.cpu.xer.set_ov(overflowed(a, b, result));
+    }
+    if instr.rc() {
+	gc.cpu.do_cr0(result);
     }
 }
 
 pub fn adde(gc: &mut Gamecube, instr: &Instruction) {
-    let (result, carry) = gc.cpu.gprs[instr.a()].carrying_add(gc.cpu.gprs[instr.b()], gc.cpu.xer.ca());
+    let a = gc.cpu.gprs[instr.a()];
+    let b = gc.cpu.gprs[instr.b()];
+    let (result, carry) = a.carrying_add(b, gc.cpu.xer.ca());
     gc.cpu.gprs[instr.d()] = result;
+    gc.cpu.xer.set_ca(carry);
+    if instr.oe() {
+	gc.cpu.xer.set_ov(overflowed(a, b, result));
+    }
     if instr.rc() {
 	gc.cpu.do_cr0(result);
     }
+}
+
+//addme, addze, subfme and subfze add the carry and a constant of all ones or zeroes to rA or !rA
+fn add_extended(gc: &mut Gamecube, instr: &Instruction, a: u32, b: u32) {
+    let (result, carry) = a.carrying_add(b, gc.cpu.xer.ca());
+    gc.cpu.gprs[instr.d()] = result;
     gc.cpu.xer.set_ca(carry);
     if instr.oe() {
-	unimplemented!("xer");
-    }    
+	gc.cpu.xer.set_ov(overflowed(a, b, result));
+    }
+    if instr.rc() {
+	gc.cpu.do_cr0(result);
+    }
+}
+
+pub fn addme(gc: &mut Gamecube, instr: &Instruction) {
+    add_extended(gc, instr, gc.cpu.gprs[instr.a()], 0xFFFF_FFFF);
+}
+
+pub fn addze(gc: &mut Gamecube, instr: &Instruction) {
+    add_extended(gc, instr, gc.cpu.gprs[instr.a()], 0);
+}
+
+pub fn subfme(gc: &mut Gamecube, instr: &Instruction) {
+    add_extended(gc, instr, !gc.cpu.gprs[instr.a()], 0xFFFF_FFFF);
+}
+
+pub fn subfze(gc: &mut Gamecube, instr: &Instruction) {
+    add_extended(gc, instr, !gc.cpu.gprs[instr.a()], 0);
 }
 
 pub fn addis(gc: &mut Gamecube, instr: &Instruction) {
@@ -72,86 +111,139 @@ pub fn addicr(gc: &mut Gamecube, instr: &Instruction) {
 }
 
 pub fn subf(gc: &mut Gamecube, instr: &Instruction) {
-    let (r1, c1) = (!gc.cpu.gprs[instr.a()]).overflowing_add(gc.cpu.gprs[instr.b()]);
-    let (r, c2) = r1.overflowing_add(1);
+    let a = !gc.cpu.gprs[instr.a()];
+    let b = gc.cpu.gprs[instr.b()];
+    let r = a.wrapping_add(b).wrapping_add(1);
 
     gc.cpu.gprs[instr.d()] = r;
 
-    if instr.rc() {
-	gc.cpu.do_cr0(r);
+    if instr.oe() {
+	gc.cpu.xer.set_ov(overflowed(a, b, r));
     }
 
-    if instr.oe() {
-	unimplemented!("xer");
+    if instr.rc() {
+	gc.cpu.do_cr0(r);
     }
 }
 
 pub fn subfc(gc: &mut Gamecube, instr: &Instruction) {
-    let (r1, c1) = (!gc.cpu.gprs[instr.a()]).overflowing_add(gc.cpu.gprs[instr.b()]);
-    let (r, c2) = r1.overflowing_add(1);
+    let a = !gc.cpu.gprs[instr.a()];
+    let b = gc.cpu.gprs[instr.b()];
+    let (r, carry) = a.carrying_add(b, true);
 
     gc.cpu.gprs[instr.d()] = r;
 
-    gc.cpu.xer.set_ca(c1 | c2);
+    gc.cpu.xer.set_ca(carry);
+
+    if instr.oe() {
+	gc.cpu.xer.set_ov(overflowed(a, b, r));
+    }
     
     if instr.rc() {
 	gc.cpu.do_cr0(r);
     }
-
-    if instr.oe() {
-	unimplemented!("xer");
-    }
 }
 
 pub fn subfic(gc: &mut Gamecube, instr: &Instruction) {
-    let simm = ((instr.simm() as i32) as u32);
+    let simm = (instr.simm() as i32) as u32;
     let (r1, c1) = (!gc.cpu.gprs[instr.a()]).overflowing_add(simm);
     let (r, c2) = r1.overflowing_add(1);
 
     gc.cpu.gprs[instr.d()] = r;
 
     gc.cpu.xer.set_ca(c1 | c2);
+}
 
+pub fn subfe(gc: &mut Gamecube, instr: &Instruction) {
+    let a = !gc.cpu.gprs[instr.a()];
+    let b = gc.cpu.gprs[instr.b()];
+    let (r, carry) = a.carrying_add(b, gc.cpu.xer.ca());
+
+    gc.cpu.gprs[instr.d()] = r;
+
+    gc.cpu.xer.set_ca(carry);
+
+    if instr.oe() {
+	gc.cpu.xer.set_ov(overflowed(a, b, r));
+    }
+    
     if instr.rc() {
 	gc.cpu.do_cr0(r);
     }
+}
+
+pub fn mullw(gc: &mut Gamecube, instr: &Instruction) {
+    let a = (gc.cpu.gprs[instr.a()] as i32) as i64;
+    let b = (gc.cpu.gprs[instr.b()] as i32) as i64;
+
+    let r = a.wrapping_mul(b);
+
+    gc.cpu.gprs[instr.d()] = r as u32;
 
     if instr.oe() {
-	unimplemented!("xer");
+	gc.cpu.xer.set_ov(!(-0x8000_0000..=0x7FFF_FFFF).contains(&r));
+    }
+
+    if instr.rc() {
+	gc.cpu.do_cr0(r as u32);
     }
 }
 
-pub fn subfe(gc: &mut Gamecube, instr: &Instruction) {
-    let (r1, c1) = (!gc.cpu.gprs[instr.a()]).overflowing_add(gc.cpu.gprs[instr.b()]);
-    let (r, c2) = r1.overflowing_add(gc.cpu.xer.ca() as u32);
+pub fn mulhw(gc: &mut Gamecube, instr: &Instruction) {
+    let a = (gc.cpu.gprs[instr.a()] as i32) as i64;
+    let b = (gc.cpu.gprs[instr.b()] as i32) as i64;
+
+    let r = ((a * b) >> 32) as u32;
 
     gc.cpu.gprs[instr.d()] = r;
 
-    gc.cpu.xer.set_ca(c1 | c2);
-    
     if instr.rc() {
 	gc.cpu.do_cr0(r);
     }
+}
+
+pub fn divw(gc: &mut Gamecube, instr: &Instruction) {
+    let a = gc.cpu.gprs[instr.a()] as i32;
+    let b = gc.cpu.gprs[instr.b()] as i32;
+
+    //the result is undefined on overflow, gekko fills rD with the sign of rA
+    let overflow = b == 0 || (a == i32::MIN && b == -1);
+    let r = if overflow {
+	if a < 0 { 0xFFFF_FFFF } else { 0 }
+    } else {
+	(a / b) as u32
+    };
+
+    gc.cpu.gprs[instr.d()] = r;
 
     if instr.oe() {
-	unimplemented!("xer");
-    }    
+	gc.cpu.xer.set_ov(overflow);
+    }
+
+    if instr.rc() {
+	gc.cpu.do_cr0(r);
+    }
 }
 
-pub fn mullw(gc: &mut Gamecube, instr: &Instruction) {
-    let a = (gc.cpu.gprs[instr.a()] as i32) as i64;
-    let b = (gc.cpu.gprs[instr.b()] as i32) as i64;
+pub fn divwu(gc: &mut Gamecube, instr: &Instruction) {
+    let a = gc.cpu.gprs[instr.a()];
+    let b = gc.cpu.gprs[instr.b()];
 
-    let r = a.wrapping_mul(b);
+    let overflow = b == 0;
+    let r = if overflow {
+	0
+    } else {
+	a / b
+    };
 
-    gc.cpu.gprs[instr.d()] = r as u32;
+    gc.cpu.gprs[instr.d()] = r;
 
     if instr.oe() {
-	gc.cpu.xer.set_ov((r < -0x80000000) | (r > 0x7FFFFFFF));
+	gc.cpu.xer.set_ov(overflow);
     }
 
     if instr.rc() {
-	gc.cpu.do_cr0(r as u32);
+	gc.cpu.do_cr0(r);
     }
 }
 
@@ -241,7 +333,7 @@ pub fn neg(gc: &mut Gamecube, instr: &Instruction) {
     gc.cpu.gprs[instr.d()] = r;
 
     if instr.oe() {
-	gc.cpu.xer.set_so(r == 0x8000_0000);
+	gc.cpu.xer.set_ov(r == 0x8000_0000);
     }
 
     if instr.rc() {
@@ -256,3 +348,39 @@ pub fn cntlzw(gc: &mut Gamecube, instr: &Instruction) {
 	gc.cpu.do_cr0(gc.cpu.gprs[instr.a()]);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_gamecube, Gamecube};
+
+    use super::{divw, Instruction};
+
+    //divwo. r3, r4, r5
+    const DIVWO_DOT: Instruction = Instruction((31 << 26) | (3 << 21) | (4 << 16) | (5 << 11) | (1 << 10) | (491 << 1) | 1);
+
+    fn divide(a: u32, b: u32) -> Gamecube {
+	let mut gc = test_gamecube();
+	gc.cpu.gprs[3] = 0xDEAD_BEEF;
+	gc.cpu.gprs[4] = a;
+	gc.cpu.gprs[5] = b;
+	divw(&mut gc, &DIVWO_DOT);
+	gc
+    }
+
+    #[test]
+    fn divw_rounds_towards_zero() {
+	assert_eq!(divide(7, 2).cpu.gprs[3], 3);
+	assert_eq!(divide(-7i32 as u32, 2).cpu.gprs[3], -3i32 as u32);
+	assert!(!divide(-7i32 as u32, 2).cpu.xer.ov());
+    }
+
+    #[test]
+    fn divw_overflow_fills_with_the_sign_of_ra() {
+	for (a, b, r) in [(0x8000_0000, 0xFFFF_FFFF, 0xFFFF_FFFF), (5, 0, 0), (-5i32 as u32, 0, 0xFFFF_FFFF), (0x8000_0000, 0, 0xFFFF_FFFF)] {
+	    let gc = divide(a, b);
+	    assert_eq!(gc.cpu.gprs[3], r, "{a:#X} / {b:#X}");
+	    assert!(gc.cpu.xer.ov(), "{a:#X} / {b:#X}");
+	    assert!(gc.cpu.xer.so(), "{a:#X} / {b:#X}");
+	}
+    }
+}
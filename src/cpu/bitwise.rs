@@ -1,5 +1,3 @@
-use std::ops::{Shl, Shr};
-
 use crate::Gamecube;
 
 use super::{instr::Instruction, util::mask};
@@ -32,6 +30,15 @@ pub fn rlwimi(gc: &mut Gamecube, instr: &Instruction) {
     }
 }
 
+pub fn rlwnm(gc: &mut Gamecube, instr: &Instruction) {
+    let n = gc.cpu.gprs[instr.b()] & 0x1F;
+    let rotated = gc.cpu.gprs[instr.s()].rotate_left(n);
+    gc.cpu.gprs[instr.a()] = rotated & mask(instr.mb(), instr.me());
+    if instr.rc() {
+	gc.cpu.do_cr0(gc.cpu.gprs[instr.a()]);
+    }
+}
+
 pub fn or(gc: &mut Gamecube, instr: &Instruction) {
     gc.cpu.gprs[instr.a()] = gc.cpu.gprs[instr.s()] | gc.cpu.gprs[instr.b()];
 
@@ -48,6 +55,22 @@ pub fn nor(gc: &mut Gamecube, instr: &Instruction) {
     }
 }
 
+pub fn orc(gc: &mut Gamecube, instr: &Instruction) {
+    gc.cpu.gprs[instr.a()] = gc.cpu.gprs[instr.s()] | !gc.cpu.gprs[instr.b()];
+
+    if instr.rc() {
+	gc.cpu.do_cr0(gc.cpu.gprs[instr.a()]);
+    }
+}
+
+pub fn nand(gc: &mut Gamecube, instr: &Instruction) {
+    gc.cpu.gprs[instr.a()] = !(gc.cpu.gprs[instr.s()] & gc.cpu.gprs[instr.b()]);
+
+    if instr.rc() {
+	gc.cpu.do_cr0(gc.cpu.gprs[instr.a()]);
+    }
+}
+
 pub fn and(gc: &mut Gamecube, instr: &Instruction) {
     gc.cpu.gprs[instr.a()] = gc.cpu.gprs[instr.s()] & gc.cpu.gprs[instr.b()];
 
@@ -62,6 +85,12 @@ pub fn andi(gc: &mut Gamecube, instr: &Instruction) {
     gc.cpu.do_cr0(gc.cpu.gprs[instr.a()]);
 }
 
+pub fn andis(gc: &mut Gamecube, instr: &Instruction) {
+    gc.cpu.gprs[instr.a()] = gc.cpu.gprs[instr.s()] & ((instr.uimm() as u32) << 16);
+
+    gc.cpu.do_cr0(gc.cpu.gprs[instr.a()]);
+}
+
 pub fn xor(gc: &mut Gamecube, instr: &Instruction) {
     gc.cpu.gprs[instr.a()] = gc.cpu.gprs[instr.s()] ^ gc.cpu.gprs[instr.b()];
 
@@ -70,13 +99,56 @@ pub fn xor(gc: &mut Gamecube, instr: &Instruction) {
     }
 }
 
+//the condition register logical ops work on single bits, crbD crbA and crbB sit where rD rA and rB would
+fn cr_logical(gc: &mut Gamecube, instr: &Instruction, op: fn(bool, bool) -> bool) {
+    let result = op(gc.cpu.cr.get_bit(instr.a()), gc.cpu.cr.get_bit(instr.b()));
+    gc.cpu.cr.set_bit(instr.d(), result);
+}
+
+pub fn eqv(gc: &mut Gamecube, instr: &Instruction) {
+    gc.cpu.gprs[instr.a()] = !(gc.cpu.gprs[instr.s()] ^ gc.cpu.gprs[instr.b()]);
+
+    if instr.rc() {
+	gc.cpu.do_cr0(gc.cpu.gprs[instr.a()]);
+    }
+}
+
+pub fn crand(gc: &mut Gamecube, instr: &Instruction) {
+    cr_logical(gc, instr, |a, b| a & b);
+}
+
+pub fn crandc(gc: &mut Gamecube, instr: &Instruction) {
+    cr_logical(gc, instr, |a, b| a & !b);
+}
+
+pub fn creqv(gc: &mut Gamecube, instr: &Instruction) {
+    cr_logical(gc, instr, |a, b| a == b);
+}
+
+pub fn crnand(gc: &mut Gamecube, instr: &Instruction) {
+    cr_logical(gc, instr, |a, b| !(a & b));
+}
+
+pub fn crnor(gc: &mut Gamecube, instr: &Instruction) {
+    cr_logical(gc, instr, |a, b| !(a | b));
+}
+
+pub fn cror(gc: &mut Gamecube, instr: &Instruction) {
+    cr_logical(gc, instr, |a, b| a | b);
+}
+
+pub fn crorc(gc: &mut Gamecube, instr: &Instruction) {
+    cr_logical(gc, instr, |a, b| a | !b);
+}
+
 pub fn crxor(gc: &mut Gamecube, instr: &Instruction) {
-    gc.cpu.cr.set_reg(instr.d(), (gc.cpu.cr.get_reg(instr.a()) as u32) ^ (gc.cpu.cr.get_reg(instr.b()) as u32));
+    cr_logical(gc, instr, |a, b| a ^ b);
 }
 
 pub fn slw(gc: &mut Gamecube, instr: &Instruction) {
-    let n = gc.cpu.gprs[instr.b()] & 0x1F;
-    let r = gc.cpu.gprs[instr.s()].shl(n);
+    //the count is 6 bits, 32 and up shifts everything out
+    let n = gc.cpu.gprs[instr.b()] & 0x3F;
+    let r = gc.cpu.gprs[instr.s()].checked_shl(n).unwrap_or(0);
     gc.cpu.gprs[instr.a()] = r;
     if instr.rc() {
 	gc.cpu.do_cr0(r);
@@ -84,8 +156,8 @@ pub fn slw(gc: &mut Gamecube, instr: &Instruction) {
 }
 
 pub fn srw(gc: &mut Gamecube, instr: &Instruction) {
-    let n = gc.cpu.gprs[instr.b()] & 0x1f;
-    let r = gc.cpu.gprs[instr.s()].shr(n);
+    let n = gc.cpu.gprs[instr.b()] & 0x3F;
+    let r = gc.cpu.gprs[instr.s()].checked_shr(n).unwrap_or(0);
     gc.cpu.gprs[instr.a()] = r;
     if instr.rc() {
 	gc.cpu.do_cr0(r);
@@ -111,7 +183,22 @@ pub fn sraw(gc: &mut Gamecube, instr: &Instruction) {
 	gc.cpu.xer.set_ca(s < 0 && n > 0  && ((s as u32) << (32 - n)) != 0);
     }
 
-    gc.cpu.do_cr0(gc.cpu.gprs[instr.a()]);
+    if instr.rc() {
+	gc.cpu.do_cr0(gc.cpu.gprs[instr.a()]);
+    }
+}
+
+pub fn srawi(gc: &mut Gamecube, instr: &Instruction) {
+    let n = instr.sh() as u32;
+    let s = gc.cpu.gprs[instr.s()] as i32;
+    gc.cpu.gprs[instr.a()] = (s >> n) as u32;
+
+    //CA only gets set when a negative value had ones shifted out of it
+    gc.cpu.xer.set_ca(s < 0 && n > 0 && ((s as u32) << (32 - n)) != 0);
+
+    if instr.rc() {
+	gc.cpu.do_cr0(gc.cpu.gprs[instr.a()]);
+    }
 }
 
 pub fn andc(gc: &mut Gamecube, instr: &Instruction) {
@@ -130,6 +217,18 @@ pub fn extsh(gc: &mut Gamecube, instr: &Instruction) {
     }
 }
 
+pub fn extsb(gc: &mut Gamecube, instr: &Instruction) {
+    gc.cpu.gprs[instr.a()] = ((gc.cpu.gprs[instr.s()] as i8) as i32) as u32;
+
+    if instr.rc() {
+	gc.cpu.do_cr0(gc.cpu.gprs[instr.a()]);
+    }
+}
+
+pub fn xori(gc: &mut Gamecube, instr: &Instruction) {
+    gc.cpu.gprs[instr.a()] = gc.cpu.gprs[instr.s()] ^ (instr.uimm() as u32);
+}
+
 pub fn xoris(gc: &mut Gamecube, instr: &Instruction) {
     gc.cpu.gprs[instr.a()] = gc.cpu.gprs[instr.s()] ^ ((instr.uimm() as u32) << 16)
 }
@@ -0,0 +1,179 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::Gamecube;
+
+use super::{abort_instruction, decode::{self, Decoded}, instr::Instruction, mmu::Access};
+
+//long enough to get through most loops in one go, short enough that an invalidation doesn't throw much away
+const MAX_BLOCK_INSTRUCTIONS: usize = 64;
+//blocks never cross a page, so everything in one lives under a single decode cache generation
+const PAGE_MASK: u32 = 0xFFF;
+//past this the whole cache is thrown away rather than tracking which blocks are still in use
+const MAX_BLOCKS: usize = 0x8000;
+
+struct Block {
+    phys: u32,
+    //decode cache generation of the page when the block was built, it's stale once they differ
+    generation: u32,
+    instrs: Arc<[Decoded]>,
+    //whichever block ran after this one last time, checked before going to the index
+    next: Option<usize>,
+}
+
+//straight line runs of pre-decoded instructions keyed by physical address, so running one is just
+//calling handlers back to back without fetching or decoding anything in between
+pub struct BlockCache {
+    blocks: Vec<Block>,
+    index: HashMap<u32, usize>,
+    //the block that ran most recently, for chaining it to the one that comes next
+    last: Option<usize>,
+}
+
+impl BlockCache {
+    pub fn new() -> Self {
+	Self {
+	    blocks: Vec::new(),
+	    index: HashMap::new(),
+	    last: None,
+	}
+    }
+
+    pub fn clear(&mut self) {
+	self.blocks.clear();
+	self.index.clear();
+	self.last = None;
+    }
+
+    fn chain(&mut self, index: usize) {
+	if let Some(last) = self.last {
+	    self.blocks[last].next = Some(index);
+	}
+	self.last = Some(index);
+    }
+
+    fn lookup(&mut self, phys: u32, generation: u32) -> Option<Arc<[Decoded]>> {
+	let chained = self.last.and_then(|last| self.blocks[last].next).filter(|&next| self.blocks[next].phys == phys);
+	let index = chained.or_else(|| self.index.get(&phys).copied())?;
+
+	if self.blocks[index].generation != generation {
+	    return None;
+	}
+
+	self.chain(index);
+	Some(self.blocks[index].instrs.clone())
+    }
+
+    fn insert(&mut self, phys: u32, generation: u32, instrs: Arc<[Decoded]>) {
+	if self.blocks.len() >= MAX_BLOCKS {
+	    self.clear();
+	}
+
+	let block = Block {
+	    phys,
+	    generation,
+	    instrs,
+	    next: None,
+	};
+
+	//a stale block gets rebuilt in place so the chains pointing at it stay good
+	let index = match self.index.get(&phys) {
+	    Some(&index) => {
+		self.blocks[index] = block;
+		index
+	    },
+	    None => {
+		self.blocks.push(block);
+		self.index.insert(phys, self.blocks.len() - 1);
+		self.blocks.len() - 1
+	    },
+	};
+
+	self.chain(index);
+    }
+}
+
+impl Default for BlockCache {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+//anything that can change where the next instruction comes from, or how its address translates
+pub(super) fn ends_block(instr: &Instruction) -> bool {
+    match instr.opcd() {
+	//bc, sc and b are next to each other
+	0b010000..=0b010010 => true,
+	//bclr, rfi, isync, bcctr
+	0b010011 => matches!(instr.sec_opcd(), 0b0000010000 | 0b0000110010 | 0b0010010110 | 0b1000010000),
+	0b011111 => match instr.sec_opcd() {
+	    //mtmsr, mtsr, mtsrin, tlbie, tlbsync, icbi
+	    0b0010010010 | 0b0011010010 | 0b0011110010 | 0b0100110010 | 0b1000110110 | 0b1111010110 => true,
+	    //mtspr to XER, LR, CTR or a GQR is common and harmless, anything else could be a BAT or IABR
+	    0b0111010011 => !matches!(instr.spr(), 0b00000_00001 | 0b00000_01000 | 0b00000_01001 | 0b11100_10000..=0b11100_10111),
+	    _ => false,
+	},
+	_ => false,
+    }
+}
+
+pub(super) fn build(gc: &mut Gamecube, phys: u32) -> Arc<[Decoded]> {
+    let mut instrs = Vec::new();
+    let mut addr = phys;
+
+    while let Some(decoded) = decode::fetch_phys(gc, addr) {
+	instrs.push(decoded);
+	addr = addr.wrapping_add(4);
+
+	if ends_block(&decoded.instr) || instrs.len() == MAX_BLOCK_INSTRUCTIONS || addr & PAGE_MASK == 0 {
+	    break;
+	}
+    }
+
+    instrs.into()
+}
+
+//runs instructions from cia until the block ends, something sends the cpu elsewhere, or the cycle count reaches until.
+//every instruction still goes through execute, so the result is exactly what stepping would have done
+pub fn run_block(gc: &mut Gamecube, until: u64) {
+    //breakpoints have to be checked before every single instruction, which step already does
+    if gc.cpu.iabr & 2 != 0 {
+	super::step(gc);
+	return;
+    }
+
+    let start = gc.cpu.cia;
+    let Ok(phys) = gc.translate(start, Access::Fetch) else {
+	abort_instruction(gc);
+	return;
+    };
+
+    //only ram and the bootrom get cached, anything else is left to the interpreter
+    let Some(generation) = gc.cpu.decode_cache.generation(phys) else {
+	super::step(gc);
+	return;
+    };
+
+    let instrs = match gc.cpu.blocks.lookup(phys, generation) {
+	Some(instrs) => instrs,
+	None => {
+	    let instrs = build(gc, phys);
+	    if instrs.is_empty() {
+		super::step(gc);
+		return;
+	    }
+	    gc.cpu.blocks.insert(phys, generation, instrs.clone());
+	    instrs
+	},
+    };
+
+    let mut expected = start;
+    for &decoded in instrs.iter() {
+	super::execute(gc, decoded);
+	expected = expected.wrapping_add(4);
+
+	//a branch, an exception or a write over the rest of the block all mean the remaining instructions are wrong
+	if gc.cpu.cia != expected || gc.cpu.cycles >= until || gc.cpu.decode_cache.generation(phys) != Some(generation) {
+	    return;
+	}
+    }
+}
@@ -1,9 +1,507 @@
 use log::debug;
 
-use crate::Gamecube;
+use crate::{bus::{AccessDirection, AccessWidth}, scheduler::EventKind, Gamecube, LOCKED_CACHE_START, RAM_SIZE};
 
-use super::instr::Instruction;
+use super::{instr::Instruction, mmu::{Access, PTEG_SIZE}, MACHINE_CHECK_EXCEPTION, PROGRAM_ILLEGAL};
 
-pub fn isync(gc: &mut Gamecube, instr: &Instruction) {
-    debug!("STUB: isync");
+//with HID2[LCE] set, half of the 32kb L1 data cache turns into a scratchpad at 0xE000_0000
+pub const LOCKED_CACHE_SIZE: usize = 0x4000;
+const CACHE_LINE: u32 = 0x20;
+
+//HID0[DCE], dcbz needs somewhere to allocate the line
+pub const HID0_DCE: u32 = 0x4000;
+//HID0[DCFI] throws away every line in the data cache, modified or not
+pub const HID0_DCFI: u32 = 0x400;
+
+//the L1 data cache is 32kb, 8-way set associative with 32 byte lines
+const DCACHE_SETS: usize = 128;
+const DCACHE_WAYS: usize = 8;
+
+//HID2 error bits and the enables that turn them into machine checks
+const HID2_DCMERR: u32 = 0x0020_0000;
+const HID2_DQOERR: u32 = 0x0010_0000;
+const HID2_DCMEE: u32 = 0x20;
+const HID2_DQOEE: u32 = 0x10;
+
+//DMA_L control bits
+const DMA_LD: u32 = 0x10;
+const DMA_T: u32 = 0x2;
+const DMA_F: u32 = 0x1;
+//DMAQL is four bits wide
+const DMA_QUEUE_MAX: usize = 0xF;
+//a line is four beats on the 64 bit bus, at three cpu cycles a beat
+const DMA_CYCLES_PER_LINE: u64 = 12;
+
+//the DMA_U/DMA_L pair that moves lines between the locked cache and ram. transfers are done the moment
+//they're queued, only DMAQL draining is spread out over time
+pub struct LockedCacheDma {
+    pub upper: u32,
+    pub lower: u32,
+    //cpu cycle the last queued transfer finishes on
+    busy_until: u64,
+}
+
+impl LockedCacheDma {
+    pub fn new() -> Self {
+	Self {
+	    upper: 0,
+	    lower: 0,
+	    busy_until: 0,
+	}
+    }
+}
+
+impl Default for LockedCacheDma {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+#[derive(Copy, Clone)]
+struct CacheLine {
+    valid: bool,
+    dirty: bool,
+    //physical address of the line
+    tag: u32,
+    data: [u8; CACHE_LINE as usize],
+}
+
+impl CacheLine {
+    const INVALID: Self = Self {
+	valid: false,
+	dirty: false,
+	tag: 0,
+	data: [0; CACHE_LINE as usize],
+    };
+}
+
+//ram normally stays up to date with every store, which is what everything but the cpu expects. with the data
+//cache modelled, cacheable stores sit in write back lines until they're evicted or flushed, and dma engines
+//reading ram directly see what real hardware would
+pub struct DataCache {
+    pub modelled: bool,
+    sets: Box<[[CacheLine; DCACHE_WAYS]; DCACHE_SETS]>,
+    //way to replace next in each set
+    next: [usize; DCACHE_SETS],
+}
+
+impl DataCache {
+    pub fn new() -> Self {
+	Self {
+	    modelled: false,
+	    sets: Box::new([[CacheLine::INVALID; DCACHE_WAYS]; DCACHE_SETS]),
+	    next: [0; DCACHE_SETS],
+	}
+    }
+
+    fn set_index(phys: u32) -> usize {
+	(phys / CACHE_LINE) as usize & (DCACHE_SETS - 1)
+    }
+
+    fn find(&self, phys: u32) -> Option<(usize, usize)> {
+	let set = Self::set_index(phys);
+	let tag = phys & !(CACHE_LINE - 1);
+	let way = self.sets[set].iter().position(|line| line.valid && line.tag == tag)?;
+	Some((set, way))
+    }
+
+    //the cached copy of a byte, if there is one
+    pub fn peek(&self, phys: u32) -> Option<u8> {
+	let (set, way) = self.find(phys)?;
+	Some(self.sets[set][way].data[(phys % CACHE_LINE) as usize])
+    }
+
+    //flash invalidation, nothing gets written back
+    pub fn invalidate_all(&mut self) {
+	self.sets.fill([CacheLine::INVALID; DCACHE_WAYS]);
+    }
+}
+
+impl Default for DataCache {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+//with HID2[LCE] set the locked half takes four of the ways
+fn ways(gc: &Gamecube) -> usize {
+    if gc.cpu.hid2.lce() {
+	DCACHE_WAYS / 2
+    } else {
+	DCACHE_WAYS
+    }
+}
+
+fn write_back(gc: &mut Gamecube, set: usize, way: usize) {
+    let line = &mut gc.cpu.dcache.sets[set][way];
+    if !line.valid || !line.dirty {
+	return;
+    }
+
+    line.dirty = false;
+    let tag = line.tag as usize;
+    gc.memory[tag..(tag + CACHE_LINE as usize)].copy_from_slice(&line.data);
+    gc.cpu.decode_cache.invalidate_range(tag as u32, CACHE_LINE as usize);
+}
+
+//the line holding phys, brought in from ram if it isn't there already. fill is false for dcbz, which
+//allocates without reading
+fn allocate(gc: &mut Gamecube, phys: u32, fill: bool) -> (usize, usize) {
+    if let Some(hit) = gc.cpu.dcache.find(phys) {
+	return hit;
+    }
+
+    let set = DataCache::set_index(phys);
+    let ways = ways(gc);
+    let way = gc.cpu.dcache.sets[set][..ways].iter().position(|line| !line.valid).unwrap_or(gc.cpu.dcache.next[set] % ways);
+    gc.cpu.dcache.next[set] = (way + 1) % ways;
+    write_back(gc, set, way);
+
+    let tag = phys & !(CACHE_LINE - 1);
+    let mut data = [0; CACHE_LINE as usize];
+    if fill {
+	data.copy_from_slice(&gc.memory[(tag as usize)..(tag as usize + CACHE_LINE as usize)]);
+    }
+    gc.cpu.dcache.sets[set][way] = CacheLine {
+	valid: true,
+	dirty: false,
+	tag,
+	data,
+    };
+
+    (set, way)
+}
+
+//cacheable loads and stores to ram, split up where they straddle two lines
+pub fn read(gc: &mut Gamecube, phys: u32, buf: &mut [u8]) {
+    for (i, byte) in buf.iter_mut().enumerate() {
+	let addr = phys + i as u32;
+	let (set, way) = allocate(gc, addr, true);
+	*byte = gc.cpu.dcache.sets[set][way].data[(addr % CACHE_LINE) as usize];
+    }
+}
+
+pub fn write(gc: &mut Gamecube, phys: u32, buf: &[u8]) {
+    for (i, &byte) in buf.iter().enumerate() {
+	let addr = phys + i as u32;
+	let (set, way) = allocate(gc, addr, true);
+	let line = &mut gc.cpu.dcache.sets[set][way];
+	line.data[(addr % CACHE_LINE) as usize] = byte;
+	line.dirty = true;
+    }
+}
+
+//(rA|0) + rB, all the cache ops are X-form
+fn ea(gc: &Gamecube, instr: &Instruction) -> u32 {
+    if instr.a() == 0 {
+	0
+    } else {
+	gc.cpu.gprs[instr.a()]
+    }.wrapping_add(gc.cpu.gprs[instr.b()])
+}
+
+//there's no prefetch queue to throw away. icbi and writes to code drop what they make stale straight away,
+//so by the time isync runs the only decoded blocks left are ones that are still good
+pub fn isync(_gc: &mut Gamecube, _instr: &Instruction) {}
+
+//icbi translates like a load, and drops the whole 32 byte block it lands in
+pub fn icbi(gc: &mut Gamecube, instr: &Instruction) {
+    let addr = ea(gc, instr);
+
+    if let Ok(phys) = gc.translate(addr, Access::Read) {
+	gc.cpu.decode_cache.invalidate_block(phys);
+    }
+}
+
+//unless the data cache is modelled there are never any lines, and the block ops are left with just their side
+//effects: translation faults, privilege checks and zeroing
+
+//the line holding phys gets written back if it's modified, then kept clean or dropped
+fn clean_line(gc: &mut Gamecube, phys: u32, store: bool, keep: bool) {
+    let Some((set, way)) = gc.cpu.dcache.find(phys) else {
+	return;
+    };
+
+    if store {
+	write_back(gc, set, way);
+    }
+    if !keep {
+	gc.cpu.dcache.sets[set][way] = CacheLine::INVALID;
+    }
+}
+
+//the hardware table walk is coherent with the data cache, while translation here reads and updates PTEs in ram
+//directly. modified lines over the PTEGs get written back before a walk can miss them, and every line over them
+//is dropped so the R and C bits it sets can't be hidden behind stale copies
+pub fn snoop_page_table(gc: &mut Gamecube, access: Access, addr: u32) {
+    if !gc.cpu.dcache.modelled {
+	return;
+    }
+    let Some(ptegs) = gc.cpu.mmu.ptegs(access, addr, &gc.cpu.msr) else {
+	return;
+    };
+
+    for pteg in ptegs {
+	for offset in (0..PTEG_SIZE).step_by(CACHE_LINE as usize) {
+	    clean_line(gc, pteg + offset, true, false);
+	}
+    }
+}
+
+//flush and store are treated as loads for protection
+pub fn dcbf(gc: &mut Gamecube, instr: &Instruction) {
+    let addr = ea(gc, instr);
+    if let Ok(phys) = gc.translate(addr, Access::Read) {
+	clean_line(gc, phys, true, false);
+    }
+}
+
+pub fn dcbst(gc: &mut Gamecube, instr: &Instruction) {
+    let addr = ea(gc, instr);
+    if let Ok(phys) = gc.translate(addr, Access::Read) {
+	clean_line(gc, phys, true, true);
+    }
+}
+
+//invalidate throws the line away, modified or not. it's a supervisor store as far as faults go
+pub fn dcbi(gc: &mut Gamecube, instr: &Instruction) {
+    if !gc.cpu.check_supervisor() {
+	return;
+    }
+
+    let addr = ea(gc, instr);
+    if let Ok(phys) = gc.translate(addr, Access::Write) {
+	clean_line(gc, phys, false, false);
+    }
+}
+
+//touches are only hints, they never fault
+pub fn dcbt(_gc: &mut Gamecube, _instr: &Instruction) {}
+
+pub fn dcbtst(_gc: &mut Gamecube, _instr: &Instruction) {}
+
+fn zero_line(gc: &mut Gamecube, addr: u32) {
+    for offset in (0..CACHE_LINE).step_by(8) {
+	if gc.write_u64(addr + offset, 0).is_err() {
+	    return;
+	}
+    }
+}
+
+//a line can't be allocated for pages mapped cache inhibited or write through, so the dcbz forms take an
+//alignment exception on them
+fn uncacheable(gc: &Gamecube, addr: u32) -> bool {
+    gc.cpu.mmu.cache_inhibited(addr, &gc.cpu.msr) || gc.cpu.mmu.write_through(addr, &gc.cpu.msr)
+}
+
+//allocates the line without reading it, which can't be done with the cache off
+pub fn dcbz(gc: &mut Gamecube, instr: &Instruction) {
+    let addr = ea(gc, instr) & !(CACHE_LINE - 1);
+
+    if gc.cpu.hid0 & HID0_DCE == 0 {
+	gc.cpu.alignment_fault(addr, instr);
+	return;
+    }
+
+    let Ok(phys) = gc.translate(addr, Access::Write) else {
+	return;
+    };
+    if uncacheable(gc, addr) {
+	gc.cpu.alignment_fault(addr, instr);
+	return;
+    }
+
+    //a modelled cache gets the zeroed line without ram being read or written
+    if gc.cached(addr, phys, CACHE_LINE as usize) {
+	let (set, way) = allocate(gc, phys, false);
+	let line = &mut gc.cpu.dcache.sets[set][way];
+	line.data = [0; CACHE_LINE as usize];
+	line.dirty = true;
+	return;
+    }
+
+    zero_line(gc, addr);
+}
+
+//same again, but the line goes in the locked half. it only exists while that half does
+pub fn dcbz_l(gc: &mut Gamecube, instr: &Instruction) {
+    if !gc.cpu.hid2.lce() {
+	gc.cpu.program_exception(PROGRAM_ILLEGAL);
+	return;
+    }
+
+    let addr = ea(gc, instr) & !(CACHE_LINE - 1);
+    if gc.translate(addr, Access::Write).is_err() {
+	return;
+    }
+    if uncacheable(gc, addr) {
+	gc.cpu.alignment_fault(addr, instr);
+	return;
+    }
+
+    zero_line(gc, addr);
+}
+
+//sticky in HID2 until software writes it back clear
+fn dma_error(gc: &mut Gamecube, error: u32, enable: u32) {
+    gc.cpu.hid2.0 |= error;
+    if gc.cpu.hid2.0 & enable != 0 {
+	gc.cpu.exceptions |= MACHINE_CHECK_EXCEPTION;
+    }
+}
+
+fn dma_complete(gc: &mut Gamecube, _kind: EventKind) {
+    let queued = gc.cpu.hid2.dmaql();
+    gc.cpu.hid2.set_dmaql(queued.saturating_sub(1));
+}
+
+//DMA_L[T] queues a transfer of the lines described by both registers, DMA_F throws the queue away.
+//neither bit reads back
+pub fn write_dma_l(gc: &mut Gamecube, val: u32) {
+    gc.cpu.lc_dma.lower = val & !(DMA_T | DMA_F);
+
+    if val & DMA_F != 0 {
+	gc.scheduler.cancel(EventKind::LockedCacheDma);
+	gc.cpu.hid2.set_dmaql(0);
+	gc.cpu.lc_dma.busy_until = gc.cpu.cycles;
+    }
+
+    if val & DMA_T == 0 {
+	return;
+    }
+
+    let queued = gc.cpu.hid2.dmaql();
+    if queued == DMA_QUEUE_MAX {
+	dma_error(gc, HID2_DQOERR, HID2_DQOEE);
+	return;
+    }
+
+    let upper = gc.cpu.lc_dma.upper;
+    //DMA_LEN_U on top of DMA_LEN_L, with 0 meaning the full 128
+    let lines = match ((upper & 0x1F) << 2) | ((val >> 2) & 3) {
+	0 => 0x80,
+	lines => lines,
+    };
+    let len = (lines * CACHE_LINE) as usize;
+
+    //the cache side has to land entirely inside the locked half
+    let offset = (val & !(CACHE_LINE - 1)).wrapping_sub(LOCKED_CACHE_START) as usize;
+    if !gc.cpu.hid2.lce() || offset + len > LOCKED_CACHE_SIZE {
+	dma_error(gc, HID2_DCMERR, HID2_DCMEE);
+	return;
+    }
+
+    //the engine is on the bus side of the mmu and only the low address bits make it out, so cached and
+    //uncached pointers both end up in ram
+    let phys = (upper & 0x0FFF_FFE0) as usize;
+    let load = val & DMA_LD != 0;
+    if phys + len > RAM_SIZE {
+	let direction = if load {
+	    AccessDirection::Read
+	} else {
+	    AccessDirection::Write
+	};
+	let _ = gc.bus_error::<()>(phys as u32, AccessWidth::U64, direction);
+	return;
+    }
+
+    if load {
+	gc.cpu.locked_cache[offset..(offset + len)].copy_from_slice(&gc.memory[phys..(phys + len)]);
+    } else {
+	gc.memory[phys..(phys + len)].copy_from_slice(&gc.cpu.locked_cache[offset..(offset + len)]);
+	gc.cpu.decode_cache.invalidate_range(phys as u32, len);
+    }
+
+    gc.cpu.hid2.set_dmaql(queued + 1);
+    gc.cpu.lc_dma.busy_until = gc.cpu.lc_dma.busy_until.max(gc.cpu.cycles) + u64::from(lines) * DMA_CYCLES_PER_LINE;
+    gc.scheduler.schedule_at(gc.cpu.lc_dma.busy_until, EventKind::LockedCacheDma, dma_complete);
+}
+
+pub fn sync(_gc: &mut Gamecube, _instr: &Instruction) {
+    debug!("STUB: sync");
+}
+
+pub fn eieio(_gc: &mut Gamecube, _instr: &Instruction) {
+    debug!("STUB: eieio");
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cpu::{config::mtspr, instr::Instruction, ALIGNMENT_EXCEPTION}, test_gamecube};
+
+    use super::{dcbz, HID0_DCE, HID0_DCFI};
+
+    //mtspr HID0, r3
+    const MTSPR_HID0: Instruction = Instruction((31 << 26) | (3 << 21) | (((16 << 5) | 31) << 11) | (467 << 1));
+    //dcbz 0, r3
+    const DCBZ: Instruction = Instruction((31 << 26) | (3 << 11) | (1014 << 1));
+    const MSR_DR: u32 = 0x10;
+
+    #[test]
+    fn dcfi_drops_dirty_lines_without_writing_them_back() {
+	let mut gc = test_gamecube();
+	gc.cpu.dcache.modelled = true;
+	gc.cpu.hid0 = HID0_DCE;
+
+	gc.write_u32(0x100, 0xDEAD_BEEF).unwrap();
+	assert_eq!(gc.cpu.dcache.peek(0x100), Some(0xDE));
+	assert_eq!(gc.memory[0x100..0x104], [0; 4]);
+
+	gc.cpu.gprs[3] = HID0_DCE | HID0_DCFI;
+	mtspr(&mut gc, &MTSPR_HID0);
+
+	assert_eq!(gc.cpu.dcache.peek(0x100), None);
+	assert_eq!(gc.memory[0x100..0x104], [0; 4]);
+	assert_eq!(gc.read_u32(0x100, false).unwrap(), 0);
+	//DCFI doesn't stay set
+	assert_eq!(gc.cpu.hid0, HID0_DCE);
+    }
+
+    #[test]
+    fn dcbz_on_write_through_or_inhibited_pages_is_an_alignment_exception() {
+	let mut gc = test_gamecube();
+	gc.cpu.hid0 = HID0_DCE;
+	gc.cpu.msr.0 |= MSR_DR;
+	gc.memory[0x1000..0x1020].fill(0xFF);
+	gc.cpu.gprs[3] = 0x1004;
+
+	//the first 128kb identity mapped read/write, with each of WIMG[W] and WIMG[I] in turn
+	gc.cpu.mmu.write_dbatu(0, 2);
+	for wimg in [0b1000, 0b0100] {
+	    gc.cpu.mmu.write_dbatl(0, (wimg << 3) | 2);
+	    gc.cpu.exceptions = 0;
+	    dcbz(&mut gc, &DCBZ);
+	    assert_ne!(gc.cpu.exceptions & ALIGNMENT_EXCEPTION, 0);
+	    assert_eq!(gc.cpu.dar, 0x1000);
+	    assert_eq!(gc.memory[0x1000..0x1020], [0xFF; 0x20]);
+	}
+
+	gc.cpu.mmu.write_dbatl(0, 2);
+	gc.cpu.exceptions = 0;
+	dcbz(&mut gc, &DCBZ);
+	assert_eq!(gc.cpu.exceptions, 0);
+	assert_eq!(gc.memory[0x1000..0x1020], [0; 0x20]);
+    }
+
+    #[test]
+    fn the_page_table_walk_sees_ptes_still_in_the_data_cache() {
+	let mut gc = test_gamecube();
+	gc.cpu.dcache.modelled = true;
+	gc.cpu.hid0 = HID0_DCE;
+	gc.memory[0x6000..0x6004].copy_from_slice(&0x1234_5678u32.to_be_bytes());
+
+	//a 64kb table at 0 and vsid 0, so EA 0x5000 hashes to the PTEG at 0x140. the PTE maps it to 0x6000
+	//and only makes it as far as the cache
+	gc.write_u32(0x140, 0x8000_0000).unwrap();
+	gc.write_u32(0x144, 0x0000_6002).unwrap();
+	assert_eq!(gc.memory[0x140..0x148], [0; 8]);
+
+	gc.cpu.msr.0 |= MSR_DR;
+	assert_eq!(gc.read_u32(0x5000, false).unwrap(), 0x1234_5678);
+
+	//the walk set R in ram, and there's no stale copy of the PTE left to read back
+	assert_eq!(gc.cpu.dcache.peek(0x144), None);
+	gc.cpu.msr.0 &= !MSR_DR;
+	assert_eq!(gc.read_u32(0x144, false).unwrap(), 0x0000_6102);
+    }
 }
@@ -1,16 +1,40 @@
-use std::io::stdin;
-
 use crate::Gamecube;
 
-use super::{instr::Instruction, write_hid0, SYSTEMCALL_EXCEPTION};
+use super::{cache::write_dma_l, gather_pipe::{read_wpar, write_wpar}, float::{update_cr1, FPSCR_EXCEPTIONS, FPSCR_FX}, instr::Instruction, write_hid0, PROGRAM_ILLEGAL, SYSTEMCALL_EXCEPTION};
+
+//gekko's processor version register
+const PVR: u32 = 0x0008_3214;
+
+//SPRs with bit 4 of their number set can only be touched in supervisor mode
+fn spr_is_privileged(spr: usize) -> bool {
+    spr & 0x10 != 0
+}
 
 pub fn mtspr(gc: &mut Gamecube, instr: &Instruction) {
     let val = gc.cpu.gprs[instr.s()];
     let spr = instr.spr();
 
+    if spr_is_privileged(spr) && !gc.cpu.check_supervisor() {
+	return;
+    }
+
     match spr {
+	0b00000_00001 => gc.cpu.xer.0 = val,
 	0b00000_01000 => gc.cpu.lr = val,
 	0b00000_01001 => gc.cpu.ctr = val,
+	0b00000_10010 => gc.cpu.dsisr = val,
+	0b00000_10011 => gc.cpu.dar = val,
+	0b00000_10110 => gc.cpu.write_dec(val),
+	0b00000_11001 => gc.cpu.mmu.sdr1 = val,
+	0b00000_11010 => gc.cpu.srr0 = val,
+	0b00000_11011 => gc.cpu.srr1 = val,
+	0b01000_10000 => gc.cpu.sprgs[0] = val,
+	0b01000_10001 => gc.cpu.sprgs[1] = val,
+	0b01000_10010 => gc.cpu.sprgs[2] = val,
+	0b01000_10011 => gc.cpu.sprgs[3] = val,
+	0b01000_11010 => gc.cpu.ear = val,
+	0b01000_11100 => gc.cpu.tb = (gc.cpu.tb & 0xFFFF_FFFF_0000_0000) | u64::from(val),
+	0b01000_11101 => gc.cpu.tb = (gc.cpu.tb & 0xFFFF_FFFF) | (u64::from(val) << 32),
 	0b10000_10000 => gc.cpu.mmu.write_ibatu(0, val),
 	0b10000_10001 => gc.cpu.mmu.write_ibatl(0, val),
 	0b10000_10010 => gc.cpu.mmu.write_ibatu(1, val),
@@ -35,8 +59,11 @@ pub fn mtspr(gc: &mut Gamecube, instr: &Instruction) {
 	0b11100_10101 => gc.cpu.gqrs[5].0 = val,
 	0b11100_10110 => gc.cpu.gqrs[6].0 = val,
 	0b11100_10111 => gc.cpu.gqrs[7].0 = val,
-	0b11100_11000 => gc.cpu.hid2.0 = val,
-	0b11100_11001 => gc.cpu.wpar = val,
+	//DMAQL is read only, it belongs to the locked cache dma queue
+	0b11100_11000 => gc.cpu.hid2.0 = (val & !0x0F00_0000) | (gc.cpu.hid2.0 & 0x0F00_0000),
+	0b11100_11001 => write_wpar(gc, val),
+	0b11100_11010 => gc.cpu.lc_dma.upper = val,
+	0b11100_11011 => write_dma_l(gc, val),
 	0b11101_11000 => gc.cpu.mmcr0 = val,
 	0b11101_11001 => gc.cpu.pmcs[0] = val,
 	0b11101_11010 => gc.cpu.pmcs[1] = val,
@@ -44,20 +71,89 @@ pub fn mtspr(gc: &mut Gamecube, instr: &Instruction) {
 	0b11101_11101 => gc.cpu.pmcs[2] = val,
 	0b11101_11110 => gc.cpu.pmcs[3] = val,
 	0b11111_11001 => gc.cpu.l2cr = val,
-	0b11111_10000 => gc.cpu.hid0 = val,
-	a => unimplemented!("mtspr {a:#012b}, instruction: {:#034b}", instr.0),
+	0b11111_10000 => write_hid0(gc, val),
+	0b11111_10001 => gc.cpu.hid1 = val,
+	0b11111_10010 => gc.cpu.iabr = val,
+	0b11111_10101 => gc.cpu.dabr = val,
+	0b11111_11011 => gc.cpu.ictc = val,
+	0b11111_11100 => gc.cpu.thrm[0] = val,
+	0b11111_11101 => gc.cpu.thrm[1] = val,
+	0b11111_11110 => gc.cpu.thrm[2] = val,
+	//PVR included, it's read only
+	_ => gc.cpu.program_exception(PROGRAM_ILLEGAL),
     }
 }
 
 pub fn mtsr(gc: &mut Gamecube, instr: &Instruction) {
-    gc.cpu.mmu.srs[instr.sr()] = gc.cpu.gprs[instr.s()];
+    if !gc.cpu.check_supervisor() {
+	return;
+    }
+
+    gc.cpu.mmu.write_sr(instr.sr(), gc.cpu.gprs[instr.s()]);
+}
+
+pub fn mtsrin(gc: &mut Gamecube, instr: &Instruction) {
+    if !gc.cpu.check_supervisor() {
+	return;
+    }
+
+    let sr = gc.cpu.gprs[instr.b()] >> 28;
+    gc.cpu.mmu.write_sr(sr as usize, gc.cpu.gprs[instr.s()]);
+}
+
+pub fn tlbie(gc: &mut Gamecube, instr: &Instruction) {
+    if !gc.cpu.check_supervisor() {
+	return;
+    }
+
+    gc.cpu.mmu.tlbie(gc.cpu.gprs[instr.b()]);
+}
+
+pub fn tlbsync(gc: &mut Gamecube, _instr: &Instruction) {
+    //single processor, there's nobody else to wait on
+    gc.cpu.check_supervisor();
+}
+
+pub fn mfsr(gc: &mut Gamecube, instr: &Instruction) {
+    if !gc.cpu.check_supervisor() {
+	return;
+    }
+
+    gc.cpu.gprs[instr.d()] = gc.cpu.mmu.srs[instr.sr()];
+}
+
+pub fn mfsrin(gc: &mut Gamecube, instr: &Instruction) {
+    if !gc.cpu.check_supervisor() {
+	return;
+    }
+
+    let sr = gc.cpu.gprs[instr.b()] >> 28;
+    gc.cpu.gprs[instr.d()] = gc.cpu.mmu.srs[sr as usize];
 }
 
 pub fn mfspr(gc: &mut Gamecube, instr: &Instruction) {
     let spr = instr.spr();
 
+    if spr_is_privileged(spr) && !gc.cpu.check_supervisor() {
+	return;
+    }
+
     gc.cpu.gprs[instr.d()] = match spr {
+	0b00000_00001 => gc.cpu.xer.0,
 	0b00000_01000 => gc.cpu.lr,
+	0b00000_01001 => gc.cpu.ctr,
+	0b00000_10010 => gc.cpu.dsisr,
+	0b00000_10011 => gc.cpu.dar,
+	0b00000_10110 => gc.cpu.dec,
+	0b00000_11001 => gc.cpu.mmu.sdr1,
+	0b00000_11010 => gc.cpu.srr0,
+	0b00000_11011 => gc.cpu.srr1,
+	0b01000_10000 => gc.cpu.sprgs[0],
+	0b01000_10001 => gc.cpu.sprgs[1],
+	0b01000_10010 => gc.cpu.sprgs[2],
+	0b01000_10011 => gc.cpu.sprgs[3],
+	0b01000_11010 => gc.cpu.ear,
+	0b01000_11111 => PVR,
 	0b11100_10000 => gc.cpu.gqrs[0].0,
 	0b11100_10001 => gc.cpu.gqrs[1].0,
 	0b11100_10010 => gc.cpu.gqrs[2].0,
@@ -67,7 +163,9 @@ pub fn mfspr(gc: &mut Gamecube, instr: &Instruction) {
 	0b11100_10110 => gc.cpu.gqrs[6].0,
 	0b11100_10111 => gc.cpu.gqrs[7].0,
 	0b11100_11000 => gc.cpu.hid2.0,
-	0b11100_11001 => gc.cpu.wpar,
+	0b11100_11001 => read_wpar(gc),
+	0b11100_11010 => gc.cpu.lc_dma.upper,
+	0b11100_11011 => gc.cpu.lc_dma.lower,
 	0b11101_11000 => gc.cpu.mmcr0,
 	0b11101_11001 => gc.cpu.pmcs[0],
 	0b11101_11010 => gc.cpu.pmcs[1],
@@ -76,7 +174,17 @@ pub fn mfspr(gc: &mut Gamecube, instr: &Instruction) {
 	0b11101_11110 => gc.cpu.pmcs[3],
 	0b11111_11001 => gc.cpu.l2cr,
 	0b11111_10000 => gc.cpu.hid0,
-	a => unimplemented!("mfspr {a:#012b}, instruction {:#034b}", instr.0),
+	0b11111_10001 => gc.cpu.hid1,
+	0b11111_10010 => gc.cpu.iabr,
+	0b11111_10101 => gc.cpu.dabr,
+	0b11111_11011 => gc.cpu.ictc,
+	0b11111_11100 => gc.cpu.thrm[0],
+	0b11111_11101 => gc.cpu.thrm[1],
+	0b11111_11110 => gc.cpu.thrm[2],
+	_ => {
+	    gc.cpu.program_exception(PROGRAM_ILLEGAL);
+	    return;
+	},
     };
 }
 
@@ -86,23 +194,68 @@ pub fn mftb(gc: &mut Gamecube, instr: &Instruction) {
     gc.cpu.gprs[instr.d()] = match reg {
 	268 => (gc.cpu.tb & 0xFFFFFFFF) as u32, //TBL
 	269 => ((gc.cpu.tb >> 32) & 0xFFFFFFFF) as u32, //TBU
-	_ => unimplemented!("tbl {reg}"),
+	_ => {
+	    gc.cpu.program_exception(PROGRAM_ILLEGAL);
+	    return;
+	},
     }
 }
 
 pub fn mfmsr(gc: &mut Gamecube, instr: &Instruction) {
+    if !gc.cpu.check_supervisor() {
+	return;
+    }
+
     gc.cpu.gprs[instr.d()] = gc.cpu.msr.0;
 }
 
 pub fn mtmsr(gc: &mut Gamecube, instr: &Instruction) {
+    if !gc.cpu.check_supervisor() {
+	return;
+    }
+
+    let old = gc.cpu.msr.0;
     gc.cpu.msr.0 = gc.cpu.gprs[instr.s()];
+
+    //IR and DR
+    if (old ^ gc.cpu.msr.0) & 0x30 != 0 {
+	gc.cpu.mmu.invalidate_tlbs();
+    }
+}
+
+pub fn mfcr(gc: &mut Gamecube, instr: &Instruction) {
+    gc.cpu.gprs[instr.d()] = gc.cpu.cr.0;
+}
+
+pub fn mtcrf(gc: &mut Gamecube, instr: &Instruction) {
+    let crm = instr.crm();
+    let mut m = 0u32;
+
+    for i in 0..8 {
+	if (crm & (1 << i)) != 0 {
+	    m |= 0xF << (i * 4);
+	}
+    }
+
+    gc.cpu.cr.0 = (gc.cpu.cr.0 & !m) | (gc.cpu.gprs[instr.s()] & m);
+}
+
+pub fn mcrf(gc: &mut Gamecube, instr: &Instruction) {
+    let val = gc.cpu.cr.get_reg(instr.crs()) as u32;
+    gc.cpu.cr.set_reg(instr.crd(), val);
+}
+
+//moves SO, OV and CA (and the reserved bit after them) into a CR field and clears them
+pub fn mcrxr(gc: &mut Gamecube, instr: &Instruction) {
+    gc.cpu.cr.set_reg(instr.crd(), gc.cpu.xer.0 >> 28);
+    gc.cpu.xer.0 &= 0x0FFF_FFFF;
 }
 
 pub fn mffs(gc: &mut Gamecube, instr: &Instruction) {
-    gc.cpu.fprs[instr.d()].as_paired_u32_mut().0 = gc.cpu.fpscr.0;
+    gc.cpu.fprs[instr.d()].ps0 = 0xFFF8_0000_0000_0000 | u64::from(gc.cpu.fpscr.0);
 
     if instr.rc() {
-	unimplemented!("cr1");
+	update_cr1(gc);
     }
 }
 
@@ -116,24 +269,116 @@ pub fn mtfsf(gc: &mut Gamecube, instr: &Instruction) {
 	}
     }
 
-    gc.cpu.fpscr.0 = (gc.cpu.fpscr.0 & (!m)) | (gc.cpu.fprs[instr.b()].as_paired_u32().0 & m);
+    gc.cpu.fpscr.0 = (gc.cpu.fpscr.0 & (!m)) | (gc.cpu.fprs[instr.b()].ps0 as u32 & m);
+    gc.cpu.fpscr.update_summary();
+
+    if instr.rc() {
+	update_cr1(gc);
+    }
+}
+
+pub fn mtfsfi(gc: &mut Gamecube, instr: &Instruction) {
+    let shift = 28 - instr.crd() * 4;
+
+    gc.cpu.fpscr.0 = (gc.cpu.fpscr.0 & !(0xF << shift)) | (instr.imm() << shift);
+    gc.cpu.fpscr.update_summary();
+
+    if instr.rc() {
+	update_cr1(gc);
+    }
+}
+
+//FEX and VX (bits 1 and 2) are summaries and can't be set or cleared directly
+pub fn mtfsb0(gc: &mut Gamecube, instr: &Instruction) {
+    if instr.d() != 1 && instr.d() != 2 {
+	gc.cpu.fpscr.0 &= !(1 << (31 - instr.d()));
+	gc.cpu.fpscr.update_summary();
+    }
 
     if instr.rc() {
-	unimplemented!("cr1");
+	update_cr1(gc);
     }
 }
 
 pub fn mtfsb1(gc: &mut Gamecube, instr: &Instruction) {
-    if instr.d() < 30 {
-	gc.cpu.fpscr.0 |= 1 << (31 - instr.d());
+    if instr.d() != 1 && instr.d() != 2 {
+	gc.cpu.fpscr.raise(1 << (31 - instr.d()));
     }
     
     if instr.rc() {
-	unimplemented!("cr1");
+	update_cr1(gc);
     }
 }
 
-pub fn sc(gc: &mut Gamecube, instr: &Instruction) {
+pub fn mcrfs(gc: &mut Gamecube, instr: &Instruction) {
+    let shift = 28 - instr.crs() * 4;
+    gc.cpu.cr.set_reg(instr.crd(), (gc.cpu.fpscr.0 >> shift) & 0xF);
+
+    //any exception bits that got copied out are cleared
+    gc.cpu.fpscr.0 &= !((0xF << shift) & (FPSCR_FX | FPSCR_EXCEPTIONS));
+    gc.cpu.fpscr.update_summary();
+}
+
+pub fn sc(gc: &mut Gamecube, _instr: &Instruction) {
     println!("msr: {:#034b}", gc.cpu.msr.0);
     gc.cpu.exceptions |= SYSTEMCALL_EXCEPTION;
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{cpu::{instr::Instruction, PROGRAM_EXCEPTION, PROGRAM_ILLEGAL}, test_gamecube};
+
+    use super::{mfspr, mftb, mtspr, PVR};
+
+    //the spr/tbr field has its two halves swapped
+    fn spr_form(xo: u32, reg: u32, spr: u32) -> Instruction {
+	Instruction((31 << 26) | (reg << 21) | ((((spr & 0x1F) << 5) | (spr >> 5)) << 11) | (xo << 1))
+    }
+
+    fn mfspr_r3(spr: u32) -> Instruction {
+	spr_form(339, 3, spr)
+    }
+
+    fn mtspr_r3(spr: u32) -> Instruction {
+	spr_form(467, 3, spr)
+    }
+
+    #[test]
+    fn pvr_reads_as_gekko() {
+	let mut gc = test_gamecube();
+	mfspr(&mut gc, &mfspr_r3(287));
+	assert_eq!(gc.cpu.gprs[3], PVR);
+	assert_eq!(gc.cpu.exceptions & PROGRAM_EXCEPTION, 0);
+    }
+
+    #[test]
+    fn ipl_sprs_hold_what_was_written() {
+	let mut gc = test_gamecube();
+	for spr in [1009, 1013, 1019, 1020, 1021, 1022] {
+	    gc.cpu.gprs[3] = 0x1234_5678 + spr;
+	    mtspr(&mut gc, &mtspr_r3(spr));
+	    gc.cpu.gprs[3] = 0;
+	    mfspr(&mut gc, &mfspr_r3(spr));
+	    assert_eq!(gc.cpu.gprs[3], 0x1234_5678 + spr, "spr {spr}");
+	}
+	assert_eq!(gc.cpu.exceptions & PROGRAM_EXCEPTION, 0);
+    }
+
+    #[test]
+    fn unknown_sprs_are_illegal() {
+	//a made up spr, PVR being written and TBL through mftb's spare encoding
+	for (name, instr, f) in [
+	    ("mfspr 1000", mfspr_r3(1000), mfspr as fn(&mut _, &_)),
+	    ("mtspr 1000", mtspr_r3(1000), mtspr),
+	    ("mtspr PVR", mtspr_r3(287), mtspr),
+	    ("mftb 270", spr_form(371, 3, 270), mftb),
+	] {
+	    let mut gc = test_gamecube();
+	    gc.cpu.gprs[3] = 0xDEAD_BEEF;
+	    f(&mut gc, &instr);
+	    assert_ne!(gc.cpu.exceptions & PROGRAM_EXCEPTION, 0, "{name}");
+	    assert_eq!(gc.cpu.srr1_flags, PROGRAM_ILLEGAL, "{name}");
+	    assert_eq!(gc.cpu.gprs[3], 0xDEAD_BEEF, "{name}");
+	}
+    }
+}
@@ -1,8 +1,6 @@
-use log::debug;
-
 use crate::Gamecube;
 
-use super::{instr::Instruction, util::sext_26};
+use super::{instr::Instruction, util::sext_26, PROGRAM_TRAP};
 
 pub fn bc(gc: &mut Gamecube, instr: &Instruction) {
     let bo = instr.bo();
@@ -79,7 +77,34 @@ pub fn bcctr(gc: &mut Gamecube, instr: &Instruction) {
     }
 }
 
-pub fn rfi(gc: &mut Gamecube, instr: &Instruction) {
+//TO picks which of signed less/greater, equal and unsigned less/greater cause the trap
+fn trap(gc: &mut Gamecube, instr: &Instruction, a: u32, b: u32) {
+    let to = instr.to();
+
+    let trapped = (to & 0x10 != 0 && (a as i32) < (b as i32))
+	|| (to & 0x08 != 0 && (a as i32) > (b as i32))
+	|| (to & 0x04 != 0 && a == b)
+	|| (to & 0x02 != 0 && a < b)
+	|| (to & 0x01 != 0 && a > b);
+
+    if trapped {
+	gc.cpu.program_exception(PROGRAM_TRAP);
+    }
+}
+
+pub fn tw(gc: &mut Gamecube, instr: &Instruction) {
+    trap(gc, instr, gc.cpu.gprs[instr.a()], gc.cpu.gprs[instr.b()]);
+}
+
+pub fn twi(gc: &mut Gamecube, instr: &Instruction) {
+    trap(gc, instr, gc.cpu.gprs[instr.a()], i32::from(instr.simm()) as u32);
+}
+
+pub fn rfi(gc: &mut Gamecube, _instr: &Instruction) {
+    if !gc.cpu.check_supervisor() {
+	return;
+    }
+
     let mask = 0x87C0_FF73;
 
     gc.cpu.msr.0 = (gc.cpu.msr.0 & !mask) | (gc.cpu.srr1 & mask);
@@ -0,0 +1,525 @@
+use byteorder::{BigEndian, ByteOrder};
+use log::warn;
+
+use crate::{bus::{AccessDirection, AccessWidth, MemoryError}, Gamecube, BIOS_START, RAM_END, RAM_SIZE, RAM_START};
+
+use super::{arithmetic::{add, addc, adde, addi, addic, addicr, addis, addme, addze, cmp, cmpi, cmpl, cmpli, cntlzw, divw, divwu, mulhw, mulhwu, mulli, mullw, neg, subf, subfc, subfe, subfic, subfme, subfze}, bitwise::{and, andc, andi, andis, crand, crandc, creqv, crnand, crnor, cror, crorc, crxor, eqv, extsb, extsh, nand, nor, or, orc, ori, oris, rlwimi, rlwinm, rlwnm, slw, sraw, srawi, srw, xor, xori, xoris}, cache::{dcbf, dcbi, dcbst, dcbt, dcbtst, dcbz, dcbz_l, eieio, icbi, isync, sync}, config::{mcrf, mcrfs, mcrxr, mfcr, mffs, mfmsr, mfspr, mfsr, mfsrin, mftb, mtcrf, mtfsb0, mtfsb1, mtfsf, mtfsfi, mtmsr, mtspr, mtsr, mtsrin, sc, tlbie, tlbsync}, control_flow::{b, bc, bcctr, bclr, rfi, tw, twi}, float::{fabs, fadd, fadds, fcmpo, fcmpu, fctiw, fctiwz, fdiv, fdivs, fmadd, fmadds, fmr, fmsub, fmsubs, fmul, fmuls, fnabs, fneg, fnmadd, fnmadds, fnmsub, fnmsubs, fres, frsp, frsqrte, fsel, fsub, fsubs}, instr::Instruction, load_store::{eciwx, ecowx, lbz, lbzu, lbzux, lbzx, lfd, lfdu, lfdux, lfdx, lfs, lfsu, lfsux, lfsx, lha, lhau, lhaux, lhax, lhbrx, lhz, lhzu, lhzux, lhzx, lmw, lswi, lswx, lwbrx, lwz, lwzu, lwzux, lwzx, psq_l, psq_lu, psq_lux, psq_lx, psq_st, psq_stu, psq_stux, psq_stx, stb, stbu, stbux, stbx, stfd, stfdu, stfdux, stfdx, stfiwx, stfs, stfsu, stfsux, stfsx, sth, sthbrx, sthu, sthux, sthx, stmw, stswi, stswx, stw, stwbrx, stwu, stwux, stwx}, mmu::Access, paired::{ps_abs, ps_add, ps_cmpo0, ps_cmpo1, ps_cmpu0, ps_cmpu1, ps_div, ps_madd, ps_madds0, ps_madds1, ps_merge00, ps_merge01, ps_merge10, ps_merge11, ps_mr, ps_msub, ps_mul, ps_muls0, ps_muls1, ps_nabs, ps_neg, ps_nmadd, ps_nmsub, ps_res, ps_rsqrte, ps_sel, ps_sub, ps_sum0, ps_sum1}, PROGRAM_ILLEGAL};
+
+pub type Handler = fn(&mut Gamecube, &Instruction);
+
+//opcodes 4, 19, 31, 59 and 63 pick the real instruction with an extended opcode, which gets its own table
+struct OpcodeTables {
+    primary: [Option<Handler>; 64],
+    table4: [Handler; 1024],
+    table19: [Handler; 1024],
+    table31: [Handler; 1024],
+    table59: [Handler; 32],
+    table63: [Handler; 1024],
+}
+
+static TABLES: OpcodeTables = build_tables();
+
+//opcodes the cpu doesn't have are illegal instructions, which the program exception handler gets to deal with
+fn unknown_primary(gc: &mut Gamecube, instr: &Instruction) {
+    warn!("illegal instruction at {:#010X}, opcode: {:#08b}, instruction: {:#034b}", gc.cpu.cia, instr.opcd(), instr.0);
+    gc.cpu.program_exception(PROGRAM_ILLEGAL);
+}
+
+fn unknown_extended(gc: &mut Gamecube, instr: &Instruction) {
+    warn!("illegal instruction at {:#010X}, secondary opcode: {:#012b}, primary: {:#08b}, instruction: {:#034b}", gc.cpu.cia, instr.sec_opcd(), instr.opcd(), instr.0);
+    gc.cpu.program_exception(PROGRAM_ILLEGAL);
+}
+
+const fn build_tables() -> OpcodeTables {
+    let mut t = OpcodeTables {
+	primary: [Some(unknown_primary); 64],
+	table4: [unknown_extended; 1024],
+	table19: [unknown_extended; 1024],
+	table31: [unknown_extended; 1024],
+	table59: [unknown_extended; 32],
+	table63: [unknown_extended; 1024],
+    };
+
+    //None sends decode to the extended table for that primary opcode
+    t.primary[0b000100] = None;
+    t.primary[0b000011] = Some(twi);
+    t.primary[0b000111] = Some(mulli);
+    t.primary[0b001000] = Some(subfic);
+    t.primary[0b001010] = Some(cmpli);
+    t.primary[0b001011] = Some(cmpi);
+    t.primary[0b001100] = Some(addic);
+    t.primary[0b001101] = Some(addicr);
+    t.primary[0b001110] = Some(addi);
+    t.primary[0b001111] = Some(addis);
+    t.primary[0b010000] = Some(bc);
+    t.primary[0b010001] = Some(sc);
+    t.primary[0b010010] = Some(b);
+    t.primary[0b010011] = None;
+    t.primary[0b010100] = Some(rlwimi);
+    t.primary[0b010101] = Some(rlwinm);
+    t.primary[0b010111] = Some(rlwnm);
+    t.primary[0b011000] = Some(ori);
+    t.primary[0b011001] = Some(oris);
+    t.primary[0b011010] = Some(xori);
+    t.primary[0b011011] = Some(xoris);
+    t.primary[0b011100] = Some(andi);
+    t.primary[0b011101] = Some(andis);
+    t.primary[0b011111] = None;
+    t.primary[0b100000] = Some(lwz);
+    t.primary[0b100001] = Some(lwzu);
+    t.primary[0b100010] = Some(lbz);
+    t.primary[0b100011] = Some(lbzu);
+    t.primary[0b100100] = Some(stw);
+    t.primary[0b100101] = Some(stwu);
+    t.primary[0b100110] = Some(stb);
+    t.primary[0b100111] = Some(stbu);
+    t.primary[0b101000] = Some(lhz);
+    t.primary[0b101001] = Some(lhzu);
+    t.primary[0b101010] = Some(lha);
+    t.primary[0b101011] = Some(lhau);
+    t.primary[0b101100] = Some(sth);
+    t.primary[0b101101] = Some(sthu);
+    t.primary[0b101110] = Some(lmw);
+    t.primary[0b101111] = Some(stmw);
+    t.primary[0b110000] = Some(lfs);
+    t.primary[0b110001] = Some(lfsu);
+    t.primary[0b110010] = Some(lfd);
+    t.primary[0b110011] = Some(lfdu);
+    t.primary[0b110100] = Some(stfs);
+    t.primary[0b110101] = Some(stfsu);
+    t.primary[0b110110] = Some(stfd);
+    t.primary[0b110111] = Some(stfdu);
+    t.primary[0b111000] = Some(psq_l);
+    t.primary[0b111001] = Some(psq_lu);
+    t.primary[0b111011] = None;
+    t.primary[0b111100] = Some(psq_st);
+    t.primary[0b111101] = Some(psq_stu);
+    t.primary[0b111111] = None;
+
+    //A-form, frC takes up the top half of the extended opcode so every value of it gets an entry
+    let mut c = 0;
+    while c < 32 {
+	let base = c << 5;
+	t.table4[base | 0b01010] = ps_sum0;
+	t.table4[base | 0b01011] = ps_sum1;
+	t.table4[base | 0b01100] = ps_muls0;
+	t.table4[base | 0b01101] = ps_muls1;
+	t.table4[base | 0b01110] = ps_madds0;
+	t.table4[base | 0b01111] = ps_madds1;
+	t.table4[base | 0b10010] = ps_div;
+	t.table4[base | 0b10100] = ps_sub;
+	t.table4[base | 0b10101] = ps_add;
+	t.table4[base | 0b10111] = ps_sel;
+	t.table4[base | 0b11000] = ps_res;
+	t.table4[base | 0b11001] = ps_mul;
+	t.table4[base | 0b11010] = ps_rsqrte;
+	t.table4[base | 0b11100] = ps_msub;
+	t.table4[base | 0b11101] = ps_madd;
+	t.table4[base | 0b11110] = ps_nmsub;
+	t.table4[base | 0b11111] = ps_nmadd;
+
+	t.table63[base | 0b10010] = fdiv;
+	t.table63[base | 0b10100] = fsub;
+	t.table63[base | 0b10101] = fadd;
+	t.table63[base | 0b10111] = fsel;
+	t.table63[base | 0b11001] = fmul;
+	t.table63[base | 0b11010] = frsqrte;
+	t.table63[base | 0b11100] = fmsub;
+	t.table63[base | 0b11101] = fmadd;
+	t.table63[base | 0b11110] = fnmsub;
+	t.table63[base | 0b11111] = fnmadd;
+	c += 1;
+    }
+
+    //the indexed psq forms keep W and I above the opcode
+    let mut wi = 0;
+    while wi < 16 {
+	let base = wi << 6;
+	t.table4[base | 0b000110] = psq_lx;
+	t.table4[base | 0b000111] = psq_stx;
+	t.table4[base | 0b100110] = psq_lux;
+	t.table4[base | 0b100111] = psq_stux;
+	wi += 1;
+    }
+
+    t.table4[0b0000000000] = ps_cmpu0;
+    t.table4[0b0000100000] = ps_cmpo0;
+    t.table4[0b0000101000] = ps_neg;
+    t.table4[0b0001000000] = ps_cmpu1;
+    t.table4[0b0001001000] = ps_mr;
+    t.table4[0b0001100000] = ps_cmpo1;
+    t.table4[0b0010001000] = ps_nabs;
+    t.table4[0b0100001000] = ps_abs;
+    t.table4[0b1000010000] = ps_merge00;
+    t.table4[0b1000110000] = ps_merge01;
+    t.table4[0b1001010000] = ps_merge10;
+    t.table4[0b1001110000] = ps_merge11;
+    t.table4[0b1111110110] = dcbz_l;
+
+    t.table19[0b0000000000] = mcrf;
+    t.table19[0b0000010000] = bclr;
+    t.table19[0b0000100001] = crnor;
+    t.table19[0b0000110010] = rfi;
+    t.table19[0b0010000001] = crandc;
+    t.table19[0b0010010110] = isync;
+    t.table19[0b0011000001] = crxor;
+    t.table19[0b0011100001] = crnand;
+    t.table19[0b0100000001] = crand;
+    t.table19[0b0100100001] = creqv;
+    t.table19[0b0110100001] = crorc;
+    t.table19[0b0111000001] = cror;
+    t.table19[0b1000010000] = bcctr;
+
+    //XO-form arithmetic shows up twice, with and without OE
+    let mut oe = 0;
+    while oe < 2 {
+	let base = oe << 9;
+	t.table31[base | 0b000001000] = subfc;
+	t.table31[base | 0b000001010] = addc;
+	t.table31[base | 0b000101000] = subf;
+	t.table31[base | 0b001101000] = neg;
+	t.table31[base | 0b010001000] = subfe;
+	t.table31[base | 0b010001010] = adde;
+	t.table31[base | 0b011001000] = subfze;
+	t.table31[base | 0b011001010] = addze;
+	t.table31[base | 0b011101000] = subfme;
+	t.table31[base | 0b011101010] = addme;
+	t.table31[base | 0b011101011] = mullw;
+	t.table31[base | 0b100001010] = add;
+	t.table31[base | 0b111001011] = divwu;
+	t.table31[base | 0b111101011] = divw;
+	oe += 1;
+    }
+
+    t.table31[0b0000000000] = cmp;
+    t.table31[0b0000000100] = tw;
+    t.table31[0b0000001011] = mulhwu;
+    t.table31[0b0000010011] = mfcr;
+    t.table31[0b0000010111] = lwzx;
+    t.table31[0b0000011000] = slw;
+    t.table31[0b0000011010] = cntlzw;
+    t.table31[0b0000011100] = and;
+    t.table31[0b0000100000] = cmpl;
+    t.table31[0b0000110111] = lwzux;
+    t.table31[0b0000110110] = dcbst;
+    t.table31[0b0000111100] = andc;
+    t.table31[0b0001001011] = mulhw;
+    t.table31[0b0001010011] = mfmsr;
+    t.table31[0b0001010110] = dcbf;
+    t.table31[0b0001010111] = lbzx;
+    t.table31[0b0001110111] = lbzux;
+    t.table31[0b0001111100] = nor;
+    t.table31[0b0010010000] = mtcrf;
+    t.table31[0b0010010010] = mtmsr;
+    t.table31[0b0010010111] = stwx;
+    t.table31[0b0010110111] = stwux;
+    t.table31[0b0011010010] = mtsr;
+    t.table31[0b0011010111] = stbx;
+    t.table31[0b0011110010] = mtsrin;
+    t.table31[0b0011110110] = dcbtst;
+    t.table31[0b0011110111] = stbux;
+    t.table31[0b0100010110] = dcbt;
+    t.table31[0b0100010111] = lhzx;
+    t.table31[0b0100011100] = eqv;
+    t.table31[0b0100110010] = tlbie;
+    t.table31[0b0100110110] = eciwx;
+    t.table31[0b0100110111] = lhzux;
+    t.table31[0b0100111100] = xor;
+    t.table31[0b0101010011] = mfspr;
+    t.table31[0b0101010111] = lhax;
+    t.table31[0b0101110011] = mftb;
+    t.table31[0b0101110111] = lhaux;
+    t.table31[0b0110010111] = sthx;
+    t.table31[0b0110011100] = orc;
+    t.table31[0b0110110110] = ecowx;
+    t.table31[0b0110110111] = sthux;
+    t.table31[0b0110111100] = or;
+    t.table31[0b0111010011] = mtspr;
+    t.table31[0b0111010110] = dcbi;
+    t.table31[0b0111011100] = nand;
+    t.table31[0b1000000000] = mcrxr;
+    t.table31[0b1000010101] = lswx;
+    t.table31[0b1000010110] = lwbrx;
+    t.table31[0b1000010111] = lfsx;
+    t.table31[0b1000011000] = srw;
+    t.table31[0b1000110110] = tlbsync;
+    t.table31[0b1000110111] = lfsux;
+    t.table31[0b1001010011] = mfsr;
+    t.table31[0b1001010101] = lswi;
+    t.table31[0b1001010110] = sync;
+    t.table31[0b1001010111] = lfdx;
+    t.table31[0b1001110111] = lfdux;
+    t.table31[0b1010010011] = mfsrin;
+    t.table31[0b1010010101] = stswx;
+    t.table31[0b1010010110] = stwbrx;
+    t.table31[0b1010010111] = stfsx;
+    t.table31[0b1010110111] = stfsux;
+    t.table31[0b1011010101] = stswi;
+    t.table31[0b1011010111] = stfdx;
+    t.table31[0b1011110111] = stfdux;
+    t.table31[0b1100010110] = lhbrx;
+    t.table31[0b1100011000] = sraw;
+    t.table31[0b1100111000] = srawi;
+    t.table31[0b1101010110] = eieio;
+    t.table31[0b1110010110] = sthbrx;
+    t.table31[0b1110011010] = extsh;
+    t.table31[0b1110111010] = extsb;
+    t.table31[0b1111010110] = icbi;
+    t.table31[0b1111010111] = stfiwx;
+    t.table31[0b1111110110] = dcbz;
+
+    t.table59[0b10010] = fdivs;
+    t.table59[0b10100] = fsubs;
+    t.table59[0b10101] = fadds;
+    t.table59[0b11000] = fres;
+    t.table59[0b11001] = fmuls;
+    t.table59[0b11100] = fmsubs;
+    t.table59[0b11101] = fmadds;
+    t.table59[0b11110] = fnmsubs;
+    t.table59[0b11111] = fnmadds;
+
+    t.table63[0b0000000000] = fcmpu;
+    t.table63[0b0000001100] = frsp;
+    t.table63[0b0000001110] = fctiw;
+    t.table63[0b0000001111] = fctiwz;
+    t.table63[0b0000100000] = fcmpo;
+    t.table63[0b0000100110] = mtfsb1;
+    t.table63[0b0000101000] = fneg;
+    t.table63[0b0001000000] = mcrfs;
+    t.table63[0b0001000110] = mtfsb0;
+    t.table63[0b0001001000] = fmr;
+    t.table63[0b0010000110] = mtfsfi;
+    t.table63[0b0010001000] = fnabs;
+    t.table63[0b0100001000] = fabs;
+    t.table63[0b1001000111] = mffs;
+    t.table63[0b1011000111] = mtfsf;
+
+    t
+}
+
+pub fn decode(instr: &Instruction) -> Handler {
+    match TABLES.primary[instr.opcd()] {
+	Some(handler) => handler,
+	None => match instr.opcd() {
+	    0b000100 => TABLES.table4[instr.sec_opcd()],
+	    0b010011 => TABLES.table19[instr.sec_opcd()],
+	    0b011111 => TABLES.table31[instr.sec_opcd()],
+	    0b111011 => TABLES.table59[instr.sec_opcd() & 0x1F],
+	    _ => TABLES.table63[instr.sec_opcd()],
+	},
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Decoded {
+    pub instr: Instruction,
+    pub handler: Handler,
+}
+
+impl Decoded {
+    pub fn new(instr: Instruction) -> Self {
+	Self {
+	    instr,
+	    handler: decode(&instr),
+	}
+    }
+}
+
+const PAGE_SHIFT: u32 = 12;
+const PAGE_INSTRUCTIONS: usize = 1 << (PAGE_SHIFT - 2);
+const RAM_PAGES: usize = RAM_SIZE >> PAGE_SHIFT;
+const BIOS_PAGES: usize = 0x10_0000 >> PAGE_SHIFT;
+//icbi works on a 32 byte cache block
+const BLOCK_INSTRUCTIONS: usize = 8;
+
+type DecodePage = [Option<Decoded>; PAGE_INSTRUCTIONS];
+
+//decoded instructions keyed by physical address, so remapping code doesn't need to flush anything.
+//it only covers ram and the bootrom since nothing else can be executed from.
+pub struct DecodeCache {
+    pages: Vec<Option<Box<DecodePage>>>,
+    //bumped whenever a decoded instruction in the page is thrown away, so whole blocks built from it can tell
+    //they're stale. writes that only hit data leave the blocks alone
+    generations: Vec<u32>,
+}
+
+impl DecodeCache {
+    pub fn new() -> Self {
+	Self {
+	    pages: vec![None; RAM_PAGES + BIOS_PAGES],
+	    generations: vec![0; RAM_PAGES + BIOS_PAGES],
+	}
+    }
+
+    fn page_index(phys: u32) -> Option<usize> {
+	match phys {
+	    RAM_START..=RAM_END => Some((phys >> PAGE_SHIFT) as usize),
+	    BIOS_START.. => Some(RAM_PAGES + ((phys - BIOS_START) >> PAGE_SHIFT) as usize),
+	    _ => None,
+	}
+    }
+
+    fn slot(phys: u32) -> usize {
+	((phys >> 2) as usize) & (PAGE_INSTRUCTIONS - 1)
+    }
+
+    //None for anywhere the cache doesn't cover
+    pub fn generation(&self, phys: u32) -> Option<u32> {
+	Self::page_index(phys).map(|index| self.generations[index])
+    }
+
+    //the slot holding the page, null until something in it gets decoded. the jit checks it before writing
+    //to ram directly, which is fine since the slots never move
+    pub fn page_ptr(&self, phys: u32) -> Option<*const *const u8> {
+	let slot: *const Option<Box<DecodePage>> = &self.pages[Self::page_index(phys)?];
+	Some(slot.cast())
+    }
+
+    pub fn lookup(&self, phys: u32) -> Option<Decoded> {
+	let page = self.pages[Self::page_index(phys)?].as_ref()?;
+	page[Self::slot(phys)]
+    }
+
+    fn insert(&mut self, phys: u32, decoded: Decoded) {
+	let Some(index) = Self::page_index(phys) else {
+	    return;
+	};
+
+	let page = self.pages[index].get_or_insert_with(|| Box::new([None; PAGE_INSTRUCTIONS]));
+	page[Self::slot(phys)] = Some(decoded);
+    }
+
+    //called for every cpu write to ram, so the common case of a page with no code in it has to stay cheap
+    pub fn invalidate_range(&mut self, phys: u32, len: usize) {
+	let end = phys.wrapping_add(len as u32);
+	let mut addr = phys & !3;
+
+	//unaligned writes can straddle two instructions, or even two pages
+	while addr < end {
+	    let index = Self::page_index(addr);
+	    match index.and_then(|index| self.pages[index].as_mut()) {
+		Some(page) => {
+		    if page[Self::slot(addr)].take().is_some() {
+			let index = index.unwrap();
+			self.generations[index] = self.generations[index].wrapping_add(1);
+		    }
+		    addr += 4;
+		},
+		//nothing has been decoded out of the page, so there's nothing in it to go stale
+		None => match (addr | ((1 << PAGE_SHIFT) - 1)).checked_add(1) {
+		    Some(next) => addr = next,
+		    None => break,
+		},
+	    }
+	}
+    }
+
+    pub fn invalidate_block(&mut self, phys: u32) {
+	let Some(index) = Self::page_index(phys) else {
+	    return;
+	};
+	let Some(page) = self.pages[index].as_mut() else {
+	    return;
+	};
+
+	let first = Self::slot(phys) & !(BLOCK_INSTRUCTIONS - 1);
+	let mut cleared = false;
+	for decoded in &mut page[first..(first + BLOCK_INSTRUCTIONS)] {
+	    cleared |= decoded.take().is_some();
+	}
+	if cleared {
+	    self.generations[index] = self.generations[index].wrapping_add(1);
+	}
+    }
+}
+
+impl Default for DecodeCache {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+//either hits the decode cache or reads and decodes the word. never has side effects, None means
+//there's no memory there to run from
+pub fn fetch_phys(gc: &mut Gamecube, phys: u32) -> Option<Decoded> {
+    if let Some(decoded) = gc.cpu.decode_cache.lookup(phys) {
+	return Some(decoded);
+    }
+
+    let decoded = Decoded::new(Instruction(BigEndian::read_u32(gc.backing(phys, 4)?)));
+    gc.cpu.decode_cache.insert(phys, decoded);
+
+    Some(decoded)
+}
+
+pub fn fetch(gc: &mut Gamecube, addr: u32) -> Result<Decoded, MemoryError> {
+    let phys = gc.translate(addr, Access::Fetch)?;
+
+    match fetch_phys(gc, phys) {
+	Some(decoded) => Ok(decoded),
+	None => {
+	    let word = gc.bus_error(phys, AccessWidth::U32, AccessDirection::Read)?;
+	    Ok(Decoded::new(Instruction(word)))
+	},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cpu::{instr::Instruction, PROGRAM_EXCEPTION, PROGRAM_ILLEGAL}, test_gamecube, Gamecube};
+
+    use super::{decode, fetch_phys};
+
+    fn execute(word: u32) -> Gamecube {
+	let mut gc = test_gamecube();
+	gc.cpu.cia = 0x100;
+	gc.cpu.nia = 0x104;
+	gc.cpu.gprs[3] = 2;
+	gc.cpu.fprs[2].set_ps0(1.0);
+	gc.cpu.fprs[3].set_ps0(2.0);
+	let instr = Instruction(word);
+	decode(&instr)(&mut gc, &instr);
+	gc
+    }
+
+    #[test]
+    fn primary_and_extended_opcodes_find_their_handlers() {
+	//addi r4, r3, 5
+	assert_eq!(execute((14 << 26) | (4 << 21) | (3 << 16) | 5).cpu.gprs[4], 7);
+	//slw r4, r3, r3 out of table 31
+	assert_eq!(execute((31 << 26) | (3 << 21) | (4 << 16) | (3 << 11) | (24 << 1)).cpu.gprs[4], 8);
+	//fadds f1, f2, f3 out of table 59, which only looks at the low five bits so frC doesn't matter
+	assert_eq!(execute((59 << 26) | (1 << 21) | (2 << 16) | (3 << 11) | (7 << 6) | (21 << 1)).cpu.fprs[1].ps0(), 3.0);
+    }
+
+    #[test]
+    fn opcodes_gekko_doesnt_have_are_illegal() {
+	//primary opcode 1, and extended opcode 1 under 31
+	for word in [1 << 26, (31 << 26) | (1 << 1)] {
+	    let gc = execute(word);
+	    assert_eq!(gc.cpu.exceptions, PROGRAM_EXCEPTION);
+	    assert_eq!(gc.cpu.srr1_flags, PROGRAM_ILLEGAL);
+	    assert_eq!(gc.cpu.nia, 0x100);
+	}
+    }
+
+    #[test]
+    fn only_writes_over_decoded_code_make_blocks_stale() {
+	let mut gc = test_gamecube();
+	//nop
+	gc.memory[0x3000..0x3004].copy_from_slice(&0x6000_0000u32.to_be_bytes());
+	fetch_phys(&mut gc, 0x3000).unwrap();
+	let generation = gc.cpu.decode_cache.generation(0x3000);
+
+	//data next to the code, and a page nothing was ever run from
+	gc.write_u32(0x3100, 5).unwrap();
+	gc.write_u32(0x5000, 5).unwrap();
+	assert_eq!(gc.cpu.decode_cache.generation(0x3000), generation);
+	assert_eq!(gc.cpu.decode_cache.generation(0x5000), Some(0));
+	assert!(gc.cpu.decode_cache.lookup(0x3000).is_some());
+
+	gc.write_u8(0x3002, 0x12).unwrap();
+	assert_ne!(gc.cpu.decode_cache.generation(0x3000), generation);
+	assert!(gc.cpu.decode_cache.lookup(0x3000).is_none());
+    }
+}
@@ -1,18 +1,907 @@
 use crate::{cpu::instr::Instruction, Gamecube};
 
-pub fn ps_mr(gc: &mut Gamecube, instr: &Instruction) {
-    if !gc.cpu.hid2.pse() {
-	unimplemented!("exception");
+use super::{FloatingPointStatusControlRegister, PROGRAM_FLOATING_POINT};
+
+pub const FPSCR_FX: u32 = 1 << 31;
+pub const FPSCR_FEX: u32 = 1 << 30;
+pub const FPSCR_VX: u32 = 1 << 29;
+pub const FPSCR_OX: u32 = 1 << 28;
+pub const FPSCR_UX: u32 = 1 << 27;
+pub const FPSCR_ZX: u32 = 1 << 26;
+pub const FPSCR_XX: u32 = 1 << 25;
+pub const FPSCR_VXSNAN: u32 = 1 << 24;
+pub const FPSCR_VXISI: u32 = 1 << 23;
+pub const FPSCR_VXIDI: u32 = 1 << 22;
+pub const FPSCR_VXZDZ: u32 = 1 << 21;
+pub const FPSCR_VXIMZ: u32 = 1 << 20;
+pub const FPSCR_VXVC: u32 = 1 << 19;
+pub const FPSCR_FR: u32 = 1 << 18;
+pub const FPSCR_FI: u32 = 1 << 17;
+const FPSCR_FPRF: u32 = 0x1F << 12;
+const FPSCR_FPCC: u32 = 0xF << 12;
+pub const FPSCR_VXSOFT: u32 = 1 << 10;
+pub const FPSCR_VXSQRT: u32 = 1 << 9;
+pub const FPSCR_VXCVI: u32 = 1 << 8;
+
+const FPSCR_VX_ANY: u32 = FPSCR_VXSNAN | FPSCR_VXISI | FPSCR_VXIDI | FPSCR_VXZDZ | FPSCR_VXIMZ | FPSCR_VXVC | FPSCR_VXSOFT | FPSCR_VXSQRT | FPSCR_VXCVI;
+//every sticky exception bit, any of these going from 0 to 1 also sets FX
+pub const FPSCR_EXCEPTIONS: u32 = FPSCR_OX | FPSCR_UX | FPSCR_ZX | FPSCR_XX | FPSCR_VX_ANY;
+
+//FPRF result classes, C followed by FPCC
+const FPRF_QNAN: u32 = 0x11;
+const FPRF_NEG_INF: u32 = 0x09;
+const FPRF_NEG_NORMAL: u32 = 0x08;
+const FPRF_NEG_DENORMAL: u32 = 0x18;
+const FPRF_NEG_ZERO: u32 = 0x12;
+const FPRF_POS_ZERO: u32 = 0x02;
+const FPRF_POS_DENORMAL: u32 = 0x14;
+const FPRF_POS_NORMAL: u32 = 0x04;
+const FPRF_POS_INF: u32 = 0x05;
+
+//compare results, shared by FPCC and the CR fields
+const CC_LT: u32 = 0x8;
+const CC_GT: u32 = 0x4;
+const CC_EQ: u32 = 0x2;
+const CC_UN: u32 = 0x1;
+
+const DEFAULT_NAN: u64 = 0x7FF8_0000_0000_0000;
+const QUIET_BIT: u64 = 1 << 51;
+
+impl FloatingPointStatusControlRegister {
+    //sets sticky bits, FX goes up for any exception bit that wasn't already set
+    pub fn raise(&mut self, bits: u32) {
+	if bits & FPSCR_EXCEPTIONS & !self.0 != 0 {
+	    self.0 |= FPSCR_FX;
+	}
+	self.0 |= bits;
+	self.update_summary();
+    }
+
+    //VX and FEX aren't real state, they're always derived from the rest of the register
+    pub fn update_summary(&mut self) {
+	self.0 &= !(FPSCR_VX | FPSCR_FEX);
+	if self.0 & FPSCR_VX_ANY != 0 {
+	    self.0 |= FPSCR_VX;
+	}
+	//the enables (VE OE UE ZE XE) sit in the same order as VX OX UX ZX XX
+	if (self.0 >> 25) & (self.0 >> 3) & 0x1F != 0 {
+	    self.0 |= FPSCR_FEX;
+	}
+    }
+
+    fn set_fprf(&mut self, class: u32) {
+	self.0 = (self.0 & !FPSCR_FPRF) | (class << 12);
+    }
+
+    fn set_fi_fr(&mut self, fi: bool, fr: bool) {
+	self.0 &= !(FPSCR_FI | FPSCR_FR);
+	if fi {
+	    self.0 |= FPSCR_FI;
+	}
+	if fr {
+	    self.0 |= FPSCR_FR;
+	}
+    }
+}
+
+pub(super) fn is_snan(val: f64) -> bool {
+    val.is_nan() && val.to_bits() & QUIET_BIT == 0
+}
+
+fn quiet(val: f64) -> f64 {
+    f64::from_bits(val.to_bits() | QUIET_BIT)
+}
+
+//what's left of a NaN once it's been squeezed through single precision
+fn single_nan(val: f64) -> f64 {
+    f64::from_bits(val.to_bits() & 0xFFFF_FFFF_E000_0000)
+}
+
+fn classify(val: f64, single: bool) -> u32 {
+    let min_normal = if single {
+	f64::from(f32::MIN_POSITIVE)
+    } else {
+	f64::MIN_POSITIVE
+    };
+
+    if val.is_nan() {
+	FPRF_QNAN
+    } else if val.is_infinite() {
+	if val.is_sign_negative() { FPRF_NEG_INF } else { FPRF_POS_INF }
+    } else if val == 0.0 {
+	if val.is_sign_negative() { FPRF_NEG_ZERO } else { FPRF_POS_ZERO }
+    } else if val.abs() < min_normal {
+	if val.is_sign_negative() { FPRF_NEG_DENORMAL } else { FPRF_POS_DENORMAL }
+    } else if val.is_sign_negative() {
+	FPRF_NEG_NORMAL
+    } else {
+	FPRF_POS_NORMAL
+    }
+}
+
+pub(super) struct Rounded {
+    value: f64,
+    fi: bool,
+    fr: bool,
+    overflow: bool,
+    underflow: bool,
+}
+
+impl Rounded {
+    pub(super) fn exact(value: f64) -> Self {
+	Self {
+	    value,
+	    fi: false,
+	    fr: false,
+	    overflow: false,
+	    underflow: false,
+	}
     }
-    gc.cpu.fprs[instr.d()] = gc.cpu.fprs[instr.b()];
+}
+
+//exact result of a + b as the nearest double plus the error
+fn two_sum(a: f64, b: f64) -> (f64, f64) {
+    let s = a + b;
+    let bb = s - a;
+    (s, (a - (s - bb)) + (b - bb))
+}
+
+fn two_product(a: f64, b: f64) -> (f64, f64) {
+    let p = a * b;
+    (p, a.mul_add(b, -p))
+}
+
+//the remainder only tells us which side of the quotient the exact result is on, which is all rounding needs
+fn quotient(a: f64, b: f64) -> (f64, f64) {
+    let q = a / b;
+    let rem = (-q).mul_add(b, a);
+    let side = if rem == 0.0 {
+	0.0
+    } else {
+	rem.signum() * b.signum()
+    };
+    (q, side)
+}
+
+//hi is the exact result rounded to the nearest double and lo has the sign of what was rounded off.
+//rust only ever rounds to nearest, the directed modes nudge the result one ulp from there.
+//only called with finite operands, so an infinite hi means the result overflowed.
+fn round(hi: f64, lo: f64, rn: usize, single: bool) -> Rounded {
+    let (r, err) = if single {
+	let s = f64::from(hi as f32);
+	let e = hi - s;
+	(s, if e != 0.0 && e.is_finite() { e } else { lo })
+    } else {
+	(hi, lo)
+    };
+
+    let max = if single {
+	f64::from(f32::MAX)
+    } else {
+	f64::MAX
+    };
+
+    if r.is_infinite() {
+	let value = match (rn, r > 0.0) {
+	    (1, _) | (2, false) | (3, true) => max.copysign(r),
+	    _ => r,
+	};
+	return Rounded {
+	    value,
+	    fi: true,
+	    fr: value.is_infinite(),
+	    overflow: true,
+	    underflow: false,
+	};
+    }
+
+    let step = |up: bool| if single {
+	let s = r as f32;
+	f64::from(if up { s.next_up() } else { s.next_down() })
+    } else if up {
+	r.next_up()
+    } else {
+	r.next_down()
+    };
+
+    //did rounding to nearest already push the magnitude up
+    let magnitude_up = r != 0.0 && err != 0.0 && (r > 0.0) == (err < 0.0);
+    let value = match rn {
+	1 if magnitude_up => step(r < 0.0),
+	2 if err > 0.0 => step(true),
+	3 if err < 0.0 => step(false),
+	_ => r,
+    };
+
+    let fi = err != 0.0;
+    let fr = if value != r {
+	value.abs() > r.abs()
+    } else {
+	magnitude_up
+    };
+    let min_normal = if single {
+	f64::from(f32::MIN_POSITIVE)
+    } else {
+	f64::MIN_POSITIVE
+    };
+
+    Rounded {
+	value,
+	fi,
+	fr,
+	overflow: value.is_infinite(),
+	underflow: fi && value.abs() < min_normal,
+    }
+}
+
+pub fn update_cr1(gc: &mut Gamecube) {
+    gc.cpu.cr.set_reg(1, gc.cpu.fpscr.0 >> 28);
+}
+
+//common tail for everything that can touch the fpscr. an enabled exception that this instruction
+//raised traps right away when MSR[FE0/FE1] allow it, Rc=1 copies FX FEX VX OX into cr1.
+fn finish(gc: &mut Gamecube, instr: &Instruction, fpscr_before: u32) {
+    if gc.cpu.fpscr.fex() && fpscr_before & FPSCR_FEX == 0 && (gc.cpu.msr.fe0() || gc.cpu.msr.fe1()) {
+	gc.cpu.program_exception(PROGRAM_FLOATING_POINT);
+    }
+
     if instr.rc() {
-	unimplemented!("cr1");
+	update_cr1(gc);
+    }
+}
+
+//NaNs lose their low bits going through single precision, and non-IEEE mode flushes denormals to zero
+fn finalize(value: f64, single: bool, ni: bool) -> f64 {
+    if value.is_nan() {
+	if single {
+	    single_nan(value)
+	} else {
+	    value
+	}
+    } else if ni && value != 0.0 && classify(value, single) & 0x10 != 0 {
+	0.0f64.copysign(value)
+    } else {
+	value
+    }
+}
+
+//writes a rounded result to frD unless an enabled invalid or zero divide exception suppresses it.
+//paired singles pass the second slot along, FPRF FI and FR only ever describe ps0.
+pub(super) fn write_result(gc: &mut Gamecube, instr: &Instruction, ps0: Rounded, ps1: Option<Rounded>, exceptions: u32, single: bool) {
+    let fpscr_before = gc.cpu.fpscr.0;
+
+    let mut bits = exceptions;
+    for rounded in std::iter::once(&ps0).chain(ps1.as_ref()) {
+	if rounded.fi {
+	    bits |= FPSCR_XX;
+	}
+	if rounded.overflow {
+	    bits |= FPSCR_OX;
+	}
+	if rounded.underflow {
+	    bits |= FPSCR_UX;
+	}
+    }
+    gc.cpu.fpscr.raise(bits);
+
+    let suppressed = (exceptions & FPSCR_VX_ANY != 0 && gc.cpu.fpscr.ve()) || (exceptions & FPSCR_ZX != 0 && gc.cpu.fpscr.ze());
+    if suppressed {
+	gc.cpu.fpscr.set_fi_fr(false, false);
+    } else {
+	let ni = gc.cpu.fpscr.ni();
+	let value = finalize(ps0.value, single, ni);
+
+	gc.cpu.fpscr.set_fi_fr(ps0.fi, ps0.fr);
+	gc.cpu.fpscr.set_fprf(classify(value, single));
+
+	match ps1 {
+	    Some(ps1) => {
+		gc.cpu.fprs[instr.d()].set_ps0(value);
+		gc.cpu.fprs[instr.d()].set_ps1(finalize(ps1.value, single, ni));
+	    },
+	    None if single => gc.cpu.fprs[instr.d()].set_both(value),
+	    None => gc.cpu.fprs[instr.d()].set_ps0(value),
+	}
+    }
+
+    finish(gc, instr, fpscr_before);
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub(super) enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    MulAdd,
+    MulSub,
+}
+
+fn arithmetic(gc: &mut Gamecube, instr: &Instruction, op: Op, negate: bool, single: bool) {
+    let a = gc.cpu.fprs[instr.a()].ps0();
+    let b = gc.cpu.fprs[instr.b()].ps0();
+    let c = gc.cpu.fprs[instr.c()].ps0();
+
+    let (rounded, exceptions) = compute(op, negate, a, b, c, gc.cpu.fpscr.rn(), single);
+    write_result(gc, instr, rounded, None, exceptions, single);
+}
+
+//a op b, or a * c op b for the fused ones, along with the exception bits it raises
+pub(super) fn compute(op: Op, negate: bool, a: f64, b: f64, c: f64, rn: usize, single: bool) -> (Rounded, u32) {
+    //NaN propagation takes the first NaN in frA, frB, frC order
+    let operands: &[f64] = match op {
+	Op::Add | Op::Sub | Op::Div => &[a, b],
+	Op::Mul => &[a, c],
+	Op::MulAdd | Op::MulSub => &[a, b, c],
+    };
+    let addend = match op {
+	Op::Sub | Op::MulSub => -b,
+	_ => b,
+    };
+
+    let mut exceptions = 0;
+    if operands.iter().any(|val| is_snan(*val)) {
+	exceptions |= FPSCR_VXSNAN;
+    }
+    match op {
+	Op::Add | Op::Sub => if a.is_infinite() && addend.is_infinite() && a.signum() != addend.signum() {
+	    exceptions |= FPSCR_VXISI;
+	},
+	Op::Mul => if (a.is_infinite() && c == 0.0) || (a == 0.0 && c.is_infinite()) {
+	    exceptions |= FPSCR_VXIMZ;
+	},
+	Op::Div => if a.is_infinite() && b.is_infinite() {
+	    exceptions |= FPSCR_VXIDI;
+	} else if a == 0.0 && b == 0.0 {
+	    exceptions |= FPSCR_VXZDZ;
+	} else if b == 0.0 && a.is_finite() {
+	    exceptions |= FPSCR_ZX;
+	},
+	Op::MulAdd | Op::MulSub => if (a.is_infinite() && c == 0.0) || (a == 0.0 && c.is_infinite()) {
+	    exceptions |= FPSCR_VXIMZ;
+	} else {
+	    let product = a * c;
+	    if product.is_infinite() && addend.is_infinite() && product.signum() != addend.signum() {
+		exceptions |= FPSCR_VXISI;
+	    }
+	},
+    }
+
+    let rounded = if let Some(nan) = operands.iter().find(|val| val.is_nan()) {
+	Rounded::exact(quiet(*nan))
+    } else if exceptions & FPSCR_VX_ANY != 0 {
+	Rounded::exact(f64::from_bits(DEFAULT_NAN))
+    } else if exceptions & FPSCR_ZX != 0 {
+	Rounded::exact(f64::INFINITY.copysign(a * b.signum()))
+    } else if operands.iter().any(|val| val.is_infinite()) {
+	//infinities are exact, no rounding to do
+	Rounded::exact(match op {
+	    Op::Add | Op::Sub => a + addend,
+	    Op::Mul => a * c,
+	    Op::Div => a / b,
+	    Op::MulAdd | Op::MulSub => a.mul_add(c, addend),
+	})
+    } else {
+	//fused ops don't get an error term, so they always round to nearest and never report FI/FR
+	let (hi, lo) = match op {
+	    Op::Add | Op::Sub => two_sum(a, addend),
+	    Op::Mul => two_product(a, c),
+	    Op::Div => quotient(a, b),
+	    Op::MulAdd | Op::MulSub => (a.mul_add(c, addend), 0.0),
+	};
+	round(hi, lo, rn, single)
+    };
+
+    let rounded = if negate && !rounded.value.is_nan() {
+	Rounded {
+	    value: -rounded.value,
+	    ..rounded
+	}
+    } else {
+	rounded
+    };
+
+    (rounded, exceptions)
+}
+
+pub fn fadd(gc: &mut Gamecube, instr: &Instruction) {
+    arithmetic(gc, instr, Op::Add, false, false);
+}
+
+pub fn fadds(gc: &mut Gamecube, instr: &Instruction) {
+    arithmetic(gc, instr, Op::Add, false, true);
+}
+
+pub fn fsub(gc: &mut Gamecube, instr: &Instruction) {
+    arithmetic(gc, instr, Op::Sub, false, false);
+}
+
+pub fn fsubs(gc: &mut Gamecube, instr: &Instruction) {
+    arithmetic(gc, instr, Op::Sub, false, true);
+}
+
+pub fn fmul(gc: &mut Gamecube, instr: &Instruction) {
+    arithmetic(gc, instr, Op::Mul, false, false);
+}
+
+pub fn fmuls(gc: &mut Gamecube, instr: &Instruction) {
+    arithmetic(gc, instr, Op::Mul, false, true);
+}
+
+pub fn fdiv(gc: &mut Gamecube, instr: &Instruction) {
+    arithmetic(gc, instr, Op::Div, false, false);
+}
+
+pub fn fdivs(gc: &mut Gamecube, instr: &Instruction) {
+    arithmetic(gc, instr, Op::Div, false, true);
+}
+
+pub fn fmadd(gc: &mut Gamecube, instr: &Instruction) {
+    arithmetic(gc, instr, Op::MulAdd, false, false);
+}
+
+pub fn fmadds(gc: &mut Gamecube, instr: &Instruction) {
+    arithmetic(gc, instr, Op::MulAdd, false, true);
+}
+
+pub fn fmsub(gc: &mut Gamecube, instr: &Instruction) {
+    arithmetic(gc, instr, Op::MulSub, false, false);
+}
+
+pub fn fmsubs(gc: &mut Gamecube, instr: &Instruction) {
+    arithmetic(gc, instr, Op::MulSub, false, true);
+}
+
+pub fn fnmadd(gc: &mut Gamecube, instr: &Instruction) {
+    arithmetic(gc, instr, Op::MulAdd, true, false);
+}
+
+pub fn fnmadds(gc: &mut Gamecube, instr: &Instruction) {
+    arithmetic(gc, instr, Op::MulAdd, true, true);
+}
+
+pub fn fnmsub(gc: &mut Gamecube, instr: &Instruction) {
+    arithmetic(gc, instr, Op::MulSub, true, false);
+}
+
+pub fn fnmsubs(gc: &mut Gamecube, instr: &Instruction) {
+    arithmetic(gc, instr, Op::MulSub, true, true);
+}
+
+//gekko's estimate tables, lifted from dolphin who got them from hardware tests
+//https://github.com/dolphin-emu/dolphin/blob/master/Source/Core/Common/FloatUtils.cpp
+const FRES_EXPECTED: [(i64, i64); 32] = [
+    (0x7FF800, 0x3E1), (0x783800, 0x3A7), (0x70EA00, 0x371), (0x6A0800, 0x340),
+    (0x638800, 0x313), (0x5D6200, 0x2EA), (0x579000, 0x2C4), (0x520800, 0x2A0),
+    (0x4CC800, 0x27F), (0x47CA00, 0x261), (0x430800, 0x245), (0x3E8000, 0x22A),
+    (0x3A2C00, 0x212), (0x360800, 0x1FB), (0x321400, 0x1E5), (0x2E4A00, 0x1D1),
+    (0x2AA800, 0x1BE), (0x272C00, 0x1AC), (0x23D600, 0x19B), (0x209E00, 0x18B),
+    (0x1D8800, 0x17C), (0x1A9000, 0x16E), (0x17AE00, 0x15B), (0x14F800, 0x15B),
+    (0x124400, 0x143), (0x0FBE00, 0x143), (0x0D3800, 0x12D), (0x0ADE00, 0x12D),
+    (0x088400, 0x11A), (0x065000, 0x11A), (0x041C00, 0x108), (0x020C00, 0x106),
+];
+
+const FRSQRTE_EXPECTED: [(i64, i64); 32] = [
+    (0x3FFA000, 0x7A4), (0x3C29000, 0x700), (0x38AA000, 0x670), (0x3572000, 0x5F2),
+    (0x3279000, 0x584), (0x2FB7000, 0x524), (0x2D26000, 0x4CC), (0x2AC0000, 0x47E),
+    (0x2881000, 0x43A), (0x2665000, 0x3FA), (0x2468000, 0x3C2), (0x2287000, 0x38E),
+    (0x20C1000, 0x35E), (0x1F12000, 0x332), (0x1D79000, 0x30A), (0x1BF4000, 0x2E6),
+    (0x1A7E800, 0x568), (0x17CB800, 0x4F3), (0x1552800, 0x48D), (0x130C000, 0x435),
+    (0x10F2000, 0x3E7), (0x0EFF000, 0x3A2), (0x0D2E000, 0x365), (0x0B7C000, 0x32E),
+    (0x09E5000, 0x2FC), (0x0867000, 0x2D0), (0x06FF000, 0x2A8), (0x05AB800, 0x283),
+    (0x046A000, 0x261), (0x0339800, 0x243), (0x0218800, 0x226), (0x0105800, 0x20B),
+];
+
+pub fn approximate_reciprocal(val: f64) -> f64 {
+    let integral = val.to_bits() as i64;
+    let mantissa = integral & ((1 << 52) - 1);
+    let sign = integral & (1 << 63);
+    let exponent = integral & (0x7FF << 52);
+
+    if mantissa == 0 && exponent == 0 {
+	return f64::INFINITY.copysign(val);
+    }
+
+    if exponent == (0x7FF << 52) {
+	if mantissa == 0 {
+	    return 0.0f64.copysign(val);
+	}
+	return quiet(val);
+    }
+
+    //too small and the estimate saturates, too big and it flushes to zero
+    if exponent < (895 << 52) {
+	return f64::from(f32::MAX).copysign(val);
+    }
+    if exponent >= (1149 << 52) {
+	return 0.0f64.copysign(val);
+    }
+
+    let exponent = (0x7FD << 52) - exponent;
+    let i = mantissa >> 37;
+    let (base, dec) = FRES_EXPECTED[(i / 1024) as usize];
+    let integral = sign | exponent | ((base - (dec * (i % 1024) + 1) / 2) << 29);
+
+    f64::from_bits(integral as u64)
+}
+
+pub fn approximate_reciprocal_sqrt(val: f64) -> f64 {
+    let integral = val.to_bits() as i64;
+    let mut mantissa = integral & ((1 << 52) - 1);
+    let sign = integral & (1 << 63);
+    let mut exponent = integral & (0x7FF << 52);
+
+    if mantissa == 0 && exponent == 0 {
+	return f64::INFINITY.copysign(val);
+    }
+
+    if exponent == (0x7FF << 52) {
+	if mantissa == 0 {
+	    if sign != 0 {
+		return f64::from_bits(DEFAULT_NAN);
+	    }
+	    return 0.0;
+	}
+	return quiet(val);
+    }
+
+    if sign != 0 {
+	return f64::from_bits(DEFAULT_NAN);
+    }
+
+    //normalize denormals
+    if exponent == 0 {
+	loop {
+	    exponent -= 1 << 52;
+	    mantissa <<= 1;
+	    if mantissa & (1 << 52) != 0 {
+		break;
+	    }
+	}
+	mantissa &= (1 << 52) - 1;
+	exponent += 1 << 52;
+    }
+
+    let odd_exponent = exponent & (1 << 52) == 0;
+    let exponent = ((0x3FF << 52) - ((exponent - (0x3FE << 52)) / 2)) & (0x7FF << 52);
+
+    let i = mantissa >> 37;
+    let index = (i / 2048) as usize + if odd_exponent { 16 } else { 0 };
+    let (base, dec) = FRSQRTE_EXPECTED[index];
+    let integral = sign | exponent | ((base - dec * (i % 2048)) << 26);
+
+    f64::from_bits(integral as u64)
+}
+
+pub fn fres(gc: &mut Gamecube, instr: &Instruction) {
+    let b = gc.cpu.fprs[instr.b()].ps0();
+
+    let mut exceptions = 0;
+    if is_snan(b) {
+	exceptions |= FPSCR_VXSNAN;
+    } else if b == 0.0 {
+	exceptions |= FPSCR_ZX;
+    }
+
+    write_result(gc, instr, Rounded::exact(approximate_reciprocal(b)), None, exceptions, true);
+}
+
+pub fn frsqrte(gc: &mut Gamecube, instr: &Instruction) {
+    let b = gc.cpu.fprs[instr.b()].ps0();
+
+    let mut exceptions = 0;
+    if is_snan(b) {
+	exceptions |= FPSCR_VXSNAN;
+    } else if b == 0.0 {
+	exceptions |= FPSCR_ZX;
+    } else if b < 0.0 {
+	exceptions |= FPSCR_VXSQRT;
+    }
+
+    write_result(gc, instr, Rounded::exact(approximate_reciprocal_sqrt(b)), None, exceptions, false);
+}
+
+pub fn frsp(gc: &mut Gamecube, instr: &Instruction) {
+    let b = gc.cpu.fprs[instr.b()].ps0();
+
+    let mut exceptions = 0;
+    let rounded = if b.is_nan() {
+	if is_snan(b) {
+	    exceptions |= FPSCR_VXSNAN;
+	}
+	Rounded::exact(quiet(b))
+    } else if b.is_infinite() || b == 0.0 {
+	Rounded::exact(b)
+    } else {
+	round(b, 0.0, gc.cpu.fpscr.rn(), true)
+    };
+
+    write_result(gc, instr, rounded, None, exceptions, true);
+}
+
+//frD gets the integer in its low word, the upper word is what gekko leaves there
+fn convert_to_integer(gc: &mut Gamecube, instr: &Instruction, rn: usize) {
+    let fpscr_before = gc.cpu.fpscr.0;
+    let b = gc.cpu.fprs[instr.b()].ps0();
+
+    let (result, exceptions, fi, fr) = if b.is_nan() {
+	let snan = if is_snan(b) { FPSCR_VXSNAN } else { 0 };
+	(0x8000_0000, FPSCR_VXCVI | snan, false, false)
+    } else {
+	let rounded = match rn {
+	    0 => b.round_ties_even(),
+	    1 => b.trunc(),
+	    2 => b.ceil(),
+	    _ => b.floor(),
+	};
+
+	if rounded > f64::from(i32::MAX) {
+	    (0x7FFF_FFFF, FPSCR_VXCVI, false, false)
+	} else if rounded < f64::from(i32::MIN) {
+	    (0x8000_0000, FPSCR_VXCVI, false, false)
+	} else {
+	    let fi = rounded != b;
+	    let xx = if fi { FPSCR_XX } else { 0 };
+	    (rounded as i32 as u32, xx, fi, rounded.abs() > b.abs())
+	}
+    };
+
+    gc.cpu.fpscr.raise(exceptions);
+
+    if exceptions & FPSCR_VX_ANY != 0 && gc.cpu.fpscr.ve() {
+	gc.cpu.fpscr.set_fi_fr(false, false);
+    } else {
+	gc.cpu.fpscr.set_fi_fr(fi, fr);
+	let mut val = 0xFFF8_0000_0000_0000 | u64::from(result);
+	//negative zero leaves a stray bit behind on hardware
+	if result == 0 && b.is_sign_negative() {
+	    val |= 1 << 32;
+	}
+	gc.cpu.fprs[instr.d()].ps0 = val;
+    }
+
+    finish(gc, instr, fpscr_before);
+}
+
+pub fn fctiw(gc: &mut Gamecube, instr: &Instruction) {
+    convert_to_integer(gc, instr, gc.cpu.fpscr.rn());
+}
+
+pub fn fctiwz(gc: &mut Gamecube, instr: &Instruction) {
+    convert_to_integer(gc, instr, 1);
+}
+
+pub(super) fn compare(gc: &mut Gamecube, instr: &Instruction, a: f64, b: f64, ordered: bool) {
+    let fpscr_before = gc.cpu.fpscr.0;
+
+    let cc = if a.is_nan() || b.is_nan() {
+	CC_UN
+    } else if a < b {
+	CC_LT
+    } else if a > b {
+	CC_GT
+    } else {
+	CC_EQ
+    };
+
+    let mut exceptions = 0;
+    if is_snan(a) || is_snan(b) {
+	exceptions |= FPSCR_VXSNAN;
+	//an ordered compare only reports VXVC on a signalling NaN if the invalid trap isn't going to fire
+	if ordered && !gc.cpu.fpscr.ve() {
+	    exceptions |= FPSCR_VXVC;
+	}
+    } else if ordered && cc == CC_UN {
+	exceptions |= FPSCR_VXVC;
+    }
+
+    gc.cpu.fpscr.0 = (gc.cpu.fpscr.0 & !FPSCR_FPCC) | (cc << 12);
+    gc.cpu.cr.set_reg(instr.crd(), cc);
+    gc.cpu.fpscr.raise(exceptions);
+
+    finish(gc, instr, fpscr_before);
+}
+
+pub fn fcmpu(gc: &mut Gamecube, instr: &Instruction) {
+    compare(gc, instr, gc.cpu.fprs[instr.a()].ps0(), gc.cpu.fprs[instr.b()].ps0(), false);
+}
+
+pub fn fcmpo(gc: &mut Gamecube, instr: &Instruction) {
+    compare(gc, instr, gc.cpu.fprs[instr.a()].ps0(), gc.cpu.fprs[instr.b()].ps0(), true);
+}
+
+pub fn fsel(gc: &mut Gamecube, instr: &Instruction) {
+    let a = gc.cpu.fprs[instr.a()].ps0();
+
+    //NaN fails the compare and picks frB
+    gc.cpu.fprs[instr.d()].ps0 = if a >= 0.0 {
+	gc.cpu.fprs[instr.c()].ps0
+    } else {
+	gc.cpu.fprs[instr.b()].ps0
+    };
+
+    if instr.rc() {
+	update_cr1(gc);
+    }
+}
+
+pub fn fneg(gc: &mut Gamecube, instr: &Instruction) {
+    gc.cpu.fprs[instr.d()].ps0 = gc.cpu.fprs[instr.b()].ps0 ^ (1 << 63);
+    if instr.rc() {
+	update_cr1(gc);
+    }
+}
+
+pub fn fabs(gc: &mut Gamecube, instr: &Instruction) {
+    gc.cpu.fprs[instr.d()].ps0 = gc.cpu.fprs[instr.b()].ps0 & !(1 << 63);
+    if instr.rc() {
+	update_cr1(gc);
+    }
+}
+
+pub fn fnabs(gc: &mut Gamecube, instr: &Instruction) {
+    gc.cpu.fprs[instr.d()].ps0 = gc.cpu.fprs[instr.b()].ps0 | (1 << 63);
+    if instr.rc() {
+	update_cr1(gc);
     }
 }
 
 pub fn fmr(gc: &mut Gamecube, instr: &Instruction) {
-    gc.cpu.fprs[instr.d()] = gc.cpu.fprs[instr.b()];
+    gc.cpu.fprs[instr.d()].ps0 = gc.cpu.fprs[instr.b()].ps0;
     if instr.rc() {
-	unimplemented!("cr1");
+	update_cr1(gc);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cpu::{decode::Handler, instr::Instruction, PROGRAM_EXCEPTION, PROGRAM_FLOATING_POINT}, test_gamecube, Gamecube};
+
+    use super::{fadd, fctiw, fctiwz, fdiv, fmul, fres, frsqrte};
+
+    const MSR_FE0: u32 = 1 << 11;
+    const FPSCR_ZE: u32 = 1 << 4;
+    const FPSCR_VE: u32 = 1 << 7;
+
+    //frD=1, frA=2, frB=3, frC=4 under primary 63
+    fn form(xo: u32) -> Instruction {
+	Instruction((63 << 26) | (1 << 21) | (2 << 16) | (3 << 11) | (4 << 6) | (xo << 1))
+    }
+
+    fn run(op: Handler, xo: u32, a: f64, b: f64, c: f64, rn: u32) -> Gamecube {
+	let mut gc = test_gamecube();
+	gc.cpu.fpscr.0 = rn;
+	gc.cpu.fprs[2].set_ps0(a);
+	gc.cpu.fprs[3].set_ps0(b);
+	gc.cpu.fprs[4].set_ps0(c);
+	op(&mut gc, &form(xo));
+	gc
+    }
+
+    #[test]
+    fn exceptions_set_their_sticky_bits_and_fprf() {
+	//1 / 0 is a zero divide, +inf
+	let gc = run(fdiv, 18, 1.0, 0.0, 0.0, 0);
+	assert_eq!(gc.cpu.fprs[1].ps0, 0x7FF0_0000_0000_0000);
+	assert_eq!(gc.cpu.fpscr.0, 0x8400_5000);
+
+	//inf - inf and 0 * inf are invalid, the default NaN
+	let gc = run(fadd, 21, f64::INFINITY, f64::NEG_INFINITY, 0.0, 0);
+	assert_eq!(gc.cpu.fprs[1].ps0, 0x7FF8_0000_0000_0000);
+	assert_eq!(gc.cpu.fpscr.0, 0xA081_1000);
+	let gc = run(fmul, 25, 0.0, 0.0, f64::INFINITY, 0);
+	assert_eq!(gc.cpu.fprs[1].ps0, 0x7FF8_0000_0000_0000);
+	assert_eq!(gc.cpu.fpscr.0, 0xA011_1000);
+
+	//a signalling NaN comes out quieted
+	let gc = run(fadd, 21, f64::from_bits(0x7FF0_0000_0000_0001), 1.0, 0.0, 0);
+	assert_eq!(gc.cpu.fprs[1].ps0, 0x7FF8_0000_0000_0001);
+	assert_eq!(gc.cpu.fpscr.0, 0xA101_1000);
+
+	//1 + 2^-60 is inexact
+	let gc = run(fadd, 21, 1.0, 2f64.powi(-60), 0.0, 0);
+	assert_eq!(gc.cpu.fprs[1].ps0, 0x3FF0_0000_0000_0000);
+	assert_eq!(gc.cpu.fpscr.0, 0x8202_4000);
+
+	//exact results leave FI and FR clear whatever their sign
+	let gc = run(fadd, 21, -2.0, 0.0, 0.0, 0);
+	assert_eq!(gc.cpu.fprs[1].ps0, 0xC000_0000_0000_0000);
+	assert_eq!(gc.cpu.fpscr.0, 0x0000_8000);
+    }
+
+    #[test]
+    fn rounding_modes_pick_the_right_neighbour() {
+	//1 + 0.75ulp and -(1 + 2^-60), with FI always set and FR set when the magnitude went up
+	let cases = [
+	    (0, 1.0, 3.0 * 2f64.powi(-54), 0x3FF0_0000_0000_0001, true),
+	    (1, 1.0, 3.0 * 2f64.powi(-54), 0x3FF0_0000_0000_0000, false),
+	    (2, 1.0, 3.0 * 2f64.powi(-54), 0x3FF0_0000_0000_0001, true),
+	    (3, 1.0, 3.0 * 2f64.powi(-54), 0x3FF0_0000_0000_0000, false),
+	    (0, -1.0, -(2f64.powi(-60)), 0xBFF0_0000_0000_0000, false),
+	    (1, -1.0, -(2f64.powi(-60)), 0xBFF0_0000_0000_0000, false),
+	    (2, -1.0, -(2f64.powi(-60)), 0xBFF0_0000_0000_0000, false),
+	    (3, -1.0, -(2f64.powi(-60)), 0xBFF0_0000_0000_0001, true),
+	];
+
+	for (rn, a, b, bits, fr) in cases {
+	    let gc = run(fadd, 21, a, b, 0.0, rn);
+	    assert_eq!(gc.cpu.fprs[1].ps0, bits, "rn {rn}");
+	    assert!(gc.cpu.fpscr.fi());
+	    assert_eq!(gc.cpu.fpscr.0 & (1 << 18) != 0, fr, "rn {rn}");
+	}
+    }
+
+    #[test]
+    fn estimates_come_from_the_hardware_tables() {
+	let gc = run(fres, 24, 0.0, 1.0, 0.0, 0);
+	assert_eq!(gc.cpu.fprs[1].ps0, 0x3FEF_FF00_0000_0000);
+	assert_eq!(gc.cpu.fprs[1].ps1, 0x3FEF_FF00_0000_0000);
+	let gc = run(fres, 24, 0.0, 2.0, 0.0, 0);
+	assert_eq!(gc.cpu.fprs[1].ps0, 0x3FDF_FF00_0000_0000);
+	let gc = run(fres, 24, 0.0, -0.0, 0.0, 0);
+	assert_eq!(gc.cpu.fprs[1].ps0, 0xFFF0_0000_0000_0000);
+	assert_eq!(gc.cpu.fpscr.0, 0x8400_9000);
+
+	let gc = run(frsqrte, 26, 0.0, 1.0, 0.0, 0);
+	assert_eq!(gc.cpu.fprs[1].ps0, 0x3FEF_FE80_0000_0000);
+	let gc = run(frsqrte, 26, 0.0, 2.0, 0.0, 0);
+	assert_eq!(gc.cpu.fprs[1].ps0, 0x3FE6_9FA0_0000_0000);
+	let gc = run(frsqrte, 26, 0.0, -1.0, 0.0, 0);
+	assert_eq!(gc.cpu.fprs[1].ps0, 0x7FF8_0000_0000_0000);
+	assert_eq!(gc.cpu.fpscr.0, 0xA001_1200);
+    }
+
+    #[test]
+    fn fctiw_saturates_and_turns_nans_into_the_most_negative_integer() {
+	let cases = [
+	    (3e9, 0xFFF8_0000_7FFF_FFFF, 0xA000_0100),
+	    (-3e9, 0xFFF8_0000_8000_0000, 0xA000_0100),
+	    (f64::NAN, 0xFFF8_0000_8000_0000, 0xA000_0100),
+	    (f64::from_bits(0x7FF0_0000_0000_0001), 0xFFF8_0000_8000_0000, 0xA100_0100),
+	    //ties go to even in round to nearest
+	    (2.5, 0xFFF8_0000_0000_0002, 0x8202_0000),
+	    (-0.0, 0xFFF8_0001_0000_0000, 0),
+	];
+	for (b, bits, fpscr) in cases {
+	    let gc = run(fctiw, 14, 0.0, b, 0.0, 0);
+	    assert_eq!(gc.cpu.fprs[1].ps0, bits, "{b}");
+	    assert_eq!(gc.cpu.fpscr.0, fpscr, "{b}");
+	}
+
+	let gc = run(fctiwz, 15, 0.0, -2.5, 0.0, 0);
+	assert_eq!(gc.cpu.fprs[1].ps0, 0xFFF8_0000_FFFF_FFFE);
+	assert_eq!(gc.cpu.fpscr.0, 0x8202_0000);
+
+	//an enabled invalid exception leaves frD alone
+	let gc = run(fctiw, 14, 0.0, f64::NAN, 0.0, FPSCR_VE);
+	assert_eq!(gc.cpu.fprs[1].ps0, 0);
+	assert_eq!(gc.cpu.fpscr.0, 0xE000_0180);
+    }
+
+    #[test]
+    fn enabled_exceptions_trap_only_with_fe0_or_fe1() {
+	let mut gc = test_gamecube();
+	gc.cpu.fpscr.0 = FPSCR_ZE;
+	gc.cpu.fprs[2].set_ps0(1.0);
+	gc.cpu.fprs[3].set_ps0(0.0);
+	fdiv(&mut gc, &form(18));
+	assert_eq!(gc.cpu.fpscr.0, 0xC400_0010);
+	assert_eq!(gc.cpu.exceptions, 0);
+
+	let mut gc = test_gamecube();
+	gc.cpu.msr.0 |= MSR_FE0;
+	gc.cpu.fpscr.0 = FPSCR_ZE;
+	gc.cpu.fprs[2].set_ps0(1.0);
+	gc.cpu.fprs[3].set_ps0(0.0);
+	fdiv(&mut gc, &form(18));
+	assert_eq!(gc.cpu.exceptions, PROGRAM_EXCEPTION);
+	assert_eq!(gc.cpu.srr1_flags, PROGRAM_FLOATING_POINT);
+	//the result is suppressed
+	assert_eq!(gc.cpu.fprs[1].ps0, 0);
+
+	//FEX was already up, so the handler doesn't get called again for the same exception
+	gc.cpu.exceptions = 0;
+	fdiv(&mut gc, &form(18));
+	assert_eq!(gc.cpu.exceptions, 0);
     }
 }
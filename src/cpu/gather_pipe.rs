@@ -0,0 +1,131 @@
+use crate::{processor_interface::{fifo_write, FIFO_BURST_SIZE}, Gamecube};
+
+//the pipe holds four bursts worth, but it starts sending as soon as one is full so it never gets past the first
+const PIPE_SIZE: usize = 0x80;
+
+//WPAR[BNE], reads back as set while there's a partial burst waiting
+const WPAR_BNE: u32 = 0x1;
+const WPAR_ADDR_MASK: u32 = !0x1F;
+
+//with HID2[WPE] set, uncached stores to the WPAR address get packed together and go out as 32 byte bursts.
+//nothing else on the bus takes writes at that address, without the pipe they're bus errors
+pub struct GatherPipe {
+    buffer: [u8; PIPE_SIZE],
+    len: usize,
+}
+
+impl GatherPipe {
+    pub fn new() -> Self {
+	Self {
+	    buffer: [0; PIPE_SIZE],
+	    len: 0,
+	}
+    }
+
+    pub fn is_empty(&self) -> bool {
+	self.len == 0
+    }
+}
+
+impl Default for GatherPipe {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+//true if the store was taken by the pipe, in which case it never reaches the bus on its own
+pub fn try_write(gc: &mut Gamecube, phys: u32, bytes: &[u8]) -> bool {
+    if !gc.cpu.hid2.wpe() || phys & WPAR_ADDR_MASK != gc.cpu.wpar {
+	return false;
+    }
+
+    let pipe = &mut gc.cpu.gather_pipe;
+    pipe.buffer[pipe.len..(pipe.len + bytes.len())].copy_from_slice(bytes);
+    pipe.len += bytes.len();
+
+    if pipe.len >= FIFO_BURST_SIZE {
+	let burst: [u8; FIFO_BURST_SIZE] = pipe.buffer[..FIFO_BURST_SIZE].try_into().unwrap();
+	pipe.buffer.copy_within(FIFO_BURST_SIZE..pipe.len, 0);
+	pipe.len -= FIFO_BURST_SIZE;
+	fifo_write(gc, &burst);
+    }
+
+    true
+}
+
+//anything half gathered is thrown away when the address changes
+pub fn write_wpar(gc: &mut Gamecube, val: u32) {
+    gc.cpu.wpar = val & WPAR_ADDR_MASK;
+    gc.cpu.gather_pipe.len = 0;
+}
+
+pub fn read_wpar(gc: &Gamecube) -> u32 {
+    if gc.cpu.gather_pipe.is_empty() {
+	gc.cpu.wpar
+    } else {
+	gc.cpu.wpar | WPAR_BNE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{bus::BusErrorPolicy, processor_interface::FIFO_WRAP, test_gamecube, Gamecube};
+
+    use super::{read_wpar, write_wpar, WPAR_BNE};
+
+    const HID2_WPE: u32 = 1 << 30;
+    const WPAR: u32 = 0x0C00_8000;
+
+    //a fifo with room for two bursts at 0x1000
+    fn setup() -> Gamecube {
+	let mut gc = test_gamecube();
+	gc.cpu.hid2.0 = HID2_WPE;
+	write_wpar(&mut gc, WPAR);
+	gc.pi.fifo_base = 0x1000;
+	gc.pi.fifo_end = 0x1020;
+	gc.pi.fifo_wptr = 0x1000;
+	gc
+    }
+
+    #[test]
+    fn stores_are_gathered_into_32_byte_bursts() {
+	let mut gc = setup();
+
+	for i in 0..7 {
+	    gc.write_u32(WPAR, 0x1111_1111 * i).unwrap();
+	}
+	gc.write_u16(WPAR, 0xAAAA).unwrap();
+	assert_eq!(read_wpar(&gc), WPAR | WPAR_BNE);
+	assert_eq!(gc.pi.fifo_wptr, 0x1000);
+	assert!(gc.memory[0x1000..0x1020].iter().all(|&byte| byte == 0));
+
+	//the burst goes out once it's full, what's left over starts the next one
+	gc.write_u32(WPAR, 0xBBBB_CCCC).unwrap();
+	assert_eq!(gc.pi.fifo_wptr, 0x1020);
+	assert_eq!(gc.memory[0x1000..0x1004], [0x00; 4]);
+	assert_eq!(gc.memory[0x1018..0x1020], [0x66, 0x66, 0x66, 0x66, 0xAA, 0xAA, 0xBB, 0xBB]);
+	assert_eq!(read_wpar(&gc), WPAR | WPAR_BNE);
+
+	//without HID2[WPE] there's nothing at that address
+	gc.cpu.hid2.0 = 0;
+	gc.bus_policy = BusErrorPolicy::Exception;
+	assert!(gc.write_u32(WPAR, 0).is_err());
+    }
+
+    #[test]
+    fn the_write_pointer_wraps_after_the_burst_at_the_end() {
+	let mut gc = setup();
+
+	for i in 0..16 {
+	    gc.write_u32(WPAR, i).unwrap();
+	}
+	assert_eq!(gc.pi.fifo_wptr, 0x1000 | FIFO_WRAP);
+	assert_eq!(gc.memory[0x103C..0x1040], 15u32.to_be_bytes());
+
+	//the flag stays up as the pointer moves on from the base
+	for i in 0..8 {
+	    gc.write_u32(WPAR, i).unwrap();
+	}
+	assert_eq!(gc.pi.fifo_wptr, 0x1020 | FIFO_WRAP);
+    }
+}
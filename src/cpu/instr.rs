@@ -1,3 +1,4 @@
+#[derive(Copy, Clone)]
 pub struct Instruction(pub u32);
 
 impl Instruction {
@@ -26,10 +27,22 @@ impl Instruction {
 	((self.0 >> 1) & 0x3FF) as usize
     }
 
+    //anything touching the fprs, these raise FP unavailable while MSR[FP] is clear
+    pub fn is_fp(&self) -> bool {
+	match self.opcd() {
+	    //dcbz_l is the only non paired single op under primary 4
+	    0b000100 => self.sec_opcd() != 0b1111110110,
+	    0b110000..=0b111001 | 0b111011..=0b111101 | 0b111111 => true,
+	    //lfsx, lfsux, lfdx, lfdux, stfsx, stfsux, stfdx, stfdux, stfiwx
+	    0b011111 => matches!(self.sec_opcd(), 0b1000010111 | 0b1000110111 | 0b1001010111 | 0b1001110111 | 0b1010010111 | 0b1010110111 | 0b1011010111 | 0b1011110111 | 0b1111010111),
+	    _ => false,
+	}
+    }
+
     pub fn spr(&self) -> usize {
 	let spr = ((self.0 >> 11) & 0x3FF) as usize;
 
-	(((spr & 0x1F) << 5) | ((spr >> 5) & 0x1F)) as usize
+	((spr & 0x1F) << 5) | ((spr >> 5) & 0x1F)
     }
 
     pub fn s(&self) -> usize {
@@ -72,6 +85,18 @@ impl Instruction {
 	((self.0 >> 23) & 7) as usize
     }
 
+    pub fn crs(&self) -> usize {
+	((self.0 >> 18) & 7) as usize
+    }
+
+    pub fn c(&self) -> usize {
+	((self.0 >> 6) & 0x1F) as usize
+    }
+
+    pub fn imm(&self) -> u32 {
+	(self.0 >> 12) & 0xF
+    }
+
    
     pub fn lk(&self) -> bool {
 	(self.0 & 1) != 0
@@ -109,6 +134,29 @@ impl Instruction {
 	((self.0 >> 15) & 1) != 0
     }
 
+    //the indexed psq forms keep I and W lower down to make room for rB
+    pub fn ix(&self) -> usize {
+	((self.0 >> 7) & 0x7) as usize
+    }
+
+    pub fn wx(&self) -> bool {
+	((self.0 >> 10) & 1) != 0
+    }
+
+    pub fn crm(&self) -> usize {
+	((self.0 >> 12) & 0xFF) as usize
+    }
+
+    //trap conditions for tw and twi
+    pub fn to(&self) -> usize {
+	((self.0 >> 21) & 0x1F) as usize
+    }
+
+    //byte count for lswi and stswi, 0 means 32
+    pub fn nb(&self) -> usize {
+	((self.0 >> 11) & 0x1F) as usize
+    }
+
     pub fn fm(&self) -> usize {
 	((self.0 >> 17) & 0xFF) as usize
     }
@@ -0,0 +1,555 @@
+use std::mem::offset_of;
+
+use crate::Gamecube;
+
+use super::{emitter::{Alu, Assembler, Cond, Label, Reg, Shift}, fallback, memory_fallback, Site, TlbEntry, TLB_ENTRIES};
+use crate::cpu::{instr::Instruction, util::mask};
+
+//guest registers get pinned to these for the length of a block. the caller saved ones are fine since every
+//call out is already surrounded by a flush and reload
+const ALLOCATABLE: [Reg; 9] = [Reg::Rbp, Reg::Rsi, Reg::Rdi, Reg::R8, Reg::R9, Reg::R10, Reg::R13, Reg::R14, Reg::R15];
+const GC: Reg = Reg::Rbx;
+//how many instructions in the block have had their cycles handed back to the cpu, everything after that is
+//still owed. the count is static along the straight line code but the slow paths settle it early
+const SYNCED: Reg = Reg::R12;
+
+//returned when a call out ended the block early, everything was settled before the call
+pub const BAILED: u32 = u32::MAX;
+
+#[derive(Copy, Clone)]
+enum Width {
+    Byte = 1,
+    Half = 2,
+    Word = 4,
+}
+
+#[derive(Copy, Clone)]
+enum Address {
+    //(rA|0) + d
+    Displacement,
+    //rA + d, written back to rA
+    Update,
+    //(rA|0) + rB
+    Indexed,
+}
+
+fn gpr(n: usize) -> i32 {
+    (offset_of!(Gamecube, cpu.gprs) + n * 4) as i32
+}
+
+fn field(offset: usize) -> i32 {
+    offset as i32
+}
+
+struct Compiler<'a> {
+    asm: Assembler,
+    homes: [Option<Reg>; 32],
+    uses: [u32; 32],
+    sites: &'a [Site],
+    tlb: u64,
+    bail: Label,
+    //memory accesses that missed the fast path, emitted after the body so the straight line code stays together
+    slow_paths: Vec<SlowPath>,
+}
+
+struct SlowPath {
+    entry: Label,
+    resume: Label,
+    index: usize,
+    write: bool,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(sites: &'a [Site], tlb: *const TlbEntry, homes: [Option<Reg>; 32]) -> Self {
+	let mut asm = Assembler::new();
+	let bail = asm.label();
+
+	Self {
+	    asm,
+	    homes,
+	    uses: [0; 32],
+	    sites,
+	    tlb: tlb as u64,
+	    bail,
+	    slow_paths: Vec::new(),
+	}
+    }
+
+    fn get(&mut self, dst: Reg, n: usize) {
+	self.uses[n] += 1;
+	match self.homes[n] {
+	    Some(home) => self.asm.mov(dst, home),
+	    None => self.asm.load(dst, GC, gpr(n)),
+	}
+    }
+
+    //(rA|0) forms
+    fn get_or_zero(&mut self, dst: Reg, n: usize) {
+	if n == 0 {
+	    self.asm.mov_imm(dst, 0);
+	} else {
+	    self.get(dst, n);
+	}
+    }
+
+    fn set(&mut self, n: usize, src: Reg) {
+	self.uses[n] += 1;
+	match self.homes[n] {
+	    Some(home) => self.asm.mov(home, src),
+	    None => self.asm.store(GC, gpr(n), src),
+	}
+    }
+
+    fn flush(&mut self) {
+	for (n, home) in self.homes.into_iter().enumerate() {
+	    if let Some(home) = home {
+		self.asm.store(GC, gpr(n), home);
+	    }
+	}
+    }
+
+    fn reload(&mut self) {
+	for (n, home) in self.homes.into_iter().enumerate() {
+	    if let Some(home) = home {
+		self.asm.load(home, GC, gpr(n));
+	    }
+	}
+    }
+
+    //turns the flags from a cmp or test into a CR field, with SO copied in from XER
+    fn record(&mut self, crf: usize, signed: bool) {
+	let (greater, less) = if signed {
+	    (Cond::Greater, Cond::Less)
+	} else {
+	    (Cond::Above, Cond::Below)
+	};
+
+	//mov leaves the flags alone
+	self.asm.mov_imm(Reg::Rcx, 0b0010);
+	self.asm.mov_imm(Reg::Rdx, 0b0100);
+	self.asm.cmov(greater, Reg::Rcx, Reg::Rdx);
+	self.asm.mov_imm(Reg::Rdx, 0b1000);
+	self.asm.cmov(less, Reg::Rcx, Reg::Rdx);
+	self.asm.load(Reg::Rdx, GC, field(offset_of!(Gamecube, cpu.xer.0)));
+	self.asm.shift_imm(Shift::Shr, Reg::Rdx, 31);
+	self.asm.alu(Alu::Or, Reg::Rcx, Reg::Rdx);
+
+	let shift = (7 - crf) * 4;
+	if shift != 0 {
+	    self.asm.shift_imm(Shift::Shl, Reg::Rcx, shift as u8);
+	}
+	let cr = field(offset_of!(Gamecube, cpu.cr.0));
+	self.asm.load(Reg::Rdx, GC, cr);
+	self.asm.alu_imm(Alu::And, Reg::Rdx, !(0xF000_0000 >> (crf * 4)));
+	self.asm.alu(Alu::Or, Reg::Rdx, Reg::Rcx);
+	self.asm.store(GC, cr, Reg::Rdx);
+    }
+
+    //the result is in eax
+    fn cr0(&mut self, rc: bool) {
+	if rc {
+	    self.asm.test(Reg::Rax, Reg::Rax);
+	    self.record(0, true);
+	}
+    }
+
+    //hands the cycles owed so far and the instruction itself over to the interpreter
+    fn fallback(&mut self, index: usize) {
+	self.flush();
+	self.call_out(index, fallback as *const () as u64);
+	self.asm.test(Reg::Rax, Reg::Rax);
+	self.asm.jcc(Cond::Equal, self.bail);
+	self.asm.mov_imm(SYNCED, index as u32 + 1);
+	self.reload();
+    }
+
+    //rdi is the Gamecube, rsi the site and edx the cycles owed, anything past that is up to the caller
+    fn call_out(&mut self, index: usize, helper: u64) {
+	self.asm.mov64(Reg::Rdi, GC);
+	self.asm.mov_imm64(Reg::Rsi, &raw const self.sites[index] as u64);
+	self.asm.mov_imm(Reg::Rdx, index as u32);
+	self.asm.alu(Alu::Sub, Reg::Rdx, SYNCED);
+	self.asm.mov_imm64(Reg::Rax, helper);
+	self.asm.call(Reg::Rax);
+    }
+
+    fn slow_path(&mut self, path: SlowPath) {
+	self.asm.bind(path.entry);
+	//eax still holds the effective address. r8 might be a guest register until the flush
+	self.flush();
+	self.asm.mov(Reg::Rcx, Reg::Rax);
+	self.asm.mov_imm(Reg::R8, path.write as u32);
+	self.call_out(path.index, memory_fallback as *const () as u64);
+	self.asm.test(Reg::Rax, Reg::Rax);
+	self.asm.jcc(Cond::Equal, self.bail);
+	self.asm.mov_imm(SYNCED, path.index as u32 + 1);
+	self.reload();
+	self.asm.jmp(path.resume);
+    }
+
+    //the fast path only handles accesses that stay inside one ram page the tlb already knows about,
+    //anything else goes out to the interpreter, which also fills the tlb for next time
+    fn memory(&mut self, index: usize, instr: &Instruction, width: Width, address: Address, write: bool) {
+	match address {
+	    Address::Displacement => {
+		self.get_or_zero(Reg::Rax, instr.a());
+		self.asm.alu_imm(Alu::Add, Reg::Rax, i32::from(instr.simm()) as u32);
+	    },
+	    Address::Update => {
+		self.get(Reg::Rax, instr.a());
+		self.asm.alu_imm(Alu::Add, Reg::Rax, i32::from(instr.simm()) as u32);
+	    },
+	    Address::Indexed => {
+		self.get_or_zero(Reg::Rax, instr.a());
+		self.get(Reg::Rcx, instr.b());
+		self.asm.alu(Alu::Add, Reg::Rax, Reg::Rcx);
+	    },
+	}
+
+	let slow = self.asm.label();
+	let resume = self.asm.label();
+
+	//rdx = &tlb[(ea >> 12) % TLB_ENTRIES]
+	self.asm.mov(Reg::Rcx, Reg::Rax);
+	self.asm.shift_imm(Shift::Shr, Reg::Rcx, 12);
+	self.asm.alu_imm(Alu::And, Reg::Rcx, TLB_ENTRIES as u32 - 1);
+	self.asm.shift_imm(Shift::Shl, Reg::Rcx, size_of::<TlbEntry>().trailing_zeros() as u8);
+	self.asm.mov_imm64(Reg::Rdx, self.tlb);
+	self.asm.alu64(Alu::Add, Reg::Rdx, Reg::Rcx);
+
+	self.asm.mov(Reg::Rcx, Reg::Rax);
+	self.asm.alu_imm(Alu::And, Reg::Rcx, 0xFFFF_F000);
+	self.asm.alu_imm(Alu::Or, Reg::Rcx, 1);
+	let tag = if write {
+	    offset_of!(TlbEntry, write_tag)
+	} else {
+	    offset_of!(TlbEntry, read_tag)
+	};
+	self.asm.alu_mem(Alu::Cmp, Reg::Rcx, Reg::Rdx, field(tag));
+	self.asm.jcc(Cond::NotEqual, slow);
+
+	self.asm.mov(Reg::Rcx, Reg::Rax);
+	self.asm.alu_imm(Alu::And, Reg::Rcx, 0xFFF);
+	self.asm.alu_imm(Alu::Cmp, Reg::Rcx, 0x1000 - width as u32);
+	self.asm.jcc(Cond::Above, slow);
+
+	//a store into a page with decoded instructions in it has to go the long way round and invalidate them
+	if write {
+	    self.asm.load64(Reg::R11, Reg::Rdx, field(offset_of!(TlbEntry, code)));
+	    self.asm.cmp_mem64_zero(Reg::R11, 0);
+	    self.asm.jcc(Cond::NotEqual, slow);
+	}
+	self.asm.load64(Reg::Rdx, Reg::Rdx, field(offset_of!(TlbEntry, host)));
+
+	if write {
+	    self.get(Reg::R11, instr.s());
+	    match width {
+		Width::Byte => self.asm.store_sib_u8(Reg::Rdx, Reg::Rcx, Reg::R11),
+		Width::Half => {
+		    self.asm.rol16_8(Reg::R11);
+		    self.asm.store_sib_u16(Reg::Rdx, Reg::Rcx, Reg::R11);
+		},
+		Width::Word => {
+		    self.asm.bswap(Reg::R11);
+		    self.asm.store_sib(Reg::Rdx, Reg::Rcx, Reg::R11);
+		},
+	    }
+	    if let Address::Update = address {
+		self.set(instr.a(), Reg::Rax);
+	    }
+	} else {
+	    self.asm.mov(Reg::R11, Reg::Rax);
+	    match width {
+		Width::Byte => self.asm.load_sib_u8(Reg::Rax, Reg::Rdx, Reg::Rcx),
+		Width::Half => {
+		    self.asm.load_sib_u16(Reg::Rax, Reg::Rdx, Reg::Rcx);
+		    self.asm.rol16_8(Reg::Rax);
+		},
+		Width::Word => {
+		    self.asm.load_sib(Reg::Rax, Reg::Rdx, Reg::Rcx);
+		    self.asm.bswap(Reg::Rax);
+		},
+	    }
+	    self.set(instr.d(), Reg::Rax);
+	    if let Address::Update = address {
+		self.set(instr.a(), Reg::R11);
+	    }
+	}
+
+	self.asm.bind(resume);
+	self.slow_paths.push(SlowPath {
+	    entry: slow,
+	    resume,
+	    index,
+	    write,
+	});
+    }
+
+    fn alu_rr(&mut self, instr: &Instruction, op: Alu) {
+	self.get(Reg::Rax, instr.s());
+	self.get(Reg::Rcx, instr.b());
+	self.asm.alu(op, Reg::Rax, Reg::Rcx);
+    }
+
+    fn alu_imm(&mut self, instr: &Instruction, op: Alu, imm: u32) {
+	self.get(Reg::Rax, instr.s());
+	self.asm.alu_imm(op, Reg::Rax, imm);
+	self.set(instr.a(), Reg::Rax);
+    }
+
+    fn rotate(&mut self, instr: &Instruction) {
+	self.get(Reg::Rax, instr.s());
+	if instr.sh() != 0 {
+	    self.asm.shift_imm(Shift::Rol, Reg::Rax, instr.sh() as u8);
+	}
+	self.asm.alu_imm(Alu::And, Reg::Rax, mask(instr.mb(), instr.me()));
+    }
+
+    //false leaves the instruction to the interpreter
+    fn instruction(&mut self, index: usize, instr: &Instruction) -> bool {
+	match instr.opcd() {
+	    //mulli
+	    0b000111 => {
+		self.get(Reg::Rax, instr.a());
+		self.asm.imul_imm(Reg::Rax, Reg::Rax, i32::from(instr.simm()) as u32);
+		self.set(instr.d(), Reg::Rax);
+	    },
+	    //cmpli
+	    0b001010 => {
+		self.get(Reg::Rax, instr.a());
+		self.asm.alu_imm(Alu::Cmp, Reg::Rax, u32::from(instr.uimm()));
+		self.record(instr.crd(), false);
+	    },
+	    //cmpi
+	    0b001011 => {
+		self.get(Reg::Rax, instr.a());
+		self.asm.alu_imm(Alu::Cmp, Reg::Rax, i32::from(instr.simm()) as u32);
+		self.record(instr.crd(), true);
+	    },
+	    //addi
+	    0b001110 => {
+		self.get_or_zero(Reg::Rax, instr.a());
+		self.asm.alu_imm(Alu::Add, Reg::Rax, i32::from(instr.simm()) as u32);
+		self.set(instr.d(), Reg::Rax);
+	    },
+	    //addis
+	    0b001111 => {
+		self.get_or_zero(Reg::Rax, instr.a());
+		self.asm.alu_imm(Alu::Add, Reg::Rax, u32::from(instr.uimm()) << 16);
+		self.set(instr.d(), Reg::Rax);
+	    },
+	    //rlwimi
+	    0b010100 => {
+		self.rotate(instr);
+		self.get(Reg::Rcx, instr.a());
+		self.asm.alu_imm(Alu::And, Reg::Rcx, !mask(instr.mb(), instr.me()));
+		self.asm.alu(Alu::Or, Reg::Rax, Reg::Rcx);
+		self.set(instr.a(), Reg::Rax);
+		self.cr0(instr.rc());
+	    },
+	    //rlwinm
+	    0b010101 => {
+		self.rotate(instr);
+		self.set(instr.a(), Reg::Rax);
+		self.cr0(instr.rc());
+	    },
+	    0b011000 => self.alu_imm(instr, Alu::Or, u32::from(instr.uimm())),
+	    0b011001 => self.alu_imm(instr, Alu::Or, u32::from(instr.uimm()) << 16),
+	    0b011010 => self.alu_imm(instr, Alu::Xor, u32::from(instr.uimm())),
+	    0b011011 => self.alu_imm(instr, Alu::Xor, u32::from(instr.uimm()) << 16),
+	    //andi. and andis. always record
+	    0b011100 => {
+		self.alu_imm(instr, Alu::And, u32::from(instr.uimm()));
+		self.cr0(true);
+	    },
+	    0b011101 => {
+		self.alu_imm(instr, Alu::And, u32::from(instr.uimm()) << 16);
+		self.cr0(true);
+	    },
+	    0b100000 => self.memory(index, instr, Width::Word, Address::Displacement, false),
+	    //lwzu is invalid with rA = 0 or rA = rD, the interpreter raises that
+	    0b100001 if instr.a() != 0 && instr.a() != instr.d() => self.memory(index, instr, Width::Word, Address::Update, false),
+	    0b100010 => self.memory(index, instr, Width::Byte, Address::Displacement, false),
+	    0b101000 => self.memory(index, instr, Width::Half, Address::Displacement, false),
+	    0b100100 => self.memory(index, instr, Width::Word, Address::Displacement, true),
+	    0b100101 => self.memory(index, instr, Width::Word, Address::Update, true),
+	    0b100110 => self.memory(index, instr, Width::Byte, Address::Displacement, true),
+	    0b101100 => self.memory(index, instr, Width::Half, Address::Displacement, true),
+	    0b011111 => return self.extended(index, instr),
+	    _ => return false,
+	}
+
+	true
+    }
+
+    //primary opcode 31. the XO forms only match with OE clear, overflow is left to the interpreter
+    fn extended(&mut self, index: usize, instr: &Instruction) -> bool {
+	match instr.sec_opcd() {
+	    //cmp, cmpl
+	    0b0000000000 | 0b0000100000 => {
+		self.get(Reg::Rax, instr.a());
+		self.get(Reg::Rcx, instr.b());
+		self.asm.alu(Alu::Cmp, Reg::Rax, Reg::Rcx);
+		self.record(instr.crd(), instr.sec_opcd() == 0);
+	    },
+	    //lwzx, stwx
+	    0b0000010111 => self.memory(index, instr, Width::Word, Address::Indexed, false),
+	    0b0010010111 => self.memory(index, instr, Width::Word, Address::Indexed, true),
+	    //slw, srw
+	    0b0000011000 | 0b1000011000 => {
+		self.get(Reg::Rax, instr.s());
+		self.get(Reg::Rcx, instr.b());
+		let op = if instr.sec_opcd() == 0b0000011000 {
+		    Shift::Shl
+		} else {
+		    Shift::Shr
+		};
+		//done 64 bits wide on the zero extended value, so the count is masked to 6 bits like the
+		//ppc's and anything from 32 up leaves the low half 0
+		self.asm.shift64_cl(op, Reg::Rax);
+		self.set(instr.a(), Reg::Rax);
+		self.cr0(instr.rc());
+	    },
+	    //and, andc, nor, xor, or
+	    0b0000011100 | 0b0000111100 | 0b0001111100 | 0b0100111100 | 0b0110111100 => {
+		match instr.sec_opcd() {
+		    0b0000011100 => self.alu_rr(instr, Alu::And),
+		    0b0000111100 => {
+			self.get(Reg::Rax, instr.s());
+			self.get(Reg::Rcx, instr.b());
+			self.asm.not(Reg::Rcx);
+			self.asm.alu(Alu::And, Reg::Rax, Reg::Rcx);
+		    },
+		    0b0001111100 => {
+			self.alu_rr(instr, Alu::Or);
+			self.asm.not(Reg::Rax);
+		    },
+		    0b0100111100 => self.alu_rr(instr, Alu::Xor),
+		    _ => self.alu_rr(instr, Alu::Or),
+		}
+		self.set(instr.a(), Reg::Rax);
+		self.cr0(instr.rc());
+	    },
+	    //subf is rB - rA
+	    0b0000101000 => {
+		self.get(Reg::Rax, instr.b());
+		self.get(Reg::Rcx, instr.a());
+		self.asm.alu(Alu::Sub, Reg::Rax, Reg::Rcx);
+		self.set(instr.d(), Reg::Rax);
+		self.cr0(instr.rc());
+	    },
+	    //neg
+	    0b0001101000 => {
+		self.get(Reg::Rax, instr.a());
+		self.asm.neg(Reg::Rax);
+		self.set(instr.d(), Reg::Rax);
+		self.cr0(instr.rc());
+	    },
+	    //mullw, add
+	    0b0011101011 | 0b0100001010 => {
+		self.get(Reg::Rax, instr.a());
+		self.get(Reg::Rcx, instr.b());
+		if instr.sec_opcd() == 0b0011101011 {
+		    self.asm.imul(Reg::Rax, Reg::Rcx);
+		} else {
+		    self.asm.alu(Alu::Add, Reg::Rax, Reg::Rcx);
+		}
+		self.set(instr.d(), Reg::Rax);
+		self.cr0(instr.rc());
+	    },
+	    //mfspr and mtspr, only for the unprivileged registers that don't end a block
+	    0b0101010011 | 0b0111010011 => {
+		let offset = match instr.spr() {
+		    0b00000_00001 => offset_of!(Gamecube, cpu.xer.0),
+		    0b00000_01000 => offset_of!(Gamecube, cpu.lr),
+		    0b00000_01001 => offset_of!(Gamecube, cpu.ctr),
+		    _ => return false,
+		};
+		if instr.sec_opcd() == 0b0101010011 {
+		    self.asm.load(Reg::Rax, GC, field(offset));
+		    self.set(instr.d(), Reg::Rax);
+		} else {
+		    self.get(Reg::Rax, instr.s());
+		    self.asm.store(GC, field(offset), Reg::Rax);
+		}
+	    },
+	    //extsh, extsb
+	    0b1110011010 | 0b1110111010 => {
+		self.get(Reg::Rax, instr.s());
+		if instr.sec_opcd() == 0b1110011010 {
+		    self.asm.movsx16(Reg::Rax, Reg::Rax);
+		} else {
+		    self.asm.movsx8(Reg::Rax, Reg::Rax);
+		}
+		self.set(instr.a(), Reg::Rax);
+		self.cr0(instr.rc());
+	    },
+	    _ => return false,
+	}
+
+	true
+    }
+
+    fn body(&mut self, instrs: &[Site]) {
+	self.asm.push(Reg::Rbx);
+	self.asm.push(Reg::Rbp);
+	self.asm.push(Reg::R12);
+	self.asm.push(Reg::R13);
+	self.asm.push(Reg::R14);
+	self.asm.push(Reg::R15);
+	//keeps the stack 16 byte aligned for the calls out
+	self.asm.push(Reg::Rax);
+	self.asm.mov64(GC, Reg::Rdi);
+	self.asm.mov_imm(SYNCED, 0);
+	self.reload();
+
+	for (index, site) in instrs.iter().enumerate() {
+	    if !self.instruction(index, &site.decoded.instr) {
+		self.fallback(index);
+	    }
+	}
+
+	//what's left owing goes back to the caller to tick
+	let done = self.asm.label();
+	self.flush();
+	self.asm.mov_imm(Reg::Rax, instrs.len() as u32);
+	self.asm.alu(Alu::Sub, Reg::Rax, SYNCED);
+	self.asm.jmp(done);
+
+	for path in std::mem::take(&mut self.slow_paths) {
+	    self.slow_path(path);
+	}
+
+	self.asm.bind(self.bail);
+	self.asm.mov_imm(Reg::Rax, BAILED);
+
+	self.asm.bind(done);
+	self.asm.pop(Reg::Rcx);
+	self.asm.pop(Reg::R15);
+	self.asm.pop(Reg::R14);
+	self.asm.pop(Reg::R13);
+	self.asm.pop(Reg::R12);
+	self.asm.pop(Reg::Rbp);
+	self.asm.pop(Reg::Rbx);
+	self.asm.ret();
+    }
+}
+
+//compiles one straight line run of instructions, whatever ends the block is left for the caller to execute.
+//it's done twice, the first time just to find out which guest registers are worth keeping in host registers
+pub fn compile(sites: &[Site], tlb: *const TlbEntry) -> Vec<u8> {
+    let mut counting = Compiler::new(sites, tlb, [None; 32]);
+    counting.body(sites);
+
+    let mut by_use = (0..32).filter(|&n| counting.uses[n] != 0).collect::<Vec<_>>();
+    by_use.sort_by_key(|&n| std::cmp::Reverse(counting.uses[n]));
+
+    let mut homes = [None; 32];
+    for (&n, &reg) in by_use.iter().zip(ALLOCATABLE.iter()) {
+	homes[n] = Some(reg);
+    }
+
+    let mut compiler = Compiler::new(sites, tlb, homes);
+    compiler.body(sites);
+    compiler.asm.finish()
+}
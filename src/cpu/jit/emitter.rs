@@ -0,0 +1,312 @@
+//just enough of an x86-64 assembler for the recompiler. everything is 32 bit unless the name says otherwise,
+//and memory operands are always [base + disp32] or [base + index]
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Reg {
+    Rax,
+    Rcx,
+    Rdx,
+    Rbx,
+    Rsp,
+    Rbp,
+    Rsi,
+    Rdi,
+    R8,
+    R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
+}
+
+impl Reg {
+    fn low(self) -> u8 {
+	self as u8 & 7
+    }
+
+    fn high(self) -> bool {
+	self as u8 >= 8
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Cond {
+    Equal = 0x4,
+    NotEqual = 0x5,
+    Above = 0x7,
+    Below = 0x2,
+    Less = 0xC,
+    Greater = 0xF,
+}
+
+//the /digit of the 0x81 group, and opcode / 8 of the register forms
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Alu {
+    Add = 0,
+    Or = 1,
+    And = 4,
+    Sub = 5,
+    Xor = 6,
+    Cmp = 7,
+}
+
+//the /digit of the 0xC1 and 0xD3 groups
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Shift {
+    Rol = 0,
+    Shl = 4,
+    Shr = 5,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Label(usize);
+
+pub struct Assembler {
+    code: Vec<u8>,
+    labels: Vec<Option<usize>>,
+    //offset of each rel32 and the label it points at
+    fixups: Vec<(usize, Label)>,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+	Self {
+	    code: Vec::new(),
+	    labels: Vec::new(),
+	    fixups: Vec::new(),
+	}
+    }
+
+    //resolves every jump, the result is position independent
+    pub fn finish(mut self) -> Vec<u8> {
+	for (at, label) in self.fixups {
+	    let target = self.labels[label.0].expect("jump to a label that was never bound");
+	    let rel = target as i32 - (at as i32 + 4);
+	    self.code[at..(at + 4)].copy_from_slice(&rel.to_le_bytes());
+	}
+
+	self.code
+    }
+
+    pub fn label(&mut self) -> Label {
+	self.labels.push(None);
+	Label(self.labels.len() - 1)
+    }
+
+    pub fn bind(&mut self, label: Label) {
+	self.labels[label.0] = Some(self.code.len());
+    }
+
+    fn byte(&mut self, byte: u8) {
+	self.code.push(byte);
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+	self.code.extend_from_slice(bytes);
+    }
+
+    //force is for byte registers, where spl..dil only exist with some REX prefix present
+    fn rex(&mut self, w: bool, reg: bool, index: bool, base: bool, force: bool) {
+	let rex = 0x40 | ((w as u8) << 3) | ((reg as u8) << 2) | ((index as u8) << 1) | (base as u8);
+	if rex != 0x40 || force {
+	    self.byte(rex);
+	}
+    }
+
+    //reg is either a register number or the /digit extending the opcode
+    fn op_rr(&mut self, w: bool, opcode: &[u8], reg: u8, rm: Reg) {
+	self.rex(w, reg >= 8, false, rm.high(), false);
+	self.bytes(opcode);
+	self.byte(0xC0 | ((reg & 7) << 3) | rm.low());
+    }
+
+    //[base + disp32], with a SIB byte when the base needs one
+    fn op_mem(&mut self, w: bool, opcode: &[u8], reg: u8, reg_high: bool, base: Reg, disp: i32) {
+	self.rex(w, reg_high, false, base.high(), false);
+	self.bytes(opcode);
+	self.byte(0x80 | ((reg & 7) << 3) | base.low());
+	if base.low() == 4 {
+	    self.byte(0x24);
+	}
+	self.bytes(&disp.to_le_bytes());
+    }
+
+    //[base + index], base can't be rbp or r13 since that encoding means disp32 with no base
+    fn op_sib(&mut self, w: bool, opcode: &[u8], reg: Reg, base: Reg, index: Reg, force: bool) {
+	debug_assert!(base.low() != 5 && index != Reg::Rsp);
+	self.rex(w, reg.high(), index.high(), base.high(), force);
+	self.bytes(opcode);
+	self.byte((reg.low() << 3) | 4);
+	self.byte((index.low() << 3) | base.low());
+    }
+
+    pub fn mov(&mut self, dst: Reg, src: Reg) {
+	self.op_rr(false, &[0x89], src as u8, dst);
+    }
+
+    pub fn mov64(&mut self, dst: Reg, src: Reg) {
+	self.op_rr(true, &[0x89], src as u8, dst);
+    }
+
+    pub fn mov_imm(&mut self, dst: Reg, imm: u32) {
+	self.rex(false, false, false, dst.high(), false);
+	self.byte(0xB8 + dst.low());
+	self.bytes(&imm.to_le_bytes());
+    }
+
+    pub fn mov_imm64(&mut self, dst: Reg, imm: u64) {
+	self.rex(true, false, false, dst.high(), false);
+	self.byte(0xB8 + dst.low());
+	self.bytes(&imm.to_le_bytes());
+    }
+
+    pub fn load(&mut self, dst: Reg, base: Reg, disp: i32) {
+	self.op_mem(false, &[0x8B], dst.low(), dst.high(), base, disp);
+    }
+
+    pub fn load64(&mut self, dst: Reg, base: Reg, disp: i32) {
+	self.op_mem(true, &[0x8B], dst.low(), dst.high(), base, disp);
+    }
+
+    pub fn store(&mut self, base: Reg, disp: i32, src: Reg) {
+	self.op_mem(false, &[0x89], src.low(), src.high(), base, disp);
+    }
+
+    pub fn alu(&mut self, op: Alu, dst: Reg, src: Reg) {
+	self.op_rr(false, &[(op as u8) * 8 + 1], src as u8, dst);
+    }
+
+    pub fn alu64(&mut self, op: Alu, dst: Reg, src: Reg) {
+	self.op_rr(true, &[(op as u8) * 8 + 1], src as u8, dst);
+    }
+
+    pub fn alu_imm(&mut self, op: Alu, dst: Reg, imm: u32) {
+	self.op_rr(false, &[0x81], op as u8, dst);
+	self.bytes(&imm.to_le_bytes());
+    }
+
+    //reg op [base + disp32]
+    pub fn alu_mem(&mut self, op: Alu, dst: Reg, base: Reg, disp: i32) {
+	self.op_mem(false, &[(op as u8) * 8 + 3], dst.low(), dst.high(), base, disp);
+    }
+
+    //cmp qword [base + disp32], 0
+    pub fn cmp_mem64_zero(&mut self, base: Reg, disp: i32) {
+	self.op_mem(true, &[0x83], 7, false, base, disp);
+	self.byte(0);
+    }
+
+    pub fn shift_imm(&mut self, op: Shift, dst: Reg, imm: u8) {
+	self.op_rr(false, &[0xC1], op as u8, dst);
+	self.byte(imm);
+    }
+
+    //the count comes from cl, masked to 6 bits by the hardware
+    pub fn shift64_cl(&mut self, op: Shift, dst: Reg) {
+	self.op_rr(true, &[0xD3], op as u8, dst);
+    }
+
+    pub fn not(&mut self, dst: Reg) {
+	self.op_rr(false, &[0xF7], 2, dst);
+    }
+
+    pub fn neg(&mut self, dst: Reg) {
+	self.op_rr(false, &[0xF7], 3, dst);
+    }
+
+    pub fn imul(&mut self, dst: Reg, src: Reg) {
+	self.op_rr(false, &[0x0F, 0xAF], dst as u8, src);
+    }
+
+    pub fn imul_imm(&mut self, dst: Reg, src: Reg, imm: u32) {
+	self.op_rr(false, &[0x69], dst as u8, src);
+	self.bytes(&imm.to_le_bytes());
+    }
+
+    pub fn movsx8(&mut self, dst: Reg, src: Reg) {
+	self.rex(false, dst.high(), false, src.high(), src as u8 >= 4);
+	self.bytes(&[0x0F, 0xBE]);
+	self.byte(0xC0 | (dst.low() << 3) | src.low());
+    }
+
+    pub fn movsx16(&mut self, dst: Reg, src: Reg) {
+	self.op_rr(false, &[0x0F, 0xBF], dst as u8, src);
+    }
+
+    pub fn test(&mut self, a: Reg, b: Reg) {
+	self.op_rr(false, &[0x85], b as u8, a);
+    }
+
+    pub fn cmov(&mut self, cond: Cond, dst: Reg, src: Reg) {
+	self.op_rr(false, &[0x0F, 0x40 + cond as u8], dst as u8, src);
+    }
+
+    pub fn bswap(&mut self, reg: Reg) {
+	self.rex(false, false, false, reg.high(), false);
+	self.bytes(&[0x0F, 0xC8 + reg.low()]);
+    }
+
+    //swaps the bytes of the low 16 bits
+    pub fn rol16_8(&mut self, reg: Reg) {
+	self.byte(0x66);
+	self.op_rr(false, &[0xC1], Shift::Rol as u8, reg);
+	self.byte(8);
+    }
+
+    pub fn load_sib(&mut self, dst: Reg, base: Reg, index: Reg) {
+	self.op_sib(false, &[0x8B], dst, base, index, false);
+    }
+
+    pub fn load_sib_u16(&mut self, dst: Reg, base: Reg, index: Reg) {
+	self.op_sib(false, &[0x0F, 0xB7], dst, base, index, false);
+    }
+
+    pub fn load_sib_u8(&mut self, dst: Reg, base: Reg, index: Reg) {
+	self.op_sib(false, &[0x0F, 0xB6], dst, base, index, false);
+    }
+
+    pub fn store_sib(&mut self, base: Reg, index: Reg, src: Reg) {
+	self.op_sib(false, &[0x89], src, base, index, false);
+    }
+
+    pub fn store_sib_u16(&mut self, base: Reg, index: Reg, src: Reg) {
+	self.byte(0x66);
+	self.op_sib(false, &[0x89], src, base, index, false);
+    }
+
+    pub fn store_sib_u8(&mut self, base: Reg, index: Reg, src: Reg) {
+	self.op_sib(false, &[0x88], src, base, index, src as u8 >= 4);
+    }
+
+    pub fn jcc(&mut self, cond: Cond, label: Label) {
+	self.bytes(&[0x0F, 0x80 + cond as u8]);
+	self.fixups.push((self.code.len(), label));
+	self.bytes(&[0; 4]);
+    }
+
+    pub fn jmp(&mut self, label: Label) {
+	self.byte(0xE9);
+	self.fixups.push((self.code.len(), label));
+	self.bytes(&[0; 4]);
+    }
+
+    pub fn call(&mut self, target: Reg) {
+	self.op_rr(false, &[0xFF], 2, target);
+    }
+
+    pub fn push(&mut self, reg: Reg) {
+	self.rex(false, false, false, reg.high(), false);
+	self.byte(0x50 + reg.low());
+    }
+
+    pub fn pop(&mut self, reg: Reg) {
+	self.rex(false, false, false, reg.high(), false);
+	self.byte(0x58 + reg.low());
+    }
+
+    pub fn ret(&mut self) {
+	self.byte(0xC3);
+    }
+}
@@ -0,0 +1,67 @@
+use std::{ffi::c_void, ptr};
+
+const PROT_READ: i32 = 1;
+const PROT_WRITE: i32 = 2;
+const PROT_EXEC: i32 = 4;
+const MAP_PRIVATE: i32 = 2;
+#[cfg(target_os = "linux")]
+const MAP_ANONYMOUS: i32 = 0x20;
+#[cfg(not(target_os = "linux"))]
+const MAP_ANONYMOUS: i32 = 0x1000;
+
+//std already links libc, so there's no point pulling in a crate for two functions
+unsafe extern "C" {
+    fn mmap(addr: *mut c_void, len: usize, prot: i32, flags: i32, fd: i32, offset: i64) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> i32;
+}
+
+//one big mapping that compiled blocks are bumped out of, everything goes at once when it fills up
+pub struct CodeBuffer {
+    base: *mut u8,
+    size: usize,
+    used: usize,
+}
+
+impl CodeBuffer {
+    pub fn new(size: usize) -> Self {
+	let base = unsafe { mmap(ptr::null_mut(), size, PROT_READ | PROT_WRITE | PROT_EXEC, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0) };
+	//MAP_FAILED
+	if base as isize == -1 {
+	    panic!("couldn't map {size:#X} bytes of executable memory for the jit");
+	}
+
+	Self {
+	    base: base.cast(),
+	    size,
+	    used: 0,
+	}
+    }
+
+    //None once it's full, the caller has to throw every block away and reset
+    pub fn push(&mut self, code: &[u8]) -> Option<*const u8> {
+	//keep entry points 16 byte aligned
+	let start = self.used.next_multiple_of(16);
+	if start + code.len() > self.size {
+	    return None;
+	}
+
+	unsafe {
+	    ptr::copy_nonoverlapping(code.as_ptr(), self.base.add(start), code.len());
+	}
+	self.used = start + code.len();
+
+	Some(unsafe { self.base.add(start) })
+    }
+
+    pub fn reset(&mut self) {
+	self.used = 0;
+    }
+}
+
+impl Drop for CodeBuffer {
+    fn drop(&mut self) {
+	unsafe {
+	    munmap(self.base.cast(), self.size);
+	}
+    }
+}
@@ -0,0 +1,278 @@
+mod compiler;
+mod emitter;
+mod memory;
+
+use std::{collections::HashMap, mem};
+
+use compiler::BAILED;
+use memory::CodeBuffer;
+
+use crate::{Gamecube, RAM_SIZE};
+
+use super::{abort_instruction, block, decode::{DecodeCache, Decoded}, mmu::{Access, Bat}, ASYNCHRONOUS_EXCEPTIONS, CYCLES_PER_TIMEBASE_TICK};
+
+const CODE_BUFFER_SIZE: usize = 0x200_0000;
+//has to be a power of two, the compiled code masks the page number with it
+const TLB_ENTRIES: usize = 0x400;
+//code that keeps getting written over costs more to compile than it saves, past this it's left to the block cache
+const MAX_COMPILES: u32 = 8;
+
+//takes the Gamecube, returns how many instructions still need ticking or BAILED
+type BlockFn = unsafe extern "sysv64" fn(*mut Gamecube) -> u32;
+
+//a compiled instruction that might need handing back to the interpreter, the code holds a pointer to it
+#[derive(Copy, Clone)]
+struct Site {
+    decoded: Decoded,
+    //position in the block, for working out cia
+    index: u32,
+    phys: u32,
+    generation: u32,
+}
+
+//one guest page the compiled code can access directly. the tags are the effective page with bit 0 set,
+//so an empty entry never matches
+#[repr(C, align(32))]
+#[derive(Copy, Clone)]
+struct TlbEntry {
+    read_tag: u32,
+    write_tag: u32,
+    host: *mut u8,
+    //the decode cache's slot for the page, stores have to go the slow way once it's filled in
+    code: *const *const u8,
+}
+
+impl TlbEntry {
+    const EMPTY: Self = Self {
+	read_tag: 0,
+	write_tag: 0,
+	host: std::ptr::null_mut(),
+	code: std::ptr::null(),
+    };
+}
+
+struct CompiledBlock {
+    generation: u32,
+    //how many times the block has been recompiled after its instructions were written over
+    compiles: u32,
+    code: BlockFn,
+    //kept alive for the code pointing into it
+    sites: Box<[Site]>,
+    //whatever ended the block, run through the interpreter after the compiled part
+    tail: Option<Decoded>,
+}
+
+impl CompiledBlock {
+    //whether a write went over the block's own instructions, rather than just somewhere else on its page
+    fn written_over(&self, decode_cache: &DecodeCache, phys: u32) -> bool {
+	let instrs = self.sites.iter().map(|site| site.decoded).chain(self.tail);
+	instrs.enumerate().any(|(index, decoded)| {
+	    decode_cache.lookup(phys.wrapping_add(index as u32 * 4)).is_none_or(|cached| cached.instr.0 != decoded.instr.0)
+	})
+    }
+}
+
+pub struct Jit {
+    buffer: CodeBuffer,
+    blocks: HashMap<u32, CompiledBlock>,
+    tlb: Box<[TlbEntry; TLB_ENTRIES]>,
+    //what the tlb entries were filled under, they all go when any of it changes
+    tlb_key: Option<([Bat; 4], bool, bool)>,
+    //effective address of the block that's running, for the helpers to work out cia
+    entry_cia: u32,
+}
+
+impl Jit {
+    pub fn new() -> Self {
+	Self {
+	    buffer: CodeBuffer::new(CODE_BUFFER_SIZE),
+	    blocks: HashMap::new(),
+	    tlb: Box::new([TlbEntry::EMPTY; TLB_ENTRIES]),
+	    tlb_key: None,
+	    entry_cia: 0,
+	}
+    }
+}
+
+impl Default for Jit {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+//settles the cycles owed and runs one instruction on the interpreter. the block carries on as long as
+//that left the cpu on the next instruction and didn't write over the block
+//a panic in here can't unwind through the compiled code, so it aborts
+unsafe extern "sysv64" fn fallback(gc: *mut Gamecube, site: *const Site, pending: u32) -> u32 {
+    let site = unsafe { *site };
+    let gc = unsafe { &mut *gc };
+
+    gc.cpu.tick(u64::from(pending));
+
+    let cia = gc.cpu.jit.entry_cia.wrapping_add(site.index * 4);
+    gc.cpu.cia = cia;
+    gc.cpu.nia = cia.wrapping_add(4);
+    super::execute(gc, site.decoded);
+
+    u32::from(gc.cpu.cia == cia.wrapping_add(4) && gc.cpu.decode_cache.generation(site.phys) == Some(site.generation))
+}
+
+//a load or store that missed the fast path. once the interpreter has done it, the page gets
+//a tlb entry if it's plain ram so the next access doesn't miss
+unsafe extern "sysv64" fn memory_fallback(gc: *mut Gamecube, site: *const Site, pending: u32, ea: u32, write: u32) -> u32 {
+    let done = unsafe { fallback(gc, site, pending) };
+    if done == 0 {
+	return done;
+    }
+
+    let gc = unsafe { &mut *gc };
+    //going straight to ram would skip the data cache
+    if gc.cpu.dcache.modelled {
+	return done;
+    }
+
+    let access = if write != 0 {
+	Access::Write
+    } else {
+	Access::Read
+    };
+
+    //only BAT and real mode translations, the page table can change under us without the key noticing
+    let Some(Ok(phys)) = gc.cpu.mmu.translate_bat(access, ea, &gc.cpu.msr) else {
+	return done;
+    };
+    let page = phys & !0xFFF;
+    if page as usize + 0x1000 > RAM_SIZE {
+	return done;
+    }
+    let Some(code) = gc.cpu.decode_cache.page_ptr(page) else {
+	return done;
+    };
+
+    let tag = (ea & !0xFFF) | 1;
+    let entry = &mut gc.cpu.jit.tlb[(ea >> 12) as usize & (TLB_ENTRIES - 1)];
+    if entry.read_tag != tag && entry.write_tag != tag {
+	*entry = TlbEntry {
+	    host: unsafe { gc.memory.as_mut_ptr().add(page as usize) },
+	    code,
+	    ..TlbEntry::EMPTY
+	};
+    }
+
+    if write != 0 {
+	entry.write_tag = tag;
+    } else {
+	entry.read_tag = tag;
+    }
+
+    done
+}
+
+fn compile(gc: &mut Gamecube, phys: u32, generation: u32, compiles: u32) -> bool {
+    let mut instrs = block::build(gc, phys).to_vec();
+    let tail = instrs.pop_if(|decoded| block::ends_block(&decoded.instr));
+    if instrs.is_empty() && tail.is_none() {
+	return false;
+    }
+
+    let sites = instrs.into_iter().enumerate().map(|(index, decoded)| Site {
+	decoded,
+	index: index as u32,
+	phys,
+	generation,
+    }).collect::<Box<[_]>>();
+
+    let jit = &mut gc.cpu.jit;
+    let code = compiler::compile(&sites, jit.tlb.as_ptr());
+    let entry = match jit.buffer.push(&code) {
+	Some(entry) => entry,
+	None => {
+	    jit.blocks.clear();
+	    jit.buffer.reset();
+	    jit.buffer.push(&code).expect("compiled block is bigger than the whole code buffer")
+	},
+    };
+
+    jit.blocks.insert(phys, CompiledBlock {
+	generation,
+	compiles,
+	code: unsafe { mem::transmute::<*const u8, BlockFn>(entry) },
+	sites,
+	tail,
+    });
+
+    true
+}
+
+//same contract as block::run_block. anything the compiled code can't account for exactly, like a pending
+//exception, a breakpoint or a decrementer about to fire, goes to the block cache instead
+pub fn run_block(gc: &mut Gamecube, until: u64) {
+    let cpu = &gc.cpu;
+    let exception_due = cpu.exceptions != 0 && (cpu.msr.ee() || cpu.exceptions & !ASYNCHRONOUS_EXCEPTIONS != 0);
+    if cpu.iabr & 2 != 0 || cpu.msr.se() || exception_due {
+	block::run_block(gc, until);
+	return;
+    }
+
+    let start = gc.cpu.cia;
+    let Ok(phys) = gc.translate(start, Access::Fetch) else {
+	abort_instruction(gc);
+	return;
+    };
+
+    let Some(generation) = gc.cpu.decode_cache.generation(phys) else {
+	super::step(gc);
+	return;
+    };
+
+    //only writes to the block itself count towards giving up on it, a page shared with data gets written all the time
+    let (fresh, compiles) = gc.cpu.jit.blocks.get(&phys).map_or((false, 0), |block| {
+	let written_over = block.generation != generation && block.written_over(&gc.cpu.decode_cache, phys);
+	(block.generation == generation, block.compiles + u32::from(written_over))
+    });
+    if !fresh {
+	if compiles >= MAX_COMPILES {
+	    block::run_block(gc, until);
+	    return;
+	}
+	if !compile(gc, phys, generation, compiles) {
+	    super::step(gc);
+	    return;
+	}
+    }
+
+    let block = &gc.cpu.jit.blocks[&phys];
+    let (code, len, tail) = (block.code, block.sites.len() as u32, block.tail);
+
+    //the compiled part ticks all at once, so it can't be allowed to reach until or run the decrementer
+    //past zero partway through
+    let cycles = gc.cpu.cycles;
+    let end = cycles + u64::from(len) + u64::from(tail.is_some());
+    let ticks = (cycles + u64::from(len)) / CYCLES_PER_TIMEBASE_TICK - cycles / CYCLES_PER_TIMEBASE_TICK;
+    if end > until || (gc.cpu.dec & 0x8000_0000 == 0 && ticks > u64::from(gc.cpu.dec)) {
+	block::run_block(gc, until);
+	return;
+    }
+
+    let key = (gc.cpu.mmu.dbats, gc.cpu.msr.dr(), gc.cpu.msr.pr());
+    if gc.cpu.jit.tlb_key != Some(key) {
+	gc.cpu.jit.tlb.fill(TlbEntry::EMPTY);
+	gc.cpu.jit.tlb_key = Some(key);
+    }
+
+    if len != 0 {
+	gc.cpu.jit.entry_cia = start;
+	let pending = unsafe { code(gc) };
+	if pending == BAILED {
+	    return;
+	}
+
+	gc.cpu.tick(u64::from(pending));
+	gc.cpu.cia = start.wrapping_add(len * 4);
+	gc.cpu.nia = gc.cpu.cia.wrapping_add(4);
+    }
+
+    if let Some(tail) = tail {
+	super::execute(gc, tail);
+    }
+}
@@ -1,8 +1,6 @@
-use log::debug;
-
 use crate::Gamecube;
 
-use super::{instr::Instruction, util::{convert_to_double, convert_to_single, dequantized, sext_12}, PROGRAM_EXCEPTION};
+use super::{instr::Instruction, util::{convert_to_double, convert_to_single, dequantized, quantized, sext_12}, DSI_EXCEPTION, PROGRAM_ILLEGAL};
 
 fn b(gc: &mut Gamecube, instr: &Instruction) -> u32 {
     ((if instr.a() == 0 {
@@ -12,152 +10,577 @@ fn b(gc: &mut Gamecube, instr: &Instruction) -> u32 {
     } as i32).wrapping_add(instr.simm() as i32)) as u32
 }
 
+//indexed forms, (rA|0) + rB
+fn bx(gc: &mut Gamecube, instr: &Instruction) -> u32 {
+    if instr.a() == 0 {
+	0
+    } else {
+	gc.cpu.gprs[instr.a()]
+    }.wrapping_add(gc.cpu.gprs[instr.b()])
+}
+
+//update forms always use rA, even when it's r0
+fn bu(gc: &mut Gamecube, instr: &Instruction) -> u32 {
+    ((gc.cpu.gprs[instr.a()] as i32).wrapping_add(instr.simm() as i32)) as u32
+}
+
+fn bux(gc: &mut Gamecube, instr: &Instruction) -> u32 {
+    gc.cpu.gprs[instr.a()].wrapping_add(gc.cpu.gprs[instr.b()])
+}
+
+//loads with update are invalid when rA is r0 or the register being loaded
+fn invalid_load_update(gc: &mut Gamecube, instr: &Instruction) -> bool {
+    if instr.a() == 0 || instr.a() == instr.d() {
+	gc.cpu.program_exception(PROGRAM_ILLEGAL);
+	return true;
+    }
+    false
+}
+
+//stores with update only have rA being r0 to worry about
+fn invalid_store_update(gc: &mut Gamecube, instr: &Instruction) -> bool {
+    if instr.a() == 0 {
+	gc.cpu.program_exception(PROGRAM_ILLEGAL);
+	return true;
+    }
+    false
+}
+
 pub fn stb(gc: &mut Gamecube, instr: &Instruction) {
     let b = b(gc, instr);
-    gc.write_u8(b, (gc.cpu.gprs[instr.s()] & 0xFF) as u8)
+    let _ = gc.write_u8(b, (gc.cpu.gprs[instr.s()] & 0xFF) as u8);
 }
 
 pub fn stbu(gc: &mut Gamecube, instr: &Instruction) {
-    let b = b(gc, instr);
-    gc.write_u8(b, (gc.cpu.gprs[instr.s()] & 0xFF) as u8);
-    gc.cpu.gprs[instr.a()] = b;
+    if invalid_store_update(gc, instr) {
+	return;
+    }
+    let b = bu(gc, instr);
+    if gc.write_u8(b, (gc.cpu.gprs[instr.s()] & 0xFF) as u8).is_ok() {
+	gc.cpu.gprs[instr.a()] = b;
+    }
 }
 
 pub fn sth(gc: &mut Gamecube, instr: &Instruction) {
     let b = b(gc, instr);
-    gc.write_u16(b, (gc.cpu.gprs[instr.s()] & 0xFFFF) as u16);
+    let _ = gc.write_u16(b, (gc.cpu.gprs[instr.s()] & 0xFFFF) as u16);
 }
 
 pub fn stw(gc: &mut Gamecube, instr: &Instruction) {
     let b = b(gc, instr);
-    gc.write_u32(b, gc.cpu.gprs[instr.s()]);
+    let _ = gc.write_u32(b, gc.cpu.gprs[instr.s()]);
 }
 
 pub fn stwu(gc: &mut Gamecube, instr: &Instruction) {
-    let b = ((gc.cpu.gprs[instr.a()] as i32).wrapping_add(instr.simm() as i32)) as u32;
-    gc.write_u32(b, gc.cpu.gprs[instr.s()]);
-    gc.cpu.gprs[instr.a()] = b;
+    if invalid_store_update(gc, instr) {
+	return;
+    }
+    let b = bu(gc, instr);
+    if gc.write_u32(b, gc.cpu.gprs[instr.s()]).is_ok() {
+	gc.cpu.gprs[instr.a()] = b;
+    }
 }
 
 pub fn stwx(gc: &mut Gamecube, instr: &Instruction) {
-    let b = if instr.a() == 0 {
-	0
-    } else {
-	gc.cpu.gprs[instr.a()]
-    }.wrapping_add(gc.cpu.gprs[instr.b()]);
-    gc.write_u32(b, gc.cpu.gprs[instr.s()]);
+    let b = bx(gc, instr);
+    let _ = gc.write_u32(b, gc.cpu.gprs[instr.s()]);
+}
+
+pub fn stwux(gc: &mut Gamecube, instr: &Instruction) {
+    if invalid_store_update(gc, instr) {
+	return;
+    }
+    let b = bux(gc, instr);
+    if gc.write_u32(b, gc.cpu.gprs[instr.s()]).is_ok() {
+	gc.cpu.gprs[instr.a()] = b;
+    }
+}
+
+pub fn stbx(gc: &mut Gamecube, instr: &Instruction) {
+    let b = bx(gc, instr);
+    let _ = gc.write_u8(b, (gc.cpu.gprs[instr.s()] & 0xFF) as u8);
+}
+
+pub fn stbux(gc: &mut Gamecube, instr: &Instruction) {
+    if invalid_store_update(gc, instr) {
+	return;
+    }
+    let b = bux(gc, instr);
+    if gc.write_u8(b, (gc.cpu.gprs[instr.s()] & 0xFF) as u8).is_ok() {
+	gc.cpu.gprs[instr.a()] = b;
+    }
+}
+
+pub fn sthu(gc: &mut Gamecube, instr: &Instruction) {
+    if invalid_store_update(gc, instr) {
+	return;
+    }
+    let b = bu(gc, instr);
+    if gc.write_u16(b, (gc.cpu.gprs[instr.s()] & 0xFFFF) as u16).is_ok() {
+	gc.cpu.gprs[instr.a()] = b;
+    }
+}
+
+pub fn sthx(gc: &mut Gamecube, instr: &Instruction) {
+    let b = bx(gc, instr);
+    let _ = gc.write_u16(b, (gc.cpu.gprs[instr.s()] & 0xFFFF) as u16);
+}
+
+pub fn sthux(gc: &mut Gamecube, instr: &Instruction) {
+    if invalid_store_update(gc, instr) {
+	return;
+    }
+    let b = bux(gc, instr);
+    if gc.write_u16(b, (gc.cpu.gprs[instr.s()] & 0xFFFF) as u16).is_ok() {
+	gc.cpu.gprs[instr.a()] = b;
+    }
+}
+
+pub fn sthbrx(gc: &mut Gamecube, instr: &Instruction) {
+    let b = bx(gc, instr);
+    let _ = gc.write_u16(b, (gc.cpu.gprs[instr.s()] as u16).swap_bytes());
+}
+
+pub fn stwbrx(gc: &mut Gamecube, instr: &Instruction) {
+    let b = bx(gc, instr);
+    let _ = gc.write_u32(b, gc.cpu.gprs[instr.s()].swap_bytes());
 }
 
 pub fn stfs(gc: &mut Gamecube, instr: &Instruction) {
     let b = b(gc, instr);
-    gc.write_u32(b, convert_to_single(*gc.cpu.fprs[instr.s()].as_u64()));
+    let _ = gc.write_u32(b, convert_to_single(gc.cpu.fprs[instr.s()].ps0));
 }
 
 pub fn stfsu(gc: &mut Gamecube, instr: &Instruction) {
-    let b = gc.cpu.gprs[instr.a()].wrapping_add(gc.cpu.gprs[instr.b()]);
-    gc.write_u32(b, convert_to_single(*gc.cpu.fprs[instr.s()].as_u64()));
-    gc.cpu.gprs[instr.a()] = b;
+    if invalid_store_update(gc, instr) {
+	return;
+    }
+    let b = bu(gc, instr);
+    if gc.write_u32(b, convert_to_single(gc.cpu.fprs[instr.s()].ps0)).is_ok() {
+	gc.cpu.gprs[instr.a()] = b;
+    }
+}
+
+pub fn stfsx(gc: &mut Gamecube, instr: &Instruction) {
+    let b = bx(gc, instr);
+    let _ = gc.write_u32(b, convert_to_single(gc.cpu.fprs[instr.s()].ps0));
+}
+
+pub fn stfsux(gc: &mut Gamecube, instr: &Instruction) {
+    if invalid_store_update(gc, instr) {
+	return;
+    }
+    let b = bux(gc, instr);
+    if gc.write_u32(b, convert_to_single(gc.cpu.fprs[instr.s()].ps0)).is_ok() {
+	gc.cpu.gprs[instr.a()] = b;
+    }
+}
+
+//the low word of frS goes out untouched
+pub fn stfiwx(gc: &mut Gamecube, instr: &Instruction) {
+    let b = bx(gc, instr);
+    let _ = gc.write_u32(b, gc.cpu.fprs[instr.s()].ps0 as u32);
 }
 
 pub fn lbz(gc: &mut Gamecube, instr: &Instruction) {
     let b = b(gc, instr);
-    gc.cpu.gprs[instr.d()] = gc.read_u8(b) as u32;
+    if let Ok(val) = gc.read_u8(b) {
+	gc.cpu.gprs[instr.d()] = val as u32;
+    }
 }
 
 pub fn lbzu(gc: &mut Gamecube, instr: &Instruction) {
-    let b = b(gc, instr);
-    gc.cpu.gprs[instr.d()] = gc.read_u8(b) as u32;
-    gc.cpu.gprs[instr.a()] = b;
+    if invalid_load_update(gc, instr) {
+	return;
+    }
+    let b = bu(gc, instr);
+    if let Ok(val) = gc.read_u8(b) {
+	gc.cpu.gprs[instr.d()] = val as u32;
+	gc.cpu.gprs[instr.a()] = b;
+    }
+}
+
+pub fn lbzx(gc: &mut Gamecube, instr: &Instruction) {
+    let b = bx(gc, instr);
+    if let Ok(val) = gc.read_u8(b) {
+	gc.cpu.gprs[instr.d()] = val as u32;
+    }
+}
+
+pub fn lbzux(gc: &mut Gamecube, instr: &Instruction) {
+    if invalid_load_update(gc, instr) {
+	return;
+    }
+    let b = bux(gc, instr);
+    if let Ok(val) = gc.read_u8(b) {
+	gc.cpu.gprs[instr.d()] = val as u32;
+	gc.cpu.gprs[instr.a()] = b;
+    }
 }
 
 pub fn lhz(gc: &mut Gamecube, instr: &Instruction) {
     let b = b(gc, instr);
-    gc.cpu.gprs[instr.d()] = gc.read_u16(b) as u32;
+    if let Ok(val) = gc.read_u16(b) {
+	gc.cpu.gprs[instr.d()] = val as u32;
+    }
 }
 
 pub fn lhzu(gc: &mut Gamecube, instr: &Instruction) {
-    let b = (gc.cpu.gprs[instr.a()] as i32).wrapping_add(instr.simm() as i32) as u32;
-    gc.cpu.gprs[instr.d()] = gc.read_u16(b) as u32;
-    gc.cpu.gprs[instr.a()] = b;
+    if invalid_load_update(gc, instr) {
+	return;
+    }
+    let b = bu(gc, instr);
+    if let Ok(val) = gc.read_u16(b) {
+	gc.cpu.gprs[instr.d()] = val as u32;
+	gc.cpu.gprs[instr.a()] = b;
+    }
+}
+
+pub fn lhzux(gc: &mut Gamecube, instr: &Instruction) {
+    if invalid_load_update(gc, instr) {
+	return;
+    }
+    let b = bux(gc, instr);
+    if let Ok(val) = gc.read_u16(b) {
+	gc.cpu.gprs[instr.d()] = val as u32;
+	gc.cpu.gprs[instr.a()] = b;
+    }
+}
+
+pub fn lha(gc: &mut Gamecube, instr: &Instruction) {
+    let b = b(gc, instr);
+    if let Ok(val) = gc.read_u16(b) {
+	gc.cpu.gprs[instr.d()] = i32::from(val as i16) as u32;
+    }
+}
+
+pub fn lhau(gc: &mut Gamecube, instr: &Instruction) {
+    if invalid_load_update(gc, instr) {
+	return;
+    }
+    let b = bu(gc, instr);
+    if let Ok(val) = gc.read_u16(b) {
+	gc.cpu.gprs[instr.d()] = i32::from(val as i16) as u32;
+	gc.cpu.gprs[instr.a()] = b;
+    }
+}
+
+pub fn lhax(gc: &mut Gamecube, instr: &Instruction) {
+    let b = bx(gc, instr);
+    if let Ok(val) = gc.read_u16(b) {
+	gc.cpu.gprs[instr.d()] = i32::from(val as i16) as u32;
+    }
+}
+
+pub fn lhaux(gc: &mut Gamecube, instr: &Instruction) {
+    if invalid_load_update(gc, instr) {
+	return;
+    }
+    let b = bux(gc, instr);
+    if let Ok(val) = gc.read_u16(b) {
+	gc.cpu.gprs[instr.d()] = i32::from(val as i16) as u32;
+	gc.cpu.gprs[instr.a()] = b;
+    }
+}
+
+pub fn lhbrx(gc: &mut Gamecube, instr: &Instruction) {
+    let b = bx(gc, instr);
+    if let Ok(val) = gc.read_u16(b) {
+	gc.cpu.gprs[instr.d()] = u32::from(val.swap_bytes());
+    }
 }
 
 pub fn lwz(gc: &mut Gamecube, instr: &Instruction) {
     let b = b(gc, instr);
-    gc.cpu.gprs[instr.d()] = gc.read_u32(b, false);
+    if let Ok(val) = gc.read_u32(b, false) {
+	gc.cpu.gprs[instr.d()] = val;
+    }
 }
 
 pub fn lwzu(gc: &mut Gamecube, instr: &Instruction) {
-    if instr.a() == 0 || instr.a() == instr.d() {
-	gc.cpu.exceptions |= PROGRAM_EXCEPTION;
+    if invalid_load_update(gc, instr) {
+	return;
+    }
+    let b = bu(gc, instr);
+    if let Ok(val) = gc.read_u32(b, false) {
+	gc.cpu.gprs[instr.d()] = val;
+	gc.cpu.gprs[instr.a()] = b;
     }
-    let b = (gc.cpu.gprs[instr.a()] as i32).wrapping_add(instr.simm() as i32) as u32;
-    gc.cpu.gprs[instr.d()] = gc.read_u32(b, false);
-    gc.cpu.gprs[instr.a()] = b;
 }
 
 pub fn lfs(gc: &mut Gamecube, instr: &Instruction) {
     let b = b(gc, instr);
-    let val = gc.cpu.gprs[instr.a()];
 
-    if !gc.cpu.hid2.pse() {
-	*gc.cpu.fprs[instr.d()].as_u64_mut() = convert_to_double(val);
-    } else {
-	*gc.cpu.fprs[instr.d()].as_paired_u32_mut() = (val, val)
+    if let Ok(val) = gc.read_u32(b, false) {
+	let val = convert_to_double(val);
+	gc.cpu.fprs[instr.d()].ps0 = val;
+	gc.cpu.fprs[instr.d()].ps1 = val;
     }
 }
 
-pub fn psq_l(gc: &mut Gamecube, instr: &Instruction) {
-    if !(gc.cpu.hid2.pse() | gc.cpu.hid2.lsqe()) {
-	unimplemented!("exception");
+pub fn lfsu(gc: &mut Gamecube, instr: &Instruction) {
+    let b = bu(gc, instr);
+
+    if let Ok(val) = gc.read_u32(b, false) {
+	let val = convert_to_double(val);
+	gc.cpu.fprs[instr.d()].ps0 = val;
+	gc.cpu.fprs[instr.d()].ps1 = val;
+	gc.cpu.gprs[instr.a()] = b;
+    }
+}
+
+pub fn lfsx(gc: &mut Gamecube, instr: &Instruction) {
+    let b = bx(gc, instr);
+
+    if let Ok(val) = gc.read_u32(b, false) {
+	let val = convert_to_double(val);
+	gc.cpu.fprs[instr.d()].ps0 = val;
+	gc.cpu.fprs[instr.d()].ps1 = val;
+    }
+}
+
+pub fn lfsux(gc: &mut Gamecube, instr: &Instruction) {
+    let b = bux(gc, instr);
+
+    if let Ok(val) = gc.read_u32(b, false) {
+	let val = convert_to_double(val);
+	gc.cpu.fprs[instr.d()].ps0 = val;
+	gc.cpu.fprs[instr.d()].ps1 = val;
+	gc.cpu.gprs[instr.a()] = b;
+    }
+}
+
+//psq loads and stores are only legal with HID2[LSQE] set
+fn check_lsqe(gc: &mut Gamecube) -> bool {
+    if !gc.cpu.hid2.lsqe() {
+	gc.cpu.program_exception(PROGRAM_ILLEGAL);
+	return false;
     }
-    let b = if instr.a() == 0 {
+    true
+}
+
+fn psq_d(gc: &mut Gamecube, instr: &Instruction) -> u32 {
+    if instr.a() == 0 {
 	sext_12(instr.uimm_d()) as u32
     } else {
 	gc.cpu.gprs[instr.a()].wrapping_add(sext_12(instr.uimm_d()) as u32)
-    };
+    }
+}
 
-    let gqr = gc.cpu.gqrs[instr.i()];
+//W set loads a single value and leaves 1.0 in ps1
+fn quantized_load(gc: &mut Gamecube, b: u32, d: usize, w: bool, i: usize) -> bool {
+    let gqr = gc.cpu.gqrs[i];
     let ld_type = gqr.ld_type();
     let ld_scale = gqr.ld_scale();
 
-    if instr.w() {
+    if w {
 	let val = match ld_type {
-	    4 | 6 => gc.read_u8(b) as u32,
-	    5 | 7 => gc.read_u16(b) as u32,
+	    4 | 6 => gc.read_u8(b).map(u32::from),
+	    5 | 7 => gc.read_u16(b).map(u32::from),
 	    _ => gc.read_u32(b, false),
 	};
-	
-	*gc.cpu.fprs[instr.d()].as_paired_f32_mut() = (dequantized(val, ld_type, ld_scale), 1.);
+	let Ok(val) = val else {
+	    return false;
+	};
+
+	gc.cpu.fprs[d].set_ps0(dequantized(val, ld_type, ld_scale));
+	gc.cpu.fprs[d].set_ps1(1.0);
     } else {
 	let (val0, val1) = match ld_type {
-	    4 | 6 => (gc.read_u8(b) as u32, gc.read_u8(b + 1) as u32),
-	    5 | 7 => (gc.read_u16(b) as u32, gc.read_u16(b + 2) as u32),
-	    _ => (gc.read_u32(b, false), gc.read_u32(b + 4, false)),
+	    4 | 6 => (gc.read_u8(b).map(u32::from), gc.read_u8(b.wrapping_add(1)).map(u32::from)),
+	    5 | 7 => (gc.read_u16(b).map(u32::from), gc.read_u16(b.wrapping_add(2)).map(u32::from)),
+	    _ => (gc.read_u32(b, false), gc.read_u32(b.wrapping_add(4), false)),
+	};
+	let (Ok(val0), Ok(val1)) = (val0, val1) else {
+	    return false;
 	};
 
-	*gc.cpu.fprs[instr.d()].as_paired_f32_mut() = (dequantized(val0, ld_type, ld_scale), dequantized(val1, ld_type, ld_scale));
+	gc.cpu.fprs[d].set_ps0(dequantized(val0, ld_type, ld_scale));
+	gc.cpu.fprs[d].set_ps1(dequantized(val1, ld_type, ld_scale));
+    }
+    true
+}
+
+//W set only stores ps0
+fn quantized_store(gc: &mut Gamecube, b: u32, s: usize, w: bool, i: usize) -> bool {
+    let gqr = gc.cpu.gqrs[i];
+    let st_type = gqr.st_type();
+    let st_scale = gqr.st_scale();
+
+    let val0 = quantized(gc.cpu.fprs[s].ps0(), st_type, st_scale);
+    let val1 = quantized(gc.cpu.fprs[s].ps1(), st_type, st_scale);
+
+    let result = match (st_type, w) {
+	(4 | 6, true) => gc.write_u8(b, val0 as u8),
+	(4 | 6, false) => gc.write_u16(b, ((val0 as u16) << 8) | (val1 as u8 as u16)),
+	(5 | 7, true) => gc.write_u16(b, val0 as u16),
+	(5 | 7, false) => gc.write_u32(b, (val0 << 16) | (val1 & 0xFFFF)),
+	(_, true) => gc.write_u32(b, val0),
+	(_, false) => gc.write_u64(b, (u64::from(val0) << 32) | u64::from(val1)),
+    };
+    result.is_ok()
+}
+
+pub fn psq_l(gc: &mut Gamecube, instr: &Instruction) {
+    if !check_lsqe(gc) {
+	return;
+    }
+    let b = psq_d(gc, instr);
+    quantized_load(gc, b, instr.d(), instr.w(), instr.i());
+}
+
+pub fn psq_lu(gc: &mut Gamecube, instr: &Instruction) {
+    if !check_lsqe(gc) {
+	return;
+    }
+    let b = gc.cpu.gprs[instr.a()].wrapping_add(sext_12(instr.uimm_d()) as u32);
+    if quantized_load(gc, b, instr.d(), instr.w(), instr.i()) {
+	gc.cpu.gprs[instr.a()] = b;
+    }
+}
+
+pub fn psq_lx(gc: &mut Gamecube, instr: &Instruction) {
+    if !check_lsqe(gc) {
+	return;
+    }
+    let b = bx(gc, instr);
+    quantized_load(gc, b, instr.d(), instr.wx(), instr.ix());
+}
+
+pub fn psq_lux(gc: &mut Gamecube, instr: &Instruction) {
+    if !check_lsqe(gc) {
+	return;
+    }
+    let b = bux(gc, instr);
+    if quantized_load(gc, b, instr.d(), instr.wx(), instr.ix()) {
+	gc.cpu.gprs[instr.a()] = b;
+    }
+}
+
+pub fn psq_st(gc: &mut Gamecube, instr: &Instruction) {
+    if !check_lsqe(gc) {
+	return;
+    }
+    let b = psq_d(gc, instr);
+    quantized_store(gc, b, instr.s(), instr.w(), instr.i());
+}
+
+pub fn psq_stu(gc: &mut Gamecube, instr: &Instruction) {
+    if !check_lsqe(gc) {
+	return;
+    }
+    if invalid_store_update(gc, instr) {
+	return;
+    }
+    let b = gc.cpu.gprs[instr.a()].wrapping_add(sext_12(instr.uimm_d()) as u32);
+    if quantized_store(gc, b, instr.s(), instr.w(), instr.i()) {
+	gc.cpu.gprs[instr.a()] = b;
+    }
+}
+
+pub fn psq_stx(gc: &mut Gamecube, instr: &Instruction) {
+    if !check_lsqe(gc) {
+	return;
+    }
+    let b = bx(gc, instr);
+    quantized_store(gc, b, instr.s(), instr.wx(), instr.ix());
+}
+
+pub fn psq_stux(gc: &mut Gamecube, instr: &Instruction) {
+    if !check_lsqe(gc) {
+	return;
+    }
+    if invalid_store_update(gc, instr) {
+	return;
+    }
+    let b = bux(gc, instr);
+    if quantized_store(gc, b, instr.s(), instr.wx(), instr.ix()) {
+	gc.cpu.gprs[instr.a()] = b;
     }
 }
 
 pub fn lfd(gc: &mut Gamecube, instr: &Instruction) {
     let b = b(gc, instr);
 
-    *gc.cpu.fprs[instr.d()].as_u64_mut() = gc.read_u64(b);
+    if let Ok(val) = gc.read_u64(b) {
+	gc.cpu.fprs[instr.d()].ps0 = val;
+    }
+}
+
+pub fn lfdu(gc: &mut Gamecube, instr: &Instruction) {
+    let b = bu(gc, instr);
+
+    if let Ok(val) = gc.read_u64(b) {
+	gc.cpu.fprs[instr.d()].ps0 = val;
+	gc.cpu.gprs[instr.a()] = b;
+    }
+}
+
+pub fn lfdx(gc: &mut Gamecube, instr: &Instruction) {
+    let b = bx(gc, instr);
+
+    if let Ok(val) = gc.read_u64(b) {
+	gc.cpu.fprs[instr.d()].ps0 = val;
+    }
+}
+
+pub fn lfdux(gc: &mut Gamecube, instr: &Instruction) {
+    let b = bux(gc, instr);
+
+    if let Ok(val) = gc.read_u64(b) {
+	gc.cpu.fprs[instr.d()].ps0 = val;
+	gc.cpu.gprs[instr.a()] = b;
+    }
 }
 
 pub fn stfd(gc: &mut Gamecube, instr: &Instruction) {
     let b = b(gc, instr);
 
-    gc.write_u64(b, *gc.cpu.fprs[instr.s()].as_u64());
+    let _ = gc.write_u64(b, gc.cpu.fprs[instr.s()].ps0);
+}
+
+pub fn stfdu(gc: &mut Gamecube, instr: &Instruction) {
+    if invalid_store_update(gc, instr) {
+	return;
+    }
+    let b = bu(gc, instr);
+
+    if gc.write_u64(b, gc.cpu.fprs[instr.s()].ps0).is_ok() {
+	gc.cpu.gprs[instr.a()] = b;
+    }
+}
+
+pub fn stfdx(gc: &mut Gamecube, instr: &Instruction) {
+    let b = bx(gc, instr);
+
+    let _ = gc.write_u64(b, gc.cpu.fprs[instr.s()].ps0);
+}
+
+pub fn stfdux(gc: &mut Gamecube, instr: &Instruction) {
+    if invalid_store_update(gc, instr) {
+	return;
+    }
+    let b = bux(gc, instr);
+
+    if gc.write_u64(b, gc.cpu.fprs[instr.s()].ps0).is_ok() {
+	gc.cpu.gprs[instr.a()] = b;
+    }
 }
 
 pub fn stmw(gc: &mut Gamecube, instr: &Instruction) {
     let mut b = b(gc, instr);
 
+    if b & 3 != 0 {
+	gc.cpu.alignment_fault(b, instr);
+	return;
+    }
+
     let mut r = instr.s();
 
     while r <= 31 {
-	gc.write_u32(b, gc.cpu.gprs[r]);
+	if gc.write_u32(b, gc.cpu.gprs[r]).is_err() {
+	    return;
+	}
 	r += 1;
 	b += 4;
     }
@@ -166,31 +589,280 @@ pub fn stmw(gc: &mut Gamecube, instr: &Instruction) {
 pub fn lmw(gc: &mut Gamecube, instr: &Instruction) {
     let mut b = b(gc, instr);
 
+    if b & 3 != 0 {
+	gc.cpu.alignment_fault(b, instr);
+	return;
+    }
+
     let mut r = instr.d();
 
     while r <= 31 {
-	gc.cpu.gprs[r] = gc.read_u32(b, false);
+	let Ok(val) = gc.read_u32(b, false) else {
+	    return;
+	};
+	gc.cpu.gprs[r] = val;
 	r += 1;
 	b += 4;
     }
 }
 
 pub fn lhzx(gc: &mut Gamecube, instr: &Instruction) {
-    let b = if instr.a() == 0 {
-	0
-    } else {
-	gc.cpu.gprs[instr.a()]
-    }.wrapping_add(gc.cpu.gprs[instr.b()]);
+    let b = bx(gc, instr);
 
-    gc.cpu.gprs[instr.d()] = gc.read_u16(b) as u32;
+    if let Ok(val) = gc.read_u16(b) {
+	gc.cpu.gprs[instr.d()] = val as u32;
+    }
 }
 
 pub fn lwzx(gc: &mut Gamecube, instr: &Instruction) {
-    let b = if instr.a() == 0 {
-	0
-    } else {
-	gc.cpu.gprs[instr.a()]
-    }.wrapping_add(gc.cpu.gprs[instr.b()]);
+    let b = bx(gc, instr);
+
+    if let Ok(val) = gc.read_u32(b, false) {
+	gc.cpu.gprs[instr.d()] = val;
+    }
+}
 
-    gc.cpu.gprs[instr.d()] = gc.read_u32(b, false);    
+pub fn lwzux(gc: &mut Gamecube, instr: &Instruction) {
+    if invalid_load_update(gc, instr) {
+	return;
+    }
+    let b = bux(gc, instr);
+
+    if let Ok(val) = gc.read_u32(b, false) {
+	gc.cpu.gprs[instr.d()] = val;
+	gc.cpu.gprs[instr.a()] = b;
+    }
+}
+
+pub fn lwbrx(gc: &mut Gamecube, instr: &Instruction) {
+    let b = bx(gc, instr);
+
+    if let Ok(val) = gc.read_u32(b, false) {
+	gc.cpu.gprs[instr.d()] = val.swap_bytes();
+    }
+}
+
+//string loads fill registers a byte at a time from the top down, wrapping from r31 back to r0
+fn load_string(gc: &mut Gamecube, instr: &Instruction, mut b: u32, n: usize) {
+    let mut r = instr.d();
+
+    for i in 0..n {
+	let Ok(byte) = gc.read_u8(b) else {
+	    return;
+	};
+
+	let shift = 24 - (i % 4) * 8;
+	if shift == 24 {
+	    gc.cpu.gprs[r] = 0;
+	}
+	gc.cpu.gprs[r] |= u32::from(byte) << shift;
+
+	if shift == 0 {
+	    r = (r + 1) % 32;
+	}
+	b = b.wrapping_add(1);
+    }
+}
+
+fn store_string(gc: &mut Gamecube, instr: &Instruction, mut b: u32, n: usize) {
+    let mut r = instr.s();
+
+    for i in 0..n {
+	let shift = 24 - (i % 4) * 8;
+	if gc.write_u8(b, (gc.cpu.gprs[r] >> shift) as u8).is_err() {
+	    return;
+	}
+
+	if shift == 0 {
+	    r = (r + 1) % 32;
+	}
+	b = b.wrapping_add(1);
+    }
+}
+
+pub fn lswi(gc: &mut Gamecube, instr: &Instruction) {
+    let b = if instr.a() == 0 { 0 } else { gc.cpu.gprs[instr.a()] };
+    let n = if instr.nb() == 0 { 32 } else { instr.nb() };
+    load_string(gc, instr, b, n);
+}
+
+pub fn lswx(gc: &mut Gamecube, instr: &Instruction) {
+    let b = bx(gc, instr);
+    load_string(gc, instr, b, gc.cpu.xer.byte_count());
+}
+
+pub fn stswi(gc: &mut Gamecube, instr: &Instruction) {
+    let b = if instr.a() == 0 { 0 } else { gc.cpu.gprs[instr.a()] };
+    let n = if instr.nb() == 0 { 32 } else { instr.nb() };
+    store_string(gc, instr, b, n);
+}
+
+pub fn stswx(gc: &mut Gamecube, instr: &Instruction) {
+    let b = bx(gc, instr);
+    store_string(gc, instr, b, gc.cpu.xer.byte_count());
+}
+
+//eciwx and ecowx need EAR[E] set, otherwise they take a DSI with DSISR[11] set, and DSISR[6] too for ecowx
+fn check_external_access(gc: &mut Gamecube, instr: &Instruction, b: u32, store: bool) -> bool {
+    if gc.cpu.ear & 0x8000_0000 == 0 {
+	gc.cpu.dsisr = 0x0010_0000 | if store { 0x0200_0000 } else { 0 };
+	gc.cpu.dar = b;
+	gc.cpu.exceptions |= DSI_EXCEPTION;
+	gc.cpu.nia = gc.cpu.cia;
+	return false;
+    }
+
+    if b & 3 != 0 {
+	gc.cpu.alignment_fault(b, instr);
+	return false;
+    }
+
+    true
+}
+
+pub fn eciwx(gc: &mut Gamecube, instr: &Instruction) {
+    let b = bx(gc, instr);
+    if !check_external_access(gc, instr, b, false) {
+	return;
+    }
+
+    if let Ok(val) = gc.read_u32(b, false) {
+	gc.cpu.gprs[instr.d()] = val;
+    }
+}
+
+pub fn ecowx(gc: &mut Gamecube, instr: &Instruction) {
+    let b = bx(gc, instr);
+    if !check_external_access(gc, instr, b, true) {
+	return;
+    }
+
+    let _ = gc.write_u32(b, gc.cpu.gprs[instr.s()]);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cpu::{decode::Handler, PROGRAM_EXCEPTION, PROGRAM_ILLEGAL}, test_gamecube};
+
+    use super::{psq_l, psq_st, stbu, stbux, stfdu, stfdux, stfsu, stfsux, sthu, sthux, stwu, stwux, Instruction};
+
+    const HID2_LSQE: u32 = 1 << 31;
+
+    //psq_l/psq_st frS, 0(r4) through GQR i, W picks a single value
+    fn psq_form(opcd: u32, w: bool, i: u32) -> Instruction {
+	Instruction((opcd << 26) | (1 << 21) | (4 << 16) | (u32::from(w) << 15) | (i << 12))
+    }
+
+    //GQRs keep the load type and scale in the upper half, the store ones in the lower half
+    fn gqr(ld_type: u32, ld_scale: u32, st_type: u32, st_scale: u32) -> u32 {
+	(ld_scale << 24) | (ld_type << 16) | (st_scale << 8) | st_type
+    }
+
+    fn d_form(opcd: u32, s: u32, a: u32, d: i16) -> Instruction {
+	Instruction((opcd << 26) | (s << 21) | (a << 16) | d as u16 as u32)
+    }
+
+    fn x_form(xo: u32, s: u32, a: u32, b: u32) -> Instruction {
+	Instruction((31 << 26) | (s << 21) | (a << 16) | (b << 11) | (xo << 1))
+    }
+
+    #[test]
+    fn stbu_stores_at_ra_plus_d_and_updates_ra() {
+	let mut gc = test_gamecube();
+	gc.cpu.gprs[3] = 0x1234_56AB;
+	gc.cpu.gprs[4] = 0x1004;
+
+	//stbu r3, -4(r4)
+	stbu(&mut gc, &d_form(39, 3, 4, -4));
+	assert_eq!(gc.memory[0x1000], 0xAB);
+	assert_eq!(gc.cpu.gprs[4], 0x1000);
+    }
+
+    #[test]
+    fn stores_with_update_into_r0_are_illegal() {
+	let forms: [(Handler, Instruction); 10] = [
+	    (stbu, d_form(39, 3, 0, 0x100)),
+	    (sthu, d_form(45, 3, 0, 0x100)),
+	    (stwu, d_form(37, 3, 0, 0x100)),
+	    (stfsu, d_form(53, 3, 0, 0x100)),
+	    (stfdu, d_form(55, 3, 0, 0x100)),
+	    (stbux, x_form(247, 3, 0, 4)),
+	    (sthux, x_form(439, 3, 0, 4)),
+	    (stwux, x_form(183, 3, 0, 4)),
+	    (stfsux, x_form(695, 3, 0, 4)),
+	    (stfdux, x_form(759, 3, 0, 4)),
+	];
+
+	for (store, instr) in forms {
+	    let mut gc = test_gamecube();
+	    gc.cpu.gprs[0] = 0x1000;
+	    gc.cpu.gprs[3] = 0xFFFF_FFFF;
+	    gc.cpu.gprs[4] = 0x100;
+	    gc.cpu.fprs[3].ps0 = u64::MAX;
+
+	    store(&mut gc, &instr);
+	    assert_eq!(gc.cpu.exceptions, PROGRAM_EXCEPTION);
+	    assert_eq!(gc.cpu.srr1_flags, PROGRAM_ILLEGAL);
+	    assert_eq!(gc.cpu.gprs[0], 0x1000);
+	    assert!(gc.memory[0x1000..0x1200].iter().all(|&byte| byte == 0));
+	}
+    }
+
+    #[test]
+    fn psq_l_dequantizes_with_the_gqr_type_and_scale() {
+	let mut gc = test_gamecube();
+	gc.cpu.hid2.0 = HID2_LSQE;
+	gc.cpu.gprs[4] = 0x1000;
+	gc.memory[0x1000..0x1004].copy_from_slice(&[0xFF, 0x00, 0x01, 0x80]);
+
+	//two s16s scaled by 2^-8
+	gc.cpu.gqrs[1].0 = gqr(7, 8, 0, 0);
+	psq_l(&mut gc, &psq_form(56, false, 1));
+	assert_eq!(gc.cpu.fprs[1].ps0, 0xBFF0_0000_0000_0000);
+	assert_eq!(gc.cpu.fprs[1].ps1, 0x3FF8_0000_0000_0000);
+
+	//one u8, ps1 is 1.0
+	gc.cpu.gqrs[2].0 = gqr(4, 0, 0, 0);
+	psq_l(&mut gc, &psq_form(56, true, 2));
+	assert_eq!(gc.cpu.fprs[1].ps0, 0x406F_E000_0000_0000);
+	assert_eq!(gc.cpu.fprs[1].ps1, 0x3FF0_0000_0000_0000);
+
+	//one s8, scales from 32 up multiply
+	gc.cpu.gqrs[3].0 = gqr(6, 63, 0, 0);
+	psq_l(&mut gc, &psq_form(56, true, 3));
+	assert_eq!(gc.cpu.fprs[1].ps0, 0xC000_0000_0000_0000);
+
+	//without HID2[LSQE] it's illegal
+	gc.cpu.hid2.0 = 0;
+	psq_l(&mut gc, &psq_form(56, false, 0));
+	assert_eq!(gc.cpu.exceptions, PROGRAM_EXCEPTION);
+	assert_eq!(gc.cpu.srr1_flags, PROGRAM_ILLEGAL);
+    }
+
+    #[test]
+    fn psq_st_quantizes_and_saturates() {
+	let mut gc = test_gamecube();
+	gc.cpu.hid2.0 = HID2_LSQE;
+	gc.cpu.gprs[4] = 0x1000;
+
+	//u16s scaled by 16, negative saturates to 0
+	gc.cpu.gqrs[1].0 = gqr(0, 0, 5, 4);
+	gc.cpu.fprs[1].set_ps0(1.5);
+	gc.cpu.fprs[1].set_ps1(-1.0);
+	psq_st(&mut gc, &psq_form(60, false, 1));
+	assert_eq!(gc.memory[0x1000..0x1004], [0x00, 0x18, 0x00, 0x00]);
+
+	//s8s clamp to their range
+	gc.cpu.gqrs[2].0 = gqr(0, 0, 6, 0);
+	gc.cpu.fprs[1].set_ps0(200.0);
+	gc.cpu.fprs[1].set_ps1(-300.0);
+	psq_st(&mut gc, &psq_form(60, false, 2));
+	assert_eq!(gc.memory[0x1000..0x1002], [0x7F, 0x80]);
+
+	//float ignores the scale, W only writes ps0
+	gc.cpu.gqrs[3].0 = gqr(0, 0, 0, 4);
+	gc.cpu.fprs[1].set_ps0(1.5);
+	psq_st(&mut gc, &psq_form(60, true, 3));
+	assert_eq!(gc.memory[0x1000..0x1008], [0x3F, 0xC0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    }
 }
@@ -1,11 +1,62 @@
-use log::debug;
+use byteorder::{BigEndian, ByteOrder};
 
 use super::MachineStateRegister;
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Access {
+    Fetch,
+    Read,
+    Write,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FaultKind {
+    //no BAT and no PTE in either PTEG
+    NotFound,
+    //BAT pp or PTE pp/key combination doesn't allow the access
+    Protection,
+    //segment register has T set
+    DirectStore,
+    //instruction fetch from a segment with N set
+    NoExecute,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TranslationFault {
+    pub addr: u32,
+    pub access: Access,
+    pub kind: FaultKind,
+}
+
+//BATs map at 128kb granularity, so a flat table indexed by the upper 15 bits of the effective address
+//answers a BAT lookup with one load. entries are the physical block address with the pp bits and a valid
+//bit packed into the (otherwise always zero) low bits.
+const BAT_BLOCK_SHIFT: u32 = 17;
+const BAT_BLOCKS: usize = 1 << (32 - BAT_BLOCK_SHIFT);
+const BAT_ENTRY_VALID: u32 = 1;
+//WIMG[I] and WIMG[W], kept alongside so the data cache can tell the uncached mirror apart
+const BAT_ENTRY_INHIBITED: u32 = 1 << 3;
+const BAT_ENTRY_WRITE_THROUGH: u32 = 1 << 4;
+//the W and I bits in a BAT's or a PTE's WIMG
+const WIMG_WRITE_THROUGH: u32 = 0b1000;
+const WIMG_INHIBITED: u32 = 0b0100;
+
+//eight 8 byte PTEs
+pub const PTEG_SIZE: u32 = 64;
+
+//the 750CL has 128 entry, 2-way set associative ITLB and DTLB indexed by EA[14-19]
+const TLB_SETS: usize = 64;
+const TLB_WAYS: usize = 2;
+
 pub struct Mmu {
     pub dbats: [Bat; 4],
     pub ibats: [Bat; 4],
     pub srs: [u32; 16],
+    pub sdr1: u32,
+    dbat_table: BatTable,
+    ibat_table: BatTable,
+    dtlb: Tlb,
+    itlb: Tlb,
 }
 
 impl Mmu {
@@ -14,62 +65,399 @@ impl Mmu {
 	    dbats: [Bat(0, 0); 4],
 	    ibats: [Bat(0, 0); 4],
 	    srs: [0; 16],
+	    sdr1: 0,
+	    dbat_table: BatTable::new(),
+	    ibat_table: BatTable::new(),
+	    dtlb: Tlb::new(),
+	    itlb: Tlb::new(),
 	}
     }
 
     pub fn write_dbatu(&mut self, idx: usize, val: u32) {
 	self.dbats[idx].0 = val;
+	self.dbat_table.rebuild(&self.dbats);
     }
 
     pub fn write_dbatl(&mut self, idx: usize, val: u32) {
 	self.dbats[idx].1 = val;
+	self.dbat_table.rebuild(&self.dbats);
     }
 
     pub fn write_ibatu(&mut self, idx: usize, val: u32) {
 	self.ibats[idx].0 = val;
+	self.ibat_table.rebuild(&self.ibats);
     }
 
     pub fn write_ibatl(&mut self, idx: usize, val: u32) {
 	self.ibats[idx].1 = val;
+	self.ibat_table.rebuild(&self.ibats);
     }
 
-    pub fn translate_addr(&self, instr: bool, addr: u32, msr: &MachineStateRegister) -> u32 {
-	let bats = if instr {
-	    if !msr.ir() {
-		return addr;
-	    }
-	    &self.ibats
+    pub fn write_sr(&mut self, idx: usize, val: u32) {
+	self.srs[idx] = val;
+	self.invalidate_tlbs();
+    }
+
+    //tlbie drops every way of the congruence class the address maps to, whatever the tag
+    pub fn tlbie(&mut self, addr: u32) {
+	self.dtlb.invalidate_set(addr);
+	self.itlb.invalidate_set(addr);
+    }
+
+    pub fn invalidate_tlbs(&mut self) {
+	self.dtlb = Tlb::new();
+	self.itlb = Tlb::new();
+    }
+
+    //memory is physical ram, the page table lives there and gets its R/C bits updated as a side effect
+    pub fn translate_addr(&mut self, access: Access, addr: u32, msr: &MachineStateRegister, memory: &mut [u8]) -> Result<u32, TranslationFault> {
+	let fault = |kind| TranslationFault {
+	    addr,
+	    access,
+	    kind,
+	};
+
+	if let Some(result) = self.translate_bat(access, addr, msr) {
+	    return result.map_err(fault);
+	}
+
+	let tlb = if access == Access::Fetch {
+	    &mut self.itlb
 	} else {
-	    if !msr.dr() {
-		return addr;
+	    &mut self.dtlb
+	};
+
+	let sr_idx = addr >> 28;
+	let sr = SegmentRegister(self.srs[sr_idx as usize]);
+	if sr.t() {
+	    return Err(fault(FaultKind::DirectStore));
+	}
+
+	if access == Access::Fetch && sr.n() {
+	    return Err(fault(FaultKind::NoExecute));
+	}
+
+	let (pte_addr, pte) = match tlb.lookup(sr.vsid(), addr) {
+	    Some(hit) => hit,
+	    None => {
+		let (pte_addr, mut pte) = search_page_table(self.sdr1, sr.vsid(), addr, memory).ok_or(fault(FaultKind::NotFound))?;
+
+		//the table walk is what sets R, not the access itself
+		if !pte.r() {
+		    pte.set_r();
+		    BigEndian::write_u32(&mut memory[(pte_addr as usize + 4)..], pte.1);
+		}
+
+		tlb.insert(sr.vsid(), addr, pte_addr, pte);
+		(pte_addr, pte)
+	    },
+	};
+
+	if !page_allowed(&sr, pte, access, msr) {
+	    return Err(fault(FaultKind::Protection));
+	}
+
+	if access == Access::Write && !pte.c() {
+	    let mut updated = pte;
+	    updated.set_c();
+	    BigEndian::write_u32(&mut memory[(pte_addr as usize + 4)..], updated.1);
+	    tlb.insert(sr.vsid(), addr, pte_addr, updated);
+	}
+
+	Ok((pte.rpn() << 12) | (addr & 0xFFF))
+    }
+
+    //the same translation without any of its side effects, for debuggers looking at memory. the TLBs are only
+    //looked in, no R or C bits get set, and None stands in for whatever fault there would have been
+    pub fn probe(&self, access: Access, addr: u32, msr: &MachineStateRegister, memory: &[u8]) -> Option<u32> {
+	if let Some(result) = self.translate_bat(access, addr, msr) {
+	    return result.ok();
+	}
+
+	let sr = SegmentRegister(self.srs[(addr >> 28) as usize]);
+	if sr.t() || (access == Access::Fetch && sr.n()) {
+	    return None;
+	}
+
+	let tlb = if access == Access::Fetch {
+	    &self.itlb
+	} else {
+	    &self.dtlb
+	};
+	let (_, pte) = tlb.find(sr.vsid(), addr).or_else(|| search_page_table(self.sdr1, sr.vsid(), addr, memory))?;
+	if !page_allowed(&sr, pte, access, msr) {
+	    return None;
+	}
+
+	Some((pte.rpn() << 12) | (addr & 0xFFF))
+    }
+
+    //whether a data access the translation just succeeded for skips the data cache. real mode accesses are
+    //always cacheable, and a page table translation has left its PTE in the DTLB
+    pub fn cache_inhibited(&self, addr: u32, msr: &MachineStateRegister) -> bool {
+	self.data_wimg(addr, msr) & WIMG_INHIBITED != 0
+    }
+
+    //same again for WIMG[W], stores to the page go through to ram as well as the cache
+    pub fn write_through(&self, addr: u32, msr: &MachineStateRegister) -> bool {
+	self.data_wimg(addr, msr) & WIMG_WRITE_THROUGH != 0
+    }
+
+    fn data_wimg(&self, addr: u32, msr: &MachineStateRegister) -> u32 {
+	if !msr.dr() {
+	    return 0;
+	}
+
+	let entry = self.dbat_table.lookup(msr.pr(), addr);
+	if entry & BAT_ENTRY_VALID != 0 {
+	    let mut wimg = 0;
+	    if entry & BAT_ENTRY_INHIBITED != 0 {
+		wimg |= WIMG_INHIBITED;
+	    }
+	    if entry & BAT_ENTRY_WRITE_THROUGH != 0 {
+		wimg |= WIMG_WRITE_THROUGH;
 	    }
-	    &self.dbats
+	    return wimg;
+	}
+
+	let sr = SegmentRegister(self.srs[(addr >> 28) as usize]);
+	self.dtlb.find(sr.vsid(), addr).map_or(0, |(_, pte)| pte.wimg())
+    }
+
+    //the two PTEGs a translation searches on a TLB miss, or None when it never goes near the page table
+    pub fn ptegs(&self, access: Access, addr: u32, msr: &MachineStateRegister) -> Option<[u32; 2]> {
+	if self.translate_bat(access, addr, msr).is_some() {
+	    return None;
+	}
+
+	let sr = SegmentRegister(self.srs[(addr >> 28) as usize]);
+	let primary = (sr.vsid() & 0x7FFFF) ^ ((addr >> 12) & 0xFFFF);
+	Some([pteg_addr(self.sdr1, primary), pteg_addr(self.sdr1, !primary)])
+    }
+
+    //real mode and BAT hits, neither of which touch the page table or the TLBs. None means the page table has to be searched
+    pub fn translate_bat(&self, access: Access, addr: u32, msr: &MachineStateRegister) -> Option<Result<u32, FaultKind>> {
+	let (enabled, bat_table) = if access == Access::Fetch {
+	    (msr.ir(), &self.ibat_table)
+	} else {
+	    (msr.dr(), &self.dbat_table)
+	};
+
+	if !enabled {
+	    return Some(Ok(addr));
+	}
+
+	let entry = bat_table.lookup(msr.pr(), addr);
+	if entry & BAT_ENTRY_VALID == 0 {
+	    return None;
+	}
+
+	//00 is no access, x1 is read only, 10 is read/write
+	let allowed = match (entry >> 1) & 3 {
+	    0b00 => false,
+	    0b10 => true,
+	    _ => access != Access::Write,
 	};
 
-	for bat in bats {
-	    let addr_15 = addr >> 17;
-	    let addr_bepi = (addr_15 & 0x7800) ^ ((addr_15 & 0x7FF) & (!bat.bl()));
+	if !allowed {
+	    return Some(Err(FaultKind::Protection));
+	}
+
+	Some(Ok((entry & !((1 << BAT_BLOCK_SHIFT) - 1)) | (addr & ((1 << BAT_BLOCK_SHIFT) - 1))))
+    }
+}
+
+impl Default for Mmu {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+//returns the physical address of the matching PTE along with the entry itself
+pub fn search_page_table(sdr1: u32, vsid: u32, addr: u32, memory: &[u8]) -> Option<(u32, Pte)> {
+    let page_index = (addr >> 12) & 0xFFFF;
+    let api = page_index >> 10;
+    let primary = (vsid & 0x7FFFF) ^ page_index;
+
+    for (h, hash) in [(false, primary), (true, !primary)] {
+	let pteg = pteg_addr(sdr1, hash);
 
-	    if addr_bepi == bat.bepi() && ((!msr.pr() && bat.vs()) || (msr.pr() && bat.vp())) {
-		let upper = u32::from(bat.brpn() ^ ((addr_15 & 0x7FF) & bat.bl()));
-		let lower = addr & 0x1FFFF;
+	for i in 0..8 {
+	    let pte_addr = pteg + i * 8;
+	    let bytes = memory.get((pte_addr as usize)..(pte_addr as usize + 8))?;
+	    let pte = Pte(BigEndian::read_u32(bytes), BigEndian::read_u32(&bytes[4..]));
 
-		return (upper << 17) ^ lower;
+	    if pte.v() && pte.vsid() == vsid && pte.h() == h && pte.api() == api {
+		return Some((pte_addr, pte));
 	    }
 	}
+    }
 
-	let sr_idx = addr >> 28;
-	let sr = self.srs[sr_idx as usize];
-	let t = (sr >> 31) != 0;
-	if t {
-	    panic!("page translation");
+    None
+}
+
+//the segment's key for the current privilege level picks what the PTE's pp bits allow
+fn page_allowed(sr: &SegmentRegister, pte: Pte, access: Access, msr: &MachineStateRegister) -> bool {
+    let key = if msr.pr() {
+	sr.kp()
+    } else {
+	sr.ks()
+    };
+
+    match (key, pte.pp()) {
+	(false, 0b11) => access != Access::Write,
+	(false, _) => true,
+	(true, 0b00) => false,
+	(true, 0b10) => true,
+	(true, _) => access != Access::Write,
+    }
+}
+
+fn pteg_addr(sdr1: u32, hash: u32) -> u32 {
+    let htaborg = (sdr1 >> 16) & 0x1FF;
+    let htabmask = sdr1 & 0x1FF;
+
+    (sdr1 & 0xFE00_0000) | ((htaborg | ((hash >> 10) & htabmask)) << 16) | ((hash & 0x3FF) << 6)
+}
+
+struct BatTable {
+    supervisor: Vec<u32>,
+    user: Vec<u32>,
+}
+
+impl BatTable {
+    fn new() -> Self {
+	Self {
+	    supervisor: vec![0; BAT_BLOCKS],
+	    user: vec![0; BAT_BLOCKS],
+	}
+    }
+
+    fn lookup(&self, pr: bool, addr: u32) -> u32 {
+	let block = (addr >> BAT_BLOCK_SHIFT) as usize;
+	if pr {
+	    self.user[block]
 	} else {
-	    panic!("direct store");
+	    self.supervisor[block]
+	}
+    }
+
+    fn rebuild(&mut self, bats: &[Bat; 4]) {
+	self.supervisor.fill(0);
+	self.user.fill(0);
+
+	//walk backwards so the lowest numbered BAT wins where two of them overlap
+	for bat in bats.iter().rev() {
+	    if !bat.vs() && !bat.vp() {
+		continue;
+	    }
+
+	    for block in 0..BAT_BLOCKS as u32 {
+		if (block & 0x7800) ^ ((block & 0x7FF) & !bat.bl()) != bat.bepi() {
+		    continue;
+		}
+
+		let upper = bat.brpn() ^ ((block & 0x7FF) & bat.bl());
+		let mut entry = (upper << BAT_BLOCK_SHIFT) | (bat.pp() << 1) | BAT_ENTRY_VALID;
+		if bat.wimg() & WIMG_INHIBITED != 0 {
+		    entry |= BAT_ENTRY_INHIBITED;
+		}
+		if bat.wimg() & WIMG_WRITE_THROUGH != 0 {
+		    entry |= BAT_ENTRY_WRITE_THROUGH;
+		}
+
+		if bat.vs() {
+		    self.supervisor[block as usize] = entry;
+		}
+		if bat.vp() {
+		    self.user[block as usize] = entry;
+		}
+	    }
 	}
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Copy, Clone)]
+struct TlbEntry {
+    valid: bool,
+    vsid: u32,
+    //effective page number, EA[0-19]
+    page: u32,
+    pte_addr: u32,
+    pte: Pte,
+}
+
+impl TlbEntry {
+    const INVALID: Self = Self {
+	valid: false,
+	vsid: 0,
+	page: 0,
+	pte_addr: 0,
+	pte: Pte(0, 0),
+    };
+}
+
+struct Tlb {
+    sets: [[TlbEntry; TLB_WAYS]; TLB_SETS],
+    //way to replace next in each set
+    lru: [usize; TLB_SETS],
+}
+
+impl Tlb {
+    fn new() -> Self {
+	Self {
+	    sets: [[TlbEntry::INVALID; TLB_WAYS]; TLB_SETS],
+	    lru: [0; TLB_SETS],
+	}
+    }
+
+    fn set_index(addr: u32) -> usize {
+	((addr >> 12) as usize) & (TLB_SETS - 1)
+    }
+
+    fn way(&self, vsid: u32, addr: u32) -> Option<usize> {
+	let page = addr >> 12;
+	self.sets[Self::set_index(addr)].iter().position(|entry| entry.valid && entry.vsid == vsid && entry.page == page)
+    }
+
+    //a lookup that leaves the replacement order alone
+    fn find(&self, vsid: u32, addr: u32) -> Option<(u32, Pte)> {
+	let entry = self.sets[Self::set_index(addr)][self.way(vsid, addr)?];
+	Some((entry.pte_addr, entry.pte))
+    }
+
+    fn lookup(&mut self, vsid: u32, addr: u32) -> Option<(u32, Pte)> {
+	let way = self.way(vsid, addr)?;
+	let set = Self::set_index(addr);
+	self.lru[set] = (way + 1) % TLB_WAYS;
+	let entry = self.sets[set][way];
+	Some((entry.pte_addr, entry.pte))
+    }
+
+    fn insert(&mut self, vsid: u32, addr: u32, pte_addr: u32, pte: Pte) {
+	let set = Self::set_index(addr);
+	let page = addr >> 12;
+
+	//refresh an existing entry in place (C bit updates), otherwise evict the lru way
+	let way = self.way(vsid, addr).unwrap_or(self.lru[set]);
+
+	self.sets[set][way] = TlbEntry {
+	    valid: true,
+	    vsid,
+	    page,
+	    pte_addr,
+	    pte,
+	};
+	self.lru[set] = (way + 1) % TLB_WAYS;
+    }
+
+    fn invalidate_set(&mut self, addr: u32) {
+	let set = Self::set_index(addr);
+	self.sets[set] = [TlbEntry::INVALID; TLB_WAYS];
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 //             batu,    batl
 pub struct Bat(pub u32, pub u32);
 
@@ -102,3 +490,155 @@ impl Bat {
 	self.1 & 3
     }
 }
+
+pub struct SegmentRegister(pub u32);
+
+impl SegmentRegister {
+    pub fn t(&self) -> bool {
+	((self.0 >> 31) & 1) != 0
+    }
+
+    pub fn ks(&self) -> bool {
+	((self.0 >> 30) & 1) != 0
+    }
+
+    pub fn kp(&self) -> bool {
+	((self.0 >> 29) & 1) != 0
+    }
+
+    pub fn n(&self) -> bool {
+	((self.0 >> 28) & 1) != 0
+    }
+
+    pub fn vsid(&self) -> u32 {
+	self.0 & 0x00FF_FFFF
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+//             word 0,  word 1
+pub struct Pte(pub u32, pub u32);
+
+impl Pte {
+    pub fn v(&self) -> bool {
+	((self.0 >> 31) & 1) != 0
+    }
+
+    pub fn vsid(&self) -> u32 {
+	(self.0 >> 7) & 0x00FF_FFFF
+    }
+
+    pub fn h(&self) -> bool {
+	((self.0 >> 6) & 1) != 0
+    }
+
+    pub fn api(&self) -> u32 {
+	self.0 & 0x3F
+    }
+
+    pub fn rpn(&self) -> u32 {
+	self.1 >> 12
+    }
+
+    pub fn r(&self) -> bool {
+	((self.1 >> 8) & 1) != 0
+    }
+
+    pub fn set_r(&mut self) {
+	self.1 |= 1 << 8;
+    }
+
+    pub fn c(&self) -> bool {
+	((self.1 >> 7) & 1) != 0
+    }
+
+    pub fn set_c(&mut self) {
+	self.1 |= 1 << 7;
+    }
+
+    pub fn wimg(&self) -> u32 {
+	(self.1 >> 3) & 0xF
+    }
+
+    pub fn pp(&self) -> u32 {
+	self.1 & 3
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{BigEndian, ByteOrder};
+
+    use crate::cpu::MachineStateRegister;
+
+    use super::{search_page_table, Access, FaultKind, Mmu};
+
+    const MSR_DR: MachineStateRegister = MachineStateRegister(0x10);
+    //a 128kb table at 0x20000
+    const SDR1: u32 = 0x0002_0001;
+    const VSID: u32 = 0x123;
+    //page 0x456 of segment 0, the hash is 0x123 ^ 0x456 = 0x575
+    const EA: u32 = 0x0045_6ABC;
+    const PRIMARY_PTEG: u32 = 0x3_5D40;
+    const SECONDARY_PTEG: u32 = 0x2_A280;
+
+    //PTE for EA in the secondary PTEG's fourth slot, mapping it read/write to page 0x789
+    fn setup() -> (Mmu, Vec<u8>) {
+	let mut mmu = Mmu::new();
+	mmu.sdr1 = SDR1;
+	mmu.write_sr(0, VSID);
+
+	let mut memory = vec![0; 0x4_0000];
+	let pte = SECONDARY_PTEG as usize + 3 * 8;
+	BigEndian::write_u32(&mut memory[pte..], 0x8000_0000 | (VSID << 7) | (1 << 6) | (0x456 >> 10));
+	BigEndian::write_u32(&mut memory[(pte + 4)..], 0x0078_9002);
+	(mmu, memory)
+    }
+
+    fn pte_lower(memory: &[u8]) -> u32 {
+	BigEndian::read_u32(&memory[(SECONDARY_PTEG as usize + 3 * 8 + 4)..])
+    }
+
+    #[test]
+    fn the_pteg_hash_finds_ptes_in_either_group() {
+	let (_, mut memory) = setup();
+	assert_eq!(search_page_table(SDR1, VSID, EA, &memory).map(|(addr, _)| addr), Some(SECONDARY_PTEG + 3 * 8));
+
+	//the same PTE in the primary group has H clear
+	let primary = PRIMARY_PTEG as usize + 5 * 8;
+	BigEndian::write_u32(&mut memory[primary..], 0x8000_0000 | (VSID << 7) | (0x456 >> 10));
+	assert_eq!(search_page_table(SDR1, VSID, EA, &memory).map(|(addr, _)| addr), Some(PRIMARY_PTEG + 5 * 8));
+
+	//a different vsid misses both
+	assert!(search_page_table(SDR1, VSID + 1, EA, &memory).is_none());
+    }
+
+    #[test]
+    fn the_walk_sets_r_and_stores_set_c() {
+	let (mut mmu, mut memory) = setup();
+
+	assert_eq!(mmu.translate_addr(Access::Read, EA, &MSR_DR, &mut memory), Ok(0x0078_9ABC));
+	assert_eq!(pte_lower(&memory), 0x0078_9102);
+
+	assert_eq!(mmu.translate_addr(Access::Write, EA, &MSR_DR, &mut memory), Ok(0x0078_9ABC));
+	assert_eq!(pte_lower(&memory), 0x0078_9182);
+
+	let fault = mmu.translate_addr(Access::Read, 0x0046_6000, &MSR_DR, &mut memory).unwrap_err();
+	assert_eq!(fault.kind, FaultKind::NotFound);
+    }
+
+    #[test]
+    fn translations_stay_in_the_tlb_until_tlbie() {
+	let (mut mmu, mut memory) = setup();
+	assert_eq!(mmu.translate_addr(Access::Read, EA, &MSR_DR, &mut memory), Ok(0x0078_9ABC));
+
+	//the TLB hit doesn't look at the table again
+	let pte = SECONDARY_PTEG as usize + 3 * 8 + 4;
+	BigEndian::write_u32(&mut memory[pte..], 0x0012_3102);
+	assert_eq!(mmu.translate_addr(Access::Read, EA, &MSR_DR, &mut memory), Ok(0x0078_9ABC));
+
+	//tlbie takes out the whole congruence class, any address in the same page index will do
+	mmu.tlbie(0x1045_6000);
+	assert_eq!(mmu.translate_addr(Access::Read, EA, &MSR_DR, &mut memory), Ok(0x0012_3ABC));
+    }
+}
@@ -8,50 +8,173 @@ pub mod cache;
 pub mod mmu;
 pub mod util;
 pub mod control_flow;
+pub mod decode;
+pub mod paired;
+pub mod block;
+pub mod gather_pipe;
+#[cfg(all(feature = "jit", target_arch = "x86_64", unix))]
+pub mod jit;
 
 use std::cmp::Ordering;
 
-use arithmetic::{add, addc, adde, addi, addic, addicr, addis, cmp, cmpi, cmpl, cmpli, cntlzw, mulhwu, mulli, mullw, neg, subf, subfc, subfe, subfic};
-use bitwise::{and, andc, andi, crxor, extsh, nor, or, ori, oris, rlwimi, rlwinm, slw, sraw, srw, xor, xoris};
-use cache::isync;
-use config::{mffs, mfmsr, mfspr, mftb, mtfsb1, mtfsf, mtmsr, mtspr, mtsr, sc};
-use control_flow::{b, bc, bcctr, bclr, rfi};
-use float::{ps_mr, fmr};
+use block::BlockCache;
+use cache::{DataCache, LockedCacheDma, HID0_DCFI, LOCKED_CACHE_SIZE};
+use decode::{DecodeCache, Decoded};
+use gather_pipe::GatherPipe;
 use instr::Instruction;
-use load_store::{lbz, lbzu, lfd, lfs, lhz, lhzu, lhzx, lmw, lwz, lwzu, lwzx, psq_l, stb, stbu, stfd, stfs, stfsu, sth, stmw, stw, stwu, stwx};
-use log::{debug, info};
-use mmu::Mmu;
+use log::{debug, warn};
+use mmu::{Access, FaultKind, Mmu, TranslationFault};
 
 pub const RESET_EXCEPTION: u32   = 0x1;
 pub const PROGRAM_EXCEPTION: u32 = 0x2;
 pub const SYSTEMCALL_EXCEPTION: u32 = 0x4;
+pub const MACHINE_CHECK_EXCEPTION: u32 = 0x8;
+pub const DSI_EXCEPTION: u32 = 0x10;
+pub const ISI_EXCEPTION: u32 = 0x20;
+pub const EXTERNAL_EXCEPTION: u32 = 0x40;
+pub const ALIGNMENT_EXCEPTION: u32 = 0x80;
+pub const FP_UNAVAILABLE_EXCEPTION: u32 = 0x100;
+pub const DECREMENTER_EXCEPTION: u32 = 0x200;
+pub const TRACE_EXCEPTION: u32 = 0x400;
+pub const PERFORMANCE_MONITOR_EXCEPTION: u32 = 0x800;
+pub const IABR_EXCEPTION: u32 = 0x1000;
+pub const THERMAL_EXCEPTION: u32 = 0x2000;
+
+//only taken while MSR[EE] is set, until then they stay pending
+const ASYNCHRONOUS_EXCEPTIONS: u32 = EXTERNAL_EXCEPTION | PERFORMANCE_MONITOR_EXCEPTION | DECREMENTER_EXCEPTION | THERMAL_EXCEPTION;
+
+//highest priority first. reset doesn't save any state so it's handled on its own
+const EXCEPTION_VECTORS: [(u32, u32); 13] = [
+    (MACHINE_CHECK_EXCEPTION, 0x200),
+    (IABR_EXCEPTION, 0x1300),
+    (ISI_EXCEPTION, 0x400),
+    (PROGRAM_EXCEPTION, 0x700),
+    (FP_UNAVAILABLE_EXCEPTION, 0x800),
+    (DSI_EXCEPTION, 0x300),
+    (ALIGNMENT_EXCEPTION, 0x600),
+    (SYSTEMCALL_EXCEPTION, 0xC00),
+    (TRACE_EXCEPTION, 0xD00),
+    (EXTERNAL_EXCEPTION, 0x500),
+    (PERFORMANCE_MONITOR_EXCEPTION, 0xF00),
+    (DECREMENTER_EXCEPTION, 0x900),
+    (THERMAL_EXCEPTION, 0x1700),
+];
+
+//the timebase and decrementer tick at a quarter of the 162MHz bus clock, and the cpu runs at 3x the bus
+pub const CYCLES_PER_TIMEBASE_TICK: u64 = 12;
+
+//SRR1 reason bits for program exceptions
+pub const PROGRAM_FLOATING_POINT: u32 = 0x0010_0000;
+pub const PROGRAM_ILLEGAL: u32 = 0x0008_0000;
+pub const PROGRAM_PRIVILEGED: u32 = 0x0004_0000;
+pub const PROGRAM_TRAP: u32 = 0x0002_0000;
 
 use crate::Gamecube;
 
+//how run_slice gets instructions executed. the interpreter is kept around as the reference implementation
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CpuEngine {
+    Interpreter,
+    Cached,
+    #[cfg(all(feature = "jit", target_arch = "x86_64", unix))]
+    Jit,
+}
+
+//architected state, for comparing two cpus that are meant to be running the same code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuSnapshot {
+    pub cia: u32,
+    pub gprs: [u32; 32],
+    pub fprs: [(u64, u64); 32],
+    pub cr: u32,
+    pub xer: u32,
+    pub lr: u32,
+    pub ctr: u32,
+    pub msr: u32,
+    pub fpscr: u32,
+    pub srr0: u32,
+    pub srr1: u32,
+    pub dec: u32,
+    pub tb: u64,
+    pub cycles: u64,
+}
+
+impl CpuSnapshot {
+    //every register that differs from other, by name, with this snapshot's value first
+    pub fn diff(&self, other: &CpuSnapshot) -> Vec<(String, u64, u64)> {
+	let mut regs = vec![
+	    ("cia".to_string(), self.cia.into(), other.cia.into()),
+	    ("cr".to_string(), self.cr.into(), other.cr.into()),
+	    ("xer".to_string(), self.xer.into(), other.xer.into()),
+	    ("lr".to_string(), self.lr.into(), other.lr.into()),
+	    ("ctr".to_string(), self.ctr.into(), other.ctr.into()),
+	    ("msr".to_string(), self.msr.into(), other.msr.into()),
+	    ("fpscr".to_string(), self.fpscr.into(), other.fpscr.into()),
+	    ("srr0".to_string(), self.srr0.into(), other.srr0.into()),
+	    ("srr1".to_string(), self.srr1.into(), other.srr1.into()),
+	    ("dec".to_string(), self.dec.into(), other.dec.into()),
+	    ("tb".to_string(), self.tb, other.tb),
+	    ("cycles".to_string(), self.cycles, other.cycles),
+	];
+	regs.extend((0..32).map(|i| (format!("r{i}"), self.gprs[i].into(), other.gprs[i].into())));
+	regs.extend((0..32).flat_map(|i| {
+	    [(format!("f{i}.ps0"), self.fprs[i].0, other.fprs[i].0), (format!("f{i}.ps1"), self.fprs[i].1, other.fprs[i].1)]
+	}));
+	regs.retain(|(_, subject, reference)| subject != reference);
+	regs
+    }
+}
+
 pub struct Cpu {
     pub cia: u32,
     pub nia: u32,
     pub gprs: [u32; 32],
     pub mmu: Mmu,
     pub hid0: u32,
+    pub hid1: u32,
     pub hid2: HID2,
     pub wpar: u32,
+    pub gather_pipe: GatherPipe,
     pub gqrs: [GraphicsQuantizationRegister; 8],
     pub fprs: [FloatingPointRegister; 32],
     pub msr: MachineStateRegister,
     pub tb: u64,
+    pub dec: u32,
+    pub cycles: u64,
     pub cr: ConditionRegister,
     pub ctr: u32,
     pub lr: u32,
     pub srr0: u32,
     pub srr1: u32,
+    pub sprgs: [u32; 4],
+    pub iabr: u32,
+    pub dabr: u32,
+    //external access register, gates eciwx and ecowx
+    pub ear: u32,
+    pub dsisr: u32,
+    pub dar: u32,
     pub exceptions: u32,
+    //extra SRR1 bits describing why the pending exception happened
+    pub srr1_flags: u32,
     pub fpscr: FloatingPointStatusControlRegister,
     pub l2cr: u32,
+    //thermal management, there's no diode to read so these only hold what was written
+    pub thrm: [u32; 3],
+    pub ictc: u32,
     pub xer: XER,
     pub pmcs: [u32; 4],
     pub mmcr0: u32,
     pub mmcr1: u32,
+    pub locked_cache: Vec<u8>,
+    pub lc_dma: LockedCacheDma,
+    pub dcache: DataCache,
+    pub decode_cache: DecodeCache,
+    pub engine: CpuEngine,
+    pub blocks: BlockCache,
+    //where the cpu checkstopped, if it has. it doesn't run anything from then on, the frontend gets to say so
+    pub checkstop: Option<u32>,
+    #[cfg(all(feature = "jit", target_arch = "x86_64", unix))]
+    pub jit: jit::Jit,
 }
 
 impl Cpu {
@@ -62,24 +185,65 @@ impl Cpu {
 	    gprs: [0; 32],
 	    mmu: Mmu::new(),
 	    hid0: 0,
+	    hid1: 0,
 	    hid2: HID2(0),
 	    wpar: 0,
+	    gather_pipe: GatherPipe::new(),
 	    gqrs: [GraphicsQuantizationRegister(0); 8],
 	    fprs: [FloatingPointRegister::from_u64(0); 32],
 	    msr: MachineStateRegister(0x40),
 	    tb: 0,
+	    dec: 0,
+	    cycles: 0,
 	    cr: ConditionRegister(0),
 	    ctr: 0,
 	    lr: 0,
 	    srr0: 0,
 	    srr1: 0,
+	    sprgs: [0; 4],
+	    iabr: 0,
+	    dabr: 0,
+	    ear: 0,
+	    dsisr: 0,
+	    dar: 0,
 	    exceptions: 0,
+	    srr1_flags: 0,
 	    fpscr: FloatingPointStatusControlRegister(0),
 	    l2cr: 0,
+	    thrm: [0; 3],
+	    ictc: 0,
 	    xer: XER(0),
 	    pmcs: [0; 4],
 	    mmcr0: 0,
 	    mmcr1: 0,
+	    locked_cache: vec![0; LOCKED_CACHE_SIZE],
+	    lc_dma: LockedCacheDma::new(),
+	    dcache: DataCache::new(),
+	    decode_cache: DecodeCache::new(),
+	    engine: CpuEngine::Cached,
+	    blocks: BlockCache::new(),
+	    checkstop: None,
+	    #[cfg(all(feature = "jit", target_arch = "x86_64", unix))]
+	    jit: jit::Jit::new(),
+	}
+    }
+
+    pub fn snapshot(&self) -> CpuSnapshot {
+	CpuSnapshot {
+	    cia: self.cia,
+	    gprs: self.gprs,
+	    fprs: self.fprs.map(|fpr| (fpr.ps0, fpr.ps1)),
+	    cr: self.cr.0,
+	    xer: self.xer.0,
+	    lr: self.lr,
+	    ctr: self.ctr,
+	    msr: self.msr.0,
+	    fpscr: self.fpscr.0,
+	    srr0: self.srr0,
+	    srr1: self.srr1,
+	    dec: self.dec,
+	    tb: self.tb,
+	    cycles: self.cycles,
 	}
     }
 
@@ -92,9 +256,85 @@ impl Cpu {
 	    Ordering::Less => 0x8,
 	};
 
-	//xer here :3
+	self.cr.set_reg(0, order | self.xer.so() as u32);
+    }
+
+    pub fn translation_fault(&mut self, fault: &TranslationFault) {
+	if fault.access == Access::Fetch {
+	    self.srr1_flags = match fault.kind {
+		FaultKind::NotFound => 0x4000_0000,
+		FaultKind::DirectStore | FaultKind::NoExecute => 0x1000_0000,
+		FaultKind::Protection => 0x0800_0000,
+	    };
+	    self.exceptions |= ISI_EXCEPTION;
+	} else {
+	    self.dsisr = match fault.kind {
+		FaultKind::NotFound => 0x4000_0000,
+		FaultKind::Protection => 0x0800_0000,
+		FaultKind::DirectStore => 0x0400_0000,
+		FaultKind::NoExecute => unreachable!("no-execute fault on a data access"),
+	    };
+	    if fault.access == Access::Write {
+		self.dsisr |= 0x0200_0000;
+	    }
+	    self.dar = fault.addr;
+	    self.exceptions |= DSI_EXCEPTION;
+	}
+
+	//the faulting instruction never completes, so srr0 has to point back at it
+	self.nia = self.cia;
+    }
+
+    //a decrementer exception is requested whenever DEC[0] goes from 0 to 1, whether by counting or by mtdec
+    pub fn write_dec(&mut self, val: u32) {
+	if self.dec & 0x8000_0000 == 0 && val & 0x8000_0000 != 0 {
+	    self.exceptions |= DECREMENTER_EXCEPTION;
+	}
+
+	self.dec = val;
+    }
+
+    pub fn tick(&mut self, cycles: u64) {
+	let before = self.cycles / CYCLES_PER_TIMEBASE_TICK;
+	self.cycles += cycles;
+	let ticks = self.cycles / CYCLES_PER_TIMEBASE_TICK - before;
+
+	if ticks != 0 {
+	    self.tb = self.tb.wrapping_add(ticks);
+	    self.write_dec(self.dec.wrapping_sub(ticks as u32));
+	}
+    }
+
+    pub fn program_exception(&mut self, reason: u32) {
+	self.srr1_flags = reason;
+	self.exceptions |= PROGRAM_EXCEPTION;
+	self.nia = self.cia;
+    }
+
+    //for instructions that only run in supervisor mode, raises a privileged program exception otherwise
+    pub fn check_supervisor(&mut self) -> bool {
+	if self.msr.pr() {
+	    self.program_exception(PROGRAM_PRIVILEGED);
+	    return false;
+	}
 
-	self.cr.set_reg(0, order);
+	true
+    }
+
+    pub fn alignment_fault(&mut self, addr: u32, instr: &Instruction) {
+	let word = instr.0;
+
+	//DSISR[15-21] describe the instruction, which bits get copied depends on the form
+	let (bits_15_16, bit_17, bits_18_21) = if instr.opcd() == 0b011111 {
+	    ((word >> 1) & 3, (word >> 6) & 1, (word >> 7) & 0xF)
+	} else {
+	    (0, (word >> 26) & 1, (word >> 27) & 0xF)
+	};
+
+	self.dsisr = (bits_15_16 << 15) | (bit_17 << 14) | (bits_18_21 << 10) | (((word >> 21) & 0x1F) << 5) | ((word >> 16) & 0x1F);
+	self.dar = addr;
+	self.exceptions |= ALIGNMENT_EXCEPTION;
+	self.nia = self.cia;
     }
 
     pub fn exception(&mut self) {
@@ -106,148 +346,145 @@ impl Cpu {
 	    }
 
 	    self.exceptions &= !RESET_EXCEPTION;
-	} else if self.exceptions & PROGRAM_EXCEPTION != 0 {
-	    self.srr0 = self.nia;
-	    self.srr1 = self.msr.0 & 0x87C0_FFFF;
-	    self.msr.set_le(self.msr.ile());
-	    self.msr.0 &= !0x04EF36;
-	    if self.msr.ip() {
-		self.cia = 0xFFF0_0700;
-	    } else {
-		self.cia = 0x700;
-	    }
+	    return;
+	}
 
-	    self.nia = self.cia;
+	let pending = EXCEPTION_VECTORS.iter().find(|(exception, _)| {
+	    self.exceptions & exception != 0 && (exception & ASYNCHRONOUS_EXCEPTIONS == 0 || self.msr.ee())
+	});
+	let Some(&(exception, vector)) = pending else {
+	    return;
+	};
 
-	    self.exceptions &= !PROGRAM_EXCEPTION;
-	} else if self.exceptions & SYSTEMCALL_EXCEPTION != 0 {
-	    self.srr0 = self.nia;
-	    self.srr1 = self.msr.0 & 0x87C0_FFFF;
-	    self.msr.set_le(self.msr.ile());
-	    self.msr.0 &= !0x04EF36;
-	    if self.msr.ip() {
-		self.cia = 0xFFF0_0C00;
-	    } else {
-		self.cia = 0xC00;
+	//machine checks also clear ME, a second one while it's clear stops the cpu
+	let msr_mask = if exception == MACHINE_CHECK_EXCEPTION {
+	    if !self.msr.me() {
+		warn!("checkstop: machine check with MSR[ME] clear at {:#010X}", self.nia);
+		self.checkstop = Some(self.nia);
+		self.exceptions &= !MACHINE_CHECK_EXCEPTION;
+		return;
 	    }
+	    0x04FF36
+	} else {
+	    0x04EF36
+	};
+
+	self.srr0 = self.nia;
+	self.srr1 = (self.msr.0 & 0x87C0_FFFF) | self.srr1_flags;
+	self.srr1_flags = 0;
+	self.msr.set_le(self.msr.ile());
+	self.msr.0 &= !msr_mask;
+	if self.msr.ip() {
+	    self.cia = 0xFFF0_0000 | vector;
+	} else {
+	    self.cia = vector;
+	}
 
-	    self.nia = self.cia;
+	self.nia = self.cia;
 
-	    self.exceptions &= !SYSTEMCALL_EXCEPTION;
+	//external is level triggered, it stays up until the PI drops it
+	if exception != EXTERNAL_EXCEPTION {
+	    self.exceptions &= !exception;
 	}
     }
 }
 
+impl Default for Cpu {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+//the instruction at cia never executes, take whatever exception is pending with srr0 pointing at it
+fn abort_instruction(gc: &mut Gamecube) {
+    gc.cpu.nia = gc.cpu.cia;
+    gc.cpu.exception();
+    gc.cpu.nia = gc.cpu.cia.wrapping_add(4);
+}
+
+//IABR[30] enables the breakpoint, the low two bits aren't part of the address
+fn check_iabr(gc: &mut Gamecube, addr: u32) -> bool {
+    if gc.cpu.iabr & 2 != 0 && gc.cpu.iabr & !3 == addr {
+	gc.cpu.exceptions |= IABR_EXCEPTION;
+	abort_instruction(gc);
+	return false;
+    }
+
+    true
+}
+
 pub fn step(gc: &mut Gamecube) {
+    //a checkstopped cpu just lets the time go by
+    if gc.cpu.checkstop.is_some() {
+	gc.cpu.tick(1);
+	return;
+    }
+
     let addr = gc.cpu.cia;
 
-    let instruction = Instruction(gc.read_u32(addr, true));
-    
-    match instruction.opcd() {
-	0b000100 => ps_mr(gc, &instruction),
-	0b000111 => mulli(gc, &instruction),
-	0b001000 => subfic(gc, &instruction),
-	0b001010 => cmpli(gc, &instruction),
-	0b001011 => cmpi(gc, &instruction),
-	0b001100 => addic(gc, &instruction),
-	0b001101 => addicr(gc, &instruction),
-	0b001110 => addi(gc, &instruction),
-	0b001111 => addis(gc, &instruction),
-	0b010000 => bc(gc, &instruction),
-	0b010001 => sc(gc, &instruction),
-	0b010010 => b(gc, &instruction),
-	0b010100 => rlwimi(gc, &instruction),
-	0b010101 => rlwinm(gc, &instruction),
-	0b011000 => ori(gc, &instruction),
-	0b011001 => oris(gc, &instruction),
-	0b011011 => xoris(gc, &instruction),
-	0b010011 => match instruction.sec_opcd() {
-	    0b0000010000 => bclr(gc, &instruction),
-	    0b0000110010 => rfi(gc, &instruction),
-	    0b0010010110 => isync(gc, &instruction),
-	    0b0011000001 => crxor(gc, &instruction),
-	    0b1000010000 => bcctr(gc, &instruction),
-	    a => unimplemented!("secondary opcode: {a:#012b}, primary: 0b010011, instruction: {:#034b}", instruction.0),
-	},
-	0b011100 => andi(gc, &instruction),
-	0b011111 => match instruction.sec_opcd() {
-	    0b0000000000 => cmp(gc, &instruction),
-	    0b0000001000 => subfc(gc, &instruction),
-	    0b0000001010 => addc(gc, &instruction),
-	    0b0000001011 => mulhwu(gc, &instruction),
-	    0b0000010111 => lwzx(gc, &instruction),
-	    0b0000011000 => slw(gc, &instruction),
-	    0b0000011010 => cntlzw(gc, &instruction),
-	    0b0000011100 => and(gc, &instruction),
-	    0b0000100000 => cmpl(gc, &instruction),
-	    0b0000101000 => subf(gc, &instruction),
-	    0b0000111100 => andc(gc, &instruction),
-	    0b0001010011 => mfmsr(gc, &instruction),
-	    0b0001010110 => info!("dcbf!"),
-	    0b0001101000 => neg(gc, &instruction),
-	    0b0001111100 => nor(gc, &instruction),
-	    0b0010001000 => subfe(gc, &instruction),
-	    0b0010001010 => adde(gc, &instruction),
-	    0b0010010010 => mtmsr(gc, &instruction),
-	    0b0010010111 => stwx(gc, &instruction),
-	    0b0011010010 => mtsr(gc, &instruction),
-	    0b0011101011 => mullw(gc, &instruction),
-	    0b0100001010 => add(gc, &instruction),
-	    0b0100010111 => lhzx(gc, &instruction),
-	    0b0100111100 => xor(gc, &instruction),
-	    0b0101010011 => mfspr(gc, &instruction),
-	    0b0101110011 => mftb(gc, &instruction),
-	    0b0110111100 => or(gc, &instruction),
-	    0b0111010011 => mtspr(gc, &instruction),
-	    0b0111010110 => info!("dcbi"),
-	    0b1000011000 => srw(gc, &instruction),
-	    0b1001010110 => info!("sync!"),
-	    0b1100011000 => sraw(gc, &instruction),
-	    0b1110011010 => extsh(gc, &instruction),
-	    0b1111010110 => info!("icbi!"),
-	    a => unimplemented!("secondary opcode: {a:#012b}, primary: 0b011111, instruction: {:#034b}", instruction.0),
-	},
-	0b100000 => lwz(gc, &instruction),
-	0b100001 => lwzu(gc, &instruction),
-	0b100010 => lbz(gc, &instruction),
-	0b100011 => lbzu(gc, &instruction),
-	0b100110 => stb(gc, &instruction),
-	0b100111 => stbu(gc, &instruction),
-	0b100100 => stw(gc, &instruction),
-	0b101000 => lhz(gc, &instruction),
-	0b101001 => lhzu(gc, &instruction),
-	0b100101 => stwu(gc, &instruction),
-	0b101100 => sth(gc, &instruction),
-	0b101110 => lmw(gc, &instruction),
-	0b101111 => stmw(gc, &instruction),
-	0b110000 => lfs(gc, &instruction),
-	0b110010 => lfd(gc, &instruction),
-	0b110100 => stfs(gc, &instruction),
-	0b110101 => stfsu(gc, &instruction),
-	0b110110 => stfd(gc, &instruction),
-	0b111000 => psq_l(gc, &instruction),
-	0b111111 => match instruction.sec_opcd() {
-	    0b0000100110 => mtfsb1(gc, &instruction),
-	    0b0001001000 => fmr(gc, &instruction),
-	    0b1001000111 => mffs(gc, &instruction),
-	    0b1011000111 => mtfsf(gc, &instruction),
-	    a => unimplemented!("secondary opcode {a:#012b}, primary: 0b111111, instruction: {:#034b}", instruction.0),
-	}
-	a => unimplemented!("opcode: {a:#08b}, instruction: {:#034b}", instruction.0),
+    if !check_iabr(gc, addr) {
+	return;
     }
+
+    let Ok(decoded) = decode::fetch(gc, addr) else {
+	abort_instruction(gc);
+	return;
+    };
+
+    execute(gc, decoded);
+}
+
+//runs an already fetched instruction at cia, anything that runs instructions has to go through here to stay in line with step
+pub fn execute(gc: &mut Gamecube, decoded: Decoded) {
+    let instruction = decoded.instr;
+
+    if !gc.cpu.msr.fp() && instruction.is_fp() {
+	gc.cpu.exceptions |= FP_UNAVAILABLE_EXCEPTION;
+	abort_instruction(gc);
+	return;
+    }
+
+    let trace = gc.cpu.msr.se();
     
+    (decoded.handler)(gc, &instruction);
+    
+    //single step trace fires after the instruction completes, unless it faulted
+    if trace && gc.cpu.exceptions & !ASYNCHRONOUS_EXCEPTIONS == 0 {
+	gc.cpu.exceptions |= TRACE_EXCEPTION;
+    }
+
     gc.cpu.cia = gc.cpu.nia;
 
     gc.cpu.exception();
 
-    gc.cpu.tb += 1;
+    //everything is treated as a single cycle instruction for now
+    gc.cpu.tick(1);
 
     gc.cpu.nia = gc.cpu.cia.wrapping_add(4);
 }
 
+//runs at least one instruction on whichever engine is selected, stopping early rather than going past until
+pub fn run_block(gc: &mut Gamecube, until: u64) {
+    if gc.cpu.checkstop.is_some() {
+	gc.cpu.tick(until.saturating_sub(gc.cpu.cycles).max(1));
+	return;
+    }
+
+    match gc.cpu.engine {
+	CpuEngine::Interpreter => step(gc),
+	CpuEngine::Cached => block::run_block(gc, until),
+	#[cfg(all(feature = "jit", target_arch = "x86_64", unix))]
+	CpuEngine::Jit => jit::run_block(gc, until),
+    }
+}
+
 pub fn write_hid0(gc: &mut Gamecube, val: u32) {
     debug!("STUB: hid0 write with val {val:#034b}");
-    gc.cpu.hid0 = val;
+    //DCFI does its thing and doesn't stay set
+    if val & HID0_DCFI != 0 {
+	gc.cpu.dcache.invalidate_all();
+    }
+    gc.cpu.hid0 = val & !HID0_DCFI;
 }
 
 pub struct MachineStateRegister(pub u32);
@@ -332,6 +569,15 @@ impl ConditionRegister {
     pub fn get_reg(&self, index: usize) -> usize {
 	((self.0 >> (28 - (index * 4))) & 0xF) as usize 
     }
+
+    //bit 0 is the most significant, matching how crbA/crbB/crbD number them
+    pub fn get_bit(&self, bit: usize) -> bool {
+	((self.0 >> (31 - bit)) & 1) != 0
+    }
+
+    pub fn set_bit(&mut self, bit: usize, val: bool) {
+	self.0 = (self.0 & !(1 << (31 - bit))) | ((val as u32) << (31 - bit));
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -355,87 +601,42 @@ impl GraphicsQuantizationRegister {
     }
 }
 
+//gekko fprs are two doubles wide. ps0 is the plain fpr everything outside of paired singles sees,
+//single precision and paired single instructions just round what they put in the halves.
 #[derive(Copy, Clone)]
-#[repr(C)]
-pub union FloatingPointRegister {
-    pub ps_i: (u32, u32),
-    pub ps_f: (f32, f32),
-    pub double: f64,
-    pub int: u64,
+pub struct FloatingPointRegister {
+    pub ps0: u64,
+    pub ps1: u64,
 }
 
-//i don't know if i need all of these fields and methods but i kinda zoned out while writing them and now they're here so might as well keep them
 impl FloatingPointRegister {
-    pub fn from_paired_u32(ints: (u32, u32)) -> Self {
-	Self {
-	    ps_i: ints
-	}
-    }
-
-    pub fn from_paired_f32(floats: (f32, f32)) -> Self {
-	Self {
-	    ps_f: floats
-	}
-    }
-
-    pub fn from_f64(double: f64) -> Self {
-	Self {
-	    double,
-	}
-    }
-
     pub fn from_u64(int: u64) -> Self {
 	Self {
-	    int,
-	}
-    }
-    
-    pub fn as_paired_u32(&self) -> &(u32, u32) {
-	unsafe {
-	    &self.ps_i
-	}
-    }
-
-    pub fn as_paired_u32_mut(&mut self) -> &mut (u32, u32) {
-	unsafe {
-	    &mut self.ps_i
+	    ps0: int,
+	    ps1: int,
 	}
     }
 
-    pub fn as_paired_f32(&self) -> &(f32, f32) {
-	unsafe {
-	    &self.ps_f
-	}
+    pub fn ps0(&self) -> f64 {
+	f64::from_bits(self.ps0)
     }
 
-    pub fn as_paired_f32_mut(&mut self) -> &mut (f32, f32) {
-	unsafe {
-	    &mut self.ps_f
-	}
+    pub fn ps1(&self) -> f64 {
+	f64::from_bits(self.ps1)
     }
 
-    pub fn as_f64(&self) -> &f64 {
-	unsafe {
-	    &self.double
-	}
+    pub fn set_ps0(&mut self, val: f64) {
+	self.ps0 = val.to_bits();
     }
 
-    pub fn as_f64_mut(&mut self) -> &mut f64 {
-	unsafe {
-	    &mut self.double
-	}
-    }
-
-    pub fn as_u64(&self) -> &u64 {
-	unsafe {
-	    &self.int
-	}
+    pub fn set_ps1(&mut self, val: f64) {
+	self.ps1 = val.to_bits();
     }
 
-    pub fn as_u64_mut(&mut self) -> &mut u64 {
-	unsafe {
-	    &mut self.int
-	}
+    //single precision results land in both halves
+    pub fn set_both(&mut self, val: f64) {
+	self.ps0 = val.to_bits();
+	self.ps1 = val.to_bits();
     }
 }
 
@@ -461,6 +662,10 @@ impl HID2 {
     pub fn dmaql(&self) -> usize {
 	((self.0 >> 24) & 0xF) as usize
     }
+
+    pub fn set_dmaql(&mut self, val: usize) {
+	self.0 = (self.0 & !0x0F00_0000) | (((val as u32) & 0xF) << 24);
+    }
 }
 
 pub struct FloatingPointStatusControlRegister(pub u32);
@@ -590,8 +795,12 @@ impl XER {
 	((self.0 >> 30) & 1) != 0
     }
 
+    //SO is sticky, once an overflow sets it only mtxer or mcrxr clear it again
     pub fn set_ov(&mut self, val: bool) {
 	self.0 = (self.0 & !(1 << 30)) | ((val as u32) << 30);
+	if val {
+	    self.0 |= 1 << 31;
+	}
     }
 
     pub fn so(&self) -> bool {
@@ -602,3 +811,45 @@ impl XER {
 	self.0 = (self.0 & !(1 << 31)) | ((val as u32) << 31);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::test_gamecube;
+
+    use super::{CYCLES_PER_TIMEBASE_TICK, DECREMENTER_EXCEPTION};
+
+    #[test]
+    fn the_timebase_ticks_every_twelve_cycles_and_keeps_the_remainder() {
+	let mut gc = test_gamecube();
+	gc.cpu.tick(100 * CYCLES_PER_TIMEBASE_TICK + 5);
+	assert_eq!(gc.cpu.tb, 100);
+	assert_eq!(gc.cpu.dec, 100u32.wrapping_neg());
+
+	//the 5 left over count towards the next tick
+	gc.cpu.tick(7);
+	assert_eq!(gc.cpu.tb, 101);
+    }
+
+    #[test]
+    fn the_decrementer_interrupts_when_it_goes_negative() {
+	let mut gc = test_gamecube();
+	gc.cpu.write_dec(2);
+
+	//reaching zero isn't enough
+	gc.cpu.tick(2 * CYCLES_PER_TIMEBASE_TICK);
+	assert_eq!(gc.cpu.dec, 0);
+	assert_eq!(gc.cpu.exceptions, 0);
+
+	gc.cpu.tick(CYCLES_PER_TIMEBASE_TICK);
+	assert_eq!(gc.cpu.dec, 0xFFFF_FFFF);
+	assert_eq!(gc.cpu.exceptions, DECREMENTER_EXCEPTION);
+
+	//counting on down from there doesn't ask again, but mtdec with the top bit set from positive does
+	gc.cpu.exceptions = 0;
+	gc.cpu.tick(CYCLES_PER_TIMEBASE_TICK);
+	assert_eq!(gc.cpu.exceptions, 0);
+	gc.cpu.write_dec(5);
+	gc.cpu.write_dec(0x8000_0000);
+	assert_eq!(gc.cpu.exceptions, DECREMENTER_EXCEPTION);
+    }
+}
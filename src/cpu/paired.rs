@@ -0,0 +1,350 @@
+use crate::Gamecube;
+
+use super::{float::{approximate_reciprocal, approximate_reciprocal_sqrt, compare, compute, is_snan, update_cr1, write_result, Op, Rounded, FPSCR_VXSNAN, FPSCR_VXSQRT, FPSCR_ZX}, instr::Instruction, PROGRAM_ILLEGAL};
+
+//everything in here is illegal unless HID2[PSE] is set
+fn check_pse(gc: &mut Gamecube) -> bool {
+    if !gc.cpu.hid2.pse() {
+	gc.cpu.program_exception(PROGRAM_ILLEGAL);
+	return false;
+    }
+    true
+}
+
+//one operand per slot for each of frA, frB and frC
+type Operands = [(f64, f64); 3];
+
+fn operands(gc: &Gamecube, instr: &Instruction) -> Operands {
+    let a = gc.cpu.fprs[instr.a()];
+    let b = gc.cpu.fprs[instr.b()];
+    let c = gc.cpu.fprs[instr.c()];
+
+    [(a.ps0(), a.ps1()), (b.ps0(), b.ps1()), (c.ps0(), c.ps1())]
+}
+
+//both slots are computed with single precision rounding and their exceptions merged
+fn arithmetic(gc: &mut Gamecube, instr: &Instruction, op: Op, negate: bool, [a, b, c]: Operands) {
+    if !check_pse(gc) {
+	return;
+    }
+    let rn = gc.cpu.fpscr.rn();
+
+    let (ps0, exceptions0) = compute(op, negate, a.0, b.0, c.0, rn, true);
+    let (ps1, exceptions1) = compute(op, negate, a.1, b.1, c.1, rn, true);
+
+    write_result(gc, instr, ps0, Some(ps1), exceptions0 | exceptions1, true);
+}
+
+pub fn ps_add(gc: &mut Gamecube, instr: &Instruction) {
+    arithmetic(gc, instr, Op::Add, false, operands(gc, instr));
+}
+
+pub fn ps_sub(gc: &mut Gamecube, instr: &Instruction) {
+    arithmetic(gc, instr, Op::Sub, false, operands(gc, instr));
+}
+
+pub fn ps_mul(gc: &mut Gamecube, instr: &Instruction) {
+    arithmetic(gc, instr, Op::Mul, false, operands(gc, instr));
+}
+
+pub fn ps_div(gc: &mut Gamecube, instr: &Instruction) {
+    arithmetic(gc, instr, Op::Div, false, operands(gc, instr));
+}
+
+pub fn ps_madd(gc: &mut Gamecube, instr: &Instruction) {
+    arithmetic(gc, instr, Op::MulAdd, false, operands(gc, instr));
+}
+
+pub fn ps_msub(gc: &mut Gamecube, instr: &Instruction) {
+    arithmetic(gc, instr, Op::MulSub, false, operands(gc, instr));
+}
+
+pub fn ps_nmadd(gc: &mut Gamecube, instr: &Instruction) {
+    arithmetic(gc, instr, Op::MulAdd, true, operands(gc, instr));
+}
+
+pub fn ps_nmsub(gc: &mut Gamecube, instr: &Instruction) {
+    arithmetic(gc, instr, Op::MulSub, true, operands(gc, instr));
+}
+
+//frC's chosen slot scales both halves of frA
+pub fn ps_muls0(gc: &mut Gamecube, instr: &Instruction) {
+    let [a, b, c] = operands(gc, instr);
+    arithmetic(gc, instr, Op::Mul, false, [a, b, (c.0, c.0)]);
+}
+
+pub fn ps_muls1(gc: &mut Gamecube, instr: &Instruction) {
+    let [a, b, c] = operands(gc, instr);
+    arithmetic(gc, instr, Op::Mul, false, [a, b, (c.1, c.1)]);
+}
+
+pub fn ps_madds0(gc: &mut Gamecube, instr: &Instruction) {
+    let [a, b, c] = operands(gc, instr);
+    arithmetic(gc, instr, Op::MulAdd, false, [a, b, (c.0, c.0)]);
+}
+
+pub fn ps_madds1(gc: &mut Gamecube, instr: &Instruction) {
+    let [a, b, c] = operands(gc, instr);
+    arithmetic(gc, instr, Op::MulAdd, false, [a, b, (c.1, c.1)]);
+}
+
+//frA.ps0 + frB.ps1 goes in one slot, the other is copied out of frC
+fn sum(gc: &mut Gamecube, instr: &Instruction, slot: usize) {
+    if !check_pse(gc) {
+	return;
+    }
+    let [a, b, c] = operands(gc, instr);
+
+    let (sum, exceptions) = compute(Op::Add, false, a.0, b.1, 0.0, gc.cpu.fpscr.rn(), true);
+    let (ps0, ps1) = if slot == 0 {
+	(sum, Rounded::exact(c.1))
+    } else {
+	(Rounded::exact(c.0), sum)
+    };
+
+    write_result(gc, instr, ps0, Some(ps1), exceptions, true);
+}
+
+pub fn ps_sum0(gc: &mut Gamecube, instr: &Instruction) {
+    sum(gc, instr, 0);
+}
+
+pub fn ps_sum1(gc: &mut Gamecube, instr: &Instruction) {
+    sum(gc, instr, 1);
+}
+
+pub fn ps_res(gc: &mut Gamecube, instr: &Instruction) {
+    if !check_pse(gc) {
+	return;
+    }
+    let [_, b, _] = operands(gc, instr);
+
+    let mut exceptions = 0;
+    for val in [b.0, b.1] {
+	if is_snan(val) {
+	    exceptions |= FPSCR_VXSNAN;
+	} else if val == 0.0 {
+	    exceptions |= FPSCR_ZX;
+	}
+    }
+
+    let ps0 = Rounded::exact(approximate_reciprocal(b.0));
+    let ps1 = Rounded::exact(approximate_reciprocal(b.1));
+    write_result(gc, instr, ps0, Some(ps1), exceptions, true);
+}
+
+pub fn ps_rsqrte(gc: &mut Gamecube, instr: &Instruction) {
+    if !check_pse(gc) {
+	return;
+    }
+    let [_, b, _] = operands(gc, instr);
+
+    let mut exceptions = 0;
+    for val in [b.0, b.1] {
+	if is_snan(val) {
+	    exceptions |= FPSCR_VXSNAN;
+	} else if val == 0.0 {
+	    exceptions |= FPSCR_ZX;
+	} else if val < 0.0 {
+	    exceptions |= FPSCR_VXSQRT;
+	}
+    }
+
+    //the estimate has more bits than a single can hold
+    let ps0 = Rounded::exact(f64::from(approximate_reciprocal_sqrt(b.0) as f32));
+    let ps1 = Rounded::exact(f64::from(approximate_reciprocal_sqrt(b.1) as f32));
+    write_result(gc, instr, ps0, Some(ps1), exceptions, true);
+}
+
+pub fn ps_sel(gc: &mut Gamecube, instr: &Instruction) {
+    if !check_pse(gc) {
+	return;
+    }
+    let a = gc.cpu.fprs[instr.a()];
+    let b = gc.cpu.fprs[instr.b()];
+    let c = gc.cpu.fprs[instr.c()];
+
+    //NaN fails the compare and picks frB
+    let d = &mut gc.cpu.fprs[instr.d()];
+    d.ps0 = if a.ps0() >= 0.0 { c.ps0 } else { b.ps0 };
+    d.ps1 = if a.ps1() >= 0.0 { c.ps1 } else { b.ps1 };
+
+    if instr.rc() {
+	update_cr1(gc);
+    }
+}
+
+//the sign bit ops and merges move bits around without looking at them
+fn move_bits(gc: &mut Gamecube, instr: &Instruction, ps0: u64, ps1: u64) {
+    gc.cpu.fprs[instr.d()].ps0 = ps0;
+    gc.cpu.fprs[instr.d()].ps1 = ps1;
+
+    if instr.rc() {
+	update_cr1(gc);
+    }
+}
+
+pub fn ps_mr(gc: &mut Gamecube, instr: &Instruction) {
+    if !check_pse(gc) {
+	return;
+    }
+    let b = gc.cpu.fprs[instr.b()];
+    move_bits(gc, instr, b.ps0, b.ps1);
+}
+
+pub fn ps_neg(gc: &mut Gamecube, instr: &Instruction) {
+    if !check_pse(gc) {
+	return;
+    }
+    let b = gc.cpu.fprs[instr.b()];
+    move_bits(gc, instr, b.ps0 ^ (1 << 63), b.ps1 ^ (1 << 63));
+}
+
+pub fn ps_abs(gc: &mut Gamecube, instr: &Instruction) {
+    if !check_pse(gc) {
+	return;
+    }
+    let b = gc.cpu.fprs[instr.b()];
+    move_bits(gc, instr, b.ps0 & !(1 << 63), b.ps1 & !(1 << 63));
+}
+
+pub fn ps_nabs(gc: &mut Gamecube, instr: &Instruction) {
+    if !check_pse(gc) {
+	return;
+    }
+    let b = gc.cpu.fprs[instr.b()];
+    move_bits(gc, instr, b.ps0 | (1 << 63), b.ps1 | (1 << 63));
+}
+
+pub fn ps_merge00(gc: &mut Gamecube, instr: &Instruction) {
+    if !check_pse(gc) {
+	return;
+    }
+    let (a, b) = (gc.cpu.fprs[instr.a()], gc.cpu.fprs[instr.b()]);
+    move_bits(gc, instr, a.ps0, b.ps0);
+}
+
+pub fn ps_merge01(gc: &mut Gamecube, instr: &Instruction) {
+    if !check_pse(gc) {
+	return;
+    }
+    let (a, b) = (gc.cpu.fprs[instr.a()], gc.cpu.fprs[instr.b()]);
+    move_bits(gc, instr, a.ps0, b.ps1);
+}
+
+pub fn ps_merge10(gc: &mut Gamecube, instr: &Instruction) {
+    if !check_pse(gc) {
+	return;
+    }
+    let (a, b) = (gc.cpu.fprs[instr.a()], gc.cpu.fprs[instr.b()]);
+    move_bits(gc, instr, a.ps1, b.ps0);
+}
+
+pub fn ps_merge11(gc: &mut Gamecube, instr: &Instruction) {
+    if !check_pse(gc) {
+	return;
+    }
+    let (a, b) = (gc.cpu.fprs[instr.a()], gc.cpu.fprs[instr.b()]);
+    move_bits(gc, instr, a.ps1, b.ps1);
+}
+
+pub fn ps_cmpu0(gc: &mut Gamecube, instr: &Instruction) {
+    if !check_pse(gc) {
+	return;
+    }
+    compare(gc, instr, gc.cpu.fprs[instr.a()].ps0(), gc.cpu.fprs[instr.b()].ps0(), false);
+}
+
+pub fn ps_cmpo0(gc: &mut Gamecube, instr: &Instruction) {
+    if !check_pse(gc) {
+	return;
+    }
+    compare(gc, instr, gc.cpu.fprs[instr.a()].ps0(), gc.cpu.fprs[instr.b()].ps0(), true);
+}
+
+pub fn ps_cmpu1(gc: &mut Gamecube, instr: &Instruction) {
+    if !check_pse(gc) {
+	return;
+    }
+    compare(gc, instr, gc.cpu.fprs[instr.a()].ps1(), gc.cpu.fprs[instr.b()].ps1(), false);
+}
+
+pub fn ps_cmpo1(gc: &mut Gamecube, instr: &Instruction) {
+    if !check_pse(gc) {
+	return;
+    }
+    compare(gc, instr, gc.cpu.fprs[instr.a()].ps1(), gc.cpu.fprs[instr.b()].ps1(), true);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cpu::{decode::Handler, instr::Instruction, PROGRAM_EXCEPTION, PROGRAM_ILLEGAL}, test_gamecube, Gamecube};
+
+    use super::{ps_add, ps_merge10, ps_res, ps_sum0};
+
+    const HID2_PSE: u32 = 1 << 29;
+
+    //frD=1, frA=2, frB=3, frC=4 under primary 4
+    fn form(xo: u32) -> Instruction {
+	Instruction((4 << 26) | (1 << 21) | (2 << 16) | (3 << 11) | (4 << 6) | (xo << 1))
+    }
+
+    fn run(op: Handler, xo: u32, a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> Gamecube {
+	let mut gc = test_gamecube();
+	gc.cpu.hid2.0 = HID2_PSE;
+	for (reg, (ps0, ps1)) in [(2, a), (3, b), (4, c)] {
+	    gc.cpu.fprs[reg].set_ps0(ps0);
+	    gc.cpu.fprs[reg].set_ps1(ps1);
+	}
+	op(&mut gc, &form(xo));
+	gc
+    }
+
+    #[test]
+    fn both_slots_round_to_single_and_fprf_describes_ps0() {
+	//1 + 2^-30 doesn't fit in a single, 2 + 3 does
+	let gc = run(ps_add, 21, (1.0, 2.0), (2f64.powi(-30), 3.0), (0.0, 0.0));
+	assert_eq!(gc.cpu.fprs[1].ps0, 0x3FF0_0000_0000_0000);
+	assert_eq!(gc.cpu.fprs[1].ps1, 0x4014_0000_0000_0000);
+	assert_eq!(gc.cpu.fpscr.0, 0x8202_4000);
+
+	//an inexact ps1 still counts towards XX, but FI and FPRF only look at ps0
+	let gc = run(ps_add, 21, (-2.0, 1.0), (0.0, 2f64.powi(-30)), (0.0, 0.0));
+	assert_eq!(gc.cpu.fprs[1].ps0, 0xC000_0000_0000_0000);
+	assert_eq!(gc.cpu.fprs[1].ps1, 0x3FF0_0000_0000_0000);
+	assert_eq!(gc.cpu.fpscr.0, 0x8200_8000);
+    }
+
+    #[test]
+    fn sums_and_merges_pick_the_right_slots() {
+	//ps0 = frA.ps0 + frB.ps1, ps1 = frC.ps1
+	let gc = run(ps_sum0, 10, (1.0, 100.0), (200.0, 2.0), (300.0, -4.0));
+	assert_eq!(gc.cpu.fprs[1].ps0, 0x4008_0000_0000_0000);
+	assert_eq!(gc.cpu.fprs[1].ps1, 0xC010_0000_0000_0000);
+
+	//merges move bits, a signalling NaN isn't quieted
+	let snan = f64::from_bits(0x7FF0_0000_0000_0001);
+	let gc = run(ps_merge10, 592, (0.0, snan), (1.0, 0.0), (0.0, 0.0));
+	assert_eq!(gc.cpu.fprs[1].ps0, 0x7FF0_0000_0000_0001);
+	assert_eq!(gc.cpu.fprs[1].ps1, 0x3FF0_0000_0000_0000);
+	assert_eq!(gc.cpu.fpscr.0, 0);
+    }
+
+    #[test]
+    fn ps_res_estimates_each_slot() {
+	let gc = run(ps_res, 24, (0.0, 0.0), (0.0, 2.0), (0.0, 0.0));
+	assert_eq!(gc.cpu.fprs[1].ps0, 0x7FF0_0000_0000_0000);
+	assert_eq!(gc.cpu.fprs[1].ps1, 0x3FDF_FF00_0000_0000);
+	assert_eq!(gc.cpu.fpscr.0, 0x8400_5000);
+    }
+
+    #[test]
+    fn paired_singles_are_illegal_without_hid2_pse() {
+	let mut gc = test_gamecube();
+	gc.cpu.fprs[2].set_ps0(1.0);
+	ps_add(&mut gc, &form(21));
+	assert_eq!(gc.cpu.exceptions, PROGRAM_EXCEPTION);
+	assert_eq!(gc.cpu.srr1_flags, PROGRAM_ILLEGAL);
+	assert_eq!(gc.cpu.fprs[1].ps0, 0);
+    }
+}
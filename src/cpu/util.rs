@@ -1,4 +1,4 @@
-use log::debug;
+use log::warn;
 
 const DEQUANTIZE_TABLE: [f32; 64] = [
     1.0 / (1 << 0) as f32,  1.0 / (1 << 1) as f32,  1.0 / (1 << 2) as f32, 1.0 / (1 << 3) as f32,
@@ -19,19 +19,49 @@ const DEQUANTIZE_TABLE: [f32; 64] = [
     (1 << 4) as f32,        (1 << 3) as f32,        (1 << 2) as f32,        (1 << 1) as f32,
     ];
 
-pub fn dequantized(val: u32, ld_type: usize, ld_scale: usize) -> f32 {
+//stores scale the other way around
+const QUANTIZE_TABLE: [f32; 64] = {
+    let mut table = [0.0; 64];
+    let mut i = 0;
+    while i < 64 {
+	table[i] = 1.0 / DEQUANTIZE_TABLE[i];
+	i += 1;
+    }
+    table
+};
+
+//types 1-3 are reserved, hardware treats them like float so that's what happens here too
+pub fn dequantized(val: u32, ld_type: usize, ld_scale: usize) -> f64 {
     let result = match ld_type {
-	0 => f32::from_bits(val),
+	0 => return f64::from_bits(convert_to_double(val)),
 	4 => (val as u8) as f32,
 	5 => (val as u16) as f32,
 	6 => (val as i8) as f32,
 	7 => (val as i16) as f32,
 	_ => {
-	    f32::from_bits(val)
+	    warn!("psq load with reserved ld_type {ld_type}, treating it as float");
+	    return f64::from_bits(convert_to_double(val));
 	},
     };
 
-    result * DEQUANTIZE_TABLE[ld_scale]
+    f64::from(result * DEQUANTIZE_TABLE[ld_scale])
+}
+
+//the integer types saturate instead of wrapping, NaN ends up as 0
+pub fn quantized(val: f64, st_type: usize, st_scale: usize) -> u32 {
+    let scaled = (val as f32) * QUANTIZE_TABLE[st_scale];
+
+    match st_type {
+	0 => convert_to_single(val.to_bits()),
+	4 => u32::from(scaled as u8),
+	5 => u32::from(scaled as u16),
+	6 => scaled as i8 as u8 as u32,
+	7 => scaled as i16 as u16 as u32,
+	_ => {
+	    warn!("psq store with reserved st_type {st_type}, treating it as float");
+	    convert_to_single(val.to_bits())
+	},
+    }
 }
 
 pub fn sext_26(val: u32) -> i32 {
@@ -69,14 +99,14 @@ pub fn convert_to_single(val: u64) -> u32 {
     let exp = ((val >> 52) & 0x7FF) as u32;
 
     if exp > 896 || (val & !0x8000_0000_0000_0000) == 0 {
-	return (((val >> 32) & 0xC000_0000) | ((val >> 29) & 0x3FFF_FFFF)) as u32;
-    } else if (exp >= 874) {
+	(((val >> 32) & 0xC000_0000) | ((val >> 29) & 0x3FFF_FFFF)) as u32
+    } else if exp >= 874 {
 	let mut t = (0x8000_0000 | ((val & 0x000F_FFFF_FFFF_FFFF) >> 21)) as u32;
-	t = t >> (905 - exp);
+	t >>= 905 - exp;
 	t |= ((val >> 32) & 0x8000_0000) as u32;
-	return t;
+	t
     } else {
-	return (((val >> 32) & 0xC000_0000) | ((val >> 29) & 0x3FFF_FFFF)) as u32;
+	(((val >> 32) & 0xC000_0000) | ((val >> 29) & 0x3FFF_FFFF)) as u32
     }
 }
 
@@ -88,17 +118,17 @@ pub fn convert_to_double(val: u32) -> u64 {
     if (exp > 0) && (exp < 255) {
 	let y = !(exp >> 7);
 	let z = y << 61 | y << 60 | y << 59;
-	return ((x & 0xC000_0000) << 32) | z | ((x & 0x3FFF_FFFF) << 29);
+	((x & 0xC000_0000) << 32) | z | ((x & 0x3FFF_FFFF) << 29)
     } else if (exp == 0) && (frac != 0) {
 	exp = 1023 - 126;
 	while (frac & 0x0800_0000) == 0 {
 	    frac <<= 1;
 	    exp -= 1;
 	}
-	return ((x & 0x8000_0000) << 32) | (exp << 52) | ((frac & 0x007F_FFFF) << 29);
+	((x & 0x8000_0000) << 32) | (exp << 52) | ((frac & 0x007F_FFFF) << 29)
     } else {
 	let y = exp >> 7;
 	let z = y << 61 | y << 60 | y << 59;
-	return ((x & 0xC000_0000) << 32) | z | ((x & 0x3FFF_FFFF) << 29);
+	((x & 0xC000_0000) << 32) | z | ((x & 0x3FFF_FFFF) << 29)
     }
 }
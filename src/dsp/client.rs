@@ -23,3 +23,9 @@ impl DSPClient {
 	}
     }
 }
+
+impl Default for DSPClient {
+    fn default() -> Self {
+	Self::new()
+    }
+}
@@ -2,7 +2,21 @@ use std::sync::atomic::Ordering;
 
 use log::debug;
 
-use crate::Gamecube;
+use crate::{audio_interface, bus::mmio::{merge, Mmio, MmioHandlers}, processor_interface::{set_interrupt, PI_INT_DSP}, scheduler::{self, EventKind, CPU_CLOCK}, Gamecube};
+
+const DSPCR_INT_STATUS: u16 = (1 << 3) | (1 << 5) | (1 << 7);
+const DSPCR_DMA_BUSY: u16 = 1 << 9;
+
+//ARAM moves data at roughly 81MB/s, so about 6 cpu cycles a byte
+const ARAM_DMA_CYCLES_PER_BYTE: u64 = 6;
+//ARAM dma goes in 32 byte lines, the direction bit sits on top of the length
+const AR_DMA_MMADDR_MASK: u32 = 0x01FF_FFE0;
+const AR_DMA_CNT_MASK: u32 = 0x7FFF_FFE0;
+
+//audio dma goes a 32 byte block at a time, which is 8 stereo samples
+const AI_DMA_BLOCK_SIZE: u32 = 32;
+const AI_DMA_BLOCK_SAMPLES: u64 = 8;
+const AI_DMA_ENABLE: u16 = 1 << 15;
 
 pub struct DSPInterface {
     ar_size: u16,
@@ -10,6 +24,12 @@ pub struct DSPInterface {
     ar_dma_mmaddr: u32,
     ar_dma_araddr: u32,
     ar_dma_cnt: u32,
+    ai_dma_start_hi: u16,
+    ai_dma_start_lo: u16,
+    ai_dma_control: u16,
+    //where the dma has got to, and how many blocks it has left before going back to the start
+    ai_dma_addr: u32,
+    ai_dma_blocks_left: u16,
 }
 
 impl DSPInterface {
@@ -20,63 +40,274 @@ impl DSPInterface {
 	    ar_dma_mmaddr: 0,
 	    ar_dma_araddr: 0,
 	    ar_dma_cnt: 0,
+	    ai_dma_start_hi: 0,
+	    ai_dma_start_lo: 0,
+	    ai_dma_control: 0,
+	    ai_dma_addr: 0,
+	    ai_dma_blocks_left: 0,
 	}
     }
+
+    fn ai_dma_start(&self) -> u32 {
+	((u32::from(self.ai_dma_start_hi) << 16) | u32::from(self.ai_dma_start_lo)) & 0x03FF_FFE0
+    }
+
+    fn ai_dma_blocks(&self) -> u16 {
+	self.ai_dma_control & !AI_DMA_ENABLE
+    }
 }
 
-pub fn dsp_read_u16(gc: &mut Gamecube, offset: u32) -> u16 {
+impl Default for DSPInterface {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+pub fn register_mmio(mmio: &mut Mmio) {
+    mmio.register("DSP", 0x0C00_5000, 0x1000, MmioHandlers {
+	read_u16: Some(dsp_read_u16),
+	write_u16: Some(dsp_write_u16),
+	write_u32: Some(dsp_write_u32),
+	..MmioHandlers::NONE
+    });
+}
+
+pub fn dsp_read_u16(gc: &mut Gamecube, offset: u32) -> Option<u16> {
     debug!("STUB: DSP read_u16 at offset {offset:#010X}!");
-    match offset {
+    Some(match offset {
 	0x04 => gc.dsp_client.cpu_mbox_h.load(Ordering::Relaxed),
 	0x06 => gc.dsp_client.cpu_mbox_l.load(Ordering::Relaxed),
 	0x0A => gc.dsp_client.control_reg.load(Ordering::Relaxed),
-	_ => unimplemented!("Unknown offset {offset:#010X} for dsp read_u16!"),
-    }
+	0x30 => gc.dsp.ai_dma_start_hi,
+	0x32 => gc.dsp.ai_dma_start_lo,
+	0x36 => gc.dsp.ai_dma_control,
+	//the block being played doesn't count
+	0x3A => gc.dsp.ai_dma_blocks_left.saturating_sub(1),
+	_ => return None,
+    })
 }
 
-pub fn dsp_write_u16(gc: &mut Gamecube, offset: u32, val: u16) {
+pub fn dsp_write_u16(gc: &mut Gamecube, offset: u32, val: u16, mask: u16) -> Option<()> {
     debug!("STUB: DSP write_u16 at offset {offset:#010X} with val {val:#06X}");
     match offset {
-	0x00 => gc.dsp_client.dsp_mbox_h.store(val, Ordering::Relaxed),
-	0x02 => gc.dsp_client.dsp_mbox_l.store(val, Ordering::Relaxed),
+	0x00 => {
+	    let old = gc.dsp_client.dsp_mbox_h.load(Ordering::Relaxed);
+	    gc.dsp_client.dsp_mbox_h.store(merge(old, val, mask), Ordering::Relaxed);
+	},
+	0x02 => {
+	    let old = gc.dsp_client.dsp_mbox_l.load(Ordering::Relaxed);
+	    gc.dsp_client.dsp_mbox_l.store(merge(old, val, mask), Ordering::Relaxed);
+	},
 	0x0A => {
-	    gc.dsp_client.control_reg.store(val, Ordering::Relaxed);
+	    //AIDINT, ARINT and DSPINT are write 1 to clear, the ARAM DMA busy bit is read only
+	    let old = gc.dsp_client.control_reg.load(Ordering::Relaxed);
+	    let status = (old & DSPCR_INT_STATUS & !(val & mask)) | (old & DSPCR_DMA_BUSY);
+	    let control = merge(old, val, mask) & !(DSPCR_INT_STATUS | DSPCR_DMA_BUSY);
+	    gc.dsp_client.control_reg.store(control | status, Ordering::Relaxed);
+	    update_interrupts(gc);
 	    if gc.dsp_client.control_reg.reset() {
 		for i in 0..0x2000 {
-		    let val = gc.read_u8(0x8100_0000 + (i as u32));
+		    let val = gc.read_u8(0x8100_0000 + (i as u32)).unwrap_or(0);
 		    gc.aram[i].store(val, Ordering::Relaxed);
 		}
 	    }
 	},
-	0x12 => gc.dsp.ar_size = val,
-	0x1A => gc.dsp.ar_refresh = val,
-	_ => unimplemented!("Unknown offset {offset:#010X} with val {val:#06X} for dsp write_u16!"),
+	0x12 => gc.dsp.ar_size = merge(gc.dsp.ar_size, val, mask),
+	0x1A => gc.dsp.ar_refresh = merge(gc.dsp.ar_refresh, val, mask),
+	0x30 => gc.dsp.ai_dma_start_hi = merge(gc.dsp.ai_dma_start_hi, val, mask),
+	0x32 => gc.dsp.ai_dma_start_lo = merge(gc.dsp.ai_dma_start_lo, val, mask),
+	0x36 => {
+	    let was_enabled = gc.dsp.ai_dma_control & AI_DMA_ENABLE != 0;
+	    gc.dsp.ai_dma_control = merge(gc.dsp.ai_dma_control, val, mask);
+
+	    if gc.dsp.ai_dma_control & AI_DMA_ENABLE == 0 {
+		gc.scheduler.cancel(EventKind::AiDma);
+	    } else if !was_enabled {
+		//AIDINT goes off as soon as the dma has picked up the address and length, so the next ones can be set up
+		gc.dsp.ai_dma_addr = gc.dsp.ai_dma_start();
+		gc.dsp.ai_dma_blocks_left = gc.dsp.ai_dma_blocks();
+		gc.dsp_client.control_reg.set_aidint();
+		update_interrupts(gc);
+		schedule_ai_dma_block(gc);
+	    }
+	},
+	_ => return None,
     }
+    Some(())
 }
 
-pub fn dsp_write_u32(gc: &mut Gamecube, offset: u32, val: u32) {
+pub fn dsp_write_u32(gc: &mut Gamecube, offset: u32, val: u32, mask: u32) -> Option<()> {
     debug!("STUB: DSP write_u32 at offset {offset:#010X} with val {val:#010X}");
     match offset {
-	0x20 => gc.dsp.ar_dma_mmaddr = val,
-	0x24 => gc.dsp.ar_dma_araddr = val,
+	0x20 => gc.dsp.ar_dma_mmaddr = merge(gc.dsp.ar_dma_mmaddr, val, mask),
+	0x24 => gc.dsp.ar_dma_araddr = merge(gc.dsp.ar_dma_araddr, val, mask),
 	0x28 => {
-	    gc.dsp.ar_dma_cnt = val;
+	    gc.dsp.ar_dma_cnt = merge(gc.dsp.ar_dma_cnt, val, mask);
 	    let read = ((gc.dsp.ar_dma_cnt >> 31) & 1) != 0;
-	    let length = (gc.dsp.ar_dma_cnt & !(1 << 31));
-	    println!("length: {length:#010X}");
+
+	    //only the address bits the engine has make it out, and a transfer stops at the end of either memory
+	    let mmaddr = (gc.dsp.ar_dma_mmaddr & AR_DMA_MMADDR_MASK) as usize;
+	    let araddr = (gc.dsp.ar_dma_araddr as usize & (gc.aram.len() - 1)) & !0x1F;
+	    let ram = gc.memory.get_mut(mmaddr..).unwrap_or_default();
+	    let aram = gc.aram.get(araddr..).unwrap_or_default();
+	    let length = ((gc.dsp.ar_dma_cnt & AR_DMA_CNT_MASK) as usize).min(ram.len()).min(aram.len());
+
 	    if read {
-		for i in 0..length {
-		    gc.memory[(gc.dsp.ar_dma_mmaddr + i) as usize] = gc.aram[(gc.dsp.ar_dma_araddr + i) as usize].load(Ordering::Relaxed);
+		for (byte, src) in ram.iter_mut().zip(aram).take(length) {
+		    *byte = src.load(Ordering::Relaxed);
 		}
+		gc.cpu.decode_cache.invalidate_range(mmaddr as u32, length);
 	    } else {
-		for i in 0..length {
-		    let val = gc.memory[(gc.dsp.ar_dma_mmaddr + i) as usize];
-		    println!("{val:#04X}");
-		    gc.aram[(gc.dsp.ar_dma_araddr + i) as usize].store(val, Ordering::Relaxed);
+		for (&byte, dst) in ram.iter().zip(aram).take(length) {
+		    dst.store(byte, Ordering::Relaxed);
 		}
 	    }
-//	    panic!();
+
+	    gc.dsp_client.control_reg.set_ar_dma_busy();
+	    gc.scheduler.cancel(EventKind::AramDma);
+	    scheduler::schedule_in(gc, length as u64 * ARAM_DMA_CYCLES_PER_BYTE, EventKind::AramDma, aram_dma_complete);
 	},
-	_ => unimplemented!("Unknown offset {offset:#010X} with val {val:#06X} for dsp write_u32!"),
+	//everything else is a pair of 16 bit registers
+	_ => {
+	    dsp_write_u16(gc, offset, (val >> 16) as u16, (mask >> 16) as u16)?;
+	    dsp_write_u16(gc, offset + 2, val as u16, mask as u16)?;
+	},
+    }
+    Some(())
+}
+
+fn aram_dma_complete(gc: &mut Gamecube, _kind: EventKind) {
+    gc.dsp_client.control_reg.clear_ar_dma_busy();
+    gc.dsp_client.control_reg.set_arint();
+    update_interrupts(gc);
+}
+
+fn schedule_ai_dma_block(gc: &mut Gamecube) {
+    let cycles = AI_DMA_BLOCK_SAMPLES * CPU_CLOCK / gc.ai.dma_sample_rate();
+    scheduler::schedule_in(gc, cycles, EventKind::AiDma, ai_dma_block);
+}
+
+//one block's worth of samples has played, the next gets fetched out of ram
+fn ai_dma_block(gc: &mut Gamecube, _kind: EventKind) {
+    if gc.dsp.ai_dma_blocks_left != 0 {
+	let addr = gc.dsp.ai_dma_addr as usize;
+	let block = gc.memory.get(addr..(addr + AI_DMA_BLOCK_SIZE as usize)).map_or([0; AI_DMA_BLOCK_SIZE as usize], |block| block.try_into().unwrap());
+	audio_interface::push_dma_block(gc, &block);
+	gc.dsp.ai_dma_addr += AI_DMA_BLOCK_SIZE;
+	gc.dsp.ai_dma_blocks_left -= 1;
+    }
+
+    //at the end it goes back round to whatever the registers say now, which is how games queue up the next buffer
+    if gc.dsp.ai_dma_blocks_left == 0 {
+	gc.dsp.ai_dma_addr = gc.dsp.ai_dma_start();
+	gc.dsp.ai_dma_blocks_left = gc.dsp.ai_dma_blocks();
+	if gc.dsp.ai_dma_blocks_left != 0 {
+	    gc.dsp_client.control_reg.set_aidint();
+	    update_interrupts(gc);
+	}
+    }
+
+    schedule_ai_dma_block(gc);
+}
+
+//each status bit has its mask right above it
+fn update_interrupts(gc: &mut Gamecube) {
+    let control = gc.dsp_client.control_reg.load(Ordering::Relaxed);
+    let active = control & DSPCR_INT_STATUS & (control >> 1) != 0;
+    set_interrupt(gc, PI_INT_DSP, active);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use crate::{processor_interface::PI_INT_DSP, scheduler::EventKind, test_gamecube, Gamecube, RAM_SIZE};
+
+    use super::ai_dma_block;
+
+    const DSPCR_AIDINT: u16 = 1 << 3;
+    const DSPCR_AIDINTMSK: u16 = 1 << 4;
+
+    fn aidint(gc: &Gamecube) -> bool {
+	gc.dsp_client.control_reg.load(Ordering::Relaxed) & DSPCR_AIDINT != 0
+    }
+
+    //runs the pending audio dma event, which has to be the next thing due
+    fn next_block(gc: &mut Gamecube) {
+	gc.cpu.cycles = gc.scheduler.next_deadline().unwrap();
+	gc.scheduler.cancel(EventKind::AiDma);
+	ai_dma_block(gc, EventKind::AiDma);
+    }
+
+    #[test]
+    fn aram_dma_stays_inside_both_memories() {
+	let mut gc = test_gamecube();
+
+	//ram to aram, through a cached pointer like the sdk hands over
+	gc.memory[0x1000..0x1020].fill(0xAB);
+	gc.write_u32(0x0C00_5020, 0x8000_1000).unwrap();
+	gc.write_u32(0x0C00_5024, 0x40).unwrap();
+	gc.write_u32(0x0C00_5028, 0x20).unwrap();
+	assert!(gc.aram[0x40..0x60].iter().all(|byte| byte.load(Ordering::Relaxed) == 0xAB));
+	assert_eq!(gc.aram[0x60].load(Ordering::Relaxed), 0);
+
+	//aram to ram, asking for far more than is left of ram
+	let aram_end = gc.aram.len() - 0x20;
+	gc.aram[aram_end].store(0xCD, Ordering::Relaxed);
+	gc.write_u32(0x0C00_5020, (RAM_SIZE - 0x20) as u32).unwrap();
+	gc.write_u32(0x0C00_5024, aram_end as u32).unwrap();
+	gc.write_u32(0x0C00_5028, 0x8000_1000).unwrap();
+	assert_eq!(gc.memory[RAM_SIZE - 0x20], 0xCD);
+
+	//and addresses and lengths that are nowhere near either
+	gc.write_u32(0x0C00_5020, 0xFFFF_FFFF).unwrap();
+	gc.write_u32(0x0C00_5024, 0xFFFF_FFFF).unwrap();
+	gc.write_u32(0x0C00_5028, 0xFFFF_FFFF).unwrap();
+	gc.write_u32(0x0C00_5028, 0x7FFF_FFFF).unwrap();
+    }
+
+    #[test]
+    fn audio_dma_plays_a_block_at_a_time_and_interrupts_on_each_restart() {
+	let mut gc = test_gamecube();
+	//nothing else gets to be due first
+	gc.scheduler.cancel(EventKind::ViLine);
+	for (i, sample) in gc.memory[0x2000..0x2040].chunks_exact_mut(4).enumerate() {
+	    //right then left
+	    sample.copy_from_slice(&[0, i as u8, 0x80, i as u8]);
+	}
+	gc.write_u16(0x0C00_500A, DSPCR_AIDINTMSK).unwrap();
+
+	//two blocks from 0x2000
+	gc.write_u16(0x0C00_5030, 0).unwrap();
+	gc.write_u16(0x0C00_5032, 0x2000).unwrap();
+	gc.write_u16(0x0C00_5036, 0x8002).unwrap();
+
+	//AIDINT as soon as it starts, so the next buffer can be queued
+	assert!(aidint(&gc));
+	assert_eq!(gc.pi.intsr & PI_INT_DSP, PI_INT_DSP);
+	assert_eq!(gc.read_u16(0x0C00_503A).unwrap(), 1);
+	gc.write_u16(0x0C00_500A, DSPCR_AIDINTMSK | DSPCR_AIDINT).unwrap();
+	assert_eq!(gc.pi.intsr & PI_INT_DSP, 0);
+
+	//eight samples at 48kHz is 81000 cpu cycles
+	assert_eq!(gc.scheduler.next_deadline(), Some(81_000));
+	next_block(&mut gc);
+	assert_eq!(gc.ai.samples.len(), 8);
+	assert_eq!(gc.ai.samples[1], [-0x7FFF, 1]);
+	assert!(!aidint(&gc));
+
+	//the last block goes back round to the start registers and interrupts again
+	assert_eq!(gc.scheduler.next_deadline(), Some(162_000));
+	gc.write_u16(0x0C00_5032, 0x3000).unwrap();
+	next_block(&mut gc);
+	assert_eq!(gc.ai.samples.len(), 16);
+	assert_eq!(gc.ai.samples[15], [-0x7FF1, 15]);
+	assert!(aidint(&gc));
+	assert_eq!(gc.dsp.ai_dma_addr, 0x3000);
+
+	//AIDFR set plays at 32kHz
+	gc.ai.control |= 1 << 6;
+	next_block(&mut gc);
+	assert_eq!(gc.scheduler.next_deadline(), Some(243_000 + 121_500));
     }
 }
@@ -1,6 +1,6 @@
 use crate::dsp::REG_CONFIG;
 
-use super::{DSP, REG_AC0_H, REG_AC0_L, REG_AC0_M, REG_AC1_M, REG_SR};
+use super::{DSP, REG_AC0_M, REG_AC1_M};
 
 impl DSP {
     pub fn op_lr(&mut self, d: u16) {
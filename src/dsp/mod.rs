@@ -1,4 +1,4 @@
-use std::{fs::File, io::Read, ops::Deref, sync::{atomic::{AtomicU16, AtomicU8, Ordering}, Arc, RwLock}};
+use std::{fs::File, io::Read, ops::Deref, sync::{atomic::{AtomicU16, AtomicU8, Ordering}, Arc}};
 
 use bitmatch::bitmatch;
 use byteorder::{BigEndian, ByteOrder};
@@ -24,7 +24,6 @@ const REG_IX0: usize = 4;
 const REG_IX1: usize = 5;
 const REG_IX2: usize = 6;
 const REG_IX3: usize = 7;
-const REG_IX4: usize = 8;
 const REG_ST0: usize = 12;
 const REG_ST1: usize = 13;
 const REG_ST2: usize = 14;
@@ -55,8 +54,6 @@ pub struct DSP {
 //    iram: [u16; 0x1000],
     dram: [u16; 0x1000],
     irom: [u16; 0x1000],
-    drom: [u16; 0x0800],
-    exceptions: u16,
     stacks: DSPStacks,
     aram: Arc<Vec<AtomicU8>>,
     control: Arc<DSPControlRegister>,
@@ -76,7 +73,7 @@ impl DSP {
 	let dsp_mbox_l = Arc::new(AtomicU16::new(0));
 	let mut irom_dump = Vec::new();
 	let mut irom = [0u16; 0x1000];
-	let irom_file = File::open("get-your-own/dsp_rom.bin").unwrap().read_to_end(&mut irom_dump);
+	File::open("get-your-own/dsp_rom.bin").unwrap().read_to_end(&mut irom_dump).unwrap();
 	for i in 0..(irom.len() / 2) {
 	    let val = BigEndian::read_u16(&irom_dump[(i * 2)..]);
 	    irom[i] = val;
@@ -87,8 +84,6 @@ impl DSP {
 //	    iram: [0; 0x1000],
 	    dram: [0; 0x1000],
 	    irom,
-	    drom: [0; 0x0800],
-	    exceptions: 0,
 	    stacks: DSPStacks::new(),
 	    aram,
 	    control: control.clone(),
@@ -276,7 +271,7 @@ impl DSP {
     fn condition(&self, c: u16) -> bool {
 	match c {
 	    0b0101 => (self.registers[REG_SR] & 0x4) != 0,
-	    0b1100 => !((self.registers[REG_SR] & SR_LZ) != 0),
+	    0b1100 => (self.registers[REG_SR] & SR_LZ) == 0,
 	    0b1111 => true,
 	    a => unimplemented!("condition code {a:#06b}"),
 	}
@@ -362,6 +357,26 @@ impl DSPControlRegister {
     pub fn init(&self) -> bool {
 	(self.0.load(Ordering::Relaxed) & 0x0800) != 0
     }
+
+    pub fn set_aidint(&self) {
+	self.0.fetch_or(0x8, Ordering::Relaxed);
+    }
+
+    pub fn set_arint(&self) {
+	self.0.fetch_or(0x20, Ordering::Relaxed);
+    }
+
+    pub fn ar_dma_busy(&self) -> bool {
+	((self.0.load(Ordering::Relaxed) >> 9) & 1) != 0
+    }
+
+    pub fn set_ar_dma_busy(&self) {
+	self.0.fetch_or(0x200, Ordering::Relaxed);
+    }
+
+    pub fn clear_ar_dma_busy(&self) {
+	self.0.fetch_and(!0x200, Ordering::Relaxed);
+    }
 }
 
 impl Deref for DSPControlRegister {
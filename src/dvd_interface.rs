@@ -1,4 +1,4 @@
-use crate::Gamecube;
+use crate::{bus::mmio::{Mmio, MmioHandlers}, Gamecube};
 
 pub struct DVDInterface {
     
@@ -12,9 +12,22 @@ impl DVDInterface {
     }
 }
 
-pub fn di_read_u32(gc: &mut Gamecube, offset: u32) -> u32 {
+impl Default for DVDInterface {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+pub fn register_mmio(mmio: &mut Mmio) {
+    mmio.register("DI", 0x0C00_6000, 0x400, MmioHandlers {
+	read_u32: Some(di_read_u32),
+	..MmioHandlers::NONE
+    });
+}
+
+pub fn di_read_u32(_gc: &mut Gamecube, offset: u32) -> Option<u32> {
     match offset {
-	0x24 => 1,
-	_ => unimplemented!("DI read_u32 at offset {offset:#010X}"),
+	0x24 => Some(1),
+	_ => None,
     }
 }
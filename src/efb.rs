@@ -0,0 +1,103 @@
+use log::debug;
+
+use crate::Gamecube;
+
+pub const EFB_WIDTH: usize = 640;
+pub const EFB_HEIGHT: usize = 528;
+
+pub struct Efb {
+    //0xAARRGGBB
+    pub color: Vec<u32>,
+    //24 bit z, 0xFFFFFF is the far plane
+    pub depth: Vec<u32>,
+}
+
+impl Efb {
+    pub fn new() -> Self {
+	Self {
+	    color: vec![0; EFB_WIDTH * EFB_HEIGHT],
+	    depth: vec![0xFF_FFFF; EFB_WIDTH * EFB_HEIGHT],
+	}
+    }
+}
+
+impl Default for Efb {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+//cpu side access goes through 0x0800_0000, x is in bits 2-11, y in bits 12-21 and bit 22 picks z instead of color
+fn decode(offset: u32) -> (usize, usize, bool) {
+    let x = ((offset >> 2) & 0x3FF) as usize;
+    let y = ((offset >> 12) & 0x3FF) as usize;
+    let z = ((offset >> 22) & 1) != 0;
+
+    (x, y, z)
+}
+
+pub fn efb_read_u32(gc: &mut Gamecube, offset: u32) -> u32 {
+    let (x, y, z) = decode(offset);
+
+    if x >= EFB_WIDTH || y >= EFB_HEIGHT {
+	debug!("EFB peek out of bounds at ({x}, {y})");
+	return 0;
+    }
+
+    if z {
+	gc.efb.depth[y * EFB_WIDTH + x]
+    } else {
+	gc.pe.peek_color(gc.efb.color[y * EFB_WIDTH + x])
+    }
+}
+
+pub fn efb_write_u32(gc: &mut Gamecube, offset: u32, val: u32) {
+    let (x, y, z) = decode(offset);
+
+    if x >= EFB_WIDTH || y >= EFB_HEIGHT {
+	debug!("EFB poke out of bounds at ({x}, {y}) with val {val:#010X}");
+	return;
+    }
+
+    if z {
+	gc.efb.depth[y * EFB_WIDTH + x] = val & 0xFF_FFFF;
+    } else {
+	gc.efb.color[y * EFB_WIDTH + x] = val;
+    }
+}
+
+//narrower accesses pick their bytes out of the pixel's word, big endian like everything else
+pub fn efb_read_u16(gc: &mut Gamecube, offset: u32) -> u16 {
+    (efb_read_u32(gc, offset & !3) >> ((!offset & 2) * 8)) as u16
+}
+
+pub fn efb_read_u8(gc: &mut Gamecube, offset: u32) -> u8 {
+    (efb_read_u32(gc, offset & !3) >> ((!offset & 3) * 8)) as u8
+}
+
+//and writes only replace those bytes of what's stored
+fn efb_merge(gc: &mut Gamecube, offset: u32, mask: u32, val: u32) {
+    let (x, y, z) = decode(offset);
+
+    if x >= EFB_WIDTH || y >= EFB_HEIGHT {
+	debug!("EFB poke out of bounds at ({x}, {y}) with val {val:#010X}");
+	return;
+    }
+
+    let stored = if z {
+	gc.efb.depth[y * EFB_WIDTH + x]
+    } else {
+	gc.efb.color[y * EFB_WIDTH + x]
+    };
+    efb_write_u32(gc, offset & !3, (stored & !mask) | (val & mask));
+}
+
+pub fn efb_write_u16(gc: &mut Gamecube, offset: u32, val: u16) {
+    let shift = (!offset & 2) * 8;
+    efb_merge(gc, offset, 0xFFFF << shift, u32::from(val) << shift);
+}
+
+pub fn efb_write_u8(gc: &mut Gamecube, offset: u32, val: u8) {
+    let shift = (!offset & 3) * 8;
+    efb_merge(gc, offset, 0xFF << shift, u32::from(val) << shift);
+}
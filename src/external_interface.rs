@@ -3,7 +3,7 @@ pub mod null;
 pub mod bootrom;
 pub mod no_device;
 
-use std::{intrinsics::unreachable, sync::{Arc, Mutex, RwLock}};
+use std::sync::{Arc, RwLock};
 
 use ad16::AD16;
 use bootrom::Bootrom;
@@ -11,7 +11,10 @@ use log::debug;
 use no_device::NoDevice;
 use null::NullDevice;
 
-use crate::{sram::Sram, Gamecube};
+use crate::{bus::mmio::{merge, Mmio, MmioHandlers}, processor_interface::{set_interrupt, PI_INT_EXI}, scheduler::{self, EventKind}, sram::Sram, Gamecube};
+
+//cpu cycles per bit at each EXICLK setting, 1MHz doubling up to 32MHz
+const EXI_CYCLES_PER_BIT: [u64; 8] = [486, 243, 122, 61, 30, 15, 15, 15];
 
 pub struct ExternalInterface {
     channel0: EXIChannel,
@@ -27,32 +30,89 @@ impl ExternalInterface {
 	    channel2: EXIChannel::new([Box::new(AD16::new()), Box::new(NullDevice), Box::new(NullDevice)]),
 	}
     }
+
+    fn channel_mut(&mut self, channel_idx: u32) -> &mut EXIChannel {
+	match channel_idx {
+	    0 => &mut self.channel0,
+	    1 => &mut self.channel1,
+	    2 => &mut self.channel2,
+	    _ => unreachable!("attempted to access exi channel {channel_idx}"),
+	}
+    }
 }
 
-pub fn exi_write_u32(gc: &mut Gamecube, offset: u32, val: u32) {
+pub fn register_mmio(mmio: &mut Mmio) {
+    mmio.register("EXI", 0x0C00_6800, 0x400, MmioHandlers {
+	read_u32: Some(exi_read_u32),
+	write_u32: Some(exi_write_u32),
+	..MmioHandlers::NONE
+    });
+}
+
+//three channels of five registers each, the rest of the block is empty
+fn exi_reg_mapped(offset: u32) -> bool {
+    offset < 3 * 0x14 && offset & 3 == 0
+}
+
+pub fn exi_write_u32(gc: &mut Gamecube, offset: u32, val: u32, mask: u32) -> Option<()> {
+    if !exi_reg_mapped(offset) {
+	return None;
+    }
+
     let channel_idx = offset / 0x14;
-    let channel = match channel_idx {
-	0 => &mut gc.exi.channel0,
-	1 => &mut gc.exi.channel1,
-	2 => &mut gc.exi.channel2,
-	_ => unreachable!("attempted to access exi channel {channel_idx}"),
-    };
+    let channel = gc.exi.channel_mut(channel_idx);
     let reg = offset % 0x14;
     debug!("EXI write_u32 to channel {channel_idx} in reg {reg:#X} with val {val:#X}");
-    channel.write(&mut gc.memory, reg, val);
+    channel.write(&mut gc.memory, reg, val, mask);
+
+    //a transfer only starts when TSTART is actually written, not when a neighbouring byte is
+    let started = reg == 0xC && val & mask & EXI_CR_TSTART != 0;
+
+    //dma reads from a device land straight in ram, behind the cpu's back
+    if started && channel.control.dma() && channel.control.rw() == 0 {
+	let (start, length) = (channel.dma_start, channel.dma_length);
+	gc.cpu.decode_cache.invalidate_range(start, length as usize);
+    }
+
+    //the data moves right away, but TSTART stays set until the bits would have made it over the wire
+    if started {
+	let cycles = u64::from(channel.transfer_bytes()) * 8 * EXI_CYCLES_PER_BIT[channel.params.clk()];
+	gc.scheduler.cancel(EventKind::ExiTransfer(channel_idx as usize));
+	scheduler::schedule_in(gc, cycles, EventKind::ExiTransfer(channel_idx as usize), exi_transfer_complete);
+    }
+
+    if reg == 0x0 {
+	update_interrupts(gc);
+    }
+
+    Some(())
 }
 
-pub fn exi_read_u32(gc: &mut Gamecube, offset: u32) -> u32 {
-    let channel_idx = offset/0x14;
-    let channel = match channel_idx {
-	0 => &mut gc.exi.channel0,
-	1 => &mut gc.exi.channel1,
-	2 => &mut gc.exi.channel2,
-	_ => unreachable!("attempted to access exi channel {channel_idx}"),
+fn exi_transfer_complete(gc: &mut Gamecube, kind: EventKind) {
+    let EventKind::ExiTransfer(channel_idx) = kind else {
+	unreachable!("exi transfer completion fired for {kind:?}");
     };
+    let channel = gc.exi.channel_mut(channel_idx as u32);
+    channel.control.clear_t_start();
+    channel.params.set_tc_int();
+    update_interrupts(gc);
+}
+
+fn update_interrupts(gc: &mut Gamecube) {
+    let active = [&gc.exi.channel0, &gc.exi.channel1, &gc.exi.channel2].iter().any(|channel| channel.params.interrupt_pending());
+    set_interrupt(gc, PI_INT_EXI, active);
+}
+
+pub fn exi_read_u32(gc: &mut Gamecube, offset: u32) -> Option<u32> {
+    if !exi_reg_mapped(offset) {
+	return None;
+    }
+
+    let channel_idx = offset/0x14;
+    let channel = gc.exi.channel_mut(channel_idx);
     let reg = offset % 0x14;
     debug!("EXI read_u32 to channel {channel_idx} in reg {reg:#X}");
-    channel.read(reg)
+    Some(channel.read(reg))
 }
 
 pub trait EXIDevice {
@@ -134,6 +194,14 @@ impl EXIChannel {
 	}
     }
 
+    fn transfer_bytes(&self) -> u32 {
+	if self.control.dma() {
+	    self.dma_length
+	} else {
+	    self.control.t_len() as u32 + 1
+	}
+    }
+
     pub fn read(&mut self, reg: u32) -> u32 {
 	match reg {
 	    0x0 => self.params.0,
@@ -145,20 +213,22 @@ impl EXIChannel {
 	}
     }
 
-    pub fn write(&mut self, mem: &mut Vec<u8>, reg: u32, val: u32) {
+    pub fn write(&mut self, mem: &mut Vec<u8>, reg: u32, val: u32, mask: u32) {
 	match reg {
 	    0x0 => {
-		self.params = EXIChannelParams(val);
+		//the interrupt status bits are write 1 to clear
+		let status = self.params.0 & EXI_INT_STATUS & !(val & mask);
+		self.params = EXIChannelParams((merge(self.params.0, val, mask) & !EXI_INT_STATUS) | status);
 		debug!("new device: {:#0b}", self.params.cs());
 		self.choose_device().select();
 		//TODO: make this more like dolphin once interrupts are actually implemented.
 	    },
-	    0x4 => self.dma_start = val,
-	    0x8 => self.dma_length = val,
+	    0x4 => self.dma_start = merge(self.dma_start, val, mask),
+	    0x8 => self.dma_length = merge(self.dma_length, val, mask),
 	    0xC => {
-		self.control = EXIChannelControl(val);
+		self.control = EXIChannelControl(merge(self.control.0, val, mask));
 		
-		if self.control.t_start() {
+		if val & mask & EXI_CR_TSTART != 0 {
 		    if self.control.dma() {
 			let dma_addr = self.dma_start;
 			let dma_size = self.dma_length;
@@ -193,17 +263,24 @@ impl EXIChannel {
 			}
 		    }
 		}
-		self.control.clear_t_start();
 	    }
-	    0x10 => self.imm_data = val,
+	    0x10 => self.imm_data = merge(self.imm_data, val, mask),
 	    _ => unreachable!("write to unsupported EXI reg: {reg:#X} with val {val:#X}"),
 	}
     }
 }
 
+//EXIINT, TCINT and EXTINT
+const EXI_INT_STATUS: u32 = (1 << 1) | (1 << 3) | (1 << 11);
+const EXI_CR_TSTART: u32 = 1 << 0;
+
 pub struct EXIChannelParams(pub u32);
 
 impl EXIChannelParams {
+    pub fn interrupt_pending(&self) -> bool {
+	(self.exi_int() && self.exi_int_mask()) || (self.tc_int() && self.tc_int_mask()) || (self.ext_int() && self.ext_int_mask())
+    }
+
     pub fn exi_int_mask(&self) -> bool {
 	(self.0 & 1) != 0
     }
@@ -224,6 +301,10 @@ impl EXIChannelParams {
 	((self.0 >> 3) & 1) != 0
     }
 
+    pub fn set_tc_int(&mut self) {
+	self.0 |= 1 << 3;
+    }
+
     pub fn clear_tc_int(&mut self) {
 	self.0 &= !(1 << 3);
     }
@@ -1,5 +1,3 @@
-use std::io::stdin;
-
 use log::debug;
 
 use super::EXIDevice;
@@ -22,6 +20,12 @@ impl AD16 {
     }
 }
 
+impl Default for AD16 {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
 impl EXIDevice for AD16 {
     fn transfer_byte(&mut self, byte: &mut u8) {
 	if self.position == 0 {
@@ -44,7 +48,6 @@ impl EXIDevice for AD16 {
 		    panic!();
 		},
 		0xA0 => {
-		    panic!();
 		    match self.position {
 			1 => {
 			    self.reg &= !0xFF;
@@ -55,16 +58,13 @@ impl EXIDevice for AD16 {
 			    self.reg |= (*byte as u32) << 8;
 			}
 			3 => {
-			    panic!();
 			    self.reg &= !(0xFF << 16);
 			    self.reg |= (*byte as u32) << 16;
 			},
 			4 => {
-			    panic!();
 			    self.reg &= !(0xFF << 24);
 			    self.reg |= (*byte as u32) << 24;
-			    println!("{:#010X}", self.reg);
-			    stdin().read_line(&mut String::new()).unwrap();
+			    debug!("AD16 written with {:#010X}", self.reg);
 			}
 			_ => {},
 			
@@ -1,4 +1,4 @@
-use std::sync::{Arc, Mutex, RwLock};
+use std::sync::{Arc, RwLock};
 
 use crate::sram::Sram;
 
@@ -46,7 +46,7 @@ impl EXIDevice for Bootrom {
 		    *byte = self.rom[(addr + self.cursor) as usize];
 		    self.cursor += 1;
 		}
-	    } else if addr >= 0x0080_0000 && addr < 0x0080_0044 {
+	    } else if (0x0080_0000..0x0080_0044).contains(&addr) {
 		let dev_addr = addr - 0x0080_0000 + self.cursor;
 		if self.is_write() {
 		    self.sram.write().unwrap().as_byte_array_mut()[dev_addr as usize] = *byte;
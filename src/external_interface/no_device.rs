@@ -5,7 +5,7 @@ use super::EXIDevice;
 pub struct NoDevice;
 
 impl EXIDevice for NoDevice {
-    fn transfer_byte(&mut self, byte: &mut u8) {
+    fn transfer_byte(&mut self, _byte: &mut u8) {
 	info!("transfered byte to no device!");
     }
 
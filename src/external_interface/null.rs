@@ -6,7 +6,7 @@ pub struct NullDevice;
 
 
 impl EXIDevice for NullDevice {
-    fn transfer_byte(&mut self, byte: &mut u8) {
+    fn transfer_byte(&mut self, _byte: &mut u8) {
 	panic!("transfer_byte called for null device!");
     }
 
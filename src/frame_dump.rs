@@ -0,0 +1,154 @@
+use std::io::{self, Write};
+
+use crate::video_interface::Frame;
+
+//the reflected ieee polynomial, the same crc png and zip use
+const CRC32_POLY: u32 = 0xEDB8_8320;
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+//8 bits per channel, rgba
+const PNG_BIT_DEPTH: u8 = 8;
+const PNG_COLOR_RGBA: u8 = 6;
+//deflate blocks stored without compression can't be any longer than this
+const DEFLATE_STORED_MAX: usize = 0xFFFF;
+const ADLER32_MOD: u32 = 65521;
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+	let mut crc = i as u32;
+	let mut bit = 0;
+	while bit < 8 {
+	    crc = if crc & 1 != 0 { (crc >> 1) ^ CRC32_POLY } else { crc >> 1 };
+	    bit += 1;
+	}
+	table[i] = crc;
+	i += 1;
+    }
+    table
+}
+
+fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, &byte| CRC32_TABLE[((crc ^ u32::from(byte)) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    !crc32_update(!0, data)
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1, 0);
+    //5552 is the most bytes that can be summed before b could overflow
+    for chunk in data.chunks(5552) {
+	for &byte in chunk {
+	    a += u32::from(byte);
+	    b += a;
+	}
+	a %= ADLER32_MOD;
+	b %= ADLER32_MOD;
+    }
+    (b << 16) | a
+}
+
+//what goes to stdout when only hashing, covering the size too so a frame changing shape changes the hash
+pub fn frame_hash(frame: &Frame) -> u32 {
+    let size = [(frame.width as u32).to_be_bytes(), (frame.height as u32).to_be_bytes()].concat();
+    !crc32_update(crc32_update(!0, &size), &frame.pixels)
+}
+
+fn png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    out.extend_from_slice(&(!crc32_update(crc32_update(!0, kind), data)).to_be_bytes());
+}
+
+//a png with the image data in stored deflate blocks. nothing gets compressed, but it needs no zlib and every
+//viewer opens it
+pub fn png(frame: &Frame) -> Vec<u8> {
+    //every row starts with its filter type, which is always none
+    let mut raw = Vec::with_capacity((frame.width * 4 + 1) * frame.height);
+    if frame.width != 0 {
+	for row in frame.pixels.chunks_exact(frame.width * 4) {
+	    raw.push(0);
+	    raw.extend_from_slice(row);
+	}
+    }
+
+    //zlib header for deflate with a 32k window and no dictionary, then the blocks and the adler32 of the data
+    let mut zlib = vec![0x78, 0x01];
+    let blocks = raw.chunks(DEFLATE_STORED_MAX).collect::<Vec<_>>();
+    for (i, block) in blocks.iter().enumerate() {
+	let len = block.len() as u16;
+	zlib.push(u8::from(i == blocks.len() - 1));
+	zlib.extend_from_slice(&len.to_le_bytes());
+	zlib.extend_from_slice(&(!len).to_le_bytes());
+	zlib.extend_from_slice(block);
+    }
+    if blocks.is_empty() {
+	zlib.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(frame.width as u32).to_be_bytes());
+    header.extend_from_slice(&(frame.height as u32).to_be_bytes());
+    //then compression, filter and interlace methods, all 0
+    header.extend_from_slice(&[PNG_BIT_DEPTH, PNG_COLOR_RGBA, 0, 0, 0]);
+
+    let mut out = PNG_SIGNATURE.to_vec();
+    png_chunk(&mut out, b"IHDR", &header);
+    png_chunk(&mut out, b"IDAT", &zlib);
+    png_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+//an uncompressed yuv 4:4:4 stream. y4m can't change size partway through, so it's fixed by the first frame
+pub struct Y4mWriter<W: Write> {
+    out: W,
+    size: Option<(usize, usize)>,
+    //frames per second as a fraction, the field rate
+    rate: (u32, u32),
+}
+
+impl<W: Write> Y4mWriter<W> {
+    pub fn new(out: W, rate: (u32, u32)) -> Self {
+	Self {
+	    out,
+	    size: None,
+	    rate,
+	}
+    }
+
+    //false if the frame was left out for not being the same size as the first
+    pub fn write(&mut self, frame: &Frame) -> io::Result<bool> {
+	let size = (frame.width, frame.height);
+	match self.size {
+	    Some(first) if first != size => return Ok(false),
+	    Some(_) => (),
+	    None => {
+		writeln!(self.out, "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444", size.0, size.1, self.rate.0, self.rate.1)?;
+		self.size = Some(size);
+	    },
+	}
+
+	//BT.601 with the usual 16-235 range for y, planes one after another
+	let pixels = frame.pixels.chunks_exact(4).map(|p| {
+	    let [r, g, b] = [p[0], p[1], p[2]].map(i32::from);
+	    [
+		16 + ((66 * r + 129 * g + 25 * b + 128) >> 8),
+		128 + ((-38 * r - 74 * g + 112 * b + 128) >> 8),
+		128 + ((112 * r - 94 * g - 18 * b + 128) >> 8),
+	    ].map(|val| val as u8)
+	}).collect::<Vec<_>>();
+
+	self.out.write_all(b"FRAME\n")?;
+	for plane in 0..3 {
+	    self.out.write_all(&pixels.iter().map(|yuv| yuv[plane]).collect::<Vec<_>>())?;
+	}
+	self.out.flush()?;
+	Ok(true)
+    }
+}
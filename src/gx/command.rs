@@ -0,0 +1,285 @@
+use byteorder::{BigEndian, ByteOrder};
+use log::{debug, warn};
+
+use crate::{Gamecube, RAM_SIZE};
+
+use super::{draw, load_bp_reg, load_cp_reg, load_xf_reg, vertex::VertexFormat, XF_MEMORY_SIZE};
+
+pub const GX_NOP: u8 = 0x00;
+pub const GX_LOAD_CP_REG: u8 = 0x08;
+pub const GX_LOAD_XF_REG: u8 = 0x10;
+pub const GX_LOAD_INDX_A: u8 = 0x20;
+pub const GX_LOAD_INDX_B: u8 = 0x28;
+pub const GX_LOAD_INDX_C: u8 = 0x30;
+pub const GX_LOAD_INDX_D: u8 = 0x38;
+pub const GX_CALL_DISPLAYLIST: u8 = 0x40;
+pub const GX_UNKNOWN_METRICS: u8 = 0x44;
+pub const GX_INVALIDATE_VTX_CACHE: u8 = 0x48;
+pub const GX_LOAD_BP_REG: u8 = 0x61;
+
+//draws have the primitive in the top five bits and the VAT in the bottom three
+pub const GX_DRAW_QUADS: u8 = 0x80;
+pub const GX_DRAW_QUADS_2: u8 = 0x88;
+pub const GX_DRAW_TRIANGLES: u8 = 0x90;
+pub const GX_DRAW_TRIANGLE_STRIP: u8 = 0x98;
+pub const GX_DRAW_TRIANGLE_FAN: u8 = 0xA0;
+pub const GX_DRAW_LINES: u8 = 0xA8;
+pub const GX_DRAW_LINE_STRIP: u8 = 0xB0;
+pub const GX_DRAW_POINTS: u8 = 0xB8;
+
+const GX_PRIMITIVE_MASK: u8 = 0xF8;
+const GX_VAT_MASK: u8 = 0x07;
+
+//LOAD_INDX_A-D pull from arrays 12-15 into xf memory
+const INDX_ARRAY_BASE: usize = 12;
+
+//runs every complete command at the start of data and returns how many bytes that was. whatever's left is
+//the start of a command that's still on its way through the fifo
+pub fn process(gc: &mut Gamecube, data: &[u8], display_list: bool) -> usize {
+    let mut pos = 0;
+    while pos < data.len() {
+	match run_command(gc, &data[pos..], display_list) {
+	    Some(len) => pos += len,
+	    None => break,
+	}
+    }
+
+    pos
+}
+
+//None if the command isn't all there yet
+fn run_command(gc: &mut Gamecube, data: &[u8], display_list: bool) -> Option<usize> {
+    let opcode = data[0];
+    match opcode {
+	GX_NOP | GX_INVALIDATE_VTX_CACHE => Some(1),
+	GX_LOAD_CP_REG => {
+	    let args = data.get(1..6)?;
+	    load_cp_reg(gc, args[0], BigEndian::read_u32(&args[1..]));
+	    Some(6)
+	},
+	GX_LOAD_XF_REG => {
+	    let header = BigEndian::read_u32(data.get(1..5)?);
+	    let addr = header & 0xFFFF;
+	    let count = ((header >> 16) & 0xF) as usize + 1;
+	    let vals = data.get(5..(5 + count * 4))?;
+	    for (i, val) in vals.chunks_exact(4).enumerate() {
+		load_xf_reg(gc, addr + i as u32, BigEndian::read_u32(val));
+	    }
+	    Some(5 + count * 4)
+	},
+	GX_LOAD_INDX_A | GX_LOAD_INDX_B | GX_LOAD_INDX_C | GX_LOAD_INDX_D => {
+	    let cmd = BigEndian::read_u32(data.get(1..5)?);
+	    load_indexed(gc, INDX_ARRAY_BASE + ((opcode - GX_LOAD_INDX_A) >> 3) as usize, cmd);
+	    Some(5)
+	},
+	GX_CALL_DISPLAYLIST => {
+	    let args = data.get(1..9)?;
+	    let (addr, size) = (BigEndian::read_u32(&args[..4]), BigEndian::read_u32(&args[4..]));
+	    //the hardware can't nest them, a call inside a display list gets skipped
+	    if display_list {
+		warn!("display list at {addr:#010X} called from inside a display list");
+	    } else {
+		call_display_list(gc, addr, size);
+	    }
+	    Some(9)
+	},
+	GX_UNKNOWN_METRICS => {
+	    debug!("STUB: GX metrics command");
+	    Some(1)
+	},
+	GX_LOAD_BP_REG => {
+	    load_bp_reg(gc, BigEndian::read_u32(data.get(1..5)?));
+	    Some(5)
+	},
+	//0x80-0xBF are the draws, with the primitive in the top bits and the vat in the bottom three
+	0x80..=0xBF => {
+	    let count = BigEndian::read_u16(data.get(1..3)?);
+	    let vat = (opcode & GX_VAT_MASK) as usize;
+	    let size = VertexFormat::new(&gc.gx.cp, vat).size() * count as usize;
+	    let vertices = data.get(3..(3 + size))?;
+	    draw(gc, opcode & GX_PRIMITIVE_MASK, vat, count, vertices);
+	    Some(3 + size)
+	},
+	_ => {
+	    warn!("unknown GX opcode {opcode:#04X}, skipping it");
+	    Some(1)
+	},
+    }
+}
+
+//the index picks an element out of the array, which gets copied a word at a time into xf memory
+fn load_indexed(gc: &mut Gamecube, array: usize, cmd: u32) {
+    let index = cmd >> 16;
+    let len = ((cmd >> 12) & 0xF) + 1;
+    let xf_addr = cmd & 0xFFF;
+    let src = gc.gx.cp.array_base[array] + index * gc.gx.cp.array_stride[array];
+
+    for i in 0..len {
+	let addr = (src + i * 4) as usize;
+	let Some(word) = gc.memory.get(addr..(addr + 4)) else {
+	    warn!("LOAD_INDX from {addr:#010X} is outside of ram");
+	    return;
+	};
+	let val = BigEndian::read_u32(word);
+	let dst = (xf_addr + i) as usize;
+	if dst < XF_MEMORY_SIZE {
+	    gc.gx.xf_memory[dst] = val;
+	}
+    }
+}
+
+fn call_display_list(gc: &mut Gamecube, addr: u32, size: u32) {
+    let (start, end) = ((addr & 0x03FF_FFE0) as usize, (addr & 0x03FF_FFE0) as usize + (size & !0x1F) as usize);
+    if end > RAM_SIZE {
+	warn!("display list at {addr:#010X} with size {size:#X} runs off the end of ram");
+	return;
+    }
+
+    let list = gc.memory[start..end].to_vec();
+    let done = process(gc, &list, true);
+    if done != list.len() {
+	warn!("display list at {addr:#010X} ends partway through a command");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{efb::{EFB_HEIGHT, EFB_WIDTH}, frame_dump::crc32, test_gamecube, Gamecube};
+
+    use super::{process, GX_DRAW_QUADS, GX_DRAW_TRIANGLES, GX_LOAD_BP_REG, GX_LOAD_CP_REG, GX_LOAD_XF_REG};
+
+    const RED: u32 = 0xFF0000FF;
+    const BLUE: u32 = 0x0000FFFF;
+
+    fn cp(fifo: &mut Vec<u8>, addr: u8, val: u32) {
+	fifo.extend_from_slice(&[GX_LOAD_CP_REG, addr]);
+	fifo.extend_from_slice(&val.to_be_bytes());
+    }
+
+    fn xf(fifo: &mut Vec<u8>, addr: u32, vals: &[u32]) {
+	fifo.push(GX_LOAD_XF_REG);
+	fifo.extend_from_slice(&(((vals.len() as u32 - 1) << 16) | addr).to_be_bytes());
+	for val in vals {
+	    fifo.extend_from_slice(&val.to_be_bytes());
+	}
+    }
+
+    fn bp(fifo: &mut Vec<u8>, addr: u32, val: u32) {
+	fifo.push(GX_LOAD_BP_REG);
+	fifo.extend_from_slice(&((addr << 24) | val).to_be_bytes());
+    }
+
+    //float xy positions and an rgba8 color, drawn with one tev stage passing the color straight through.
+    //the projection is orthographic and the viewport maps positions one to one onto efb pixels
+    fn setup() -> Vec<u8> {
+	let mut fifo = Vec::new();
+	//VCD: direct position and color0, VAT 0: xy float, rgba8888
+	cp(&mut fifo, 0x50, (1 << 9) | (1 << 13));
+	cp(&mut fifo, 0x60, 0);
+	cp(&mut fifo, 0x70, (4 << 1) | (1 << 13) | (5 << 14));
+
+	//one color channel, its color and alpha from the vertex
+	xf(&mut fifo, 0x1009, &[1]);
+	xf(&mut fifo, 0x100E, &[1]);
+	xf(&mut fifo, 0x1010, &[1]);
+	xf(&mut fifo, 0x1018, &[0]);
+	xf(&mut fifo, 0x0000, &[1.0f32.to_bits(), 0, 0, 0, 0, 1.0f32.to_bits(), 0, 0, 0, 0, 1.0f32.to_bits(), 0]);
+	xf(&mut fifo, 0x101A, &[320.0f32, -240.0, 16_777_215.0, 662.0, 582.0, 16_777_215.0].map(f32::to_bits));
+	xf(&mut fifo, 0x1020, &[2.0 / 640.0, -1.0, -2.0 / 480.0, 1.0, 0.0, -0.5].map(f32::to_bits));
+	xf(&mut fifo, 0x1026, &[1]);
+	xf(&mut fifo, 0x103F, &[0]);
+
+	//one color, one stage, no textures
+	bp(&mut fifo, 0x00, 1 << 4);
+	bp(&mut fifo, 0x20, (342 << 12) | 342);
+	bp(&mut fifo, 0x21, ((342 + 639) << 12) | (342 + 527));
+	bp(&mut fifo, 0x59, 171 | (171 << 10));
+	bp(&mut fifo, 0x28, 0);
+	//rasterized color and alpha into d, clamped
+	bp(&mut fifo, 0xC0, 0x08_000A);
+	bp(&mut fifo, 0xC1, 0x08_0050);
+	bp(&mut fifo, 0xF3, (7 << 16) | (7 << 19));
+	//swap table 0 leaves rgba where it is
+	bp(&mut fifo, 0xF6, 0b0100);
+	bp(&mut fifo, 0xF7, 0b1110);
+	//color and alpha updates on, no blending or z
+	bp(&mut fifo, 0x40, 0);
+	bp(&mut fifo, 0x41, 0x18);
+	fifo
+    }
+
+    fn draw(fifo: &mut Vec<u8>, primitive: u8, vertices: &[(f32, f32, u32)]) {
+	fifo.push(primitive);
+	fifo.extend_from_slice(&(vertices.len() as u16).to_be_bytes());
+	for &(x, y, color) in vertices {
+	    fifo.extend_from_slice(&x.to_be_bytes());
+	    fifo.extend_from_slice(&y.to_be_bytes());
+	    fifo.extend_from_slice(&color.to_be_bytes());
+	}
+    }
+
+    fn run(fifo: &[u8]) -> Gamecube {
+	let mut gc = test_gamecube();
+	assert_eq!(process(&mut gc, fifo, false), fifo.len());
+	gc
+    }
+
+    //the efb keeps 0xAARRGGBB
+    fn argb(rgba: u32) -> u32 {
+	rgba.rotate_right(8)
+    }
+
+    fn covered(gc: &Gamecube, rgba: u32) -> Vec<(usize, usize)> {
+	let argb = argb(rgba);
+	(0..EFB_HEIGHT).flat_map(|y| (0..EFB_WIDTH).map(move |x| (x, y))).filter(|&(x, y)| gc.efb.color[y * EFB_WIDTH + x] == argb).collect()
+    }
+
+    #[test]
+    fn quad_fills_exactly_its_pixels() {
+	let mut fifo = setup();
+	draw(&mut fifo, GX_DRAW_QUADS, &[(100.0, 50.0, RED), (200.0, 50.0, RED), (200.0, 150.0, RED), (100.0, 150.0, RED)]);
+	let gc = run(&fifo);
+
+	let pixels = covered(&gc, RED);
+	assert_eq!(pixels.len(), 100 * 100);
+	assert!(pixels.iter().all(|&(x, y)| (100..200).contains(&x) && (50..150).contains(&y)));
+    }
+
+    #[test]
+    fn triangle_leaves_out_pixels_on_its_bottom_right_edge() {
+	let mut fifo = setup();
+	draw(&mut fifo, GX_DRAW_TRIANGLES, &[(100.0, 50.0, BLUE), (200.0, 50.0, BLUE), (100.0, 150.0, BLUE)]);
+	let gc = run(&fifo);
+
+	//a row r pixels down has 99 - r pixel centers strictly inside, the ones on the diagonal belong to
+	//whatever's drawn on the other side of it
+	let pixels = covered(&gc, BLUE);
+	assert_eq!(pixels.len(), (0..100).map(|r| 99 - r).sum::<usize>());
+	assert!(pixels.iter().all(|&(x, y)| x >= 100 && y >= 50 && (x - 100) + (y - 50) < 99));
+    }
+
+    #[test]
+    fn shared_edge_is_drawn_once() {
+	//two triangles making up a quad, the second over the first's diagonal
+	let mut fifo = setup();
+	draw(&mut fifo, GX_DRAW_TRIANGLES, &[
+	    (300.0, 200.0, RED), (400.0, 200.0, RED), (300.0, 300.0, RED),
+	    (400.0, 200.0, BLUE), (400.0, 300.0, BLUE), (300.0, 300.0, BLUE),
+	]);
+	let gc = run(&fifo);
+
+	assert_eq!(covered(&gc, RED).len() + covered(&gc, BLUE).len(), 100 * 100);
+    }
+
+    #[test]
+    fn efb_hash_is_stable() {
+	let mut fifo = setup();
+	draw(&mut fifo, GX_DRAW_QUADS, &[(100.0, 50.0, RED), (200.0, 50.0, RED), (200.0, 150.0, RED), (100.0, 150.0, RED)]);
+	draw(&mut fifo, GX_DRAW_TRIANGLES, &[(320.0, 100.0, 0x00FF00FF), (560.0, 400.0, BLUE), (80.0, 400.0, RED)]);
+	let gc = run(&fifo);
+
+	//pinned from a known good run, anything that changes how these get rasterized or shaded changes it
+	let bytes = gc.efb.color.iter().flat_map(|color| color.to_be_bytes()).collect::<Vec<_>>();
+	assert_eq!(crc32(&bytes), 0x1A8F_9EEA);
+    }
+}
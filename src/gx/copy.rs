@@ -0,0 +1,374 @@
+use log::debug;
+
+use crate::{efb::{EFB_HEIGHT, EFB_WIDTH}, Gamecube};
+
+use super::{pixel::PixelPipeline, texture::{tile_size, TEX_I4, TEX_I8, TEX_IA4, TEX_IA8, TEX_RGB565, TEX_RGB5A3, TEX_RGBA8}};
+
+//BP registers
+const BP_PE_CONTROL: usize = 0x43;
+const BP_COPY_SRC_TL: usize = 0x49;
+const BP_COPY_SRC_WH: usize = 0x4A;
+const BP_COPY_DST: usize = 0x4B;
+const BP_COPY_STRIDE: usize = 0x4D;
+const BP_COPY_YSCALE: usize = 0x4E;
+const BP_CLEAR_AR: usize = 0x4F;
+const BP_CLEAR_GB: usize = 0x50;
+const BP_CLEAR_Z: usize = 0x51;
+const BP_COPY_FILTER0: usize = 0x53;
+const BP_COPY_FILTER1: usize = 0x54;
+
+//the PE_COPY_EXECUTE write itself
+const COPY_CLAMP_TOP: u32 = 1 << 0;
+const COPY_CLAMP_BOTTOM: u32 = 1 << 1;
+const COPY_HALF_SCALE: u32 = 1 << 9;
+const COPY_SCALE_Y: u32 = 1 << 10;
+const COPY_CLEAR: u32 = 1 << 11;
+const COPY_TO_XFB: u32 = 1 << 14;
+const COPY_INTENSITY: u32 = 1 << 15;
+
+//efb pixel format that holds depth as color, for copying z out to a texture
+const PIXEL_Z24: u32 = 3;
+
+//texture copy formats, after undoing the rotation they get in the register
+const COPY_R4: u32 = 0x0;
+const COPY_R8_1: u32 = 0x1;
+const COPY_RA4: u32 = 0x2;
+const COPY_RA8: u32 = 0x3;
+const COPY_RGB565: u32 = 0x4;
+const COPY_RGB5A3: u32 = 0x5;
+const COPY_RGBA8: u32 = 0x6;
+const COPY_A8: u32 = 0x7;
+const COPY_R8: u32 = 0x8;
+const COPY_G8: u32 = 0x9;
+const COPY_B8: u32 = 0xA;
+const COPY_RG8: u32 = 0xB;
+const COPY_GB8: u32 = 0xC;
+
+//the copy filter coefficients add up to this for a filter that leaves the image alone
+const FILTER_ONE: u32 = 64;
+
+//the source rectangle and how to read it, in efb pixels
+struct Source {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    clamp_top: bool,
+    clamp_bottom: bool,
+    //weights of the line above, the line itself and the line below, out of FILTER_ONE
+    filter: [u32; 3],
+    gamma: f32,
+    depth: bool,
+}
+
+impl Source {
+    fn new(gc: &Gamecube, val: u32) -> Self {
+	let bp = &gc.gx.bp_regs;
+	let (tl, wh) = (bp[BP_COPY_SRC_TL], bp[BP_COPY_SRC_WH]);
+	let (f0, f1) = (bp[BP_COPY_FILTER0], bp[BP_COPY_FILTER1]);
+	let c: [u32; 7] = std::array::from_fn(|i| (if i < 4 { f0 >> (i * 6) } else { f1 >> ((i - 4) * 6) }) & 0x3F);
+
+	Self {
+	    x: (tl & 0x3FF) as usize,
+	    y: ((tl >> 10) & 0x3FF) as usize,
+	    width: (wh & 0x3FF) as usize + 1,
+	    height: ((wh >> 10) & 0x3FF) as usize + 1,
+	    clamp_top: val & COPY_CLAMP_TOP != 0,
+	    clamp_bottom: val & COPY_CLAMP_BOTTOM != 0,
+	    //the seven taps come from the antialiasing sample pattern, which without aa is one line each
+	    filter: [c[0] + c[1], c[2] + c[3] + c[4], c[5] + c[6]],
+	    gamma: match (val >> 7) & 0b11 {
+		0 => 1.0,
+		1 => 1.7,
+		_ => 2.2,
+	    },
+	    depth: bp[BP_PE_CONTROL] & 0b111 == PIXEL_Z24,
+	}
+    }
+
+    fn efb(&self, gc: &Gamecube, x: usize, y: usize) -> u32 {
+	gc.efb.color[y.min(EFB_HEIGHT - 1) * EFB_WIDTH + x.min(EFB_WIDTH - 1)]
+    }
+
+    //rgba of a pixel in the rectangle, through the vertical filter and gamma
+    fn color(&self, gc: &Gamecube, x: usize, y: usize) -> [u8; 4] {
+	let (x, y) = (self.x + x, self.y + y);
+	let above = if self.clamp_top && y == self.y { y } else { y.saturating_sub(1) };
+	let below = if self.clamp_bottom && y == self.y + self.height - 1 { y } else { y + 1 };
+
+	let center = self.efb(gc, x, y);
+	let lines = [self.efb(gc, x, above), center, self.efb(gc, x, below)];
+	let filtered = |shift: u32| -> u8 {
+	    let sum = lines.iter().zip(&self.filter).map(|(line, weight)| ((line >> shift) & 0xFF) * weight).sum::<u32>();
+	    (sum / FILTER_ONE).min(255) as u8
+	};
+
+	//alpha isn't filtered
+	let color = [filtered(16), filtered(8), filtered(0), (center >> 24) as u8];
+	if self.gamma == 1.0 {
+	    return color;
+	}
+
+	let gamma = |val: u8| ((f32::from(val) / 255.0).powf(1.0 / self.gamma) * 255.0).round() as u8;
+	[gamma(color[0]), gamma(color[1]), gamma(color[2]), color[3]]
+    }
+
+    //24 bit z of a pixel in the rectangle, which isn't filtered
+    fn depth(&self, gc: &Gamecube, x: usize, y: usize) -> u32 {
+	gc.efb.depth[(self.y + y).min(EFB_HEIGHT - 1) * EFB_WIDTH + (self.x + x).min(EFB_WIDTH - 1)]
+    }
+}
+
+//the BT.601 conversion the copy engine does for the xfb and for intensity textures
+fn yuv(color: [u8; 4]) -> [u8; 3] {
+    let [r, g, b, _] = color.map(i32::from);
+    [
+	(16 + ((66 * r + 129 * g + 25 * b + 128) >> 8)) as u8,
+	(128 + ((-38 * r - 74 * g + 112 * b + 128) >> 8)) as u8,
+	(128 + ((112 * r - 94 * g - 18 * b + 128) >> 8)) as u8,
+    ]
+}
+
+//PE_COPY_EXECUTE, both GXCopyDisp and GXCopyTex end up here
+pub fn copy(gc: &mut Gamecube, val: u32) {
+    let source = Source::new(gc, val);
+
+    if val & COPY_TO_XFB != 0 {
+	copy_display(gc, &source, val);
+    } else {
+	copy_texture(gc, &source, val);
+    }
+
+    if val & COPY_CLEAR != 0 {
+	let bp = &gc.gx.bp_regs;
+	let (ar, gb) = (bp[BP_CLEAR_AR], bp[BP_CLEAR_GB]);
+	let color = ((ar & 0xFF) << 24) | ((ar & 0xFF00) << 8) | (gb & 0xFFFF);
+	let z = bp[BP_CLEAR_Z];
+	let pixel = PixelPipeline::new(&gc.gx);
+
+	for y in source.y..(source.y + source.height).min(EFB_HEIGHT) {
+	    for x in source.x..(source.x + source.width).min(EFB_WIDTH) {
+		pixel.clear(&mut gc.efb, y * EFB_WIDTH + x, color, z);
+	    }
+	}
+    }
+}
+
+//into the external framebuffer as yuyv, two pixels sharing a u and v
+fn copy_display(gc: &mut Gamecube, source: &Source, val: u32) {
+    let bp = &gc.gx.bp_regs;
+    let dst = ((bp[BP_COPY_DST] & 0xFF_FFFF) << 5) as usize;
+    let stride = ((bp[BP_COPY_STRIDE] & 0x3FF) << 5) as usize;
+    //lines of the source per line of the xfb, in 1/256ths
+    let yscale = if val & COPY_SCALE_Y != 0 { (bp[BP_COPY_YSCALE] & 0x1FF).max(1) as usize } else { 256 };
+    let lines = 1 + (source.height - 1) * 256 / yscale;
+
+    for line in 0..lines {
+	let y = (line * yscale / 256).min(source.height - 1);
+	let row = (0..source.width).map(|x| yuv(source.color(gc, x, y))).collect::<Vec<_>>();
+
+	let mut out = Vec::with_capacity(source.width * 2);
+	for pair in row.chunks(2) {
+	    let (a, b) = (pair[0], pair[pair.len() - 1]);
+	    let u = ((u16::from(a[1]) + u16::from(b[1])) / 2) as u8;
+	    let v = ((u16::from(a[2]) + u16::from(b[2])) / 2) as u8;
+	    out.extend_from_slice(&[a[0], u, b[0], v]);
+	}
+
+	let start = dst + line * stride;
+	if let Some(mem) = gc.memory.get_mut(start..(start + out.len())) {
+	    mem.copy_from_slice(&out);
+	}
+    }
+}
+
+//into ram as a tiled texture, which GXSetTexCopyDst set the format and size of
+fn copy_texture(gc: &mut Gamecube, source: &Source, val: u32) {
+    let bp = &gc.gx.bp_regs;
+    let dst = ((bp[BP_COPY_DST] & 0xFF_FFFF) << 5) as usize;
+    //32 byte lines per row of tiles
+    let stride = ((bp[BP_COPY_STRIDE] & 0x3FF) << 5) as usize;
+    let format = (val >> 3) & 0xF;
+    let format = (format >> 1) | ((format & 1) << 3);
+    let intensity = val & COPY_INTENSITY != 0;
+    let half = val & COPY_HALF_SCALE != 0;
+
+    let tex_format = match format {
+	COPY_R4 => TEX_I4,
+	COPY_R8_1 | COPY_A8 | COPY_R8 | COPY_G8 | COPY_B8 => TEX_I8,
+	COPY_RA4 => TEX_IA4,
+	COPY_RA8 | COPY_RG8 | COPY_GB8 => TEX_IA8,
+	COPY_RGB565 => TEX_RGB565,
+	COPY_RGB5A3 => TEX_RGB5A3,
+	COPY_RGBA8 => TEX_RGBA8,
+	_ => {
+	    debug!("STUB: EFB copy to texture format {format:#X}");
+	    return;
+	},
+    };
+
+    let (width, height) = if half { (source.width / 2, source.height / 2) } else { (source.width, source.height) };
+    let (tile_width, tile_height, tile_bytes) = tile_size(tex_format);
+
+    for y in 0..height {
+	for x in 0..width {
+	    let texel = if source.depth {
+		depth_texel(format, source.depth(gc, x << u32::from(half), y << u32::from(half)))
+	    } else {
+		let color = if half {
+		    //box filtered down from the 2x2 block
+		    let block = [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(dx, dy)| source.color(gc, x * 2 + dx, y * 2 + dy));
+		    std::array::from_fn(|i| (block.iter().map(|color| u32::from(color[i])).sum::<u32>() / 4) as u8)
+		} else {
+		    source.color(gc, x, y)
+		};
+		color_texel(format, color, intensity)
+	    };
+
+	    let tile = dst + (y / tile_height) * stride + (x / tile_width) * tile_bytes;
+	    let (x, y) = (x % tile_width, y % tile_height);
+	    store(&mut gc.memory, tile, tex_format, x, y, texel);
+	}
+    }
+}
+
+//the components a color texel is made of, in the order they're stored. for intensity formats the first is y
+fn color_texel(format: u32, color: [u8; 4], intensity: bool) -> [u8; 4] {
+    let [r, g, b, a] = color;
+    let i = if intensity { yuv(color)[0] } else { r };
+
+    match format {
+	COPY_R4 | COPY_R8_1 | COPY_R8 => [i, 0, 0, 0],
+	COPY_RA4 | COPY_RA8 => [a, i, 0, 0],
+	COPY_A8 => [a, 0, 0, 0],
+	COPY_G8 => [g, 0, 0, 0],
+	COPY_B8 => [b, 0, 0, 0],
+	COPY_RG8 => [g, r, 0, 0],
+	COPY_GB8 => [b, g, 0, 0],
+	_ => color,
+    }
+}
+
+//depth copies reuse the color formats for picking bytes out of the 24 bit z
+fn depth_texel(format: u32, z: u32) -> [u8; 4] {
+    let [_, hi, mid, lo] = z.to_be_bytes();
+
+    match format {
+	COPY_R4 | COPY_R8_1 | COPY_R8 => [hi, 0, 0, 0],
+	COPY_RA8 | COPY_RG8 => [hi, mid, 0, 0],
+	COPY_G8 => [mid, 0, 0, 0],
+	COPY_B8 => [lo, 0, 0, 0],
+	COPY_GB8 => [mid, lo, 0, 0],
+	_ => [hi, mid, lo, 0xFF],
+    }
+}
+
+//puts one texel into its tile, laid out the way texture::texel reads it back
+fn store(memory: &mut [u8], tile: usize, format: u32, x: usize, y: usize, texel: [u8; 4]) {
+    let mut put = |offset: usize, val: u8, mask: u8| {
+	if let Some(byte) = memory.get_mut(tile + offset) {
+	    *byte = (*byte & !mask) | (val & mask);
+	}
+    };
+
+    match format {
+	TEX_I4 => {
+	    let offset = y * 8 + x;
+	    let shift = (!offset & 1) * 4;
+	    put(offset / 2, (texel[0] >> 4) << shift, 0xF << shift);
+	},
+	TEX_I8 => put(y * 8 + x, texel[0], 0xFF),
+	TEX_IA4 => put(y * 8 + x, (texel[0] & 0xF0) | (texel[1] >> 4), 0xFF),
+	TEX_IA8 => {
+	    put((y * 4 + x) * 2, texel[0], 0xFF);
+	    put((y * 4 + x) * 2 + 1, texel[1], 0xFF);
+	},
+	TEX_RGB565 => {
+	    let [r, g, b, _] = texel.map(u16::from);
+	    let val = ((r >> 3) << 11) | ((g >> 2) << 5) | (b >> 3);
+	    put((y * 4 + x) * 2, (val >> 8) as u8, 0xFF);
+	    put((y * 4 + x) * 2 + 1, val as u8, 0xFF);
+	},
+	TEX_RGB5A3 => {
+	    let [r, g, b, a] = texel.map(u16::from);
+	    let val = if a >= 0xE0 {
+		0x8000 | ((r >> 3) << 10) | ((g >> 3) << 5) | (b >> 3)
+	    } else {
+		((a >> 5) << 12) | ((r >> 4) << 8) | ((g >> 4) << 4) | (b >> 4)
+	    };
+	    put((y * 4 + x) * 2, (val >> 8) as u8, 0xFF);
+	    put((y * 4 + x) * 2 + 1, val as u8, 0xFF);
+	},
+	//alpha and red in the first 32 bytes of the tile, green and blue in the second
+	_ => {
+	    let offset = (y * 4 + x) * 2;
+	    put(offset, texel[3], 0xFF);
+	    put(offset + 1, texel[0], 0xFF);
+	    put(offset + 32, texel[1], 0xFF);
+	    put(offset + 33, texel[2], 0xFF);
+	},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_gamecube;
+
+    const WHITE: u32 = 0xFFFF_FFFF;
+    const BLACK: u32 = 0xFF00_0000;
+    const RED: u32 = 0xFFFF_0000;
+
+    //an efb with the given rows in its top left corner, a copy source covering them and an unfiltered
+    //copy filter, copying to 0x1000 with 32 byte lines
+    fn setup(rows: &[&[u32]]) -> Gamecube {
+	let mut gc = test_gamecube();
+	for (y, row) in rows.iter().enumerate() {
+	    gc.efb.color[(y * EFB_WIDTH)..(y * EFB_WIDTH + row.len())].copy_from_slice(row);
+	}
+
+	let bp = &mut gc.gx.bp_regs;
+	bp[BP_COPY_SRC_TL] = 0;
+	bp[BP_COPY_SRC_WH] = (rows[0].len() as u32 - 1) | ((rows.len() as u32 - 1) << 10);
+	bp[BP_COPY_DST] = 0x1000 >> 5;
+	bp[BP_COPY_STRIDE] = 1;
+	bp[BP_COPY_FILTER0] = (32 << 12) | (32 << 18);
+	gc
+    }
+
+    #[test]
+    fn yuv_is_bt601_with_studio_swing() {
+	assert_eq!(yuv([255, 255, 255, 255]), [235, 128, 128]);
+	assert_eq!(yuv([0, 0, 0, 255]), [16, 128, 128]);
+	assert_eq!(yuv([255, 0, 0, 255]), [82, 90, 240]);
+    }
+
+    #[test]
+    fn display_copies_share_u_and_v_between_pixel_pairs() {
+	let mut gc = setup(&[&[WHITE, BLACK, RED, BLACK]]);
+	copy(&mut gc, COPY_TO_XFB);
+	assert_eq!(gc.memory[0x1000..0x1008], [235, 128, 16, 128, 82, 109, 16, 184]);
+    }
+
+    #[test]
+    fn display_copies_scale_lines_by_yscale() {
+	let mut gc = setup(&[&[WHITE, WHITE], &[BLACK, BLACK]]);
+	//256/128 xfb lines per efb line
+	gc.gx.bp_regs[BP_COPY_YSCALE] = 128;
+	copy(&mut gc, COPY_TO_XFB | COPY_SCALE_Y);
+
+	assert_eq!(gc.memory[0x1000..0x1004], [235, 128, 235, 128]);
+	assert_eq!(gc.memory[0x1020..0x1024], [235, 128, 235, 128]);
+	assert_eq!(gc.memory[0x1040..0x1044], [16, 128, 16, 128]);
+	assert_eq!(gc.memory[0x1060..0x1064], [0; 4]);
+    }
+
+    #[test]
+    fn half_scale_texture_copies_box_filter_2x2_blocks() {
+	let mut gc = setup(&[&[WHITE, WHITE, RED, RED], &[BLACK, BLACK, RED, RED]]);
+	//I8 from the intensity of each block
+	let format = 2 << 3;
+	copy(&mut gc, format | COPY_HALF_SCALE | COPY_INTENSITY);
+	assert_eq!(gc.memory[0x1000..0x1003], [125, 82, 0]);
+    }
+}
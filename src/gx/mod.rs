@@ -0,0 +1,167 @@
+pub mod command;
+pub mod vertex;
+pub mod xf;
+pub mod raster;
+pub mod tev;
+pub mod texture;
+pub mod pixel;
+pub mod copy;
+
+use log::debug;
+use vertex::VertexFormat;
+
+use crate::{pixel_engine::{self, BP_PE_DONE, BP_PE_TOKEN, BP_PE_TOKEN_INT}, Gamecube};
+
+pub const XF_MEMORY_SIZE: usize = 0x1000;
+//xf registers sit right above its memory in the same address space
+pub const XF_REGS_START: u32 = 0x1000;
+pub const XF_REGS_SIZE: usize = 0x100;
+pub const BP_REGS_SIZE: usize = 0x100;
+//texture memory, only palettes get loaded into it
+pub const TMEM_SIZE: usize = 0x10_0000;
+
+//the tev color registers double as the konst colors, picked by the top bit of the write
+const BP_TEV_REGISTERS: u32 = 0xE0;
+const BP_TEV_REGISTERS_END: u32 = 0xE7;
+const BP_TEV_KONST: u32 = 1 << 23;
+const BP_LOAD_TLUT1: u32 = 0x65;
+const BP_PE_COPY_EXECUTE: u32 = 0x52;
+
+//BP register 0xFE masks which bits the next BP write actually changes
+const BP_MASK: u32 = 0xFE;
+const BP_MASK_ALL: u32 = 0xFF_FFFF;
+
+//the vertex loader's state, set through LOAD_CP_REG. not to be confused with the CP's mmio registers
+pub struct CpRegs {
+    pub matindex_a: u32,
+    pub matindex_b: u32,
+    pub vcd_lo: u32,
+    pub vcd_hi: u32,
+    pub vat_a: [u32; 8],
+    pub vat_b: [u32; 8],
+    pub vat_c: [u32; 8],
+    pub array_base: [u32; 16],
+    pub array_stride: [u32; 16],
+}
+
+impl CpRegs {
+    pub fn new() -> Self {
+	Self {
+	    matindex_a: 0,
+	    matindex_b: 0,
+	    vcd_lo: 0,
+	    vcd_hi: 0,
+	    vat_a: [0; 8],
+	    vat_b: [0; 8],
+	    vat_c: [0; 8],
+	    array_base: [0; 16],
+	    array_stride: [0; 16],
+	}
+    }
+}
+
+impl Default for CpRegs {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+pub struct Gx {
+    pub cp: CpRegs,
+    //matrices and lights, in words
+    pub xf_memory: Box<[u32; XF_MEMORY_SIZE]>,
+    pub xf_regs: Box<[u32; XF_REGS_SIZE]>,
+    pub bp_regs: Box<[u32; BP_REGS_SIZE]>,
+    pub tev_konst: [u32; 8],
+    pub tmem: Box<[u8; TMEM_SIZE]>,
+    bp_mask: u32,
+}
+
+impl Gx {
+    pub fn new() -> Self {
+	Self {
+	    cp: CpRegs::new(),
+	    xf_memory: Box::new([0; XF_MEMORY_SIZE]),
+	    xf_regs: Box::new([0; XF_REGS_SIZE]),
+	    bp_regs: Box::new([0; BP_REGS_SIZE]),
+	    tev_konst: [0; 8],
+	    tmem: vec![0; TMEM_SIZE].into_boxed_slice().try_into().unwrap(),
+	    bp_mask: BP_MASK_ALL,
+	}
+    }
+}
+
+impl Default for Gx {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+pub fn load_cp_reg(gc: &mut Gamecube, addr: u8, val: u32) {
+    let cp = &mut gc.gx.cp;
+    let idx = (addr & 0xF) as usize;
+    match addr & 0xF0 {
+	0x30 => cp.matindex_a = val,
+	0x40 => cp.matindex_b = val,
+	0x50 => cp.vcd_lo = val,
+	0x60 => cp.vcd_hi = val,
+	0x70 if idx < 8 => cp.vat_a[idx] = val,
+	0x80 if idx < 8 => cp.vat_b[idx] = val,
+	0x90 if idx < 8 => cp.vat_c[idx] = val,
+	0xA0 => cp.array_base[idx] = val & 0x03FF_FFFF,
+	0xB0 => cp.array_stride[idx] = val & 0xFF,
+	_ => debug!("STUB: LOAD_CP_REG at {addr:#04X} with val {val:#010X}"),
+    }
+}
+
+pub fn load_xf_reg(gc: &mut Gamecube, addr: u32, val: u32) {
+    match addr {
+	0x0000..=0x0FFF => gc.gx.xf_memory[addr as usize] = val,
+	0x1000..=0x10FF => gc.gx.xf_regs[(addr - XF_REGS_START) as usize] = val,
+	_ => debug!("STUB: LOAD_XF_REG at {addr:#06X} with val {val:#010X}"),
+    }
+}
+
+//the top byte of a BP write picks the register, the rest is the value
+pub fn load_bp_reg(gc: &mut Gamecube, cmd: u32) {
+    let addr = cmd >> 24;
+    let val = cmd & BP_MASK_ALL;
+
+    if addr == BP_MASK {
+	gc.gx.bp_mask = val;
+	return;
+    }
+
+    let reg = match addr {
+	BP_TEV_REGISTERS..=BP_TEV_REGISTERS_END if val & BP_TEV_KONST != 0 => &mut gc.gx.tev_konst[(addr - BP_TEV_REGISTERS) as usize],
+	_ => &mut gc.gx.bp_regs[addr as usize],
+    };
+    let val = (*reg & !gc.gx.bp_mask) | (val & gc.gx.bp_mask);
+    *reg = val;
+    gc.gx.bp_mask = BP_MASK_ALL;
+
+    match addr {
+	BP_PE_COPY_EXECUTE => copy::copy(gc, val),
+	BP_LOAD_TLUT1 => texture::load_tlut(gc, val),
+	BP_PE_DONE => pixel_engine::set_finish(gc),
+	BP_PE_TOKEN => pixel_engine::set_token(gc, val as u16, false),
+	BP_PE_TOKEN_INT => pixel_engine::set_token(gc, val as u16, true),
+	_ => (),
+    }
+}
+
+//vertex data comes in exactly as it sat in the fifo, laid out by the VCD and the given VAT
+pub fn draw(gc: &mut Gamecube, primitive: u8, vat: usize, count: u16, vertices: &[u8]) {
+    let format = VertexFormat::new(&gc.gx.cp, vat);
+    let size = format.size();
+    if size == 0 {
+	return;
+    }
+
+    let transformed = vertices.chunks_exact(size).take(count as usize).map(|data| {
+	let vertex = vertex::decode(gc, &format, data);
+	xf::transform(&gc.gx, &vertex)
+    }).collect::<Vec<_>>();
+
+    raster::draw(gc, primitive, &transformed);
+}
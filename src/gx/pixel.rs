@@ -0,0 +1,187 @@
+use crate::efb::Efb;
+
+use super::Gx;
+
+//BP registers
+const BP_PE_ZMODE: usize = 0x40;
+const BP_PE_CMODE0: usize = 0x41;
+const BP_PE_CMODE1: usize = 0x42;
+const BP_PE_CONTROL: usize = 0x43;
+
+//efb pixel formats, what the color gets cut down to before it's stored
+const PIXEL_RGB8_Z24: u32 = 0;
+const PIXEL_RGBA6_Z24: u32 = 1;
+const PIXEL_RGB565_Z16: u32 = 2;
+
+//blend factors, shared between source and destination apart from 2 and 3
+const BLEND_ZERO: u32 = 0;
+const BLEND_ONE: u32 = 1;
+const BLEND_OTHER_COLOR: u32 = 2;
+const BLEND_INV_OTHER_COLOR: u32 = 3;
+const BLEND_SRC_ALPHA: u32 = 4;
+const BLEND_INV_SRC_ALPHA: u32 = 5;
+const BLEND_DST_ALPHA: u32 = 6;
+
+//what happens to a pixel after the tev, set up from the PE's BP registers once per draw
+pub struct PixelPipeline {
+    z_test: bool,
+    z_func: u32,
+    z_update: bool,
+    //z test before texturing, so pixels the alpha test throws away still update z
+    pub early_z: bool,
+    blend: bool,
+    logic_op: Option<u32>,
+    color_update: bool,
+    alpha_update: bool,
+    src_factor: u32,
+    dst_factor: u32,
+    subtract: bool,
+    dst_alpha: Option<u8>,
+    format: u32,
+}
+
+impl PixelPipeline {
+    pub fn new(gx: &Gx) -> Self {
+	let (zmode, cmode0, cmode1, control) = (gx.bp_regs[BP_PE_ZMODE], gx.bp_regs[BP_PE_CMODE0], gx.bp_regs[BP_PE_CMODE1], gx.bp_regs[BP_PE_CONTROL]);
+	let blend = cmode0 & 1 != 0;
+
+	Self {
+	    z_test: zmode & 1 != 0,
+	    z_func: (zmode >> 1) & 0b111,
+	    z_update: (zmode >> 4) & 1 != 0,
+	    early_z: (control >> 6) & 1 != 0,
+	    blend,
+	    logic_op: (!blend && (cmode0 >> 1) & 1 != 0).then_some((cmode0 >> 12) & 0xF),
+	    color_update: (cmode0 >> 3) & 1 != 0,
+	    alpha_update: (cmode0 >> 4) & 1 != 0,
+	    dst_factor: (cmode0 >> 5) & 0b111,
+	    src_factor: (cmode0 >> 8) & 0b111,
+	    subtract: (cmode0 >> 11) & 1 != 0,
+	    dst_alpha: ((cmode1 >> 8) & 1 != 0).then_some(cmode1 as u8),
+	    format: control & 0b111,
+	}
+    }
+
+    //passes or fails the pixel against what's in the depth buffer, updating it if it passes
+    pub fn depth(&self, efb: &mut Efb, index: usize, z: u32) -> bool {
+	if !self.z_test {
+	    return true;
+	}
+
+	let stored = efb.depth[index];
+	let pass = match self.z_func {
+	    0 => false,
+	    1 => z < stored,
+	    2 => z == stored,
+	    3 => z <= stored,
+	    4 => z > stored,
+	    5 => z != stored,
+	    6 => z >= stored,
+	    _ => true,
+	};
+
+	if pass && self.z_update {
+	    efb.depth[index] = z;
+	}
+
+	pass
+    }
+
+    //blends or logic ops the rgba color into the efb
+    pub fn write(&self, efb: &mut Efb, index: usize, src: [u8; 4]) {
+	let old = efb.color[index];
+	//efb is 0xAARRGGBB
+	let dst = [(old >> 16) as u8, (old >> 8) as u8, old as u8, (old >> 24) as u8];
+
+	let mut out = if self.blend {
+	    self.blend(src, dst)
+	} else if let Some(op) = self.logic_op {
+	    std::array::from_fn(|i| logic_op(op, src[i], dst[i]))
+	} else {
+	    src
+	};
+
+	if let Some(alpha) = self.dst_alpha {
+	    out[3] = alpha;
+	}
+	if !self.color_update {
+	    out[..3].copy_from_slice(&dst[..3]);
+	}
+	if !self.alpha_update {
+	    out[3] = dst[3];
+	}
+
+	let out = match self.format {
+	    PIXEL_RGB8_Z24 => [out[0], out[1], out[2], 0xFF],
+	    PIXEL_RGBA6_Z24 => out.map(|val| (val & 0xFC) | (val >> 6)),
+	    PIXEL_RGB565_Z16 => [(out[0] & 0xF8) | (out[0] >> 5), (out[1] & 0xFC) | (out[1] >> 6), (out[2] & 0xF8) | (out[2] >> 5), 0xFF],
+	    _ => out,
+	};
+
+	efb.color[index] = (u32::from(out[3]) << 24) | (u32::from(out[0]) << 16) | (u32::from(out[1]) << 8) | u32::from(out[2]);
+    }
+
+    //what a copy with clear set does to each pixel of the source rectangle, under the same update masks as drawing
+    pub fn clear(&self, efb: &mut Efb, index: usize, color: u32, z: u32) {
+	let old = efb.color[index];
+	let mut mask = 0;
+	if self.color_update {
+	    mask |= 0x00FF_FFFF;
+	}
+	if self.alpha_update {
+	    mask |= 0xFF00_0000;
+	}
+	efb.color[index] = (old & !mask) | (color & mask);
+
+	if self.z_update {
+	    efb.depth[index] = z & 0xFF_FFFF;
+	}
+    }
+
+    fn blend(&self, src: [u8; 4], dst: [u8; 4]) -> [u8; 4] {
+	if self.subtract {
+	    return std::array::from_fn(|i| dst[i].saturating_sub(src[i]));
+	}
+
+	//factors run 0-255, bumped up to 0-256 so one leaves the color alone
+	let factor = |factor: u32, other: [u8; 4], i: usize| -> u32 {
+	    let val = match factor {
+		BLEND_ZERO => 0,
+		BLEND_ONE => 255,
+		BLEND_OTHER_COLOR => other[i],
+		BLEND_INV_OTHER_COLOR => 255 - other[i],
+		BLEND_SRC_ALPHA => src[3],
+		BLEND_INV_SRC_ALPHA => 255 - src[3],
+		BLEND_DST_ALPHA => dst[3],
+		_ => 255 - dst[3],
+	    };
+	    u32::from(val) + u32::from(val >> 7)
+	};
+
+	std::array::from_fn(|i| {
+	    let val = u32::from(src[i]) * factor(self.src_factor, dst, i) + u32::from(dst[i]) * factor(self.dst_factor, src, i);
+	    (val >> 8).min(255) as u8
+	})
+    }
+}
+
+fn logic_op(op: u32, src: u8, dst: u8) -> u8 {
+    match op {
+	0x0 => 0,
+	0x1 => src & dst,
+	0x2 => src & !dst,
+	0x3 => src,
+	0x4 => !src & dst,
+	0x5 => dst,
+	0x6 => src ^ dst,
+	0x7 => src | dst,
+	0x8 => !(src | dst),
+	0x9 => !(src ^ dst),
+	0xA => !dst,
+	0xB => src | !dst,
+	0xC => !src,
+	0xD => !src | dst,
+	0xE => !(src & dst),
+	_ => 0xFF,
+    }
+}
@@ -0,0 +1,383 @@
+use log::warn;
+
+use crate::{efb::{EFB_HEIGHT, EFB_WIDTH}, Gamecube};
+
+use super::{command::{GX_DRAW_LINES, GX_DRAW_LINE_STRIP, GX_DRAW_POINTS, GX_DRAW_QUADS, GX_DRAW_QUADS_2, GX_DRAW_TRIANGLES, GX_DRAW_TRIANGLE_FAN, GX_DRAW_TRIANGLE_STRIP}, pixel::PixelPipeline, tev::Tev, texture::{self, TexMap}, xf::{self, TransformedVertex}};
+
+//BP registers
+const BP_GENMODE: usize = 0x00;
+const BP_SCISSOR_TL: usize = 0x20;
+const BP_SCISSOR_BR: usize = 0x21;
+const BP_LINE_POINT_WIDTH: usize = 0x22;
+const BP_SU_SSIZE: usize = 0x30;
+const BP_SCISSOR_OFFSET: usize = 0x59;
+
+//screen coordinates carry a 342 pixel guard band so they never go negative
+const GUARD_BAND: i32 = 342;
+//vertex positions snap to 1/16th of a pixel
+const SUBPIXEL_BITS: u32 = 4;
+const SUBPIXEL_ONE: i64 = 1 << SUBPIXEL_BITS;
+//how far outside the viewport x and y get before they're clipped, in viewports
+const CLIP_GUARD: f32 = 4.0;
+//lines and points are sized in 1/6ths of a pixel
+const LINE_POINT_UNITS: f32 = 6.0;
+
+const CULL_BACK: u32 = 1;
+const CULL_FRONT: u32 = 2;
+const CULL_ALL: u32 = 3;
+
+//one vertex after clipping and the viewport, everything a pixel needs to interpolate
+#[derive(Debug, Copy, Clone)]
+struct ScreenVertex {
+    //efb pixels
+    x: f32,
+    y: f32,
+    //24 bit depth
+    z: f32,
+    inv_w: f32,
+    colors: [[f32; 4]; 2],
+    tex: [[f32; 3]; 8],
+}
+
+//everything that stays the same across a draw
+struct DrawState {
+    tev: Tev,
+    pixel: PixelPipeline,
+    maps: [TexMap; 8],
+    tex_scale: [[f32; 2]; 8],
+    viewport: [f32; 6],
+    //efb pixel coordinates are screen coordinates minus this
+    offset: [f32; 2],
+    //inclusive, in efb pixels
+    scissor: [i32; 4],
+    cull: u32,
+}
+
+impl DrawState {
+    fn new(gc: &Gamecube) -> Self {
+	let gx = &gc.gx;
+	let bp = &gx.bp_regs;
+	let offset = bp[BP_SCISSOR_OFFSET];
+	let offset = [(offset & 0x3FF) as i32 * 2 - GUARD_BAND, ((offset >> 10) & 0x3FF) as i32 * 2 - GUARD_BAND];
+	let (tl, br) = (bp[BP_SCISSOR_TL], bp[BP_SCISSOR_BR]);
+	let corner = |reg: u32, axis: usize| {
+	    let val = if axis == 0 { (reg >> 12) & 0x7FF } else { reg & 0x7FF };
+	    val as i32 - GUARD_BAND - offset[axis]
+	};
+
+	Self {
+	    tev: Tev::new(gx),
+	    pixel: PixelPipeline::new(gx),
+	    maps: std::array::from_fn(|map| TexMap::new(gx, map)),
+	    tex_scale: std::array::from_fn(|coord| {
+		let size = |reg: u32| ((reg & 0xFFFF) + 1) as f32;
+		[size(bp[BP_SU_SSIZE + coord * 2]), size(bp[BP_SU_SSIZE + coord * 2 + 1])]
+	    }),
+	    viewport: xf::viewport(gx),
+	    offset: offset.map(|val| val as f32),
+	    scissor: [
+		corner(tl, 0).max(0),
+		corner(tl, 1).max(0),
+		corner(br, 0).min(EFB_WIDTH as i32 - 1),
+		corner(br, 1).min(EFB_HEIGHT as i32 - 1),
+	    ],
+	    cull: (bp[BP_GENMODE] >> 14) & 0b11,
+	}
+    }
+
+    fn to_screen(&self, v: &TransformedVertex) -> ScreenVertex {
+	let inv_w = 1.0 / v.pos[3];
+	let vp = &self.viewport;
+	ScreenVertex {
+	    x: v.pos[0] * inv_w * vp[0] + vp[3] - GUARD_BAND as f32 - self.offset[0],
+	    y: v.pos[1] * inv_w * vp[1] + vp[4] - GUARD_BAND as f32 - self.offset[1],
+	    z: (v.pos[2] * inv_w * vp[2] + vp[5]).clamp(0.0, 16_777_215.0),
+	    inv_w,
+	    colors: v.colors,
+	    tex: v.tex,
+	}
+    }
+}
+
+pub fn draw(gc: &mut Gamecube, primitive: u8, vertices: &[TransformedVertex]) {
+    let state = DrawState::new(gc);
+    let n = vertices.len();
+
+    match primitive {
+	GX_DRAW_QUADS | GX_DRAW_QUADS_2 => {
+	    for quad in vertices.chunks_exact(4) {
+		triangle(gc, &state, [quad[0], quad[1], quad[2]]);
+		triangle(gc, &state, [quad[0], quad[2], quad[3]]);
+	    }
+	},
+	GX_DRAW_TRIANGLES => {
+	    for tri in vertices.chunks_exact(3) {
+		triangle(gc, &state, [tri[0], tri[1], tri[2]]);
+	    }
+	},
+	//every other triangle in a strip is flipped so they all wind the same way
+	GX_DRAW_TRIANGLE_STRIP => {
+	    for i in 0..n.saturating_sub(2) {
+		let tri = if i % 2 == 0 {
+		    [vertices[i], vertices[i + 1], vertices[i + 2]]
+		} else {
+		    [vertices[i + 1], vertices[i], vertices[i + 2]]
+		};
+		triangle(gc, &state, tri);
+	    }
+	},
+	GX_DRAW_TRIANGLE_FAN => {
+	    for i in 1..n.saturating_sub(1) {
+		triangle(gc, &state, [vertices[0], vertices[i], vertices[i + 1]]);
+	    }
+	},
+	GX_DRAW_LINES => {
+	    for line in vertices.chunks_exact(2) {
+		self::line(gc, &state, line[0], line[1]);
+	    }
+	},
+	GX_DRAW_LINE_STRIP => {
+	    for line in vertices.windows(2) {
+		self::line(gc, &state, line[0], line[1]);
+	    }
+	},
+	GX_DRAW_POINTS => {
+	    for &vertex in vertices {
+		point(gc, &state, vertex);
+	    }
+	},
+	_ => warn!("draw with unknown primitive {primitive:#04X}, skipping it"),
+    }
+}
+
+fn lerp_vertex(a: &TransformedVertex, b: &TransformedVertex, t: f32) -> TransformedVertex {
+    let mix = |a: f32, b: f32| a + (b - a) * t;
+    TransformedVertex {
+	pos: std::array::from_fn(|i| mix(a.pos[i], b.pos[i])),
+	colors: std::array::from_fn(|c| std::array::from_fn(|i| mix(a.colors[c][i], b.colors[c][i]))),
+	tex: std::array::from_fn(|c| std::array::from_fn(|i| mix(a.tex[c][i], b.tex[c][i]))),
+    }
+}
+
+//clip space planes as the distance a vertex is inside them: -w <= z <= 0, and x and y inside the guard band
+fn plane_distance(plane: usize, v: &TransformedVertex) -> f32 {
+    let [x, y, z, w] = v.pos;
+    match plane {
+	0 => z + w,
+	1 => -z,
+	2 => CLIP_GUARD * w - x,
+	3 => CLIP_GUARD * w + x,
+	4 => CLIP_GUARD * w - y,
+	_ => CLIP_GUARD * w + y,
+    }
+}
+
+//sutherland-hodgman against each plane in turn
+fn clip(polygon: Vec<TransformedVertex>) -> Vec<TransformedVertex> {
+    let mut polygon = polygon;
+    for plane in 0..6 {
+	if polygon.is_empty() {
+	    break;
+	}
+
+	let mut out = Vec::with_capacity(polygon.len() + 1);
+	for (i, current) in polygon.iter().enumerate() {
+	    let next = &polygon[(i + 1) % polygon.len()];
+	    let (dc, dn) = (plane_distance(plane, current), plane_distance(plane, next));
+	    if dc >= 0.0 {
+		out.push(*current);
+	    }
+	    if (dc >= 0.0) != (dn >= 0.0) {
+		out.push(lerp_vertex(current, next, dc / (dc - dn)));
+	    }
+	}
+	polygon = out;
+    }
+
+    polygon
+}
+
+fn triangle(gc: &mut Gamecube, state: &DrawState, vertices: [TransformedVertex; 3]) {
+    let inside = |v: &TransformedVertex| (0..6).all(|plane| plane_distance(plane, v) >= 0.0);
+    let polygon = if vertices.iter().all(inside) {
+	vertices.to_vec()
+    } else {
+	clip(vertices.to_vec())
+    };
+    if polygon.len() < 3 {
+	return;
+    }
+
+    let screen = polygon.iter().map(|v| state.to_screen(v)).collect::<Vec<_>>();
+    for i in 1..(screen.len() - 1) {
+	rasterize(gc, state, [screen[0], screen[i], screen[i + 1]], true);
+    }
+}
+
+//lines get widened across whichever axis they move along least
+fn line(gc: &mut Gamecube, state: &DrawState, a: TransformedVertex, b: TransformedVertex) {
+    let inside = |v: &TransformedVertex| (0..2).all(|plane| plane_distance(plane, v) >= 0.0) && v.pos[3] > 0.0;
+    if !inside(&a) || !inside(&b) {
+	return;
+    }
+
+    let width = (gc.gx.bp_regs[BP_LINE_POINT_WIDTH] & 0xFF) as f32 / LINE_POINT_UNITS;
+    let (a, b) = (state.to_screen(&a), state.to_screen(&b));
+    let (dx, dy) = if (b.x - a.x).abs() >= (b.y - a.y).abs() { (0.0, width / 2.0) } else { (width / 2.0, 0.0) };
+    let shift = |v: ScreenVertex, sign: f32| ScreenVertex {
+	x: v.x + dx * sign,
+	y: v.y + dy * sign,
+	..v
+    };
+
+    let quad = [shift(a, -1.0), shift(b, -1.0), shift(b, 1.0), shift(a, 1.0)];
+    rasterize(gc, state, [quad[0], quad[1], quad[2]], false);
+    rasterize(gc, state, [quad[0], quad[2], quad[3]], false);
+}
+
+fn point(gc: &mut Gamecube, state: &DrawState, v: TransformedVertex) {
+    if (0..2).any(|plane| plane_distance(plane, &v) < 0.0) || v.pos[3] <= 0.0 {
+	return;
+    }
+
+    let half = ((gc.gx.bp_regs[BP_LINE_POINT_WIDTH] >> 8) & 0xFF) as f32 / LINE_POINT_UNITS / 2.0;
+    let v = state.to_screen(&v);
+    let corner = |dx: f32, dy: f32| ScreenVertex {
+	x: v.x + dx,
+	y: v.y + dy,
+	..v
+    };
+
+    let quad = [corner(-half, -half), corner(half, -half), corner(half, half), corner(-half, half)];
+    rasterize(gc, state, [quad[0], quad[1], quad[2]], false);
+    rasterize(gc, state, [quad[0], quad[2], quad[3]], false);
+}
+
+//edge function for the edge from a to b at p, all in subpixels
+fn edge(a: (i64, i64), b: (i64, i64), p: (i64, i64)) -> i64 {
+    (b.0 - a.0) * (p.1 - a.1) - (b.1 - a.1) * (p.0 - a.0)
+}
+
+fn rasterize(gc: &mut Gamecube, state: &DrawState, vertices: [ScreenVertex; 3], cull: bool) {
+    let fixed = vertices.map(|v| ((v.x * SUBPIXEL_ONE as f32).floor() as i64, (v.y * SUBPIXEL_ONE as f32).floor() as i64));
+    let area = edge(fixed[0], fixed[1], fixed[2]);
+    if area == 0 {
+	return;
+    }
+
+    //clockwise on screen is front facing
+    if cull {
+	let culled = match state.cull {
+	    CULL_BACK => area < 0,
+	    CULL_FRONT => area > 0,
+	    CULL_ALL => true,
+	    _ => false,
+	};
+	if culled {
+	    return;
+	}
+    }
+
+    let (vertices, fixed, area) = if area < 0 {
+	([vertices[0], vertices[2], vertices[1]], [fixed[0], fixed[2], fixed[1]], -area)
+    } else {
+	(vertices, fixed, area)
+    };
+
+    let [x0, y0, x1, y1] = state.scissor;
+    let min_x = (fixed.iter().map(|p| p.0).min().unwrap() >> SUBPIXEL_BITS).max(i64::from(x0)) as i32;
+    let max_x = (fixed.iter().map(|p| p.0).max().unwrap() >> SUBPIXEL_BITS).min(i64::from(x1)) as i32;
+    let min_y = (fixed.iter().map(|p| p.1).min().unwrap() >> SUBPIXEL_BITS).max(i64::from(y0)) as i32;
+    let max_y = (fixed.iter().map(|p| p.1).max().unwrap() >> SUBPIXEL_BITS).min(i64::from(y1)) as i32;
+
+    //top-left fill rule, pixels exactly on a bottom or right edge belong to the neighbouring triangle
+    let edges = [(1, 2), (2, 0), (0, 1)];
+    let bias = edges.map(|(a, b)| {
+	let (dx, dy) = (fixed[b].0 - fixed[a].0, fixed[b].1 - fixed[a].1);
+	if dy < 0 || (dy == 0 && dx > 0) { 0 } else { -1 }
+    });
+
+    let setup = Setup {
+	vertices,
+	area: area as f64,
+	steps: edges.map(|(a, b)| (-(fixed[b].1 - fixed[a].1) * SUBPIXEL_ONE, (fixed[b].0 - fixed[a].0) * SUBPIXEL_ONE)),
+    };
+
+    for y in min_y..=max_y {
+	for x in min_x..=max_x {
+	    let center = (i64::from(x) * SUBPIXEL_ONE + SUBPIXEL_ONE / 2, i64::from(y) * SUBPIXEL_ONE + SUBPIXEL_ONE / 2);
+	    let weights = edges.map(|(a, b)| edge(fixed[a], fixed[b], center));
+	    if (0..3).any(|i| weights[i] + bias[i] < 0) {
+		continue;
+	    }
+
+	    shade(gc, state, &setup, x as usize, y as usize, weights);
+	}
+    }
+}
+
+//what a pixel needs to work out its barycentric weights and those of its neighbours
+struct Setup {
+    vertices: [ScreenVertex; 3],
+    area: f64,
+    //how much each edge function moves for a pixel step in x and y
+    steps: [(i64, i64); 3],
+}
+
+impl Setup {
+    //screen space barycentrics turned into perspective correct ones
+    fn perspective(&self, weights: [i64; 3]) -> [f32; 3] {
+	let lambda = weights.map(|w| w as f64 / self.area);
+	let scaled: [f64; 3] = std::array::from_fn(|i| lambda[i] * f64::from(self.vertices[i].inv_w));
+	let sum = scaled[0] + scaled[1] + scaled[2];
+	scaled.map(|val| (val / sum) as f32)
+    }
+
+    fn tex_coord(&self, weights: [f32; 3], coord: usize, scale: [f32; 2]) -> (f32, f32) {
+	let tex = |i: usize| (0..3).map(|v| weights[v] * self.vertices[v].tex[coord][i]).sum::<f32>();
+	let q = tex(2);
+	let q = if q == 0.0 { 1.0 } else { q };
+	(tex(0) / q * scale[0], tex(1) / q * scale[1])
+    }
+}
+
+fn shade(gc: &mut Gamecube, state: &DrawState, setup: &Setup, x: usize, y: usize, weights: [i64; 3]) {
+    let index = y * EFB_WIDTH + x;
+    let lambda = weights.map(|w| w as f64 / setup.area);
+    let z = (0..3).map(|i| lambda[i] * f64::from(setup.vertices[i].z)).sum::<f64>().clamp(0.0, 16_777_215.0) as u32;
+
+    if state.pixel.early_z && !state.pixel.depth(&mut gc.efb, index, z) {
+	return;
+    }
+
+    let persp = setup.perspective(weights);
+    let ras = std::array::from_fn(|c| std::array::from_fn(|i| {
+	let val = (0..3).map(|v| persp[v] * setup.vertices[v].colors[c][i]).sum::<f32>();
+	val.round().clamp(0.0, 255.0) as u8
+    }));
+
+    let step = |axis: usize| -> [i64; 3] { std::array::from_fn(|i| weights[i] + if axis == 0 { setup.steps[i].0 } else { setup.steps[i].1 }) };
+    let (right, below) = (setup.perspective(step(0)), setup.perspective(step(1)));
+
+    let gc_ref: &Gamecube = gc;
+    let color = state.tev.run(|map, coord| {
+	let scale = state.tex_scale[coord];
+	let (s, t) = setup.tex_coord(persp, coord, scale);
+	let (sx, tx) = setup.tex_coord(right, coord, scale);
+	let (sy, ty) = setup.tex_coord(below, coord, scale);
+	let footprint = ((sx - s).hypot(tx - t)).max((sy - s).hypot(ty - t));
+	let lod = if footprint > 0.0 { (footprint.log2() * 16.0) as i32 } else { i32::MIN / 2 };
+	texture::sample(gc_ref, &state.maps[map], s, t, lod)
+    }, ras);
+
+    let color = state.tev.fog(color, z);
+    if !state.tev.alpha_test(color[3]) {
+	return;
+    }
+
+    if !state.pixel.early_z && !state.pixel.depth(&mut gc.efb, index, z) {
+	return;
+    }
+
+    state.pixel.write(&mut gc.efb, index, color);
+}
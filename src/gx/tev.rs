@@ -0,0 +1,374 @@
+use super::Gx;
+
+//BP registers
+const BP_GENMODE: usize = 0x00;
+const BP_TREF: usize = 0x28;
+const BP_TEV_COLOR_ENV: usize = 0xC0;
+const BP_TEV_ALPHA_ENV: usize = 0xC1;
+const BP_TEV_REGISTERS: usize = 0xE0;
+const BP_FOG_PARAM0: usize = 0xEE;
+const BP_FOG_PARAM1: usize = 0xEF;
+const BP_FOG_PARAM2: usize = 0xF0;
+const BP_FOG_PARAM3: usize = 0xF1;
+const BP_FOG_COLOR: usize = 0xF2;
+const BP_ALPHA_COMPARE: usize = 0xF3;
+const BP_TEV_KSEL: usize = 0xF6;
+
+//registers a stage can write, prev is also what comes out of the end
+const TEV_PREV: usize = 0;
+
+//color inputs, the alpha inputs are a subset in their own numbering. below 8 they're the registers'
+//color then alpha
+const CC_TEXC: u32 = 8;
+const CC_TEXA: u32 = 9;
+const CC_RASC: u32 = 10;
+const CC_RASA: u32 = 11;
+const CC_ONE: u32 = 12;
+const CC_HALF: u32 = 13;
+const CC_KONST: u32 = 14;
+const CA_TEXA: u32 = 4;
+const CA_RASA: u32 = 5;
+const CA_KONST: u32 = 6;
+
+const BIAS_COMPARE: u32 = 3;
+//compare mode, picked by the scale bits when the bias says compare
+const COMPARE_R8: u32 = 0;
+const COMPARE_GR16: u32 = 1;
+const COMPARE_BGR24: u32 = 2;
+
+const RAS_COLOR0: u32 = 0;
+const RAS_COLOR1: u32 = 1;
+
+//fractions for the konst selects below 8, the rest pick out a konst register
+const KONST_FRACTIONS: [i32; 8] = [255, 223, 191, 159, 128, 96, 64, 32];
+
+//fog types
+const FOG_OFF: u32 = 0;
+const FOG_EXP: u32 = 4;
+const FOG_EXP2: u32 = 5;
+const FOG_BACKWARD_EXP: u32 = 6;
+const FOG_BACKWARD_EXP2: u32 = 7;
+
+#[derive(Debug, Copy, Clone)]
+struct Combiner {
+    a: u32,
+    b: u32,
+    c: u32,
+    d: u32,
+    bias: u32,
+    sub: bool,
+    clamp: bool,
+    scale: u32,
+    dest: usize,
+}
+
+impl Combiner {
+    fn new(val: u32, arg_bits: u32, arg_shift: u32) -> Self {
+	let mask = (1 << arg_bits) - 1;
+	Self {
+	    d: (val >> arg_shift) & mask,
+	    c: (val >> (arg_shift + arg_bits)) & mask,
+	    b: (val >> (arg_shift + arg_bits * 2)) & mask,
+	    a: (val >> (arg_shift + arg_bits * 3)) & mask,
+	    bias: (val >> 16) & 0b11,
+	    sub: (val >> 18) & 1 != 0,
+	    clamp: (val >> 19) & 1 != 0,
+	    scale: (val >> 20) & 0b11,
+	    dest: ((val >> 22) & 0b11) as usize,
+	}
+    }
+
+    //a, b and c only see the bottom 8 bits of their inputs, d gets all 11
+    fn combine(&self, a: i32, b: i32, c: i32, d: i32) -> i32 {
+	let (a, b, c) = (a & 0xFF, b & 0xFF, c & 0xFF);
+	let (lshift, rshift) = match self.scale {
+	    0 => (0, 0),
+	    1 => (1, 0),
+	    2 => (2, 0),
+	    _ => (0, 1),
+	};
+	let bias = match self.bias {
+	    1 => 128,
+	    2 => -128,
+	    _ => 0,
+	};
+
+	let c = c + (c >> 7);
+	let mut lerp = (a * (256 - c) + b * c) << lshift;
+	lerp += if rshift != 0 { 0 } else if self.sub { 127 } else { 128 };
+	lerp >>= 8;
+	if self.sub {
+	    lerp = -lerp;
+	}
+
+	let result = (((d + bias) << lshift) + lerp) >> rshift;
+	self.clamp_result(result)
+    }
+
+    fn clamp_result(&self, result: i32) -> i32 {
+	if self.clamp {
+	    result.clamp(0, 255)
+	} else {
+	    result.clamp(-1024, 1023)
+	}
+    }
+
+    //d plus c where a beats (or matches) b, with the comparison mode in the scale bits
+    fn compare(&self, a: [i32; 4], b: [i32; 4], c: i32, d: i32, channel: usize) -> i32 {
+	let packed = |v: [i32; 4], n: usize| (0..n).fold(0, |acc, i| acc | ((v[i] & 0xFF) << (8 * i)));
+	let (x, y) = match self.scale {
+	    COMPARE_R8 => (packed(a, 1), packed(b, 1)),
+	    COMPARE_GR16 => (packed(a, 2), packed(b, 2)),
+	    COMPARE_BGR24 => (packed(a, 3), packed(b, 3)),
+	    _ => (a[channel] & 0xFF, b[channel] & 0xFF),
+	};
+
+	let pass = if self.sub { x == y } else { x > y };
+	self.clamp_result(d + if pass { c & 0xFF } else { 0 })
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Stage {
+    color: Combiner,
+    alpha: Combiner,
+    //texture map and the coordinate it's sampled at
+    tex: Option<(usize, usize)>,
+    ras: u32,
+    kcsel: u32,
+    kasel: u32,
+    ras_swap: usize,
+    tex_swap: usize,
+}
+
+//the combiner setup out of the BP registers, worked out once per draw rather than per pixel
+pub struct Tev {
+    stages: Vec<Stage>,
+    regs: [[i32; 4]; 4],
+    konst: [[i32; 4]; 4],
+    swap_tables: [[usize; 4]; 4],
+    alpha_compare: u32,
+    fog: u32,
+    fog_a: f32,
+    fog_b_magnitude: i32,
+    fog_b_shift: u32,
+    fog_c: f32,
+    fog_color: [i32; 3],
+    fog_orthographic: bool,
+}
+
+//sign extends 11 bits
+fn s11(val: u32) -> i32 {
+    ((val << 21) as i32) >> 21
+}
+
+//a float with an 11 bit mantissa and the sign above an 8 bit exponent
+fn fog_float(val: u32) -> f32 {
+    let mantissa = val & 0x7FF;
+    let exponent = (val >> 11) & 0xFF;
+    let sign = (val >> 19) & 1;
+    f32::from_bits((sign << 31) | (exponent << 23) | (mantissa << 12))
+}
+
+impl Tev {
+    pub fn new(gx: &Gx) -> Self {
+	let bp = &gx.bp_regs;
+	let num_stages = ((bp[BP_GENMODE] >> 10) & 0xF) as usize + 1;
+
+	let stages = (0..num_stages).map(|stage| {
+	    let order = bp[BP_TREF + stage / 2] >> ((stage & 1) * 12);
+	    let ksel = bp[BP_TEV_KSEL + stage / 2] >> ((stage & 1) * 10);
+	    let alpha = bp[BP_TEV_ALPHA_ENV + stage * 2];
+	    Stage {
+		color: Combiner::new(bp[BP_TEV_COLOR_ENV + stage * 2], 4, 0),
+		alpha: Combiner::new(alpha, 3, 4),
+		tex: ((order >> 6) & 1 != 0).then_some(((order & 0b111) as usize, ((order >> 3) & 0b111) as usize)),
+		ras: (order >> 7) & 0b111,
+		kcsel: (ksel >> 4) & 0x1F,
+		kasel: (ksel >> 9) & 0x1F,
+		ras_swap: (alpha & 0b11) as usize,
+		tex_swap: ((alpha >> 2) & 0b11) as usize,
+	    }
+	}).collect();
+
+	//red and alpha in one register, blue and green in the next
+	let color = |ra: u32, bg: u32| [s11(ra), s11(bg >> 12), s11(bg), s11(ra >> 12)];
+	let regs = std::array::from_fn(|i| color(bp[BP_TEV_REGISTERS + i * 2], bp[BP_TEV_REGISTERS + i * 2 + 1]));
+	let konst = std::array::from_fn(|i| color(gx.tev_konst[i * 2], gx.tev_konst[i * 2 + 1]).map(|val| val & 0xFF));
+
+	let swap_tables = std::array::from_fn(|table| {
+	    let (rg, ba) = (bp[BP_TEV_KSEL + table * 2], bp[BP_TEV_KSEL + table * 2 + 1]);
+	    [(rg & 0b11) as usize, ((rg >> 2) & 0b11) as usize, (ba & 0b11) as usize, ((ba >> 2) & 0b11) as usize]
+	});
+
+	let fog_color = bp[BP_FOG_COLOR];
+
+	Self {
+	    stages,
+	    regs,
+	    konst,
+	    swap_tables,
+	    alpha_compare: bp[BP_ALPHA_COMPARE],
+	    fog: (bp[BP_FOG_PARAM3] >> 21) & 0b111,
+	    fog_a: fog_float(bp[BP_FOG_PARAM0]),
+	    fog_b_magnitude: (bp[BP_FOG_PARAM1] & 0xFF_FFFF) as i32,
+	    fog_b_shift: bp[BP_FOG_PARAM2] & 0x1F,
+	    fog_c: fog_float(bp[BP_FOG_PARAM3]),
+	    fog_color: [((fog_color >> 16) & 0xFF) as i32, ((fog_color >> 8) & 0xFF) as i32, (fog_color & 0xFF) as i32],
+	    fog_orthographic: (bp[BP_FOG_PARAM3] >> 20) & 1 != 0,
+	}
+    }
+
+    fn konst_color(&self, sel: u32) -> [i32; 3] {
+	match sel {
+	    0..=7 => [KONST_FRACTIONS[sel as usize]; 3],
+	    12..=15 => {
+		let k = self.konst[(sel - 12) as usize];
+		[k[0], k[1], k[2]]
+	    },
+	    16..=31 => [self.konst[(sel & 3) as usize][((sel - 16) / 4) as usize]; 3],
+	    _ => [0; 3],
+	}
+    }
+
+    fn konst_alpha(&self, sel: u32) -> i32 {
+	match sel {
+	    0..=7 => KONST_FRACTIONS[sel as usize],
+	    16..=31 => self.konst[(sel & 3) as usize][((sel - 16) / 4) as usize],
+	    _ => 0,
+	}
+    }
+
+    fn swap(&self, color: [u8; 4], table: usize) -> [i32; 4] {
+	let table = self.swap_tables[table];
+	std::array::from_fn(|i| i32::from(color[table[i]]))
+    }
+
+    //tex samples texture map n at coordinate m, ras is the two lit color channels
+    pub fn run(&self, mut tex: impl FnMut(usize, usize) -> [u8; 4], ras: [[u8; 4]; 2]) -> [u8; 4] {
+	let mut regs = self.regs;
+	let mut last = (TEV_PREV, TEV_PREV);
+
+	for stage in &self.stages {
+	    let tex = self.swap(stage.tex.map_or([0xFF; 4], |(map, coord)| tex(map, coord)), stage.tex_swap);
+	    let ras = match stage.ras {
+		RAS_COLOR0 => self.swap(ras[0], stage.ras_swap),
+		RAS_COLOR1 => self.swap(ras[1], stage.ras_swap),
+		_ => [0; 4],
+	    };
+	    let konst = self.konst_color(stage.kcsel);
+	    let konst_alpha = self.konst_alpha(stage.kasel);
+
+	    let color_arg = |arg: u32, channel: usize| -> i32 {
+		match arg {
+		    0..=7 if arg & 1 == 0 => regs[(arg / 2) as usize][channel],
+		    0..=7 => regs[(arg / 2) as usize][3],
+		    CC_TEXC => tex[channel],
+		    CC_TEXA => tex[3],
+		    CC_RASC => ras[channel],
+		    CC_RASA => ras[3],
+		    CC_ONE => 255,
+		    CC_HALF => 128,
+		    CC_KONST => konst[channel],
+		    _ => 0,
+		}
+	    };
+	    let alpha_arg = |arg: u32| -> i32 {
+		match arg {
+		    0..=3 => regs[arg as usize][3],
+		    CA_TEXA => tex[3],
+		    CA_RASA => ras[3],
+		    CA_KONST => konst_alpha,
+		    _ => 0,
+		}
+	    };
+
+	    let cc = &stage.color;
+	    let color: [i32; 3] = if cc.bias == BIAS_COMPARE {
+		let a = [color_arg(cc.a, 0), color_arg(cc.a, 1), color_arg(cc.a, 2), 0];
+		let b = [color_arg(cc.b, 0), color_arg(cc.b, 1), color_arg(cc.b, 2), 0];
+		std::array::from_fn(|i| cc.compare(a, b, color_arg(cc.c, i), color_arg(cc.d, i), i))
+	    } else {
+		std::array::from_fn(|i| cc.combine(color_arg(cc.a, i), color_arg(cc.b, i), color_arg(cc.c, i), color_arg(cc.d, i)))
+	    };
+
+	    let ac = &stage.alpha;
+	    let alpha = if ac.bias == BIAS_COMPARE {
+		//the color modes compare the color inputs the alpha args line up with
+		let as_color = |arg: u32| -> [i32; 4] {
+		    let color = match arg {
+			CA_TEXA => CC_TEXC,
+			CA_RASA => CC_RASC,
+			CA_KONST => CC_KONST,
+			0..=3 => arg * 2,
+			_ => 15,
+		    };
+		    [color_arg(color, 0), color_arg(color, 1), color_arg(color, 2), alpha_arg(arg)]
+		};
+		ac.compare(as_color(ac.a), as_color(ac.b), alpha_arg(ac.c), alpha_arg(ac.d), 3)
+	    } else {
+		ac.combine(alpha_arg(ac.a), alpha_arg(ac.b), alpha_arg(ac.c), alpha_arg(ac.d))
+	    };
+
+	    regs[cc.dest][..3].copy_from_slice(&color);
+	    regs[ac.dest][3] = alpha;
+	    last = (cc.dest, ac.dest);
+	}
+
+	//whatever the last stage wrote is what comes out, wherever it went
+	let (color, alpha) = (regs[last.0], regs[last.1]);
+	[color[0], color[1], color[2], alpha[3]].map(|val| val.clamp(0, 255) as u8)
+    }
+
+    pub fn alpha_test(&self, alpha: u8) -> bool {
+	let reg = self.alpha_compare;
+	let compare = |func: u32, reference: u32| -> bool {
+	    let (alpha, reference) = (u32::from(alpha), reference & 0xFF);
+	    match func {
+		0 => false,
+		1 => alpha < reference,
+		2 => alpha == reference,
+		3 => alpha <= reference,
+		4 => alpha > reference,
+		5 => alpha != reference,
+		6 => alpha >= reference,
+		_ => true,
+	    }
+	};
+
+	let (a, b) = (compare((reg >> 16) & 0b111, reg), compare((reg >> 19) & 0b111, reg >> 8));
+	match (reg >> 22) & 0b11 {
+	    0 => a && b,
+	    1 => a || b,
+	    2 => a != b,
+	    _ => a == b,
+	}
+    }
+
+    //z is the 24 bit screen depth
+    pub fn fog(&self, color: [u8; 4], z: u32) -> [u8; 4] {
+	if self.fog == FOG_OFF {
+	    return color;
+	}
+
+	let ze = if self.fog_orthographic {
+	    self.fog_a * (z as f32 / 16_777_215.0)
+	} else {
+	    let denom = self.fog_b_magnitude - (z >> self.fog_b_shift) as i32;
+	    if denom == 0 { f32::MAX } else { self.fog_a / denom as f32 }
+	};
+
+	let fog = (ze - self.fog_c).clamp(0.0, 1.0);
+	let fog = match self.fog {
+	    FOG_EXP => 1.0 - (-8.0 * fog).exp2(),
+	    FOG_EXP2 => 1.0 - (-8.0 * fog * fog).exp2(),
+	    FOG_BACKWARD_EXP => (-8.0 * (1.0 - fog)).exp2(),
+	    FOG_BACKWARD_EXP2 => (-8.0 * (1.0 - fog) * (1.0 - fog)).exp2(),
+	    _ => fog,
+	};
+
+	let weight = (fog * 256.0) as i32;
+	let mix = |val: u8, fog: i32| ((i32::from(val) * (256 - weight) + fog * weight) >> 8) as u8;
+	[mix(color[0], self.fog_color[0]), mix(color[1], self.fog_color[1]), mix(color[2], self.fog_color[2]), color[3]]
+    }
+}
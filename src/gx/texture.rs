@@ -0,0 +1,486 @@
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::Gamecube;
+
+use super::{Gx, TMEM_SIZE};
+
+pub const TEX_I4: u32 = 0x0;
+pub const TEX_I8: u32 = 0x1;
+pub const TEX_IA4: u32 = 0x2;
+pub const TEX_IA8: u32 = 0x3;
+pub const TEX_RGB565: u32 = 0x4;
+pub const TEX_RGB5A3: u32 = 0x5;
+pub const TEX_RGBA8: u32 = 0x6;
+pub const TEX_C4: u32 = 0x8;
+pub const TEX_C8: u32 = 0x9;
+pub const TEX_C14X2: u32 = 0xA;
+pub const TEX_CMPR: u32 = 0xE;
+
+pub const TLUT_IA8: u32 = 0;
+pub const TLUT_RGB565: u32 = 1;
+pub const TLUT_RGB5A3: u32 = 2;
+
+//BP registers for texture maps 0-3, maps 4-7 are the same 0x20 further on
+const BP_TX_SETMODE0: usize = 0x80;
+const BP_TX_SETMODE1: usize = 0x84;
+const BP_TX_SETIMAGE0: usize = 0x88;
+const BP_TX_SETIMAGE3: usize = 0x94;
+const BP_TX_SETTLUT: usize = 0x98;
+const BP_TX_HIGH_MAPS: usize = 0x20;
+
+//where LOAD_TLUT1 copies from, and how much to where
+const BP_LOAD_TLUT0: usize = 0x64;
+
+const WRAP_CLAMP: u32 = 0;
+const WRAP_REPEAT: u32 = 1;
+
+//texture coordinates carry this many bits of fraction into the sampler
+const SUBTEXEL_BITS: u32 = 7;
+const SUBTEXEL_ONE: i32 = 1 << SUBTEXEL_BITS;
+
+//width, height and bytes of one tile. textures are stored a tile at a time, left to right then top to bottom
+pub fn tile_size(format: u32) -> (usize, usize, usize) {
+    match format {
+	TEX_I4 | TEX_C4 | TEX_CMPR => (8, 8, 32),
+	TEX_I8 | TEX_IA4 | TEX_C8 => (8, 4, 32),
+	TEX_RGBA8 => (4, 4, 64),
+	_ => (4, 4, 32),
+    }
+}
+
+//bytes taken by a whole level, which is padded out to whole tiles
+pub fn level_size(format: u32, width: usize, height: usize) -> usize {
+    let (tile_width, tile_height, tile_bytes) = tile_size(format);
+    width.div_ceil(tile_width) * height.div_ceil(tile_height) * tile_bytes
+}
+
+//bytes from the start of the texture to a mip level, the levels follow on from each other halving down to 1x1
+pub fn level_offset(format: u32, width: usize, height: usize, level: u32) -> usize {
+    (0..level).map(|i| level_size(format, (width >> i).max(1), (height >> i).max(1))).sum()
+}
+
+fn expand(val: u32, bits: u32) -> u8 {
+    let val = val & ((1 << bits) - 1);
+    match bits {
+	3 => ((val << 5) | (val << 2) | (val >> 1)) as u8,
+	4 => (val * 0x11) as u8,
+	5 => ((val << 3) | (val >> 2)) as u8,
+	_ => ((val << 2) | (val >> 4)) as u8,
+    }
+}
+
+pub fn rgb565(val: u16) -> [u8; 4] {
+    let val = u32::from(val);
+    [expand(val >> 11, 5), expand(val >> 5, 6), expand(val, 5), 0xFF]
+}
+
+//opaque 555 with the top bit set, otherwise 4443 with alpha in the top three
+pub fn rgb5a3(val: u16) -> [u8; 4] {
+    let val = u32::from(val);
+    if val & 0x8000 != 0 {
+	[expand(val >> 10, 5), expand(val >> 5, 5), expand(val, 5), 0xFF]
+    } else {
+	[expand(val >> 8, 4), expand(val >> 4, 4), expand(val, 4), expand(val >> 12, 3)]
+    }
+}
+
+//alpha in the top byte, intensity in the bottom
+pub fn ia8(val: u16) -> [u8; 4] {
+    let (a, i) = ((val >> 8) as u8, val as u8);
+    [i, i, i, a]
+}
+
+pub fn palette_color(tlut: &[u8], tlut_format: u32, index: usize) -> [u8; 4] {
+    let val = tlut.get((index * 2)..(index * 2 + 2)).map_or(0, BigEndian::read_u16);
+    match tlut_format {
+	TLUT_IA8 => ia8(val),
+	TLUT_RGB565 => rgb565(val),
+	_ => rgb5a3(val),
+    }
+}
+
+//one rgba texel of a level starting at the front of data. anything past the end of data reads as 0
+pub fn texel(data: &[u8], format: u32, width: usize, x: usize, y: usize, tlut: &[u8], tlut_format: u32) -> [u8; 4] {
+    let (tile_width, tile_height, tile_bytes) = tile_size(format);
+    let tiles_per_row = width.div_ceil(tile_width);
+    let tile = ((y / tile_height) * tiles_per_row + x / tile_width) * tile_bytes;
+    let (x, y) = (x % tile_width, y % tile_height);
+    let byte = |offset: usize| data.get(tile + offset).copied().unwrap_or(0);
+    let half = |offset: usize| (u16::from(byte(offset)) << 8) | u16::from(byte(offset + 1));
+    let nibble = |offset: usize| u32::from(byte(offset / 2) >> ((!offset & 1) * 4)) & 0xF;
+
+    match format {
+	TEX_I4 => {
+	    let i = expand(nibble(y * 8 + x), 4);
+	    [i, i, i, i]
+	},
+	TEX_I8 => {
+	    let i = byte(y * 8 + x);
+	    [i, i, i, i]
+	},
+	TEX_IA4 => {
+	    let val = u32::from(byte(y * 8 + x));
+	    let (a, i) = (expand(val >> 4, 4), expand(val, 4));
+	    [i, i, i, a]
+	},
+	TEX_IA8 => ia8(half((y * 4 + x) * 2)),
+	TEX_RGB565 => rgb565(half((y * 4 + x) * 2)),
+	TEX_RGB5A3 => rgb5a3(half((y * 4 + x) * 2)),
+	//the alpha and red of the whole tile come first, then green and blue
+	TEX_RGBA8 => {
+	    let offset = (y * 4 + x) * 2;
+	    [byte(offset + 1), byte(offset + 32), byte(offset + 33), byte(offset)]
+	},
+	TEX_C4 => palette_color(tlut, tlut_format, nibble(y * 8 + x) as usize),
+	TEX_C8 => palette_color(tlut, tlut_format, byte(y * 8 + x) as usize),
+	TEX_C14X2 => palette_color(tlut, tlut_format, (half((y * 4 + x) * 2) & 0x3FFF) as usize),
+	TEX_CMPR => {
+	    //four 4x4 dxt1 blocks in a 2x2 grid
+	    let block = ((y / 4) * 2 + x / 4) * 8;
+	    let (x, y) = (x % 4, y % 4);
+	    let (c0, c1) = (half(block), half(block + 2));
+	    let index = (byte(block + 4 + y) >> ((3 - x) * 2)) & 0b11;
+	    cmpr_color(c0, c1, index)
+	},
+	_ => [0; 4],
+    }
+}
+
+//the in between colors are 3/8 and 5/8 of the way, not thirds like on a pc
+fn cmpr_color(c0: u16, c1: u16, index: u8) -> [u8; 4] {
+    let (a, b) = (rgb565(c0), rgb565(c1));
+    let mix = |wa: u32, wb: u32| -> [u8; 4] {
+	std::array::from_fn(|i| ((u32::from(a[i]) * wa + u32::from(b[i]) * wb) >> 3) as u8)
+    };
+
+    match index {
+	0 => a,
+	1 => b,
+	2 if c0 > c1 => mix(5, 3),
+	3 if c0 > c1 => mix(3, 5),
+	2 => mix(4, 4),
+	_ => {
+	    let [r, g, b, _] = mix(4, 4);
+	    [r, g, b, 0]
+	},
+    }
+}
+
+//everything the sampler needs to know about one of the eight texture maps, out of its BP registers
+#[derive(Debug, Copy, Clone)]
+pub struct TexMap {
+    pub addr: u32,
+    pub width: usize,
+    pub height: usize,
+    pub format: u32,
+    pub wrap_s: u32,
+    pub wrap_t: u32,
+    pub mag_linear: bool,
+    pub min_linear: bool,
+    //0 for no mipmapping, 1 for the nearest level, 2 for blending between levels
+    pub mip_filter: u32,
+    //in 1/16ths of a level
+    pub lod_bias: i32,
+    pub min_lod: i32,
+    pub max_lod: i32,
+    pub tlut_offset: usize,
+    pub tlut_format: u32,
+}
+
+impl TexMap {
+    pub fn new(gx: &Gx, map: usize) -> Self {
+	let reg = |base: usize| gx.bp_regs[base + (map & 3) + if map >= 4 { BP_TX_HIGH_MAPS } else { 0 }];
+	let (mode0, mode1, image0, image3, tlut) = (reg(BP_TX_SETMODE0), reg(BP_TX_SETMODE1), reg(BP_TX_SETIMAGE0), reg(BP_TX_SETIMAGE3), reg(BP_TX_SETTLUT));
+	let min_filter = (mode0 >> 5) & 0b111;
+
+	Self {
+	    addr: (image3 & 0xFF_FFFF) << 5,
+	    width: (image0 & 0x3FF) as usize + 1,
+	    height: ((image0 >> 10) & 0x3FF) as usize + 1,
+	    format: (image0 >> 20) & 0xF,
+	    wrap_s: mode0 & 0b11,
+	    wrap_t: (mode0 >> 2) & 0b11,
+	    mag_linear: (mode0 >> 4) & 1 != 0,
+	    min_linear: min_filter & 0b100 != 0,
+	    mip_filter: min_filter & 0b11,
+	    //s2.5 in the register, brought up to 1/16ths
+	    lod_bias: ((((mode0 >> 9) & 0xFF) as u8 as i8) as i32) / 2,
+	    min_lod: (mode1 & 0xFF) as i32,
+	    max_lod: ((mode1 >> 8) & 0xFF) as i32,
+	    tlut_offset: ((tlut & 0x3FF) as usize) << 9,
+	    tlut_format: (tlut >> 10) & 0b11,
+	}
+    }
+
+    fn level(&self, gc: &Gamecube, level: u32, s: i32, t: i32, linear: bool) -> [u8; 4] {
+	let offset = self.addr as usize + level_offset(self.format, self.width, self.height, level);
+	let (width, height) = ((self.width >> level).max(1), (self.height >> level).max(1));
+	let (s, t) = (s >> level, t >> level);
+
+	let data = gc.memory.get(offset..).unwrap_or(&[]);
+	let tlut = &gc.gx.tmem[self.tlut_offset.min(TMEM_SIZE)..];
+	let fetch = |x: i32, y: i32| texel(data, self.format, width, wrap(x, width, self.wrap_s), wrap(y, height, self.wrap_t), tlut, self.tlut_format);
+
+	if !linear {
+	    return fetch(s >> SUBTEXEL_BITS, t >> SUBTEXEL_BITS);
+	}
+
+	//bilinear between the four texels around the centre of the sample
+	let (s, t) = (s - SUBTEXEL_ONE / 2, t - SUBTEXEL_ONE / 2);
+	let (x, y) = (s >> SUBTEXEL_BITS, t >> SUBTEXEL_BITS);
+	let (fs, ft) = (s & (SUBTEXEL_ONE - 1), t & (SUBTEXEL_ONE - 1));
+	let texels = [fetch(x, y), fetch(x + 1, y), fetch(x, y + 1), fetch(x + 1, y + 1)];
+	let weights = [
+	    (SUBTEXEL_ONE - fs) * (SUBTEXEL_ONE - ft),
+	    fs * (SUBTEXEL_ONE - ft),
+	    (SUBTEXEL_ONE - fs) * ft,
+	    fs * ft,
+	];
+
+	std::array::from_fn(|i| {
+	    let sum = texels.iter().zip(&weights).map(|(texel, weight)| i32::from(texel[i]) * weight).sum::<i32>();
+	    (sum >> (SUBTEXEL_BITS * 2)) as u8
+	})
+    }
+
+    //the whole of one mip level, palette and all, as it would be sampled right now
+    pub fn decode(&self, gc: &Gamecube, level: u32) -> Texture {
+	let offset = self.addr as usize + level_offset(self.format, self.width, self.height, level);
+	let data = gc.memory.get(offset..).unwrap_or(&[]);
+	let tlut = &gc.gx.tmem[self.tlut_offset.min(TMEM_SIZE)..];
+	decode(data, self.format, (self.width >> level).max(1), (self.height >> level).max(1), tlut, self.tlut_format)
+    }
+}
+
+//a decoded texture, untiled into rows of rgba8 from the top left
+#[derive(Debug, Clone)]
+pub struct Texture {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Texture {
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+	let offset = (y * self.width + x) * 4;
+	self.pixels[offset..(offset + 4)].try_into().unwrap()
+    }
+}
+
+//untiles a whole level starting at the front of data. tlut is the palette for the color indexed formats
+pub fn decode(data: &[u8], format: u32, width: usize, height: usize, tlut: &[u8], tlut_format: u32) -> Texture {
+    let (tile_width, tile_height, _) = tile_size(format);
+    let mut pixels = vec![0; width * height * 4];
+
+    //a tile at a time so each one's bytes are only looked up once
+    for tile_y in (0..height).step_by(tile_height) {
+	for tile_x in (0..width).step_by(tile_width) {
+	    for y in tile_y..(tile_y + tile_height).min(height) {
+		for x in tile_x..(tile_x + tile_width).min(width) {
+		    let offset = (y * width + x) * 4;
+		    pixels[offset..(offset + 4)].copy_from_slice(&texel(data, format, width, x, y, tlut, tlut_format));
+		}
+	    }
+	}
+    }
+
+    Texture {
+	width,
+	height,
+	pixels,
+    }
+}
+
+//for looking at textures straight out of a ram image, without going through the BP registers. addresses are
+//physical, with the cached and uncached mirror bits masked off. tlut is the address and format of the palette in
+//ram, for the formats that have one
+pub fn decode_ram(memory: &[u8], addr: u32, format: u32, width: usize, height: usize, level: u32, tlut: Option<(u32, u32)>) -> Texture {
+    let offset = (addr & 0x1FFF_FFFF) as usize + level_offset(format, width, height, level);
+    let data = memory.get(offset..).unwrap_or(&[]);
+    let (tlut_addr, tlut_format) = tlut.unwrap_or((0, TLUT_IA8));
+    let tlut = tlut.and_then(|_| memory.get((tlut_addr & 0x1FFF_FFFF) as usize..)).unwrap_or(&[]);
+    decode(data, format, (width >> level).max(1), (height >> level).max(1), tlut, tlut_format)
+}
+
+fn wrap(coord: i32, size: usize, mode: u32) -> usize {
+    let size = size as i32;
+    let coord = match mode {
+	WRAP_CLAMP => coord.clamp(0, size - 1),
+	WRAP_REPEAT => coord.rem_euclid(size),
+	_ => {
+	    let period = coord.rem_euclid(size * 2);
+	    if period >= size { size * 2 - 1 - period } else { period }
+	},
+    };
+
+    coord as usize
+}
+
+//s and t are in texels, lod is log2 of how many texels the pixel covers, in 1/16ths
+pub fn sample(gc: &Gamecube, map: &TexMap, s: f32, t: f32, lod: i32) -> [u8; 4] {
+    let s = (s * SUBTEXEL_ONE as f32) as i32;
+    let t = (t * SUBTEXEL_ONE as f32) as i32;
+    let lod = (lod + map.lod_bias).clamp(map.min_lod, map.max_lod);
+
+    if lod <= 0 || map.mip_filter == 0 {
+	let linear = if lod <= 0 { map.mag_linear } else { map.min_linear };
+	return map.level(gc, 0, s, t, linear);
+    }
+
+    let max_level = (map.width.max(map.height).ilog2()) as i32;
+    let level = (lod >> 4).min(max_level);
+    if map.mip_filter == 1 || level == max_level {
+	let level = ((lod + 8) >> 4).min(max_level);
+	return map.level(gc, level as u32, s, t, map.min_linear);
+    }
+
+    let (a, b) = (map.level(gc, level as u32, s, t, map.min_linear), map.level(gc, level as u32 + 1, s, t, map.min_linear));
+    let frac = (lod & 0xF) as u32;
+    std::array::from_fn(|i| ((u32::from(a[i]) * (16 - frac) + u32::from(b[i]) * frac) >> 4) as u8)
+}
+
+//LOAD_TLUT1 copies a palette out of ram into tmem, where the texture maps' TLUT registers point
+pub fn load_tlut(gc: &mut Gamecube, val: u32) {
+    let src = ((gc.gx.bp_regs[BP_LOAD_TLUT0] & 0xF_FFFF) << 5) as usize;
+    let dst = ((val & 0x3FF) as usize) << 9;
+    let len = (((val >> 10) & 0x7FF) as usize * 32).min(TMEM_SIZE - dst);
+
+    if let Some(palette) = gc.memory.get(src..(src + len)) {
+	gc.gx.tmem[dst..(dst + len)].copy_from_slice(palette);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //one format's worth of tiles, texels picked out of the decoded result
+    fn decoded(data: &[u8], format: u32, width: usize, height: usize) -> Texture {
+	decode(data, format, width, height, &[], TLUT_IA8)
+    }
+
+    #[test]
+    fn i4_is_a_nibble_a_texel_in_8x8_tiles() {
+	let mut data = [0; 64];
+	data[0] = 0x1F;
+	data[4] = 0x80;
+	//the second tile along
+	data[32] = 0xA0;
+	let texture = decoded(&data, TEX_I4, 16, 8);
+
+	assert_eq!(texture.pixel(0, 0), [0x11; 4]);
+	assert_eq!(texture.pixel(1, 0), [0xFF; 4]);
+	assert_eq!(texture.pixel(0, 1), [0x88; 4]);
+	assert_eq!(texture.pixel(1, 1), [0; 4]);
+	assert_eq!(texture.pixel(8, 0), [0xAA; 4]);
+    }
+
+    #[test]
+    fn i8_is_a_byte_a_texel_in_8x4_tiles() {
+	let mut data = [0; 32];
+	data[9] = 0x42;
+	let texture = decoded(&data, TEX_I8, 8, 4);
+
+	assert_eq!(texture.pixel(1, 1), [0x42; 4]);
+	assert_eq!(texture.pixel(0, 1), [0; 4]);
+    }
+
+    #[test]
+    fn ia4_has_alpha_in_the_top_nibble() {
+	let mut data = [0; 32];
+	data[0] = 0x3C;
+	let texture = decoded(&data, TEX_IA4, 8, 4);
+
+	assert_eq!(texture.pixel(0, 0), [0xCC, 0xCC, 0xCC, 0x33]);
+    }
+
+    #[test]
+    fn ia8_has_alpha_in_the_first_byte() {
+	let mut data = [0; 32];
+	data[2..4].copy_from_slice(&[0x80, 0x40]);
+	let texture = decoded(&data, TEX_IA8, 4, 4);
+
+	assert_eq!(texture.pixel(1, 0), [0x40, 0x40, 0x40, 0x80]);
+    }
+
+    #[test]
+    fn rgb565_expands_to_8_bits_a_channel() {
+	let mut data = [0; 32];
+	for (i, val) in [0xF800u16, 0x07E0, 0x001F, 0x8410].into_iter().enumerate() {
+	    data[(i * 2)..(i * 2 + 2)].copy_from_slice(&val.to_be_bytes());
+	}
+	let texture = decoded(&data, TEX_RGB565, 4, 4);
+
+	assert_eq!(texture.pixel(0, 0), [0xFF, 0, 0, 0xFF]);
+	assert_eq!(texture.pixel(1, 0), [0, 0xFF, 0, 0xFF]);
+	assert_eq!(texture.pixel(2, 0), [0, 0, 0xFF, 0xFF]);
+	assert_eq!(texture.pixel(3, 0), [0x84, 0x82, 0x84, 0xFF]);
+    }
+
+    #[test]
+    fn rgb5a3_is_opaque_555_or_4443() {
+	let mut data = [0; 32];
+	data[0..2].copy_from_slice(&0xFC00u16.to_be_bytes());
+	data[2..4].copy_from_slice(&0x3F00u16.to_be_bytes());
+	let texture = decoded(&data, TEX_RGB5A3, 4, 4);
+
+	assert_eq!(texture.pixel(0, 0), [0xFF, 0, 0, 0xFF]);
+	assert_eq!(texture.pixel(1, 0), [0xFF, 0, 0, 0x6D]);
+    }
+
+    #[test]
+    fn rgba8_splits_ar_and_gb_across_the_tile() {
+	let mut data = [0; 64];
+	data[2..4].copy_from_slice(&[0x11, 0x22]);
+	data[34..36].copy_from_slice(&[0x33, 0x44]);
+	let texture = decoded(&data, TEX_RGBA8, 4, 4);
+
+	assert_eq!(texture.pixel(1, 0), [0x22, 0x33, 0x44, 0x11]);
+	assert_eq!(texture.pixel(0, 0), [0; 4]);
+    }
+
+    #[test]
+    fn color_indexed_formats_look_up_the_palette() {
+	let mut tlut = [0; 8];
+	tlut[2..4].copy_from_slice(&0x801Fu16.to_be_bytes());
+	tlut[4..6].copy_from_slice(&0xF800u16.to_be_bytes());
+	tlut[6..8].copy_from_slice(&[0x80, 0x40]);
+
+	let mut data = [0; 32];
+	data[0] = 0x20;
+	let c4 = decode(&data, TEX_C4, 8, 8, &tlut, TLUT_RGB565);
+	assert_eq!(c4.pixel(0, 0), [0xFF, 0, 0, 0xFF]);
+
+	let mut data = [0; 32];
+	data[1] = 3;
+	let c8 = decode(&data, TEX_C8, 8, 4, &tlut, TLUT_IA8);
+	assert_eq!(c8.pixel(1, 0), [0x40, 0x40, 0x40, 0x80]);
+
+	//the top two bits of a c14x2 index are ignored
+	let mut data = [0; 32];
+	data[0..2].copy_from_slice(&0xC001u16.to_be_bytes());
+	let c14x2 = decode(&data, TEX_C14X2, 4, 4, &tlut, TLUT_RGB5A3);
+	assert_eq!(c14x2.pixel(0, 0), [0, 0, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn cmpr_blocks_interpolate_at_3_8ths() {
+	let mut data = [0; 32];
+	//c0 > c1 is four colors, each index two bits from the left
+	data[0..2].copy_from_slice(&0xF800u16.to_be_bytes());
+	data[2..4].copy_from_slice(&0x001Fu16.to_be_bytes());
+	data[4] = 0b00_01_10_11;
+	//otherwise it's three and the fourth is transparent
+	data[8..10].copy_from_slice(&0x001Fu16.to_be_bytes());
+	data[10..12].copy_from_slice(&0xF800u16.to_be_bytes());
+	data[12] = 0b11_10_11_11;
+	let texture = decoded(&data, TEX_CMPR, 8, 8);
+
+	assert_eq!(texture.pixel(0, 0), [0xFF, 0, 0, 0xFF]);
+	assert_eq!(texture.pixel(1, 0), [0, 0, 0xFF, 0xFF]);
+	assert_eq!(texture.pixel(2, 0), [0x9F, 0, 0x5F, 0xFF]);
+	assert_eq!(texture.pixel(3, 0), [0x5F, 0, 0x9F, 0xFF]);
+	assert_eq!(texture.pixel(4, 0), [0x7F, 0, 0x7F, 0]);
+	assert_eq!(texture.pixel(5, 0), [0x7F, 0, 0x7F, 0xFF]);
+    }
+}
@@ -0,0 +1,316 @@
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::Gamecube;
+
+use super::CpRegs;
+
+//which of the CP's arrays each attribute gets indexed out of
+const ARRAY_POS: usize = 0;
+const ARRAY_NORMAL: usize = 1;
+const ARRAY_COLOR0: usize = 2;
+const ARRAY_TEX0: usize = 4;
+
+//normals have a fixed point position set by their format instead of a frac in the VAT
+const NORMAL_FRAC_8: u32 = 6;
+const NORMAL_FRAC_16: u32 = 14;
+
+//how an attribute shows up in the vertex, from its two bits in the VCD
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AttrType {
+    None,
+    Direct,
+    Index8,
+    Index16,
+}
+
+impl AttrType {
+    fn from_bits(bits: u32) -> Self {
+	match bits & 0b11 {
+	    0b00 => Self::None,
+	    0b01 => Self::Direct,
+	    0b10 => Self::Index8,
+	    _ => Self::Index16,
+	}
+    }
+}
+
+//what's in a vertex and how big each piece of it is, worked out from the VCD and one VAT
+#[derive(Debug, Copy, Clone)]
+pub struct VertexFormat {
+    pub pos_mtx_idx: bool,
+    pub tex_mtx_idx: [bool; 8],
+    pub pos: AttrType,
+    pub normal: AttrType,
+    pub colors: [AttrType; 2],
+    pub tex_coords: [AttrType; 8],
+    //xyz rather than xy
+    pub pos_3d: bool,
+    pub pos_format: u32,
+    pub pos_frac: u32,
+    //normal, binormal and tangent rather than just the normal
+    pub nbt: bool,
+    //with nbt, each of the three gets its own index
+    pub nbt_index3: bool,
+    pub normal_format: u32,
+    pub color_formats: [u32; 2],
+    //st rather than just s
+    pub tex_2d: [bool; 8],
+    pub tex_formats: [u32; 8],
+    pub tex_fracs: [u32; 8],
+}
+
+impl VertexFormat {
+    pub fn new(cp: &CpRegs, vat: usize) -> Self {
+	let (lo, hi) = (cp.vcd_lo, cp.vcd_hi);
+	let (a, b, c) = (cp.vat_a[vat], cp.vat_b[vat], cp.vat_c[vat]);
+
+	Self {
+	    pos_mtx_idx: lo & 1 != 0,
+	    tex_mtx_idx: std::array::from_fn(|i| (lo >> (1 + i)) & 1 != 0),
+	    pos: AttrType::from_bits(lo >> 9),
+	    normal: AttrType::from_bits(lo >> 11),
+	    colors: [AttrType::from_bits(lo >> 13), AttrType::from_bits(lo >> 15)],
+	    tex_coords: std::array::from_fn(|i| AttrType::from_bits(hi >> (i * 2))),
+	    pos_3d: a & 1 != 0,
+	    pos_format: (a >> 1) & 0b111,
+	    pos_frac: (a >> 4) & 0b11111,
+	    nbt: (a >> 9) & 1 != 0,
+	    nbt_index3: (a >> 31) & 1 != 0,
+	    normal_format: (a >> 10) & 0b111,
+	    color_formats: [(a >> 14) & 0b111, (a >> 18) & 0b111],
+	    tex_2d: [
+		(a >> 21) & 1 != 0,
+		b & 1 != 0,
+		(b >> 9) & 1 != 0,
+		(b >> 18) & 1 != 0,
+		(b >> 27) & 1 != 0,
+		(c >> 5) & 1 != 0,
+		(c >> 14) & 1 != 0,
+		(c >> 23) & 1 != 0,
+	    ],
+	    tex_formats: [
+		(a >> 22) & 0b111,
+		(b >> 1) & 0b111,
+		(b >> 10) & 0b111,
+		(b >> 19) & 0b111,
+		(b >> 28) & 0b111,
+		(c >> 6) & 0b111,
+		(c >> 15) & 0b111,
+		(c >> 24) & 0b111,
+	    ],
+	    tex_fracs: [
+		(a >> 25) & 0b11111,
+		(b >> 4) & 0b11111,
+		(b >> 13) & 0b11111,
+		(b >> 22) & 0b11111,
+		c & 0b11111,
+		(c >> 9) & 0b11111,
+		(c >> 18) & 0b11111,
+		(c >> 27) & 0b11111,
+	    ],
+	}
+    }
+
+    //bytes per vertex in the fifo
+    pub fn size(&self) -> usize {
+	let mut size = usize::from(self.pos_mtx_idx) + self.tex_mtx_idx.iter().filter(|&&idx| idx).count();
+
+	size += attr_size(self.pos, || component_size(self.pos_format) * if self.pos_3d { 3 } else { 2 });
+
+	size += match self.normal {
+	    AttrType::Index8 | AttrType::Index16 if self.nbt && self.nbt_index3 => 3 * attr_size(self.normal, || 0),
+	    _ => attr_size(self.normal, || component_size(self.normal_format) * if self.nbt { 9 } else { 3 }),
+	};
+
+	for (&color, &format) in self.colors.iter().zip(&self.color_formats) {
+	    size += attr_size(color, || color_size(format));
+	}
+
+	for i in 0..8 {
+	    size += attr_size(self.tex_coords[i], || component_size(self.tex_formats[i]) * if self.tex_2d[i] { 2 } else { 1 });
+	}
+
+	size
+    }
+}
+
+fn attr_size(attr: AttrType, direct: impl FnOnce() -> usize) -> usize {
+    match attr {
+	AttrType::None => 0,
+	AttrType::Direct => direct(),
+	AttrType::Index8 => 1,
+	AttrType::Index16 => 2,
+    }
+}
+
+//u8, s8, u16, s16, f32
+pub fn component_size(format: u32) -> usize {
+    match format {
+	0 | 1 => 1,
+	2 | 3 => 2,
+	_ => 4,
+    }
+}
+
+//rgb565, rgb888, rgb888x, rgba4444, rgba6666, rgba8888
+pub fn color_size(format: u32) -> usize {
+    match format {
+	0 | 3 => 2,
+	1 | 4 => 3,
+	_ => 4,
+    }
+}
+
+//one vertex as the xf sees it, everything dequantised
+#[derive(Debug, Copy, Clone)]
+pub struct Vertex {
+    pub pos_mtx: Option<u8>,
+    pub tex_mtx: [Option<u8>; 8],
+    pub pos: [f32; 3],
+    //normal, binormal and tangent
+    pub normal: [[f32; 3]; 3],
+    pub colors: [Option<[u8; 4]>; 2],
+    pub tex: [[f32; 2]; 8],
+}
+
+//reads the next vertex off the front of data, which has to be at least format.size() bytes
+pub fn decode(gc: &Gamecube, format: &VertexFormat, data: &[u8]) -> Vertex {
+    let mut reader = Reader {
+	data,
+	pos: 0,
+    };
+
+    let pos_mtx = format.pos_mtx_idx.then(|| reader.u8());
+    let tex_mtx = std::array::from_fn(|i| format.tex_mtx_idx[i].then(|| reader.u8()));
+
+    let mut pos = [0.0; 3];
+    let components = if format.pos_3d { 3 } else { 2 };
+    reader.attr(gc, format.pos, ARRAY_POS, 0, |src| read_components(src, format.pos_format, format.pos_frac, &mut pos[..components]));
+
+    let mut normal = [[0.0; 3]; 3];
+    let frac = if component_size(format.normal_format) == 1 { NORMAL_FRAC_8 } else { NORMAL_FRAC_16 };
+    match format.normal {
+	AttrType::Index8 | AttrType::Index16 if format.nbt && format.nbt_index3 => {
+	    //each vector gets its own index, pointing at its own part of the element
+	    for (i, vector) in normal.iter_mut().enumerate() {
+		let offset = i * 3 * component_size(format.normal_format);
+		reader.attr(gc, format.normal, ARRAY_NORMAL, offset, |src| read_components(src, format.normal_format, frac, vector));
+	    }
+	},
+	_ => {
+	    let vectors = if format.nbt { 3 } else { 1 };
+	    reader.attr(gc, format.normal, ARRAY_NORMAL, 0, |src| {
+		let mut flat = [0.0; 9];
+		let size = read_components(src, format.normal_format, frac, &mut flat[..vectors * 3]);
+		for (i, vector) in normal.iter_mut().take(vectors).enumerate() {
+		    vector.copy_from_slice(&flat[(i * 3)..(i * 3 + 3)]);
+		}
+		size
+	    });
+	},
+    }
+
+    let mut colors = [None; 2];
+    for (i, color) in colors.iter_mut().enumerate() {
+	let format_bits = format.color_formats[i];
+	reader.attr(gc, format.colors[i], ARRAY_COLOR0 + i, 0, |src| {
+	    *color = Some(read_color(src, format_bits));
+	    color_size(format_bits)
+	});
+    }
+
+    let mut tex = [[0.0; 2]; 8];
+    for (i, coord) in tex.iter_mut().enumerate() {
+	let components = if format.tex_2d[i] { 2 } else { 1 };
+	reader.attr(gc, format.tex_coords[i], ARRAY_TEX0 + i, 0, |src| read_components(src, format.tex_formats[i], format.tex_fracs[i], &mut coord[..components]));
+    }
+
+    Vertex {
+	pos_mtx,
+	tex_mtx,
+	pos,
+	normal,
+	colors,
+	tex,
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn u8(&mut self) -> u8 {
+	self.pos += 1;
+	self.data[self.pos - 1]
+    }
+
+    fn u16(&mut self) -> u16 {
+	self.pos += 2;
+	BigEndian::read_u16(&self.data[(self.pos - 2)..self.pos])
+    }
+
+    //passes the attribute's data to read, either straight out of the vertex or out of its array in ram. read
+    //returns how many bytes it used
+    fn attr(&mut self, gc: &Gamecube, attr: AttrType, array: usize, offset: usize, read: impl FnOnce(&[u8]) -> usize) {
+	let index = match attr {
+	    AttrType::None => return,
+	    AttrType::Direct => {
+		self.pos += read(&self.data[self.pos..]);
+		return;
+	    },
+	    AttrType::Index8 => u32::from(self.u8()),
+	    AttrType::Index16 => u32::from(self.u16()),
+	};
+
+	let addr = (gc.gx.cp.array_base[array] + index * gc.gx.cp.array_stride[array]) as usize + offset;
+	//big enough for the largest element, nine f32s
+	let mut element = [0; 36];
+	let len = gc.memory.len().saturating_sub(addr).min(element.len());
+	element[..len].copy_from_slice(&gc.memory[addr.min(gc.memory.len())..][..len]);
+	read(&element);
+    }
+}
+
+fn read_components(src: &[u8], format: u32, frac: u32, out: &mut [f32]) -> usize {
+    let scale = 1.0 / (1u32 << frac) as f32;
+    let size = component_size(format);
+    for (i, out) in out.iter_mut().enumerate() {
+	let src = &src[(i * size)..];
+	*out = match format {
+	    0 => f32::from(src[0]) * scale,
+	    1 => f32::from(src[0] as i8) * scale,
+	    2 => f32::from(BigEndian::read_u16(src)) * scale,
+	    3 => f32::from(BigEndian::read_i16(src)) * scale,
+	    _ => BigEndian::read_f32(src),
+	};
+    }
+
+    size * out.len()
+}
+
+//rgba out whatever the format, with the missing alpha filled in as opaque
+fn read_color(src: &[u8], format: u32) -> [u8; 4] {
+    let expand = |val: u32, bits: u32| -> u8 {
+	let val = val & ((1 << bits) - 1);
+	((val << (8 - bits)) | (val >> (2 * bits - 8).min(bits))) as u8
+    };
+
+    match format {
+	0 => {
+	    let val = u32::from(BigEndian::read_u16(src));
+	    [expand(val >> 11, 5), expand(val >> 5, 6), expand(val, 5), 0xFF]
+	},
+	1 | 2 => [src[0], src[1], src[2], 0xFF],
+	3 => {
+	    let val = u32::from(BigEndian::read_u16(src));
+	    [expand(val >> 12, 4), expand(val >> 8, 4), expand(val >> 4, 4), expand(val, 4)]
+	},
+	4 => {
+	    let val = (u32::from(src[0]) << 16) | (u32::from(src[1]) << 8) | u32::from(src[2]);
+	    [expand(val >> 18, 6), expand(val >> 12, 6), expand(val >> 6, 6), expand(val, 6)]
+	},
+	_ => [src[0], src[1], src[2], src[3]],
+    }
+}
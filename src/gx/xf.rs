@@ -0,0 +1,271 @@
+use super::{vertex::Vertex, Gx};
+
+//registers, as offsets from XF_REGS_START
+const XF_NUM_COLORS: usize = 0x09;
+const XF_AMBIENT0: usize = 0x0A;
+const XF_MATERIAL0: usize = 0x0C;
+const XF_COLOR0_CNTRL: usize = 0x0E;
+const XF_ALPHA0_CNTRL: usize = 0x10;
+const XF_DUAL_TEX: usize = 0x12;
+const XF_MATINDEX_A: usize = 0x18;
+const XF_MATINDEX_B: usize = 0x19;
+const XF_VIEWPORT: usize = 0x1A;
+const XF_PROJECTION: usize = 0x20;
+const XF_PROJECTION_TYPE: usize = 0x26;
+const XF_NUM_TEXGENS: usize = 0x3F;
+const XF_TEXGEN: usize = 0x40;
+const XF_POST_MTX: usize = 0x50;
+
+//memory, in words
+const XF_NORMAL_MATRICES: usize = 0x400;
+const XF_POST_MATRICES: usize = 0x500;
+const XF_LIGHTS: usize = 0x600;
+const XF_LIGHT_SIZE: usize = 0x10;
+
+//channel control bits
+const CHAN_MAT_VERTEX: u32 = 1 << 0;
+const CHAN_LIGHTING: u32 = 1 << 1;
+const CHAN_AMB_VERTEX: u32 = 1 << 6;
+
+//attenuation functions
+const ATTN_SPEC: u32 = 1;
+const ATTN_SPOT: u32 = 3;
+
+//diffuse functions
+const DIFFUSE_NONE: u32 = 0;
+const DIFFUSE_SIGN: u32 = 1;
+
+//texgen types and sources
+const TEXGEN_REGULAR: u32 = 0;
+const TEXGEN_EMBOSS: u32 = 1;
+const TEXGEN_COLOR0: u32 = 2;
+const SOURCE_POS: u32 = 0;
+const SOURCE_NORMAL: u32 = 1;
+const SOURCE_BINORMAL_T: u32 = 3;
+const SOURCE_BINORMAL_B: u32 = 4;
+const SOURCE_TEX0: u32 = 5;
+
+#[derive(Debug, Copy, Clone)]
+pub struct TransformedVertex {
+    //clip space, before the divide by w
+    pub pos: [f32; 4],
+    //rgba, 0-255
+    pub colors: [[f32; 4]; 2],
+    //s, t and q. q stays 1 unless the texgen projects
+    pub tex: [[f32; 3]; 8],
+}
+
+//scale then origin for x, y and z. the origins are offset by 342 so the guard band doesn't go negative
+pub fn viewport(gx: &Gx) -> [f32; 6] {
+    std::array::from_fn(|i| f32::from_bits(gx.xf_regs[XF_VIEWPORT + i]))
+}
+
+fn dot3(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let len = dot3(v, v).sqrt();
+    if len == 0.0 {
+	v
+    } else {
+	[v[0] / len, v[1] / len, v[2] / len]
+    }
+}
+
+fn sub3(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+impl Gx {
+    fn xf_f32(&self, addr: usize) -> f32 {
+	f32::from_bits(self.xf_memory[addr % self.xf_memory.len()])
+    }
+
+    fn xf_vec3(&self, addr: usize) -> [f32; 3] {
+	[self.xf_f32(addr), self.xf_f32(addr + 1), self.xf_f32(addr + 2)]
+    }
+
+    //row of a 3x4 or 2x4 matrix times (v, w)
+    fn row_dot(&self, addr: usize, v: [f32; 3], w: f32) -> f32 {
+	dot3(self.xf_vec3(addr), v) + self.xf_f32(addr + 3) * w
+    }
+
+    fn tex_matrix_index(&self, i: usize) -> u32 {
+	if i < 4 {
+	    (self.xf_regs[XF_MATINDEX_A] >> (6 + i * 6)) & 0x3F
+	} else {
+	    (self.xf_regs[XF_MATINDEX_B] >> ((i - 4) * 6)) & 0x3F
+	}
+    }
+}
+
+pub fn transform(gx: &Gx, vertex: &Vertex) -> TransformedVertex {
+    let pos_mtx = vertex.pos_mtx.map_or(gx.xf_regs[XF_MATINDEX_A] & 0x3F, u32::from) as usize;
+    let pos_addr = pos_mtx * 4;
+    let eye = [
+	gx.row_dot(pos_addr, vertex.pos, 1.0),
+	gx.row_dot(pos_addr + 4, vertex.pos, 1.0),
+	gx.row_dot(pos_addr + 8, vertex.pos, 1.0),
+    ];
+
+    let normal_addr = XF_NORMAL_MATRICES + (pos_mtx & 0x1F) * 3;
+    let normal = normalize([
+	dot3(gx.xf_vec3(normal_addr), vertex.normal[0]),
+	dot3(gx.xf_vec3(normal_addr + 3), vertex.normal[0]),
+	dot3(gx.xf_vec3(normal_addr + 6), vertex.normal[0]),
+    ]);
+
+    let p: [f32; 6] = std::array::from_fn(|i| f32::from_bits(gx.xf_regs[XF_PROJECTION + i]));
+    let pos = if gx.xf_regs[XF_PROJECTION_TYPE] & 1 == 0 {
+	[p[0] * eye[0] + p[1] * eye[2], p[2] * eye[1] + p[3] * eye[2], p[4] * eye[2] + p[5], -eye[2]]
+    } else {
+	[p[0] * eye[0] + p[1], p[2] * eye[1] + p[3], p[4] * eye[2] + p[5], 1.0]
+    };
+
+    let mut colors = [[0.0; 4]; 2];
+    let num_colors = (gx.xf_regs[XF_NUM_COLORS] as usize).min(2);
+    for (i, color) in colors.iter_mut().enumerate().take(num_colors) {
+	let vertex_color = vertex.colors[i].unwrap_or([0xFF; 4]).map(f32::from);
+	let material = rgba(gx.xf_regs[XF_MATERIAL0 + i]);
+	let ambient = rgba(gx.xf_regs[XF_AMBIENT0 + i]);
+	let lit = |ctrl: u32, channel: usize| -> f32 {
+	    let material = if ctrl & CHAN_MAT_VERTEX != 0 { vertex_color[channel] } else { material[channel] };
+	    if ctrl & CHAN_LIGHTING == 0 {
+		return material;
+	    }
+
+	    let ambient = if ctrl & CHAN_AMB_VERTEX != 0 { vertex_color[channel] } else { ambient[channel] };
+	    let light = light_sum(gx, ctrl, eye, normal, channel);
+	    material * (ambient + light).clamp(0.0, 255.0) / 255.0
+	};
+
+	let (color_ctrl, alpha_ctrl) = (gx.xf_regs[XF_COLOR0_CNTRL + i], gx.xf_regs[XF_ALPHA0_CNTRL + i]);
+	*color = [lit(color_ctrl, 0), lit(color_ctrl, 1), lit(color_ctrl, 2), lit(alpha_ctrl, 3)].map(|val| val.clamp(0.0, 255.0));
+    }
+
+    let tex = texgen(gx, vertex, &colors);
+
+    TransformedVertex {
+	pos,
+	colors,
+	tex,
+    }
+}
+
+//0xRRGGBBAA
+fn rgba(val: u32) -> [f32; 4] {
+    val.to_be_bytes().map(f32::from)
+}
+
+//what the channel's lights add up to for one component
+fn light_sum(gx: &Gx, ctrl: u32, eye: [f32; 3], normal: [f32; 3], channel: usize) -> f32 {
+    let mask = ((ctrl >> 2) & 0xF) | (((ctrl >> 11) & 0xF) << 4);
+    let diffuse = (ctrl >> 7) & 0b11;
+    let attn_func = (ctrl >> 9) & 0b11;
+
+    let mut sum = 0.0;
+    for light in (0..8).filter(|light| mask & (1 << light) != 0) {
+	let base = XF_LIGHTS + light * XF_LIGHT_SIZE;
+	let color = rgba(gx.xf_memory[base + 3])[channel];
+	let cos_att = gx.xf_vec3(base + 4);
+	let dist_att = gx.xf_vec3(base + 7);
+	let light_pos = gx.xf_vec3(base + 10);
+	let light_dir = gx.xf_vec3(base + 13);
+
+	let to_light = sub3(light_pos, eye);
+	let mut ldir = normalize(to_light);
+	let attn = match attn_func {
+	    ATTN_SPEC => {
+		//the direction is the half angle for specular lights
+		let angle = if dot3(ldir, normal) >= 0.0 { dot3(light_dir, normal).max(0.0) } else { 0.0 };
+		let weights = [1.0, angle, angle * angle];
+		safe_divide(dot3(cos_att, weights).max(0.0), dot3(dist_att, weights))
+	    },
+	    ATTN_SPOT => {
+		let dist2 = dot3(to_light, to_light);
+		let angle = dot3(ldir, light_dir).max(0.0);
+		safe_divide(dot3(cos_att, [1.0, angle, angle * angle]).max(0.0), dot3(dist_att, [1.0, dist2.sqrt(), dist2]))
+	    },
+	    _ => {
+		if ldir == [0.0; 3] {
+		    ldir = normal;
+		}
+		1.0
+	    },
+	};
+
+	let diffuse = match diffuse {
+	    DIFFUSE_NONE => 1.0,
+	    DIFFUSE_SIGN => dot3(ldir, normal),
+	    _ => dot3(ldir, normal).max(0.0),
+	};
+
+	sum += color * attn * diffuse;
+    }
+
+    sum
+}
+
+fn safe_divide(n: f32, d: f32) -> f32 {
+    if d == 0.0 {
+	if n == 0.0 { 0.0 } else { f32::MAX }
+    } else {
+	n / d
+    }
+}
+
+fn texgen(gx: &Gx, vertex: &Vertex, colors: &[[f32; 4]; 2]) -> [[f32; 3]; 8] {
+    let mut out = [[0.0, 0.0, 1.0]; 8];
+    let num_texgens = (gx.xf_regs[XF_NUM_TEXGENS] as usize).min(8);
+
+    for i in 0..num_texgens {
+	let info = gx.xf_regs[XF_TEXGEN + i];
+	let stq = (info >> 1) & 1 != 0;
+	let abc1 = (info >> 2) & 1 != 0;
+	let kind = (info >> 4) & 0b111;
+	let source = (info >> 7) & 0x1F;
+
+	out[i] = match kind {
+	    TEXGEN_REGULAR => {
+		let mut src = match source {
+		    SOURCE_POS => vertex.pos,
+		    SOURCE_NORMAL => vertex.normal[0],
+		    SOURCE_BINORMAL_T => vertex.normal[1],
+		    SOURCE_BINORMAL_B => vertex.normal[2],
+		    _ if (SOURCE_TEX0..SOURCE_TEX0 + 8).contains(&source) => {
+			let [s, t] = vertex.tex[(source - SOURCE_TEX0) as usize];
+			[s, t, 1.0]
+		    },
+		    _ => [0.0; 3],
+		};
+		if !abc1 {
+		    src[2] = 1.0;
+		}
+
+		let addr = vertex.tex_mtx[i].map_or(gx.tex_matrix_index(i), u32::from) as usize * 4;
+		let q = if stq { gx.row_dot(addr + 8, src, 1.0) } else { 1.0 };
+		let mut coord = [gx.row_dot(addr, src, 1.0), gx.row_dot(addr + 4, src, 1.0), q];
+
+		if gx.xf_regs[XF_DUAL_TEX] & 1 != 0 {
+		    let post = gx.xf_regs[XF_POST_MTX + i];
+		    if (post >> 8) & 1 != 0 {
+			coord = normalize(coord);
+		    }
+		    let addr = XF_POST_MATRICES + (post & 0x3F) as usize * 4;
+		    coord = [gx.row_dot(addr, coord, 1.0), gx.row_dot(addr + 4, coord, 1.0), gx.row_dot(addr + 8, coord, 1.0)];
+		}
+
+		coord
+	    },
+	    //bump offsets aren't done, the coordinate is just the one being embossed
+	    TEXGEN_EMBOSS => out[((info >> 12) & 0b111) as usize],
+	    _ => {
+		let color = colors[((kind - TEXGEN_COLOR0) & 1) as usize];
+		[color[0] / 255.0, color[1] / 255.0, 1.0]
+	    },
+	};
+    }
+
+    out
+}
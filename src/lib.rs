@@ -1,21 +1,27 @@
-#![feature(core_intrinsics)]
 #![feature(bigint_helper_methods)]
 
-use std::{fs::File, io::Write, sync::{atomic::AtomicU8, Arc, RwLock}};
+use std::sync::{atomic::AtomicU8, Arc, RwLock};
 
-use audio_interface::{ai_read_u32, ai_write_u16, ai_write_u32, AudioInterface};
+use audio_interface::AudioInterface;
+use bus::{mmio::{mmio_read_u16, mmio_read_u32, mmio_read_u8, mmio_write_u16, mmio_write_u32, mmio_write_u8, Mmio, MMIO_BASE, MMIO_SIZE}, AccessDirection, AccessWidth, BusError, BusErrorPolicy, MemoryError};
 use byteorder::{BigEndian, ByteOrder};
-use cpu::Cpu;
-use dsp::{client::DSPClient, dsp_interface::{dsp_read_u16, dsp_write_u16, dsp_write_u32, DSPInterface}};
-use dvd_interface::di_read_u32;
-use external_interface::{exi_read_u32, exi_write_u32, ExternalInterface};
-use memory_interface::{mi_write_u16, MemoryInterface};
-use processor_interface::{pi_read_u32, pi_write_u32};
-use serial_interface::{si_read_u32, si_write_u32, SerialInterface};
+use command_processor::CommandProcessor;
+use cpu::{cache::{self, HID0_DCE, LOCKED_CACHE_SIZE}, gather_pipe, mmu::Access, Cpu, MACHINE_CHECK_EXCEPTION};
+use dsp::{client::DSPClient, dsp_interface::DSPInterface, DSP};
+use efb::{efb_read_u16, efb_read_u32, efb_read_u8, efb_write_u16, efb_write_u32, efb_write_u8, Efb};
+use external_interface::ExternalInterface;
+use gx::Gx;
+use memory_interface::MemoryInterface;
+use pixel_engine::PixelEngine;
+use processor_interface::ProcessorInterface;
+use scheduler::Scheduler;
+use serial_interface::SerialInterface;
 use sram::Sram;
-use video_interface::vi_read_u16;
+use video_interface::VideoInterface;
+use log::warn;
 
 pub mod cpu;
+pub mod bus;
 pub mod audio_interface;
 pub mod memory_interface;
 pub mod processor_interface;
@@ -25,11 +31,39 @@ pub mod video_interface;
 pub mod dvd_interface;
 pub mod sram;
 pub mod dsp;
+pub mod efb;
+pub mod command_processor;
+pub mod gx;
+pub mod pixel_engine;
+pub mod frame_dump;
+pub mod scheduler;
+
+pub const RAM_SIZE: usize = 0x180_0000;
+
+//physical memory map. the 0x8000_0000 (cached) and 0xC000_0000 (uncached) views games use are
+//just BAT mappings onto the same ram, so they don't show up here. the uncached one only differs in
+//WIMG[I], which matters once the data cache is modelled.
+const RAM_START: u32 = 0x0000_0000;
+const RAM_END: u32 = RAM_SIZE as u32 - 1;
+//the MI decodes a full 32mb but retail units only populate 24mb. reads come back as 0 and writes
+//go nowhere, which is what memory size probes rely on to find the end of ram.
+const UNPOPULATED_START: u32 = 0x0180_0000;
+const UNPOPULATED_END: u32 = 0x01FF_FFFF;
+const EFB_START: u32 = 0x0800_0000;
+const EFB_END: u32 = 0x0BFF_FFFF;
+const MMIO_START: u32 = MMIO_BASE;
+const MMIO_END: u32 = MMIO_BASE + MMIO_SIZE - 1;
+//only backed while HID2[LCE] is set
+const LOCKED_CACHE_START: u32 = 0xE000_0000;
+const LOCKED_CACHE_END: u32 = LOCKED_CACHE_START + LOCKED_CACHE_SIZE as u32 - 1;
+const BIOS_START: u32 = 0xFFF0_0000;
+const BIOS_END: u32 = 0xFFFF_FFFF;
 
 pub struct Gamecube {
     pub cpu: Cpu,
     pub bios: Vec<u8>,
     pub exi: ExternalInterface,
+    pub pi: ProcessorInterface,
     pub si: SerialInterface,
     pub mi: MemoryInterface,
     pub ai: AudioInterface,
@@ -38,6 +72,14 @@ pub struct Gamecube {
     pub dsp_client: DSPClient,
     pub dsp: DSPInterface,
     pub memory: Vec<u8>,
+    pub efb: Efb,
+    pub cp: CommandProcessor,
+    pub gx: Gx,
+    pub pe: PixelEngine,
+    pub vi: VideoInterface,
+    pub mmio: Mmio,
+    pub scheduler: Scheduler,
+    pub bus_policy: BusErrorPolicy,
 }
 
 impl Gamecube {
@@ -45,10 +87,22 @@ impl Gamecube {
 	let mut bios = bios;
 	descramble(&mut bios[0x100..0x1AFF00]);
 	let sram = Arc::new(RwLock::new(Sram::new()));
-	Self {
+	let mut mmio = Mmio::new();
+	command_processor::register_mmio(&mut mmio);
+	pixel_engine::register_mmio(&mut mmio);
+	video_interface::register_mmio(&mut mmio);
+	processor_interface::register_mmio(&mut mmio);
+	memory_interface::register_mmio(&mut mmio);
+	dsp::dsp_interface::register_mmio(&mut mmio);
+	dvd_interface::register_mmio(&mut mmio);
+	serial_interface::register_mmio(&mut mmio);
+	external_interface::register_mmio(&mut mmio);
+	audio_interface::register_mmio(&mut mmio);
+	let mut gc = Self {
 	    cpu: Cpu::new(),
 	    bios: bios.clone(),
 	    exi: ExternalInterface::new(bios, sram.clone()),
+	    pi: ProcessorInterface::new(),
 	    si: SerialInterface::new(),
 	    mi: MemoryInterface::new(),
 	    ai: AudioInterface::new(),
@@ -56,108 +110,385 @@ impl Gamecube {
 	    aram,
 	    dsp_client,
 	    dsp: DSPInterface::new(),
-	    memory: vec![0; 0x180_0000],
+	    memory: vec![0; RAM_SIZE],
+	    efb: Efb::new(),
+	    cp: CommandProcessor::new(),
+	    gx: Gx::new(),
+	    pe: PixelEngine::new(),
+	    vi: VideoInterface::new(),
+	    mmio,
+	    scheduler: Scheduler::new(),
+	    bus_policy: BusErrorPolicy::Panic,
+	};
+
+	video_interface::start(&mut gc);
+	gc
+    }
+
+    fn bus_error<T: Default>(&mut self, addr: u32, width: AccessWidth, direction: AccessDirection) -> Result<T, MemoryError> {
+	let err = BusError {
+	    addr,
+	    width,
+	    direction,
+	    pc: self.cpu.cia,
+	};
+
+	match self.bus_policy {
+	    BusErrorPolicy::Panic => panic!("{err}"),
+	    BusErrorPolicy::OpenBus => {
+		warn!("{err}");
+		Ok(T::default())
+	    },
+	    BusErrorPolicy::Exception => {
+		warn!("{err}");
+		self.cpu.exceptions |= MACHINE_CHECK_EXCEPTION;
+		//the faulting instruction never completes, so srr0 has to point back at it
+		self.cpu.nia = self.cpu.cia;
+		Err(MemoryError::Bus(err))
+	    },
+	}
+    }
+
+    fn translate(&mut self, addr: u32, access: Access) -> Result<u32, MemoryError> {
+	cache::snoop_page_table(self, access, addr);
+	match self.cpu.mmu.translate_addr(access, addr, &self.cpu.msr, &mut self.memory) {
+	    Ok(phys) => Ok(phys),
+	    Err(fault) => {
+		self.cpu.translation_fault(&fault);
+		Err(MemoryError::Translation(fault))
+	    },
 	}
     }
 
-    pub fn read_u8(&mut self, addr: u32) -> u8 {
-	let phys = self.cpu.mmu.translate_addr(false, addr, &self.cpu.msr);
+    //for debuggers and tooling, reads ram or bios without raising exceptions, poking hardware or leaving a trace
+    //in the page table and TLBs
+    pub fn peek_u32(&self, addr: u32, instr: bool) -> Option<u32> {
+	let access = if instr {
+	    Access::Fetch
+	} else {
+	    Access::Read
+	};
+	let phys = self.cpu.mmu.probe(access, addr, &self.cpu.msr, &self.memory)?;
 
-	match phys {
-	    0x0000_0000..=0x017F_FFFF => self.memory[phys as usize],
-	    _ => unimplemented!("addr {phys:#010X} for read_u8"),
+	//what a load would see, which can still be sitting in the data cache
+	let mut bytes = self.backing(phys, 4)?.to_vec();
+	if !instr && self.cached(addr, phys, 4) {
+	    for (i, byte) in bytes.iter_mut().enumerate() {
+		*byte = self.cpu.dcache.peek(phys + i as u32).unwrap_or(*byte);
+	    }
 	}
+	Some(BigEndian::read_u32(&bytes))
     }
 
-    pub fn read_u16(&mut self, addr: u32) -> u16 {
-	let phys = self.cpu.mmu.translate_addr(false, addr, &self.cpu.msr);
+    //cacheable data accesses to ram go through the data cache when it's modelled. anything mapped with
+    //WIMG[I] set, like the uncached mirror, goes straight to ram the same as dma does
+    fn cached(&self, addr: u32, phys: u32, len: usize) -> bool {
+	self.cpu.dcache.modelled
+	    && self.cpu.hid0 & HID0_DCE != 0
+	    && phys as usize + len <= RAM_SIZE
+	    && !self.cpu.mmu.cache_inhibited(addr, &self.cpu.msr)
+    }
+
+    //plain memory that can be accessed at any width, as opposed to hardware registers
+    fn backing(&self, phys: u32, len: usize) -> Option<&[u8]> {
+	match phys {
+	    RAM_START..=RAM_END => self.memory.get((phys as usize)..(phys as usize + len)),
+	    LOCKED_CACHE_START..=LOCKED_CACHE_END if self.cpu.hid2.lce() => {
+		let offset = (phys - LOCKED_CACHE_START) as usize;
+		self.cpu.locked_cache.get(offset..(offset + len))
+	    },
+	    BIOS_START..=BIOS_END => {
+		let offset = (phys - BIOS_START) as usize;
+		self.bios.get(offset..(offset + len))
+	    },
+	    _ => None,
+	}
+    }
 
+    //anything written through here might be code, so it gets dropped from the decode cache
+    fn backing_mut(&mut self, phys: u32, len: usize) -> Option<&mut [u8]> {
 	match phys {
-	    0x0000_0000..=0x017F_FFFF => BigEndian::read_u16(&self.memory[(phys as usize)..]),
-	    0x0C00_2000..=0x0C00_2FFF => vi_read_u16(self, phys - 0x0C00_2000),
-	    0x0C00_5000..=0x0C00_5FFF => dsp_read_u16(self, phys - 0x0C00_5000),
-	    _ => unimplemented!("addr {phys:#010X} for read_u16"),
+	    RAM_START..=RAM_END => {
+		self.cpu.decode_cache.invalidate_range(phys, len);
+		self.memory.get_mut((phys as usize)..(phys as usize + len))
+	    },
+	    LOCKED_CACHE_START..=LOCKED_CACHE_END if self.cpu.hid2.lce() => {
+		let offset = (phys - LOCKED_CACHE_START) as usize;
+		self.cpu.locked_cache.get_mut(offset..(offset + len))
+	    },
+	    _ => None,
+	}
+    }
+
+    pub fn read_u8(&mut self, addr: u32) -> Result<u8, MemoryError> {
+	let phys = self.translate(addr, Access::Read)?;
+
+	let val = if self.cached(addr, phys, 1) {
+	    let mut bytes = [0; 1];
+	    cache::read(self, phys, &mut bytes);
+	    Some(bytes[0])
+	} else if let Some(bytes) = self.backing(phys, 1) {
+	    Some(bytes[0])
+	} else {
+	    match phys {
+		UNPOPULATED_START..=UNPOPULATED_END => Some(0),
+		EFB_START..=EFB_END => Some(efb_read_u8(self, phys - EFB_START)),
+		MMIO_START..=MMIO_END => mmio_read_u8(self, phys),
+		_ => None,
+	    }
+	};
+
+	match val {
+	    Some(val) => Ok(val),
+	    None => self.bus_error(phys, AccessWidth::U8, AccessDirection::Read),
+	}
+    }
+
+    pub fn read_u16(&mut self, addr: u32) -> Result<u16, MemoryError> {
+	let phys = self.translate(addr, Access::Read)?;
+
+	let val = if self.cached(addr, phys, 2) {
+	    let mut bytes = [0; 2];
+	    cache::read(self, phys, &mut bytes);
+	    Some(BigEndian::read_u16(&bytes))
+	} else if let Some(bytes) = self.backing(phys, 2) {
+	    Some(BigEndian::read_u16(bytes))
+	} else {
+	    match phys {
+		UNPOPULATED_START..=UNPOPULATED_END => Some(0),
+		EFB_START..=EFB_END => Some(efb_read_u16(self, phys - EFB_START)),
+		MMIO_START..=MMIO_END => mmio_read_u16(self, phys),
+		_ => None,
+	    }
+	};
+
+	match val {
+	    Some(val) => Ok(val),
+	    None => self.bus_error(phys, AccessWidth::U16, AccessDirection::Read),
 	}
     }
     
-    pub fn read_u32(&mut self, addr: u32, instr: bool) -> u32 {
-	let phys = self.cpu.mmu.translate_addr(instr, addr, &self.cpu.msr);
+    pub fn read_u32(&mut self, addr: u32, instr: bool) -> Result<u32, MemoryError> {
+	let access = if instr {
+	    Access::Fetch
+	} else {
+	    Access::Read
+	};
+	let phys = self.translate(addr, access)?;
 	
-	match phys {
-	    0x0000_0000..=0x017F_FFFF => BigEndian::read_u32(&self.memory[(phys as usize)..]),
-	    0x0C00_3000..=0x0C00_3FFF => pi_read_u32(self, phys - 0x0C00_3000),
-	    0x0C00_6000..=0x0C00_63FF => di_read_u32(self, phys - 0x0C00_6000),
-	    0x0C00_6400..=0x0C00_67FF => si_read_u32(self, phys - 0x0C00_6400),
-	    0x0C00_6800..=0x0C00_6BFF => exi_read_u32(self, phys - 0x0C00_6800),
-	    0x0C00_6C00..=0x0C00_6FFF => ai_read_u32(self, phys - 0x0C00_6C00),
-	    0xFFF0_0000..=0xFFFF_FFFF => BigEndian::read_u32(&self.bios[(phys as usize - 0xFFF0_0000)..]),
-	    _ => unimplemented!("addr {phys:#010X} for read_u32"),
+	let val = if !instr && self.cached(addr, phys, 4) {
+	    let mut bytes = [0; 4];
+	    cache::read(self, phys, &mut bytes);
+	    Some(BigEndian::read_u32(&bytes))
+	} else if let Some(bytes) = self.backing(phys, 4) {
+	    Some(BigEndian::read_u32(bytes))
+	} else {
+	    match phys {
+		UNPOPULATED_START..=UNPOPULATED_END => Some(0),
+		EFB_START..=EFB_END => Some(efb_read_u32(self, phys - EFB_START)),
+		MMIO_START..=MMIO_END => mmio_read_u32(self, phys),
+		_ => None,
+	    }
+	};
+
+	match val {
+	    Some(val) => Ok(val),
+	    None => self.bus_error(phys, AccessWidth::U32, AccessDirection::Read),
 	}
     }
 
-    pub fn read_u64(&mut self, addr: u32) -> u64 {
-	let phys = self.cpu.mmu.translate_addr(false, addr, &self.cpu.msr);
+    pub fn read_u64(&mut self, addr: u32) -> Result<u64, MemoryError> {
+	let phys = self.translate(addr, Access::Read)?;
 
-	match phys {
-	    0x0000_0000..=0x017F_FFFF => BigEndian::read_u64(&self.memory[(phys as usize)..]),
-	    _ => unimplemented!("addr {phys:#010X} for read_u64"),
+	let val = if self.cached(addr, phys, 8) {
+	    let mut bytes = [0; 8];
+	    cache::read(self, phys, &mut bytes);
+	    Some(BigEndian::read_u64(&bytes))
+	} else if let Some(bytes) = self.backing(phys, 8) {
+	    Some(BigEndian::read_u64(bytes))
+	} else {
+	    match phys {
+		UNPOPULATED_START..=UNPOPULATED_END => Some(0),
+		_ => None,
+	    }
+	};
+
+	match val {
+	    Some(val) => Ok(val),
+	    None => self.bus_error(phys, AccessWidth::U64, AccessDirection::Read),
 	}
     }
 
-    pub fn write_u8(&mut self, addr: u32, val: u8) {
-	let phys = self.cpu.mmu.translate_addr(false, addr, &self.cpu.msr);
+    pub fn write_u8(&mut self, addr: u32, val: u8) -> Result<(), MemoryError> {
+	let phys = self.translate(addr, Access::Write)?;
 
-	match phys {
-	    0x0000_0000..=0x017F_FFFF => self.memory[phys as usize] = val,
-	    _ => unimplemented!("addr {phys:#010X} for write_u8"),
+	if gather_pipe::try_write(self, phys, &val.to_be_bytes()) {
+	    return Ok(());
+	}
+
+	let mapped = if self.cached(addr, phys, 1) {
+	    cache::write(self, phys, &[val]);
+	    Some(())
+	} else if let Some(bytes) = self.backing_mut(phys, 1) {
+	    bytes[0] = val;
+	    Some(())
+	} else {
+	    match phys {
+		UNPOPULATED_START..=UNPOPULATED_END => Some(()),
+		EFB_START..=EFB_END => {
+		    efb_write_u8(self, phys - EFB_START, val);
+		    Some(())
+		},
+		MMIO_START..=MMIO_END => mmio_write_u8(self, phys, val),
+		_ => None,
+	    }
+	};
+
+	match mapped {
+	    Some(()) => Ok(()),
+	    None => self.bus_error(phys, AccessWidth::U8, AccessDirection::Write),
 	}
     }
 
-    pub fn write_u16(&mut self, addr: u32, val: u16) {
-	let phys = self.cpu.mmu.translate_addr(false, addr, &self.cpu.msr);
+    pub fn write_u16(&mut self, addr: u32, val: u16) -> Result<(), MemoryError> {
+	let phys = self.translate(addr, Access::Write)?;
+
+	if gather_pipe::try_write(self, phys, &val.to_be_bytes()) {
+	    return Ok(());
+	}
 	
-	match phys {
-	    0x0000_0000..=0x017F_FFFF => BigEndian::write_u16(&mut self.memory[(phys as usize)..], val),
-	    0x0C00_4000..=0x0C00_4FFF => mi_write_u16(self, phys - 0x0C00_4000, val),
-	    0x0C00_5000..=0x0C00_5FFF => dsp_write_u16(self, phys - 0x0C00_5000, val),
-	    _ => unimplemented!("addr {phys:#010X} for write_u16"),
+	let mapped = if self.cached(addr, phys, 2) {
+	    cache::write(self, phys, &val.to_be_bytes());
+	    Some(())
+	} else if let Some(bytes) = self.backing_mut(phys, 2) {
+	    BigEndian::write_u16(bytes, val);
+	    Some(())
+	} else {
+	    match phys {
+		UNPOPULATED_START..=UNPOPULATED_END => Some(()),
+		EFB_START..=EFB_END => {
+		    efb_write_u16(self, phys - EFB_START, val);
+		    Some(())
+		},
+		MMIO_START..=MMIO_END => mmio_write_u16(self, phys, val),
+		_ => None,
+	    }
+	};
+
+	match mapped {
+	    Some(()) => Ok(()),
+	    None => self.bus_error(phys, AccessWidth::U16, AccessDirection::Write),
 	}
     }
 
-    pub fn write_u32(&mut self, addr: u32, val: u32) {
-	let phys = self.cpu.mmu.translate_addr(false, addr, &self.cpu.msr);
+    pub fn write_u32(&mut self, addr: u32, val: u32) -> Result<(), MemoryError> {
+	let phys = self.translate(addr, Access::Write)?;
 
-	match phys {
-	    0x0000_0000..=0x017F_FFFF => BigEndian::write_u32(&mut self.memory[(phys as usize)..], val),
-	    0x0C00_3000..=0x0C00_3FFF => pi_write_u32(self, phys - 0x0C00_3000, val),
-	    0x0C00_5000..=0x0C00_5FFF => dsp_write_u32(self, phys - 0x0C00_5000, val),
-	    0x0C00_6400..=0x0C00_67FF => si_write_u32(self, phys - 0x0C00_6400, val),
-	    0x0C00_6800..=0x0C00_6BFF => exi_write_u32(self, phys - 0x0C00_6800, val),
-	    0x0C00_6C00..=0x0C00_6FFF => ai_write_u32(self, phys - 0x0C00_6C00, val),
-	    _ => unimplemented!("addr {phys:#010X} for write_u32"),
+	if gather_pipe::try_write(self, phys, &val.to_be_bytes()) {
+	    return Ok(());
+	}
+
+	let mapped = if self.cached(addr, phys, 4) {
+	    cache::write(self, phys, &val.to_be_bytes());
+	    Some(())
+	} else if let Some(bytes) = self.backing_mut(phys, 4) {
+	    BigEndian::write_u32(bytes, val);
+	    Some(())
+	} else {
+	    match phys {
+		UNPOPULATED_START..=UNPOPULATED_END => Some(()),
+		EFB_START..=EFB_END => {
+		    efb_write_u32(self, phys - EFB_START, val);
+		    Some(())
+		},
+		MMIO_START..=MMIO_END => mmio_write_u32(self, phys, val),
+		_ => None,
+	    }
+	};
+
+	match mapped {
+	    Some(()) => Ok(()),
+	    None => self.bus_error(phys, AccessWidth::U32, AccessDirection::Write),
 	}
     }
 
-    pub fn write_u64(&mut self, addr: u32, val: u64) {
-	let phys = self.cpu.mmu.translate_addr(false, addr, &self.cpu.msr);
+    pub fn write_u64(&mut self, addr: u32, val: u64) -> Result<(), MemoryError> {
+	let phys = self.translate(addr, Access::Write)?;
 
-	match phys {
-	    0x0000_0000..=0x017F_FFFF => BigEndian::write_u64(&mut self.memory[(phys as usize)..], val),
-	    _ => unimplemented!("addr {phys:#010X} for write_u64"),
+	if gather_pipe::try_write(self, phys, &val.to_be_bytes()) {
+	    return Ok(());
+	}
+
+	let mapped = if self.cached(addr, phys, 8) {
+	    cache::write(self, phys, &val.to_be_bytes());
+	    Some(())
+	} else if let Some(bytes) = self.backing_mut(phys, 8) {
+	    BigEndian::write_u64(bytes, val);
+	    Some(())
+	} else {
+	    match phys {
+		UNPOPULATED_START..=UNPOPULATED_END => Some(()),
+		_ => None,
+	    }
+	};
+
+	match mapped {
+	    Some(()) => Ok(()),
+	    None => self.bus_error(phys, AccessWidth::U64, AccessDirection::Write),
 	}
     }
 }
 
-pub fn step(gc: &mut Gamecube) {
-    cpu::step(gc);
+pub fn step(gc: &mut Gamecube, dsp: &mut DSP) {
+    scheduler::step(gc, dsp);
 }
 
-pub fn run(gc: &mut Gamecube) {
-    loop {
-	step(gc);
+//runs until the cpu checkstops, which is left in gc.cpu.checkstop for the frontend to report
+pub fn run(gc: &mut Gamecube, dsp: &mut DSP) {
+    while gc.cpu.checkstop.is_none() {
+	scheduler::run_slice(gc, dsp);
     }
 }
 
+//like run, but hands every field the VI starts scanning out to on_field, stopping once it returns false.
+//with a reference system the cpu is checked against the interpreter the way run_lockstep does
+pub fn run_fields(
+    gc: &mut Gamecube,
+    dsp: &mut DSP,
+    mut reference: Option<(&mut Gamecube, &mut DSP)>,
+    mut on_field: impl FnMut(&Gamecube) -> bool,
+) -> Result<(), scheduler::Divergence> {
+    let mut fields = gc.vi.fields;
+    while gc.cpu.checkstop.is_none() {
+	match reference {
+	    Some((ref mut reference, ref mut reference_dsp)) => scheduler::run_lockstep_slice(gc, dsp, reference, reference_dsp)?,
+	    None => scheduler::run_slice(gc, dsp),
+	}
+	if gc.vi.fields != fields {
+	    fields = gc.vi.fields;
+	    if !on_field(gc) {
+		break;
+	    }
+	}
+    }
+    Ok(())
+}
+
+//for checking the block cache or the jit, reference should be a second system booted from the same bios
+pub fn run_lockstep(gc: &mut Gamecube, dsp: &mut DSP, reference: &mut Gamecube, reference_dsp: &mut DSP) -> Result<(), scheduler::Divergence> {
+    while gc.cpu.checkstop.is_none() {
+	scheduler::run_lockstep_slice(gc, dsp, reference, reference_dsp)?;
+    }
+    Ok(())
+}
+
+//a system with an empty bios and a fresh dsp client, for tests that poke at hardware directly
+#[cfg(test)]
+pub(crate) fn test_gamecube() -> Gamecube {
+    let aram = Arc::new(std::iter::repeat_with(|| AtomicU8::new(0)).take(0x0100_0000).collect::<Vec<_>>());
+    Gamecube::new(vec![0; 0x20_0000], aram, DSPClient::new())
+}
+
 //translated from dolphin :3
 //https://github.com/dolphin-emu/dolphin/blob/master/Source/Core/Core/HW/EXI/EXI_DeviceIPL.cpp
 // bootrom descrambler reversed by segher
@@ -1,10 +1,28 @@
-use std::{env, fs::File, io::{stdout, Read}, sync::{atomic::AtomicU8, Arc, RwLock}, time::SystemTime};
+use std::{env, fs::{self, File}, io::{stdout, BufWriter, Read}, path::Path, process, sync::{atomic::AtomicU8, Arc}, time::SystemTime};
 
-use crude::{dsp::DSP, Gamecube};
+use crude::{cpu::CpuEngine, dsp::DSP, frame_dump::{self, Y4mWriter}, scheduler::Divergence, video_interface::{self, FORMAT_PAL}, Gamecube};
 use fern::Dispatch;
-use log::LevelFilter;
+use log::{error, warn, LevelFilter};
 
 fn main() {
+    let mut args = env::args().skip(1);
+    let bios_path = args.next().unwrap();
+    let flags = args.collect::<Vec<_>>();
+    let flag_value = |name: &str| flags.iter().find_map(|flag| flag.strip_prefix(name)?.strip_prefix('='));
+
+    let dump_png = flag_value("--dump-png");
+    let dump_y4m = flag_value("--dump-y4m");
+    let hash_frames = flags.iter().any(|flag| flag == "--hash-frames");
+    let max_fields = flag_value("--frames").map(|val| match val.parse::<u64>() {
+	Ok(max) => max,
+	Err(_) => {
+	    //logging isn't set up yet
+	    eprintln!("--frames takes a number of fields, not {val:?}");
+	    process::exit(2);
+	}
+    });
+    let headless = dump_png.is_some() || dump_y4m.is_some() || hash_frames || max_fields.is_some();
+
     Dispatch::new()
         .format(|out, message, record| {
 	    out.finish(format_args!(
@@ -15,17 +33,100 @@ fn main() {
 		message
 	    ))
 	})
-        .level(LevelFilter::Debug)
+        //headless runs are for ci, where the debug log would bury the hashes
+        .level(if headless { LevelFilter::Warn } else { LevelFilter::Debug })
         .chain(stdout())
         .apply().unwrap();
-    let bios_path = env::args().nth(1).unwrap();
     let mut bios_data = Vec::new();
     File::open(bios_path).unwrap().read_to_end(&mut bios_data).unwrap();
     let aram = Arc::new(std::iter::repeat_with(|| AtomicU8::new(0)).take(0x0100_0000).collect::<Vec<_>>());
     let (mut dsp, client) = DSP::new(aram.clone());
-    let mut gamecube = Gamecube::new(bios_data, aram, client);
-    loop {
-	dsp.step();
-	crude::step(&mut gamecube);
+    let mut gamecube = Gamecube::new(bios_data.clone(), aram, client);
+
+    if flags.iter().any(|flag| flag == "--interpreter") {
+	gamecube.cpu.engine = CpuEngine::Interpreter;
+    }
+
+    //keeps stores in the data cache until they're flushed, for chasing down dma coherency bugs
+    if flags.iter().any(|flag| flag == "--dcache") {
+	gamecube.cpu.dcache.modelled = true;
+    }
+
+    #[cfg(all(feature = "jit", target_arch = "x86_64", unix))]
+    if flags.iter().any(|flag| flag == "--jit") {
+	gamecube.cpu.engine = CpuEngine::Jit;
+    }
+
+    //a second system on the interpreter, checked against the first after every block
+    let mut reference = flags.iter().any(|flag| flag == "--lockstep").then(|| {
+	let reference_aram = Arc::new(std::iter::repeat_with(|| AtomicU8::new(0)).take(0x0100_0000).collect::<Vec<_>>());
+	let (reference_dsp, reference_client) = DSP::new(reference_aram.clone());
+	(Gamecube::new(bios_data, reference_aram, reference_client), reference_dsp)
+    });
+
+    let result = if headless {
+	let reference = reference.as_mut().map(|(gc, dsp)| (gc, dsp));
+	run_headless(&mut gamecube, &mut dsp, reference, dump_png, dump_y4m, hash_frames, max_fields)
+    } else if let Some((reference, reference_dsp)) = &mut reference {
+	crude::run_lockstep(&mut gamecube, &mut dsp, reference, reference_dsp)
+    } else {
+	crude::run(&mut gamecube, &mut dsp);
+	Ok(())
+    };
+
+    if let Err(divergence) = result {
+	error!("{divergence}");
+	process::exit(1);
     }
+
+    if let Some(addr) = gamecube.cpu.checkstop {
+	error!("the cpu checkstopped at {addr:#010X}, a machine check came in with MSR[ME] clear");
+	process::exit(1);
+    }
+}
+
+//no window, every field the VI scans out goes to png files, a y4m stream and/or a hash on stdout
+fn run_headless(
+    gamecube: &mut Gamecube,
+    dsp: &mut DSP,
+    reference: Option<(&mut Gamecube, &mut DSP)>,
+    dump_png: Option<&str>,
+    dump_y4m: Option<&str>,
+    hash_frames: bool,
+    max_fields: Option<u64>,
+) -> Result<(), Divergence> {
+    if let Some(dir) = dump_png {
+	fs::create_dir_all(dir).unwrap();
+    }
+
+    let mut y4m = None;
+    let mut field = 0;
+
+    crude::run_fields(gamecube, dsp, reference, |gc| {
+	let frame = video_interface::frame(gc);
+	field += 1;
+
+	if hash_frames {
+	    println!("field {field} {}x{} {:08x}", frame.width, frame.height, frame_dump::frame_hash(&frame));
+	}
+
+	//nothing to look at until the VI has been set up
+	if frame.width != 0 && frame.height != 0 {
+	    if let Some(dir) = dump_png {
+		fs::write(Path::new(dir).join(format!("field_{field:06}.png")), frame_dump::png(&frame)).unwrap();
+	    }
+	    if let Some(path) = dump_y4m {
+		//the ipl picks the video format, so the field rate isn't known until there's a picture
+		let y4m = y4m.get_or_insert_with(|| {
+		    let rate = if gc.vi.format() == FORMAT_PAL { (50, 1) } else { (60000, 1001) };
+		    Y4mWriter::new(BufWriter::new(File::create(path).unwrap()), rate)
+		});
+		if !y4m.write(&frame).unwrap() {
+		    warn!("field {field} is {}x{}, which doesn't match the rest of the y4m", frame.width, frame.height);
+		}
+	    }
+	}
+
+	max_fields.is_none_or(|max| field < max)
+    })
 }
@@ -1,6 +1,6 @@
 use log::debug;
 
-use crate::Gamecube;
+use crate::{bus::mmio::{merge, Mmio, MmioHandlers}, Gamecube};
 
 pub struct MemoryInterface {
     pub mask_ints: u16,
@@ -14,11 +14,25 @@ impl MemoryInterface {
     }
 }
 
-pub fn mi_write_u16(gc: &mut Gamecube, offset: u32, val: u16) {
+impl Default for MemoryInterface {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+pub fn register_mmio(mmio: &mut Mmio) {
+    mmio.register("MI", 0x0C00_4000, 0x1000, MmioHandlers {
+	write_u16: Some(mi_write_u16),
+	..MmioHandlers::NONE
+    });
+}
+
+pub fn mi_write_u16(gc: &mut Gamecube, offset: u32, val: u16, mask: u16) -> Option<()> {
     match offset {
-	0x1C => gc.mi.mask_ints = val,
+	0x1C => gc.mi.mask_ints = merge(gc.mi.mask_ints, val, mask),
 	0x26 => {}, //ipl tries to set this, i couldn't find anything documenting what it means...
-	_ => unimplemented!("MI write_u16 at offset {offset:#010X} with val {val:#06X}")
+	_ => return None,
     }
     debug!("STUB: MI  write_u16 at offset {offset:#010X} with val {val:#06X}");
+    Some(())
 }
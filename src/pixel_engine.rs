@@ -0,0 +1,169 @@
+use log::debug;
+
+use crate::{bus::mmio::{merge, Mmio, MmioHandlers}, processor_interface::{set_interrupt, PI_INT_PE_FINISH, PI_INT_PE_TOKEN}, Gamecube};
+
+//interrupt control, the enables sit below their status bits
+const PE_CTRL_TOKEN_ENABLE: u16 = 1 << 0;
+const PE_CTRL_FINISH_ENABLE: u16 = 1 << 1;
+const PE_CTRL_TOKEN: u16 = 1 << 2;
+const PE_CTRL_FINISH: u16 = 1 << 3;
+
+//what peeks give back for alpha
+const ALPHA_READ_00: u16 = 0;
+const ALPHA_READ_FF: u16 = 1;
+
+//BP registers the pe watches for
+pub const BP_PE_DONE: u32 = 0x45;
+pub const BP_PE_TOKEN: u32 = 0x47;
+pub const BP_PE_TOKEN_INT: u32 = 0x48;
+
+//the cpu's side of the pixel engine. the gpu side is set up through BP registers instead, these only
+//cover how cpu accesses to the efb behave and the draw sync interrupts
+pub struct PixelEngine {
+    pub z_conf: u16,
+    pub alpha_conf: u16,
+    pub dst_alpha: u16,
+    pub alpha_mode: u16,
+    pub alpha_read: u16,
+    pub control: u16,
+    pub token: u16,
+}
+
+impl PixelEngine {
+    pub fn new() -> Self {
+	Self {
+	    z_conf: 0,
+	    alpha_conf: 0,
+	    dst_alpha: 0,
+	    alpha_mode: 0,
+	    alpha_read: 0,
+	    control: 0,
+	    token: 0,
+	}
+    }
+
+    //peeks return the efb's alpha, or a constant in its place
+    pub fn peek_color(&self, color: u32) -> u32 {
+	match self.alpha_read & 0b11 {
+	    ALPHA_READ_00 => color & 0x00FF_FFFF,
+	    ALPHA_READ_FF => color | 0xFF00_0000,
+	    _ => color,
+	}
+    }
+}
+
+impl Default for PixelEngine {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+pub fn register_mmio(mmio: &mut Mmio) {
+    mmio.register("PE", 0x0C00_1000, 0x1000, MmioHandlers {
+	read_u16: Some(pe_read_u16),
+	write_u16: Some(pe_write_u16),
+	..MmioHandlers::NONE
+    });
+}
+
+pub fn pe_write_u16(gc: &mut Gamecube, offset: u32, val: u16, mask: u16) -> Option<()> {
+    debug!("PE write_u16 at offset {offset:#010X} with val {val:#06X}");
+    match offset {
+	0x00 => gc.pe.z_conf = merge(gc.pe.z_conf, val, mask),
+	0x02 => gc.pe.alpha_conf = merge(gc.pe.alpha_conf, val, mask),
+	0x04 => gc.pe.dst_alpha = merge(gc.pe.dst_alpha, val, mask),
+	0x06 => gc.pe.alpha_mode = merge(gc.pe.alpha_mode, val, mask),
+	0x08 => gc.pe.alpha_read = merge(gc.pe.alpha_read, val, mask),
+	0x0A => {
+	    //the status bits are write 1 to clear
+	    let status = gc.pe.control & (PE_CTRL_TOKEN | PE_CTRL_FINISH) & !(val & mask);
+	    let enables = merge(gc.pe.control, val, mask) & (PE_CTRL_TOKEN_ENABLE | PE_CTRL_FINISH_ENABLE);
+	    gc.pe.control = enables | status;
+	    update_interrupts(gc);
+	},
+	_ => debug!("STUB: PE write_u16 at offset {offset:#010X} with val {val:#06X}"),
+    }
+    Some(())
+}
+
+pub fn pe_read_u16(gc: &mut Gamecube, offset: u32) -> Option<u16> {
+    debug!("PE read_u16 at offset {offset:#010X}");
+    Some(match offset {
+	0x00 => gc.pe.z_conf,
+	0x02 => gc.pe.alpha_conf,
+	0x04 => gc.pe.dst_alpha,
+	0x06 => gc.pe.alpha_mode,
+	0x08 => gc.pe.alpha_read,
+	0x0A => gc.pe.control,
+	0x0E => gc.pe.token,
+	_ => {
+	    debug!("STUB: PE read_u16 at offset {offset:#010X}");
+	    0
+	},
+    })
+}
+
+//the gpu reaching a GXSetDrawSync token. only the BP_PE_TOKEN_INT flavour raises the interrupt
+pub fn set_token(gc: &mut Gamecube, token: u16, interrupt: bool) {
+    gc.pe.token = token;
+    if interrupt {
+	gc.pe.control |= PE_CTRL_TOKEN;
+	update_interrupts(gc);
+    }
+}
+
+//the gpu reaching the end of a GXDrawDone
+pub fn set_finish(gc: &mut Gamecube) {
+    gc.pe.control |= PE_CTRL_FINISH;
+    update_interrupts(gc);
+}
+
+fn update_interrupts(gc: &mut Gamecube) {
+    let control = gc.pe.control;
+    set_interrupt(gc, PI_INT_PE_TOKEN, control & PE_CTRL_TOKEN != 0 && control & PE_CTRL_TOKEN_ENABLE != 0);
+    set_interrupt(gc, PI_INT_PE_FINISH, control & PE_CTRL_FINISH != 0 && control & PE_CTRL_FINISH_ENABLE != 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{processor_interface::{PI_INT_PE_FINISH, PI_INT_PE_TOKEN}, test_gamecube};
+
+    use super::{pe_read_u16, pe_write_u16, set_finish, set_token, PE_CTRL_FINISH, PE_CTRL_FINISH_ENABLE, PE_CTRL_TOKEN, PE_CTRL_TOKEN_ENABLE};
+
+    #[test]
+    fn tokens_only_interrupt_when_asked_to_and_enabled() {
+	let mut gc = test_gamecube();
+
+	//a plain token just updates the register
+	set_token(&mut gc, 0x1234, false);
+	assert_eq!(pe_read_u16(&mut gc, 0x0E), Some(0x1234));
+	assert_eq!(gc.pi.intsr, 0);
+
+	//the interrupt flavour latches the status bit, which only reaches the PI once it's enabled
+	set_token(&mut gc, 0x5678, true);
+	assert_eq!(pe_read_u16(&mut gc, 0x0A), Some(PE_CTRL_TOKEN));
+	assert_eq!(gc.pi.intsr, 0);
+	pe_write_u16(&mut gc, 0x0A, PE_CTRL_TOKEN_ENABLE, !0);
+	assert_eq!(gc.pi.intsr, PI_INT_PE_TOKEN);
+
+	//writing the status bit back acknowledges it and leaves the enable alone
+	pe_write_u16(&mut gc, 0x0A, PE_CTRL_TOKEN_ENABLE | PE_CTRL_TOKEN, !0);
+	assert_eq!(pe_read_u16(&mut gc, 0x0A), Some(PE_CTRL_TOKEN_ENABLE));
+	assert_eq!(gc.pi.intsr, 0);
+    }
+
+    #[test]
+    fn finish_interrupts_are_acknowledged_separately() {
+	let mut gc = test_gamecube();
+	pe_write_u16(&mut gc, 0x0A, PE_CTRL_TOKEN_ENABLE | PE_CTRL_FINISH_ENABLE, !0);
+
+	set_finish(&mut gc);
+	set_token(&mut gc, 1, true);
+	assert_eq!(gc.pi.intsr, PI_INT_PE_TOKEN | PI_INT_PE_FINISH);
+
+	//writing 0 to a status bit doesn't clear it
+	pe_write_u16(&mut gc, 0x0A, PE_CTRL_TOKEN_ENABLE | PE_CTRL_FINISH_ENABLE | PE_CTRL_FINISH, !0);
+	assert_eq!(gc.pi.intsr, PI_INT_PE_TOKEN);
+	assert_eq!(gc.pe.control & (PE_CTRL_TOKEN | PE_CTRL_FINISH), PE_CTRL_TOKEN);
+    }
+}
@@ -1,22 +1,181 @@
 use log::debug;
 
-use crate::Gamecube;
+use crate::{bus::mmio::{merge, Mmio, MmioHandlers}, command_processor, cpu::EXTERNAL_EXCEPTION, Gamecube};
+
+//interrupt sources, the same bit positions in INTSR and INTMR
+pub const PI_INT_ERROR: u32 = 1 << 0;
+pub const PI_INT_RSW: u32 = 1 << 1;
+pub const PI_INT_DI: u32 = 1 << 2;
+pub const PI_INT_SI: u32 = 1 << 3;
+pub const PI_INT_EXI: u32 = 1 << 4;
+pub const PI_INT_AI: u32 = 1 << 5;
+pub const PI_INT_DSP: u32 = 1 << 6;
+pub const PI_INT_MEM: u32 = 1 << 7;
+pub const PI_INT_VI: u32 = 1 << 8;
+pub const PI_INT_PE_TOKEN: u32 = 1 << 9;
+pub const PI_INT_PE_FINISH: u32 = 1 << 10;
+pub const PI_INT_CP: u32 = 1 << 11;
+pub const PI_INT_DEBUG: u32 = 1 << 12;
+pub const PI_INT_HSP: u32 = 1 << 13;
+//not an interrupt, reads back as set while the reset button is *not* held
+const PI_RSWST: u32 = 1 << 16;
+
+//flipper revision C, which is what retail units shipped with
+const FLIPPER_REVISION: u32 = 0x2465_00B1;
+
+//bit 26 of the write pointer flags that it wrapped back around to the base
+pub const FIFO_WRAP: u32 = 1 << 26;
+const FIFO_ADDR_MASK: u32 = 0x03FF_FFE0;
+pub const FIFO_BURST_SIZE: usize = 0x20;
 
 pub struct ProcessorInterface {
-    
+    pub intsr: u32,
+    pub intmr: u32,
+    pub fifo_base: u32,
+    pub fifo_end: u32,
+    pub fifo_wptr: u32,
+    pub reset_code: u32,
 }
 
 impl ProcessorInterface {
     pub fn new() -> Self {
-        Self {  }
+	Self {
+	    intsr: 0,
+	    intmr: 0,
+	    fifo_base: 0,
+	    fifo_end: 0,
+	    fifo_wptr: 0,
+	    reset_code: 0,
+	}
     }
 }
 
-pub fn pi_write_u32(gc: &mut Gamecube, offset: u32, val: u32) {
-    debug!("STUB: PI write_u32 at offset {offset:#010X} with val {val:#06X}");
+impl Default for ProcessorInterface {
+    fn default() -> Self {
+	Self::new()
+    }
 }
 
-pub fn pi_read_u32(gc: &mut Gamecube, offset: u32) -> u32 {
-    debug!("STUB: PI read_u32 at offset {offset:#010X}");
-    0
+pub fn register_mmio(mmio: &mut Mmio) {
+    mmio.register("PI", 0x0C00_3000, 0x1000, MmioHandlers {
+	read_u32: Some(pi_read_u32),
+	write_u32: Some(pi_write_u32),
+	..MmioHandlers::NONE
+    });
+}
+
+//the gather pipe's bursts land in ram at the write pointer, which goes back to the base after the
+//burst at the end has been written. the cp picks them up from there if it's linked
+pub fn fifo_write(gc: &mut Gamecube, burst: &[u8; FIFO_BURST_SIZE]) {
+    let wptr = gc.pi.fifo_wptr & FIFO_ADDR_MASK;
+    if let Some(dst) = gc.memory.get_mut((wptr as usize)..(wptr as usize + FIFO_BURST_SIZE)) {
+	dst.copy_from_slice(burst);
+	gc.cpu.decode_cache.invalidate_range(wptr, FIFO_BURST_SIZE);
+    }
+
+    gc.pi.fifo_wptr = if wptr == gc.pi.fifo_end {
+	gc.pi.fifo_base | FIFO_WRAP
+    } else {
+	(gc.pi.fifo_wptr & FIFO_WRAP) | (wptr + FIFO_BURST_SIZE as u32)
+    };
+
+    command_processor::gather_pipe_burst(gc);
+}
+
+//devices call this to raise or drop their interrupt line, the cpu sees a level triggered external exception
+//for as long as any unmasked line is up
+pub fn set_interrupt(gc: &mut Gamecube, source: u32, active: bool) {
+    if active {
+	gc.pi.intsr |= source;
+    } else {
+	gc.pi.intsr &= !source;
+    }
+
+    update_interrupts(gc);
+}
+
+fn update_interrupts(gc: &mut Gamecube) {
+    if gc.pi.intsr & gc.pi.intmr != 0 {
+	gc.cpu.exceptions |= EXTERNAL_EXCEPTION;
+    } else {
+	gc.cpu.exceptions &= !EXTERNAL_EXCEPTION;
+    }
+}
+
+pub fn pi_write_u32(gc: &mut Gamecube, offset: u32, val: u32, mask: u32) -> Option<()> {
+    match offset {
+	//only the error and reset switch causes belong to the PI, everything else gets acknowledged at its device
+	0x00 => {
+	    gc.pi.intsr &= !(val & mask & (PI_INT_ERROR | PI_INT_RSW));
+	    update_interrupts(gc);
+	},
+	0x04 => {
+	    gc.pi.intmr = merge(gc.pi.intmr, val, mask);
+	    update_interrupts(gc);
+	},
+	0x0C => gc.pi.fifo_base = merge(gc.pi.fifo_base, val, mask) & FIFO_ADDR_MASK,
+	0x10 => gc.pi.fifo_end = merge(gc.pi.fifo_end, val, mask) & FIFO_ADDR_MASK,
+	0x14 => gc.pi.fifo_wptr = merge(gc.pi.fifo_wptr, val, mask) & (FIFO_ADDR_MASK | FIFO_WRAP),
+	0x24 => gc.pi.reset_code = merge(gc.pi.reset_code, val, mask),
+	_ => debug!("STUB: PI write_u32 at offset {offset:#010X} with val {val:#010X}"),
+    }
+    Some(())
+}
+
+pub fn pi_read_u32(gc: &mut Gamecube, offset: u32) -> Option<u32> {
+    Some(match offset {
+	0x00 => gc.pi.intsr | PI_RSWST,
+	0x04 => gc.pi.intmr,
+	0x0C => gc.pi.fifo_base,
+	0x10 => gc.pi.fifo_end,
+	0x14 => gc.pi.fifo_wptr,
+	0x24 => gc.pi.reset_code,
+	0x2C => FLIPPER_REVISION,
+	_ => {
+	    debug!("STUB: PI read_u32 at offset {offset:#010X}");
+	    0
+	},
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cpu::EXTERNAL_EXCEPTION, test_gamecube};
+
+    use super::{pi_read_u32, pi_write_u32, set_interrupt, PI_INT_DI, PI_INT_RSW, PI_INT_VI, PI_RSWST};
+
+    #[test]
+    fn only_unmasked_causes_reach_the_cpu() {
+	let mut gc = test_gamecube();
+
+	set_interrupt(&mut gc, PI_INT_DI, true);
+	assert_eq!(pi_read_u32(&mut gc, 0x00), Some(PI_INT_DI | PI_RSWST));
+	assert_eq!(gc.cpu.exceptions, 0);
+
+	//unmasking a cause that's already up interrupts straight away
+	pi_write_u32(&mut gc, 0x04, PI_INT_DI | PI_INT_VI, !0);
+	assert_eq!(gc.cpu.exceptions, EXTERNAL_EXCEPTION);
+
+	//and masking it again takes it back
+	pi_write_u32(&mut gc, 0x04, PI_INT_VI, !0);
+	assert_eq!(gc.cpu.exceptions, 0);
+
+	set_interrupt(&mut gc, PI_INT_VI, true);
+	assert_eq!(gc.cpu.exceptions, EXTERNAL_EXCEPTION);
+	set_interrupt(&mut gc, PI_INT_VI, false);
+	assert_eq!(gc.cpu.exceptions, 0);
+    }
+
+    #[test]
+    fn intsr_writes_only_acknowledge_the_pi_causes() {
+	let mut gc = test_gamecube();
+	pi_write_u32(&mut gc, 0x04, PI_INT_DI | PI_INT_RSW, !0);
+	set_interrupt(&mut gc, PI_INT_DI, true);
+	set_interrupt(&mut gc, PI_INT_RSW, true);
+
+	//DI has to be acknowledged at the DI, the reset switch goes here
+	pi_write_u32(&mut gc, 0x00, PI_INT_DI | PI_INT_RSW, !0);
+	assert_eq!(gc.pi.intsr, PI_INT_DI);
+	assert_eq!(gc.cpu.exceptions, EXTERNAL_EXCEPTION);
+    }
 }
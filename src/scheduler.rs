@@ -0,0 +1,197 @@
+use std::{cmp::Ordering, collections::BinaryHeap, fmt};
+
+use crate::{cpu::{self, CpuEngine}, dsp::DSP, Gamecube};
+
+pub const CPU_CLOCK: u64 = 486_000_000;
+//the dsp runs at 81MHz against the cpu's 486MHz
+pub const CPU_CYCLES_PER_DSP_CYCLE: u64 = 6;
+//upper bound on how long the cpu runs without the rest of the system getting a look in
+pub const MAX_SLICE_CYCLES: u64 = 20_000;
+
+//identifies an event so the module that scheduled it can find it again to cancel it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EventKind {
+    ExiTransfer(usize),
+    AramDma,
+    LockedCacheDma,
+    ViLine,
+    AiDma,
+    AiInterrupt,
+}
+
+pub type EventCallback = fn(&mut Gamecube, EventKind);
+
+struct Event {
+    //absolute cpu cycle the event fires on
+    when: u64,
+    //breaks ties so events scheduled for the same cycle fire in the order they were scheduled
+    seq: u64,
+    kind: EventKind,
+    callback: EventCallback,
+}
+
+impl PartialEq for Event {
+    fn eq(&self, other: &Self) -> bool {
+	self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Event {}
+
+impl PartialOrd for Event {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+	Some(self.cmp(other))
+    }
+}
+
+impl Ord for Event {
+    //BinaryHeap is a max heap, so this is backwards to pop the earliest event first
+    fn cmp(&self, other: &Self) -> Ordering {
+	(other.when, other.seq).cmp(&(self.when, self.seq))
+    }
+}
+
+pub struct Scheduler {
+    events: BinaryHeap<Event>,
+    next_seq: u64,
+    //dsp cycles run so far, so it can be kept in line with the cpu cycle count
+    dsp_cycles: u64,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+	Self {
+	    events: BinaryHeap::new(),
+	    next_seq: 0,
+	    dsp_cycles: 0,
+	}
+    }
+
+    //at is an absolute cpu cycle count, see schedule_in for the relative version
+    pub fn schedule_at(&mut self, at: u64, kind: EventKind, callback: EventCallback) {
+	self.events.push(Event {
+	    when: at,
+	    seq: self.next_seq,
+	    kind,
+	    callback,
+	});
+	self.next_seq += 1;
+    }
+
+    pub fn cancel(&mut self, kind: EventKind) {
+	self.events.retain(|event| event.kind != kind);
+    }
+
+    pub fn is_scheduled(&self, kind: EventKind) -> bool {
+	self.events.iter().any(|event| event.kind == kind)
+    }
+
+    pub fn next_deadline(&self) -> Option<u64> {
+	self.events.peek().map(|event| event.when)
+    }
+
+    fn pop_due(&mut self, now: u64) -> Option<Event> {
+	if self.events.peek()?.when <= now {
+	    self.events.pop()
+	} else {
+	    None
+	}
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+pub fn schedule_in(gc: &mut Gamecube, cycles: u64, kind: EventKind, callback: EventCallback) {
+    let at = gc.cpu.cycles + cycles;
+    gc.scheduler.schedule_at(at, kind, callback);
+}
+
+//runs the cpu up to the next event (or for a whole slice if nothing is due sooner), then lets everything else catch up
+pub fn run_slice(gc: &mut Gamecube, dsp: &mut DSP) {
+    let limit = gc.cpu.cycles + MAX_SLICE_CYCLES;
+
+    //a block can schedule something sooner than what was due when the slice started, so the deadline is
+    //looked at again after every one
+    loop {
+	let slice_end = gc.scheduler.next_deadline().map_or(limit, |when| when.min(limit));
+	if gc.cpu.cycles >= slice_end {
+	    break;
+	}
+	cpu::run_block(gc, slice_end);
+    }
+
+    catch_up(gc, dsp);
+}
+
+//where a lockstep run first saw the engine under test disagree with the interpreter
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    pub engine: CpuEngine,
+    pub block_start: u32,
+    pub pc: u32,
+    //register name, then its value under engine and under the interpreter
+    pub registers: Vec<(String, u64, u64)>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	write!(f, "{:?} block at {:#010X} diverged from the interpreter, pc {:#010X}", self.engine, self.block_start, self.pc)?;
+	for (name, subject, reference) in &self.registers {
+	    write!(f, "\n    {name}: {subject:#X} (interpreter {reference:#X})")?;
+	}
+	Ok(())
+    }
+}
+
+impl std::error::Error for Divergence {}
+
+//runs gc a block at a time on its engine, keeping reference on the interpreter right behind it
+//and stopping the moment the two cpus disagree
+pub fn run_lockstep_slice(gc: &mut Gamecube, dsp: &mut DSP, reference: &mut Gamecube, reference_dsp: &mut DSP) -> Result<(), Divergence> {
+    let limit = gc.cpu.cycles + MAX_SLICE_CYCLES;
+
+    loop {
+	let slice_end = gc.scheduler.next_deadline().map_or(limit, |when| when.min(limit));
+	if gc.cpu.cycles >= slice_end {
+	    break;
+	}
+
+	let block_start = gc.cpu.cia;
+	cpu::run_block(gc, slice_end);
+
+	while reference.cpu.cycles < gc.cpu.cycles {
+	    cpu::step(reference);
+	}
+
+	let registers = gc.cpu.snapshot().diff(&reference.cpu.snapshot());
+	if !registers.is_empty() {
+	    return Err(Divergence { engine: gc.cpu.engine, block_start, pc: gc.cpu.cia, registers });
+	}
+    }
+
+    catch_up(gc, dsp);
+    catch_up(reference, reference_dsp);
+    Ok(())
+}
+
+//a single cpu instruction, for debuggers that need to stop on an exact address
+pub fn step(gc: &mut Gamecube, dsp: &mut DSP) {
+    cpu::step(gc);
+    catch_up(gc, dsp);
+}
+
+fn catch_up(gc: &mut Gamecube, dsp: &mut DSP) {
+    let dsp_target = gc.cpu.cycles / CPU_CYCLES_PER_DSP_CYCLE;
+    while gc.scheduler.dsp_cycles < dsp_target {
+	dsp.step();
+	gc.scheduler.dsp_cycles += 1;
+    }
+
+    while let Some(event) = gc.scheduler.pop_due(gc.cpu.cycles) {
+	(event.callback)(gc, event.kind);
+    }
+}
@@ -1,6 +1,6 @@
 use log::debug;
 
-use crate::Gamecube;
+use crate::{bus::mmio::{merge, Mmio, MmioHandlers}, Gamecube};
 
 pub struct SerialInterface {
     clock_lock: u32,
@@ -14,18 +14,32 @@ impl SerialInterface {
     }
 }
 
-pub fn si_read_u32(gc: &mut Gamecube, offset: u32) -> u32 {
+impl Default for SerialInterface {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+pub fn register_mmio(mmio: &mut Mmio) {
+    mmio.register("SI", 0x0C00_6400, 0x400, MmioHandlers {
+	read_u32: Some(si_read_u32),
+	write_u32: Some(si_write_u32),
+	..MmioHandlers::NONE
+    });
+}
+
+pub fn si_read_u32(gc: &mut Gamecube, offset: u32) -> Option<u32> {
     debug!("STUB: SI read_u32 at offset {offset:#010X}");
-    match offset {
+    Some(match offset {
 	0x3C => gc.si.clock_lock,
 	_ => 0,
-    }
+    })
 }
 
-pub fn si_write_u32(gc: &mut Gamecube, offset: u32, val: u32) {
-    match offset {
-	0x3C => gc.si.clock_lock = val,
-	_ => {},
+pub fn si_write_u32(gc: &mut Gamecube, offset: u32, val: u32, mask: u32) -> Option<()> {
+    if offset == 0x3C {
+	gc.si.clock_lock = merge(gc.si.clock_lock, val, mask);
     }
     debug!("STUB: SI write_u32 at offset {offset:#010X} with val {val:#010X}");
+    Some(())
 }
@@ -23,7 +23,7 @@ impl Sram {
 		ntd: 0,
 		language: 0,
 		flags: 0x20,
-		card_flash_id: [[b'D', b'O', b'L', b'P', b'H', b'I', b'N', b'S', b'L', b'O', b'T', b'A'], [b'D', b'O', b'L', b'P', b'H', b'I', b'N', b'S', b'L', b'O', b'T', b'B']],
+		card_flash_id: [*b"DOLPHINSLOTA", *b"DOLPHINSLOTB"],
 		wireless_kbd_id: 0,
 		wireless_pad_id: [0; 4],
 		di_error_code: 0,
@@ -60,6 +60,12 @@ impl Sram {
     }
 }
 
+impl Default for Sram {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
 impl Drop for Sram {
     fn drop(&mut self) {
         unsafe {
@@ -1,20 +1,392 @@
-use crate::Gamecube;
+use log::debug;
+
+use crate::{bus::mmio::{merge, Mmio, MmioHandlers}, processor_interface::{set_interrupt, PI_INT_VI}, scheduler::{self, EventKind, CPU_CLOCK}, Gamecube};
+
+//display control
+const DCR_RESET: u16 = 1 << 1;
+const DCR_NON_INTERLACED: u16 = 1 << 2;
+const DCR_FORMAT_SHIFT: u16 = 8;
+
+//video formats, in DCR
+pub const FORMAT_NTSC: u16 = 0;
+pub const FORMAT_PAL: u16 = 1;
+pub const FORMAT_MPAL: u16 = 2;
+
+//display interrupts, the status bit is cleared by writing it back as 0
+const DI_ENABLE: u32 = 1 << 28;
+const DI_STATUS: u32 = 1 << 31;
+
+//framebuffer addresses are in 32 byte units with this set, in bytes without it
+const FB_PAGE_OFFSET: u32 = 1 << 28;
+
+//the VI clock, picked by VICLK. 54MHz is only for progressive scan
+const VI_CLOCK: [u64; 2] = [27_000_000, 54_000_000];
+
+//what the registers describe before the ipl sets anything up, so the beam still moves
+const DEFAULT_HALF_LINE_WIDTH: u64 = 429;
+const DEFAULT_NTSC_LINES: u32 = 525;
+const DEFAULT_PAL_LINES: u32 = 625;
 
 pub struct VideoInterface {
-    
+    pub vtr: u16,
+    pub dcr: u16,
+    pub htr0: u32,
+    pub htr1: u32,
+    pub vto: u32,
+    pub vte: u32,
+    pub bbei: u32,
+    pub bboi: u32,
+    pub tfbl: u32,
+    pub tfbr: u32,
+    pub bfbl: u32,
+    pub bfbr: u32,
+    pub di: [u32; 4],
+    pub dl: [u32; 2],
+    pub hsw: u16,
+    pub hsr: u16,
+    pub fct: [u32; 7],
+    pub viclk: u16,
+    pub visel: u16,
+    pub hbe: u16,
+    pub hbs: u16,
+    //the line being scanned out, counted from 1 like the hardware's DPV
+    pub vct: u32,
+    //fields scanned out so far, so frontends know when there's a new one to show
+    pub fields: u64,
+    //cpu cycle the current line started on, for working out DPH
+    line_start: u64,
 }
 
 impl VideoInterface {
     pub fn new() -> Self {
 	Self {
-	    
+	    vtr: 0,
+	    dcr: 0,
+	    htr0: 0,
+	    htr1: 0,
+	    vto: 0,
+	    vte: 0,
+	    bbei: 0,
+	    bboi: 0,
+	    tfbl: 0,
+	    tfbr: 0,
+	    bfbl: 0,
+	    bfbr: 0,
+	    di: [0; 4],
+	    dl: [0; 2],
+	    hsw: 0,
+	    hsr: 0,
+	    fct: [0; 7],
+	    viclk: 0,
+	    visel: 0,
+	    hbe: 0,
+	    hbs: 0,
+	    vct: 1,
+	    fields: 0,
+	    line_start: 0,
 	}
     }
+
+    pub fn format(&self) -> u16 {
+	(self.dcr >> DCR_FORMAT_SHIFT) & 0b11
+    }
+
+    pub fn interlaced(&self) -> bool {
+	self.dcr & DCR_NON_INTERLACED == 0
+    }
+
+    //cpu cycles per VI sample, two samples to a pixel clock
+    fn cycles_per_sample(&self) -> u64 {
+	2 * CPU_CLOCK / VI_CLOCK[(self.viclk & 1) as usize]
+    }
+
+    fn cycles_per_line(&self) -> u64 {
+	let half_line_width = match u64::from(self.htr0 & 0x1FF) {
+	    0 => DEFAULT_HALF_LINE_WIDTH,
+	    width => width,
+	};
+	half_line_width * 2 * self.cycles_per_sample()
+    }
+
+    //half lines in a field: the equalization pulses, the blanking before and after, and the active video
+    fn field_half_lines(&self, vt: u32) -> u32 {
+	let equ = u32::from(self.vtr & 0xF);
+	let acv = u32::from((self.vtr >> 4) & 0x3FF);
+	let (prb, psb) = (vt & 0x3FF, (vt >> 16) & 0x3FF);
+	3 * equ + prb + 2 * acv + psb
+    }
+
+    //lines before vct wraps back round to 1. that's both fields when interlaced, one when not
+    pub fn lines(&self) -> u32 {
+	let (odd, even) = (self.field_half_lines(self.vto), self.field_half_lines(self.vte));
+	let lines = if self.interlaced() { (odd + even) / 2 } else { odd / 2 };
+
+	match lines {
+	    0 if self.format() == FORMAT_PAL => DEFAULT_PAL_LINES,
+	    0 => DEFAULT_NTSC_LINES,
+	    lines => lines,
+	}
+    }
+
+    //lines in the first field, after which the second starts
+    fn first_field_lines(&self) -> u32 {
+	if self.interlaced() {
+	    self.field_half_lines(self.vto).div_ceil(2).max(1)
+	} else {
+	    self.lines()
+	}
+    }
+}
+
+impl Default for VideoInterface {
+    fn default() -> Self {
+	Self::new()
+    }
 }
 
-pub fn vi_read_u16(gc: &mut Gamecube, offset: u32) -> u16 {
+pub fn register_mmio(mmio: &mut Mmio) {
+    mmio.register("VI", 0x0C00_2000, 0x1000, MmioHandlers {
+	read_u16: Some(vi_read_u16),
+	write_u16: Some(vi_write_u16),
+	..MmioHandlers::NONE
+    });
+}
+
+//starts the beam going, the line event reschedules itself from then on
+pub fn start(gc: &mut Gamecube) {
+    gc.vi.line_start = gc.cpu.cycles;
+    let cycles = gc.vi.cycles_per_line();
+    scheduler::schedule_in(gc, cycles, EventKind::ViLine, next_line);
+}
+
+//most of the registers are 32 bits wide, accessed as two halves with the upper one first
+fn reg_u32(vi: &mut VideoInterface, offset: u32) -> Option<&mut u32> {
+    Some(match offset & !3 {
+	0x04 => &mut vi.htr0,
+	0x08 => &mut vi.htr1,
+	0x0C => &mut vi.vto,
+	0x10 => &mut vi.vte,
+	0x14 => &mut vi.bbei,
+	0x18 => &mut vi.bboi,
+	0x1C => &mut vi.tfbl,
+	0x20 => &mut vi.tfbr,
+	0x24 => &mut vi.bfbl,
+	0x28 => &mut vi.bfbr,
+	0x30..=0x3C => &mut vi.di[((offset - 0x30) / 4) as usize],
+	0x40 | 0x44 => &mut vi.dl[((offset - 0x40) / 4) as usize],
+	0x4C..=0x64 => &mut vi.fct[((offset - 0x4C) / 4) as usize],
+	_ => return None,
+    })
+}
+
+pub fn vi_write_u16(gc: &mut Gamecube, offset: u32, val: u16, mask: u16) -> Option<()> {
+    debug!("VI write_u16 at offset {offset:#010X} with val {val:#06X}");
     match offset {
-	0x6C => 0,
-	_ => unimplemented!("vi read_u16 at offset {:#010X}", offset),
+	0x00 => gc.vi.vtr = merge(gc.vi.vtr, val, mask),
+	0x02 => {
+	    gc.vi.dcr = merge(gc.vi.dcr, val, mask);
+	    if val & mask & DCR_RESET != 0 {
+		gc.vi.vct = 1;
+		gc.scheduler.cancel(EventKind::ViLine);
+		start(gc);
+	    }
+	},
+	0x2C | 0x2E => debug!("STUB: VI beam position write"),
+	0x48 => gc.vi.hsw = merge(gc.vi.hsw, val, mask),
+	0x4A => gc.vi.hsr = merge(gc.vi.hsr, val, mask),
+	0x6C => gc.vi.viclk = merge(gc.vi.viclk, val, mask),
+	0x6E => gc.vi.visel = merge(gc.vi.visel, val, mask),
+	0x70 => gc.vi.hbe = merge(gc.vi.hbe, val, mask),
+	0x72 => gc.vi.hbs = merge(gc.vi.hbs, val, mask),
+	_ => match reg_u32(&mut gc.vi, offset) {
+	    Some(reg) => {
+		let shift = (!offset & 2) * 8;
+		*reg = merge(*reg, u32::from(val) << shift, u32::from(mask) << shift);
+		if (0x30..0x40).contains(&offset) {
+		    update_interrupts(gc);
+		}
+	    },
+	    None => debug!("STUB: VI write_u16 at offset {offset:#010X} with val {val:#06X}"),
+	},
+    }
+    Some(())
+}
+
+pub fn vi_read_u16(gc: &mut Gamecube, offset: u32) -> Option<u16> {
+    debug!("VI read_u16 at offset {offset:#010X}");
+    Some(match offset {
+	0x00 => gc.vi.vtr,
+	0x02 => gc.vi.dcr,
+	0x2C => gc.vi.vct as u16,
+	//the pixel along the line the beam is at, also counted from 1
+	0x2E => ((gc.cpu.cycles - gc.vi.line_start) / gc.vi.cycles_per_sample()) as u16 + 1,
+	0x48 => gc.vi.hsw,
+	0x4A => gc.vi.hsr,
+	0x6C => gc.vi.viclk,
+	0x6E => gc.vi.visel,
+	0x70 => gc.vi.hbe,
+	0x72 => gc.vi.hbs,
+	_ => match reg_u32(&mut gc.vi, offset) {
+	    Some(reg) => (*reg >> ((!offset & 2) * 8)) as u16,
+	    None => {
+		debug!("STUB: VI read_u16 at offset {offset:#010X}");
+		0
+	    },
+	},
+    })
+}
+
+fn next_line(gc: &mut Gamecube, _kind: EventKind) {
+    let vi = &mut gc.vi;
+    vi.vct += 1;
+    if vi.vct > vi.lines() {
+	vi.vct = 1;
+    }
+    if vi.vct == 1 || vi.vct == vi.first_field_lines() + 1 {
+	vi.fields += 1;
+    }
+
+    //the interrupts go off at the start of their line, hct isn't looked at
+    let vct = vi.vct;
+    for di in &mut vi.di {
+	if *di & DI_ENABLE != 0 && (*di >> 16) & 0x3FF == vct {
+	    *di |= DI_STATUS;
+	}
+    }
+    update_interrupts(gc);
+
+    start(gc);
+}
+
+fn update_interrupts(gc: &mut Gamecube) {
+    let active = gc.vi.di.iter().any(|di| di & DI_STATUS != 0 && di & DI_ENABLE != 0);
+    set_interrupt(gc, PI_INT_VI, active);
+}
+
+//an rgba8 image of what's on screen, from the top left
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+//BT.601, the other way round to what the efb copy does
+fn rgb(y: u8, u: u8, v: u8) -> [u8; 3] {
+    let c = 298 * (i32::from(y) - 16);
+    let (d, e) = (i32::from(u) - 128, i32::from(v) - 128);
+    [
+	((c + 409 * e + 128) >> 8).clamp(0, 255) as u8,
+	((c - 100 * d - 208 * e + 128) >> 8).clamp(0, 255) as u8,
+	((c + 516 * d + 128) >> 8).clamp(0, 255) as u8,
+    ]
+}
+
+fn fb_addr(reg: u32) -> usize {
+    let addr = reg & 0xFF_FFFF;
+    (if reg & FB_PAGE_OFFSET != 0 { addr << 5 } else { addr }) as usize
+}
+
+//converts the yuyv external framebuffer the VI is pointed at into rgba. interlaced, the top field makes the
+//even lines and the bottom field the odd ones. horizontal scaling isn't applied, the frame is as wide as the xfb
+pub fn frame(gc: &Gamecube) -> Frame {
+    let vi = &gc.vi;
+    let width = usize::from((vi.hsw >> 8) & 0x7F) * 16;
+    let stride = usize::from(vi.hsw & 0xFF) * 16;
+    let field_lines = usize::from((vi.vtr >> 4) & 0x3FF);
+    let fields = if vi.interlaced() { [fb_addr(vi.tfbl), fb_addr(vi.bfbl)].to_vec() } else { vec![fb_addr(vi.tfbl)] };
+    let height = field_lines * fields.len();
+
+    let mut pixels = vec![0; width * height * 4];
+    for (y, row) in pixels.chunks_exact_mut(width * 4).enumerate() {
+	let start = fields[y % fields.len()] + (y / fields.len()) * stride;
+	let Some(line) = gc.memory.get(start..(start + width * 2)) else {
+	    continue;
+	};
+
+	for (out, yuyv) in row.chunks_exact_mut(8).zip(line.chunks_exact(4)) {
+	    let [y0, u, y1, v] = [yuyv[0], yuyv[1], yuyv[2], yuyv[3]];
+	    let ([r0, g0, b0], [r1, g1, b1]) = (rgb(y0, u, v), rgb(y1, u, v));
+	    out.copy_from_slice(&[r0, g0, b0, 0xFF, r1, g1, b1, 0xFF]);
+	}
+    }
+
+    Frame {
+	width,
+	height,
+	pixels,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_gamecube;
+
+    //the NTSC 480i setup the ipl programs
+    fn ntsc(gc: &mut Gamecube) {
+	gc.vi.vtr = (240 << 4) | 6;
+	gc.vi.htr0 = 429;
+	gc.vi.vto = (3 << 16) | 24;
+	gc.vi.vte = (2 << 16) | 25;
+    }
+
+    #[test]
+    fn ntsc_runs_at_29_97_frames_a_second() {
+	let mut gc = test_gamecube();
+	//the beam moves with nothing set up too
+	assert_eq!(gc.vi.cycles_per_line(), 30888);
+	assert_eq!(gc.vi.lines(), 525);
+
+	ntsc(&mut gc);
+	assert_eq!(gc.vi.cycles_per_line(), 30888);
+	assert_eq!(gc.vi.lines(), 525);
+	assert_eq!(gc.vi.first_field_lines(), 263);
+	assert_eq!(CPU_CLOCK * 1001 / (30888 * 525), 30000);
+
+	//54MHz halves the line, progressive halves the lines
+	gc.vi.viclk = 1;
+	gc.vi.dcr |= DCR_NON_INTERLACED;
+	assert_eq!(gc.vi.cycles_per_line(), 15444);
+	assert_eq!(gc.vi.lines(), 262);
+    }
+
+    #[test]
+    fn the_beam_counts_lines_and_fields() {
+	let mut gc = test_gamecube();
+	ntsc(&mut gc);
+	start(&mut gc);
+
+	gc.cpu.cycles += 10 * gc.vi.cycles_per_sample();
+	assert_eq!(vi_read_u16(&mut gc, 0x2C), Some(1));
+	assert_eq!(vi_read_u16(&mut gc, 0x2E), Some(11));
+
+	//the second field starts on line 264, and line 525 wraps back round to 1
+	for _ in 0..263 {
+	    next_line(&mut gc, EventKind::ViLine);
+	}
+	assert_eq!((gc.vi.vct, gc.vi.fields), (264, 1));
+	for _ in 263..525 {
+	    next_line(&mut gc, EventKind::ViLine);
+	}
+	assert_eq!((gc.vi.vct, gc.vi.fields), (1, 2));
+    }
+
+    #[test]
+    fn display_interrupts_fire_on_their_line_until_acknowledged() {
+	let mut gc = test_gamecube();
+	ntsc(&mut gc);
+	gc.vi.di[0] = DI_ENABLE | (3 << 16);
+
+	next_line(&mut gc, EventKind::ViLine);
+	assert_eq!(gc.pi.intsr, 0);
+	next_line(&mut gc, EventKind::ViLine);
+	assert_eq!(gc.pi.intsr, PI_INT_VI);
+
+	//writing the upper half back without the status bit
+	let upper = (gc.vi.di[0] >> 16) as u16 & 0x7FFF;
+	vi_write_u16(&mut gc, 0x30, upper, 0xFFFF);
+	assert_eq!(gc.pi.intsr, 0);
+	assert_eq!(gc.vi.di[0], DI_ENABLE | (3 << 16));
     }
 }